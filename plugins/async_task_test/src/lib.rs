@@ -0,0 +1,214 @@
+use cubemelon_sdk::prelude::*;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Simulated work duration per task
+const WORK_STEPS: u32 = 10;
+const WORK_STEP_MS: u64 = 50;
+
+#[plugin]
+pub struct Plugin {
+    initialized: bool,
+    host_services: Option<CubeMelonHostServices>,
+    // Cancellation flags keyed by request address
+    cancel_flags: Arc<Mutex<HashMap<usize, Arc<AtomicBool>>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+#[plugin_impl]
+impl Plugin {
+    fn log_message(&self, level: CubeMelonLogLevel, message: &str) {
+        if let Some(ref services) = self.host_services {
+            services.log_message(level, "AsyncTaskPlugin", message);
+        }
+    }
+
+    pub fn new() -> Self {
+        Self {
+            initialized: false,
+            host_services: None,
+            cancel_flags: Arc::new(Mutex::new(HashMap::new())),
+            workers: Vec::new(),
+        }
+    }
+
+    pub fn get_uuid() -> CubeMelonUUID {
+        uuid!("3f0f6a52-8f0e-4d43-a3a4-6f3b0c1d2e71")
+    }
+
+    pub fn get_version() -> CubeMelonVersion {
+        version!(1, 0, 0)
+    }
+
+    pub fn get_supported_types() -> u64 {
+        CubeMelonPluginType::AsyncTask as u64
+    }
+
+    pub fn get_name(&self, language: CubeMelonLanguage) -> *const u8 {
+        multilang_map!(language, "Async Task Plugin", {
+            "ja-JP" => "非同期実行プラグイン",
+        })
+    }
+
+    pub fn get_description(&self, language: CubeMelonLanguage) -> *const u8 {
+        multilang_map!(language, "Plugin for asynchronous task execution test", {
+            "ja-JP" => "非同期実行プラグインのテストです",
+        })
+    }
+
+    pub fn initialize(
+        &mut self,
+        host_services: Option<&CubeMelonHostServices>,
+    ) -> Result<(), CubeMelonPluginErrorCode> {
+        if self.initialized {
+            return Err(CubeMelonPluginErrorCode::AlreadyInitialized);
+        }
+
+        if let Some(services) = host_services {
+            self.host_services = Some(*services);
+        }
+
+        self.initialized = true;
+        self.log_message(CubeMelonLogLevel::Info, "Plugin initialized.");
+
+        Ok(())
+    }
+
+    pub fn uninitialize(&mut self) -> Result<(), CubeMelonPluginErrorCode> {
+        if !self.initialized {
+            return Err(CubeMelonPluginErrorCode::NotInitialized);
+        }
+
+        // Stop outstanding tasks and wait for the worker threads
+        if let Ok(flags) = self.cancel_flags.lock() {
+            for flag in flags.values() {
+                flag.store(true, Ordering::SeqCst);
+            }
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+
+        self.log_message(CubeMelonLogLevel::Info, "Plugin uninitialized.");
+        self.host_services = None;
+        self.initialized = false;
+
+        Ok(())
+    }
+}
+
+#[async_task_plugin_impl]
+impl Plugin {
+    pub fn execute(
+        &mut self,
+        request: &CubeMelonTaskRequest,
+        callback: Option<CubeMelonTaskCallback>,
+    ) -> CubeMelonPluginErrorCode {
+        if !self.initialized {
+            return CubeMelonPluginErrorCode::NotInitialized;
+        }
+
+        // Reap finished workers
+        let (finished, running): (Vec<_>, Vec<_>) = self.workers.drain(..).partition(|w| w.is_finished());
+        for worker in finished {
+            let _ = worker.join();
+        }
+        self.workers = running;
+
+        let key = request as *const CubeMelonTaskRequest as usize;
+        let flag = Arc::new(AtomicBool::new(false));
+        match self.cancel_flags.lock() {
+            Ok(mut flags) => { flags.insert(key, flag.clone()); }
+            Err(_) => return CubeMelonPluginErrorCode::LockFailed,
+        }
+
+        self.log_message(CubeMelonLogLevel::Info, "Starting asynchronous task...");
+
+        let flags = self.cancel_flags.clone();
+        let services = self.host_services;
+        let worker = std::thread::spawn(move || {
+            let mut cancelled = false;
+            for _ in 0..WORK_STEPS {
+                if flag.load(Ordering::SeqCst) {
+                    cancelled = true;
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(WORK_STEP_MS));
+            }
+
+            if let Ok(mut flags) = flags.lock() {
+                flags.remove(&key);
+            }
+
+            let mut result = if cancelled {
+                CubeMelonTaskResult::new(
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                    CubeMelonString::empty(),
+                    CubeMelonExecutionStatus::Cancelled,
+                    CubeMelonPluginErrorCode::Cancelled,
+                    0,
+                )
+            } else {
+                CubeMelonTaskResult::success(
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                    CubeMelonString::from_string("{\"message\":\"async task completed\"}".to_string()),
+                    0,
+                )
+            };
+            result.progress_ratio = if cancelled { -1.0 } else { 1.0 };
+
+            if let Some(cb) = callback {
+                unsafe { cb(key as *mut CubeMelonTaskRequest, &result as *const _) };
+            }
+
+            // The result is owned by this plugin; release it once the callback returns
+            if let Some(free_fn) = result.output_json.free_string {
+                unsafe { free_fn(result.output_json.str) };
+            }
+
+            if let Some(services) = services {
+                let message = if cancelled { "Asynchronous task cancelled." } else { "Asynchronous task completed." };
+                services.log_message(CubeMelonLogLevel::Info, "AsyncTaskPlugin", message);
+            }
+        });
+        self.workers.push(worker);
+
+        CubeMelonPluginErrorCode::Success
+    }
+
+    pub fn cancel(
+        &mut self,
+        request: &mut CubeMelonTaskRequest,
+    ) -> CubeMelonPluginErrorCode {
+        let key = request as *const CubeMelonTaskRequest as usize;
+        let flag = match self.cancel_flags.lock() {
+            Ok(flags) => flags.get(&key).cloned(),
+            Err(_) => return CubeMelonPluginErrorCode::LockFailed,
+        };
+
+        match flag {
+            Some(flag) => {
+                flag.store(true, Ordering::SeqCst);
+                self.log_message(CubeMelonLogLevel::Info, "Cancellation requested.");
+                CubeMelonPluginErrorCode::Success
+            }
+            // Already completed: ignore as the specification says
+            None => CubeMelonPluginErrorCode::Success,
+        }
+    }
+}
+
+impl Default for Plugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[plugin_interface(basic, async_task)]
+impl Plugin {}
@@ -0,0 +1,7 @@
+fn main() {
+    if cfg!(target_os = "windows") {
+        let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let def_path = std::path::Path::new(&manifest_dir).join("exports.def");
+        println!("cargo:rustc-link-arg=/DEF:{}", def_path.display());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,282 @@
+use cubemelon_sdk::prelude::*;
+
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Heartbeat interval when the configuration does not specify one
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+
+/// State shared with the heartbeat thread
+struct Heartbeat {
+    status: Mutex<CubeMelonExecutionStatus>,
+    stop: AtomicBool,
+    interval_ms: AtomicU64,
+    /// Report an error after this many heartbeats (0 = never)
+    fail_after: AtomicU64,
+    beats: AtomicU64,
+}
+
+#[plugin]
+pub struct Plugin {
+    initialized: bool,
+    host_services: Option<CubeMelonHostServices>,
+    heartbeat: Arc<Heartbeat>,
+    worker: Option<JoinHandle<()>>,
+    configuration: Option<CString>,
+}
+
+/// Read `"<key>": <number>` from the configuration JSON
+fn number_from_config(config_json: &str, key: &str) -> Option<u64> {
+    config_json
+        .split_once(&format!("\"{}\"", key))
+        .and_then(|(_, rest)| rest.trim_start().strip_prefix(':'))
+        .map(|rest| rest.trim_start().chars().take_while(|c| c.is_ascii_digit()).collect::<String>())
+        .and_then(|digits| digits.parse::<u64>().ok())
+}
+
+#[plugin_impl]
+impl Plugin {
+    fn log_message(&self, level: CubeMelonLogLevel, message: &str) {
+        if let Some(ref services) = self.host_services {
+            services.log_message(level, "ResidentPlugin", message);
+        }
+    }
+
+    pub fn new() -> Self {
+        Self {
+            initialized: false,
+            host_services: None,
+            heartbeat: Arc::new(Heartbeat {
+                status: Mutex::new(CubeMelonExecutionStatus::Idle),
+                stop: AtomicBool::new(false),
+                interval_ms: AtomicU64::new(DEFAULT_INTERVAL_MS),
+                fail_after: AtomicU64::new(0),
+                beats: AtomicU64::new(0),
+            }),
+            worker: None,
+            configuration: None,
+        }
+    }
+
+    pub fn get_uuid() -> CubeMelonUUID {
+        uuid!("b7e4c2a1-5d3f-4e8a-9c61-2f7d8e0a4b19")
+    }
+
+    pub fn get_version() -> CubeMelonVersion {
+        version!(1, 0, 0)
+    }
+
+    pub fn get_supported_types() -> u64 {
+        CubeMelonPluginType::Resident as u64
+    }
+
+    /// Exercises dependency ordering: Hello World is loaded before this plugin
+    pub fn get_dependencies() -> Vec<CubeMelonPluginDependency> {
+        vec![CubeMelonPluginDependency::new(uuid!("d6090f56-26a4-420c-9e25-a37dd8ebae2e"), version!(1, 0, 0))]
+    }
+
+    pub fn get_name(&self, language: CubeMelonLanguage) -> *const u8 {
+        multilang_map!(language, "Resident Plugin", {
+            "ja-JP" => "常駐プラグイン",
+        })
+    }
+
+    pub fn get_description(&self, language: CubeMelonLanguage) -> *const u8 {
+        multilang_map!(language, "Plugin for resident service test (logs a heartbeat)", {
+            "ja-JP" => "常駐サービスのテスト用プラグインです（ハートビートを記録します）",
+        })
+    }
+
+    pub fn initialize(
+        &mut self,
+        host_services: Option<&CubeMelonHostServices>,
+    ) -> Result<(), CubeMelonPluginErrorCode> {
+        if self.initialized {
+            return Err(CubeMelonPluginErrorCode::AlreadyInitialized);
+        }
+
+        if let Some(services) = host_services {
+            self.host_services = Some(*services);
+        }
+
+        self.initialized = true;
+        self.log_message(CubeMelonLogLevel::Info, "Plugin initialized.");
+
+        Ok(())
+    }
+
+    pub fn uninitialize(&mut self) -> Result<(), CubeMelonPluginErrorCode> {
+        if !self.initialized {
+            return Err(CubeMelonPluginErrorCode::NotInitialized);
+        }
+
+        // Never leave the heartbeat thread running past uninitialize
+        self.join_worker();
+
+        self.log_message(CubeMelonLogLevel::Info, "Plugin uninitialized.");
+        self.host_services = None;
+        self.initialized = false;
+
+        Ok(())
+    }
+
+    fn set_status(&self, status: CubeMelonExecutionStatus) {
+        if let Ok(mut current) = self.heartbeat.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn join_worker(&mut self) {
+        self.heartbeat.stop.store(true, Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[resident_plugin_impl]
+impl Plugin {
+    pub fn get_status(&self) -> CubeMelonExecutionStatus {
+        self.heartbeat
+            .status
+            .lock()
+            .map(|status| *status)
+            .unwrap_or(CubeMelonExecutionStatus::Error)
+    }
+
+    pub fn get_configuration(&self) -> *const u8 {
+        match &self.configuration {
+            Some(config) => config.as_ptr() as *const u8,
+            None => std::ptr::null(),
+        }
+    }
+
+    pub fn update_configuration(&mut self, config_json: &str) -> CubeMelonPluginErrorCode {
+        let config = match CString::new(config_json) {
+            Ok(config) => config,
+            Err(_) => return CubeMelonPluginErrorCode::InvalidParameter,
+        };
+        let interval_ms = number_from_config(config_json, "interval_ms").filter(|ms| *ms > 0).unwrap_or(DEFAULT_INTERVAL_MS);
+        self.heartbeat.interval_ms.store(interval_ms, Ordering::SeqCst);
+        self.heartbeat.fail_after.store(number_from_config(config_json, "fail_after").unwrap_or(0), Ordering::SeqCst);
+        self.configuration = Some(config);
+        self.log_message(CubeMelonLogLevel::Info, &format!("Configuration updated: {}", config_json));
+        CubeMelonPluginErrorCode::Success
+    }
+
+    pub fn start(&mut self, config_json: &str) -> CubeMelonPluginErrorCode {
+        if !self.initialized {
+            return CubeMelonPluginErrorCode::NotInitialized;
+        }
+        if self.get_status() != CubeMelonExecutionStatus::Idle {
+            return CubeMelonPluginErrorCode::InvalidState;
+        }
+
+        let rc = self.update_configuration(config_json);
+        if rc != CubeMelonPluginErrorCode::Success {
+            return rc;
+        }
+
+        self.heartbeat.stop.store(false, Ordering::SeqCst);
+        self.set_status(CubeMelonExecutionStatus::Running);
+
+        let heartbeat = self.heartbeat.clone();
+        let services = self.host_services;
+        self.worker = Some(std::thread::spawn(move || {
+            while !heartbeat.stop.load(Ordering::SeqCst) {
+                let running = heartbeat
+                    .status
+                    .lock()
+                    .map(|status| *status == CubeMelonExecutionStatus::Running)
+                    .unwrap_or(false);
+                if running {
+                    let beat = heartbeat.beats.fetch_add(1, Ordering::SeqCst) + 1;
+                    if let Some(services) = services {
+                        services.log_message(CubeMelonLogLevel::Debug, "ResidentPlugin", &format!("Heartbeat #{}", beat));
+                    }
+
+                    // Simulated failure for supervisor tests
+                    let fail_after = heartbeat.fail_after.load(Ordering::SeqCst);
+                    if fail_after > 0 && beat >= fail_after {
+                        if let Ok(mut status) = heartbeat.status.lock() {
+                            *status = CubeMelonExecutionStatus::Error;
+                        }
+                        if let Some(services) = services {
+                            services.log_message(CubeMelonLogLevel::Error, "ResidentPlugin", "Simulated failure.");
+                        }
+                        break;
+                    }
+                }
+
+                // Sleep in short slices so stop() is honored promptly
+                let mut slept = 0;
+                let interval = heartbeat.interval_ms.load(Ordering::SeqCst);
+                while slept < interval && !heartbeat.stop.load(Ordering::SeqCst) {
+                    let slice = (interval - slept).min(20);
+                    std::thread::sleep(Duration::from_millis(slice));
+                    slept += slice;
+                }
+            }
+        }));
+
+        self.log_message(CubeMelonLogLevel::Info, "Resident service started.");
+        CubeMelonPluginErrorCode::Success
+    }
+
+    pub fn suspend(&mut self) -> CubeMelonPluginErrorCode {
+        if self.get_status() != CubeMelonExecutionStatus::Running {
+            return CubeMelonPluginErrorCode::InvalidState;
+        }
+        self.set_status(CubeMelonExecutionStatus::Suspended);
+        self.log_message(CubeMelonLogLevel::Info, "Resident service suspended.");
+        CubeMelonPluginErrorCode::Success
+    }
+
+    pub fn resume(&mut self) -> CubeMelonPluginErrorCode {
+        if self.get_status() != CubeMelonExecutionStatus::Suspended {
+            return CubeMelonPluginErrorCode::InvalidState;
+        }
+        self.set_status(CubeMelonExecutionStatus::Running);
+        self.log_message(CubeMelonLogLevel::Info, "Resident service resumed.");
+        CubeMelonPluginErrorCode::Success
+    }
+
+    pub fn stop(&mut self) -> CubeMelonPluginErrorCode {
+        match self.get_status() {
+            CubeMelonExecutionStatus::Running | CubeMelonExecutionStatus::Suspended => {}
+            _ => return CubeMelonPluginErrorCode::InvalidState,
+        }
+        self.join_worker();
+        self.set_status(CubeMelonExecutionStatus::Completed);
+        self.log_message(
+            CubeMelonLogLevel::Info,
+            &format!("Resident service stopped after {} heartbeats.", self.heartbeat.beats.load(Ordering::SeqCst)),
+        );
+        CubeMelonPluginErrorCode::Success
+    }
+
+    pub fn reset(&mut self) -> CubeMelonPluginErrorCode {
+        match self.get_status() {
+            CubeMelonExecutionStatus::Completed
+            | CubeMelonExecutionStatus::Error
+            | CubeMelonExecutionStatus::Cancelled => {}
+            _ => return CubeMelonPluginErrorCode::InvalidState,
+        }
+        self.join_worker();
+        self.heartbeat.beats.store(0, Ordering::SeqCst);
+        self.set_status(CubeMelonExecutionStatus::Idle);
+        CubeMelonPluginErrorCode::Success
+    }
+}
+
+impl Default for Plugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[plugin_interface(basic, resident)]
+impl Plugin {}
@@ -45,6 +45,14 @@ impl Plugin {
         })
     }
 
+    pub fn get_capabilities() -> &'static str {
+        r#"
+            task_types = ["generic"]
+            input_formats = ["json"]
+            languages = ["en-US", "ja-JP"]
+        "#
+    }
+
     pub fn initialize(
         &mut self,
         host_services: Option<&CubeMelonHostServices>,
@@ -116,6 +124,42 @@ impl Plugin {
                         } else {
                             self.log_message(CubeMelonLogLevel::Warn, "Host State.get_format_name returned null");
                         }
+
+                        // Read a host setting
+                        let mut language = CubeMelonValue::null();
+                        let ec = (vtbl.get_state_value)(host_plugin, CubeMelonPluginStateScope::Host, c"language".as_ptr() as *const u8, &mut language);
+                        if ec == CubeMelonPluginErrorCode::Success && language.tag == CubeMelonValueTag::String {
+                            self.log_message(CubeMelonLogLevel::Info, &format!("Host State language: {}", language.as_str().unwrap_or("?")));
+                        } else {
+                            self.log_message(CubeMelonLogLevel::Warn, &format!("Host State.get_state_value(language) failed: {:?}", ec));
+                        }
+                        if let Some(free_fn) = language.free_value {
+                            free_fn(&mut language);
+                        }
+
+                        // Count initializations in this plugin's Local state
+                        let key = c"initialize_count".as_ptr() as *const u8;
+                        let mut value = CubeMelonValue::null();
+                        let previous = if (vtbl.get_state_value)(host_plugin, CubeMelonPluginStateScope::Local, key, &mut value) == CubeMelonPluginErrorCode::Success {
+                            let count = if value.tag == CubeMelonValueTag::String {
+                                value.as_str().ok().and_then(|s| s.parse::<u64>().ok()).unwrap_or(0)
+                            } else {
+                                0
+                            };
+                            if let Some(free_fn) = value.free_value {
+                                free_fn(&mut value);
+                            }
+                            count
+                        } else {
+                            0
+                        };
+                        let count = (previous + 1).to_string();
+                        let ec = (vtbl.set_state_value)(host_plugin as *mut CubeMelonPlugin, CubeMelonPluginStateScope::Local, key, count.as_ptr(), count.len());
+                        if ec == CubeMelonPluginErrorCode::Success {
+                            self.log_message(CubeMelonLogLevel::Info, &format!("Host State Local initialize_count: {}", count));
+                        } else {
+                            self.log_message(CubeMelonLogLevel::Warn, &format!("Host State.set_state_value(Local) failed: {:?}", ec));
+                        }
                     } else {
                         self.log_message(CubeMelonLogLevel::Warn, &format!("get_host_interface(State) failed: {:?}", ec));
                     }
@@ -0,0 +1,1073 @@
+//! Asynchronous Task Dispatch
+//!
+//! This module drives `execute_async_task` and `cancel_async_task` for RuntimeData.
+//!
+//! Every in-flight request is tracked in a process-wide registry, because the
+//! C ABI callback carries no context of its own. The plugin never sees the
+//! caller's `CubeMelonTaskRequest` directly: it receives a host-owned copy whose
+//! address is the registry key, so a late callback can never be confused with a
+//! newer request that happens to reuse the caller's address. The copy owns its
+//! input buffers, so the caller may destroy its request once it has been told,
+//! even if the plugin is still running. Input that cannot be copied (pointer or
+//! custom values) is shared with the caller instead.
+//!
+//! Ownership follows `sdk/src/interfaces/async_task.rs`:
+//! - The caller's request is handed back to the caller's callback, which destroys it.
+//! - The result belongs to whoever produced it (the plugin, or the worker thread
+//!   for SingleTask fallbacks) and is released after the callback returns.
+//! - A successful cancel delivers a `Cancelled` result to the caller's callback
+//!   before returning; the caller destroys the request afterwards, so the callback
+//!   must not destroy it when it sees `CubeMelonExecutionStatus::Cancelled`.
+//!   Whatever the plugin reports later is swallowed by the host. If the input is
+//!   shared, the `Cancelled` result is delivered only once the plugin reports
+//!   back, since it may read the caller's buffers until then.
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread::ThreadId;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use cubemelon_sdk::{
+    CubeMelonUUID, CubeMelonPlugin, CubeMelonPluginErrorCode, CubeMelonLogLevel, CubeMelonPluginType,
+    CubeMelonInterface, CubeMelonAsyncTaskInterfaceImpl, CubeMelonSingleTaskInterfaceImpl,
+    CubeMelonHostServices, CubeMelonTaskRequest, CubeMelonTaskResult, CubeMelonTaskCallback,
+    CubeMelonExecutionStatus, CubeMelonString,
+};
+
+use crate::host_services::{runtime_log, enter_plugin};
+use crate::ipc::{OwnedRequest, WireRequest};
+use crate::isolation;
+use crate::loader::PluginEntryPoints;
+use crate::metrics::{self, CallInterface};
+use crate::trace::{self, SpanContext};
+
+/// Delivery state of a tracked request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TaskState {
+    /// Submitted to the plugin, no result yet
+    InFlight,
+    /// The caller's callback is running on the given thread
+    Delivering(ThreadId),
+    /// `cancel` is notifying the plugin and the caller
+    Cancelling {
+        /// The plugin already reported back and will not touch the request again
+        plugin_finished: bool,
+    },
+    /// The caller has been told; waiting for the plugin to let go of the request
+    Cancelled,
+    /// Cancelled, but the plugin still shares the caller's input; the caller is
+    /// told once the plugin reports back
+    CancelPending,
+}
+
+/// Plugin entry used to forward cancellation
+#[derive(Clone, Copy)]
+struct CancelTarget {
+    instance: usize,
+    cancel: extern "C" fn(*mut CubeMelonPlugin, *mut CubeMelonTaskRequest) -> CubeMelonPluginErrorCode,
+}
+
+/// Host-owned copy of a request, handed to the plugin
+enum HostRequest {
+    /// Deep copy; the caller's buffers may go away at any time
+    Owned(Box<OwnedRequest>),
+    /// Shares the caller's input, which could not be copied
+    Borrowed(Box<CubeMelonTaskRequest>),
+}
+
+// The plugin reaches the copy through its address; the host only frees it
+unsafe impl Send for HostRequest {}
+
+impl HostRequest {
+    fn new(request: &CubeMelonTaskRequest) -> Self {
+        match owned_copy(request) {
+            Some(copy) => HostRequest::Owned(Box::new(copy)),
+            None => HostRequest::Borrowed(Box::new(request.borrowed_copy())),
+        }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut CubeMelonTaskRequest {
+        match self {
+            HostRequest::Owned(copy) => &mut copy.0,
+            HostRequest::Borrowed(copy) => &mut **copy,
+        }
+    }
+
+    fn shares_input(&self) -> bool {
+        matches!(self, HostRequest::Borrowed(_))
+    }
+}
+
+/// Bookkeeping for one in-flight request
+struct PendingTask {
+    target_uuid: CubeMelonUUID,
+    /// Copy handed to the plugin; its address is the registry key
+    host_request: HostRequest,
+    /// Address of the caller's request (handed back to the callback)
+    caller_request: usize,
+    callback: Option<CubeMelonTaskCallback>,
+    /// None for worker thread fallbacks, which cannot be interrupted
+    cancel_target: Option<CancelTarget>,
+    state: TaskState,
+    /// When the task was submitted, for its latency metric
+    started: Instant,
+    /// Span of the task in its trace, closed when the caller is told
+    span: SpanContext,
+}
+
+/// Registry of in-flight requests keyed by the host-owned request address
+struct TaskRegistry {
+    tasks: Mutex<HashMap<usize, PendingTask>>,
+    changed: Condvar,
+}
+
+static TASK_REGISTRY: OnceLock<TaskRegistry> = OnceLock::new();
+
+fn registry() -> &'static TaskRegistry {
+    TASK_REGISTRY.get_or_init(|| TaskRegistry {
+        tasks: Mutex::new(HashMap::new()),
+        changed: Condvar::new(),
+    })
+}
+
+fn lock_tasks(registry: &TaskRegistry) -> MutexGuard<'_, HashMap<usize, PendingTask>> {
+    // A panicking callback must not wedge every other task
+    registry.tasks.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Copy a request so it outlives the caller's buffers
+///
+/// None when the input holds values that cannot be copied.
+pub(crate) fn owned_copy(request: &CubeMelonTaskRequest) -> Option<OwnedRequest> {
+    let mut copy = WireRequest::from_request(request).ok()?.to_request();
+    copy.0.caller = request.caller;
+    copy.0.user_data = request.user_data;
+    copy.0.reserved = request.reserved;
+    Some(copy)
+}
+
+fn cancelled_result() -> CubeMelonTaskResult {
+    CubeMelonTaskResult::new(
+        std::ptr::null(),
+        std::ptr::null_mut(),
+        CubeMelonString::empty(),
+        CubeMelonExecutionStatus::Cancelled,
+        CubeMelonPluginErrorCode::Cancelled,
+        now_us(),
+    )
+}
+
+/// Track a new request
+///
+/// Returns the host-owned copy handed to the plugin, which carries the trace
+/// ID, and the task's span.
+fn register(
+    target_uuid: CubeMelonUUID,
+    request: &CubeMelonTaskRequest,
+    callback: Option<CubeMelonTaskCallback>,
+    cancel_target: Option<CancelTarget>,
+) -> (*mut CubeMelonTaskRequest, SpanContext) {
+    let span = trace::start_span(request.trace_id(), target_uuid, CallInterface::AsyncTask);
+    let mut copy = HostRequest::new(request);
+    let host_request = copy.as_mut_ptr();
+    unsafe { (*host_request).set_trace_id(span.trace_id) };
+    let task = PendingTask {
+        target_uuid,
+        host_request: copy,
+        caller_request: request as *const CubeMelonTaskRequest as usize,
+        callback,
+        cancel_target,
+        state: TaskState::InFlight,
+        started: Instant::now(),
+        span,
+    };
+    lock_tasks(registry()).insert(host_request as usize, task);
+    (host_request, span)
+}
+
+/// Drop a request that could not be started, closing its span with `rc`
+fn discard(key: usize, rc: CubeMelonPluginErrorCode) {
+    let reg = registry();
+    let removed = {
+        let mut tasks = lock_tasks(reg);
+        match tasks.get(&key) {
+            Some(task) if task.state == TaskState::InFlight => tasks.remove(&key),
+            _ => None,
+        }
+    };
+    if let Some(task) = removed {
+        trace::finish_span(task.span, rc);
+        reg.changed.notify_all();
+    }
+}
+
+/// Whether the caller has cancelled the request behind a host-owned copy
+fn is_cancelled(key: usize) -> bool {
+    matches!(
+        lock_tasks(registry()).get(&key).map(|t| t.state),
+        Some(TaskState::Cancelling { .. }) | Some(TaskState::Cancelled) | Some(TaskState::CancelPending)
+    )
+}
+
+pub(crate) fn now_us() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+/// Number of requests still in flight for a plugin
+pub(crate) fn in_flight_count(target_uuid: CubeMelonUUID) -> usize {
+    lock_tasks(registry())
+        .values()
+        .filter(|t| t.target_uuid == target_uuid)
+        .count()
+}
+
+/// Forget every request served by a plugin instance that has been destroyed
+///
+/// Only call this once the instance can no longer invoke the callback.
+pub(crate) fn discard_all_for_instance(instance: *mut CubeMelonPlugin) -> usize {
+    let reg = registry();
+    let count = {
+        let mut tasks = lock_tasks(reg);
+        let before = tasks.len();
+        tasks.retain(|_, t| t.cancel_target.is_none_or(|c| c.instance != instance as usize));
+        before - tasks.len()
+    };
+    reg.changed.notify_all();
+    count
+}
+
+/// Host callback handed to plugins in place of the caller's callback
+///
+/// Routes the result to the caller's callback exactly once, then releases the
+/// host-owned request copy. Results of cancelled requests and unknown requests
+/// are ignored, except that a request cancelled while sharing the caller's
+/// input is reported as `Cancelled` now.
+pub(crate) unsafe extern "C" fn async_task_callback(
+    request: *mut CubeMelonTaskRequest,
+    result: *const CubeMelonTaskResult,
+) {
+    let key = request as usize;
+    let reg = registry();
+
+    let (caller_request, callback, cancelled) = {
+        let mut tasks = lock_tasks(reg);
+        match tasks.get_mut(&key) {
+            Some(task) => match task.state {
+                TaskState::InFlight => {
+                    task.state = TaskState::Delivering(std::thread::current().id());
+                    let outcome = result.as_ref().map_or(CubeMelonPluginErrorCode::NullPointer, |result| {
+                        metrics::call_outcome(CubeMelonPluginErrorCode::Success, result)
+                    });
+                    metrics::record_call(task.target_uuid, CallInterface::AsyncTask, outcome, task.started.elapsed());
+                    trace::finish_span(task.span, outcome);
+                    (task.caller_request, task.callback, false)
+                }
+                TaskState::CancelPending => {
+                    // Metrics and span were closed by the canceller
+                    task.state = TaskState::Delivering(std::thread::current().id());
+                    (task.caller_request, task.callback, true)
+                }
+                TaskState::Cancelling { ref mut plugin_finished } => {
+                    // The canceller releases the request once it is done with it
+                    *plugin_finished = true;
+                    return;
+                }
+                TaskState::Cancelled => {
+                    tasks.remove(&key);
+                    drop(tasks);
+                    reg.changed.notify_all();
+                    return;
+                }
+                TaskState::Delivering(_) => {
+                    runtime_log(CubeMelonLogLevel::Debug, "Duplicate async task callback ignored");
+                    return;
+                }
+            },
+            None => {
+                runtime_log(CubeMelonLogLevel::Debug, "Callback for unknown async task ignored");
+                return;
+            }
+        }
+    };
+
+    // The caller's callback owns (and destroys) the caller's request from here on
+    if let Some(cb) = callback {
+        if cancelled {
+            cb(caller_request as *mut CubeMelonTaskRequest, &cancelled_result());
+        } else {
+            cb(caller_request as *mut CubeMelonTaskRequest, result);
+        }
+    }
+
+    lock_tasks(reg).remove(&key);
+    reg.changed.notify_all();
+}
+
+/// Start a task through the plugin's AsyncTask interface
+pub(crate) fn execute_with_async_interface(
+    target_uuid: CubeMelonUUID,
+    instance: *mut CubeMelonPlugin,
+    interface: &CubeMelonAsyncTaskInterfaceImpl,
+    request: &CubeMelonTaskRequest,
+    callback: Option<CubeMelonTaskCallback>,
+) -> CubeMelonPluginErrorCode {
+    let cancel_target = CancelTarget {
+        instance: instance as usize,
+        cancel: interface.cancel,
+    };
+    let (host_request, span) = register(target_uuid, request, callback, Some(cancel_target));
+
+    let rc = {
+        let _active = trace::enter(Some(span));
+        let _caller = enter_plugin(target_uuid);
+        (interface.execute)(instance, host_request, async_task_callback)
+    };
+    if rc != CubeMelonPluginErrorCode::Success {
+        runtime_log(CubeMelonLogLevel::Warn, &format!("Plugin refused async task: {:?}", rc));
+        discard(host_request as usize, rc);
+    }
+    rc
+}
+
+/// Run a SingleTask plugin on a worker thread and report through the callback
+///
+/// The worker owns a dedicated plugin instance for the duration of the task.
+/// Its manager and state calls wait for the thread owning the runtime (see
+/// `isolation`).
+pub(crate) fn execute_on_worker_thread(
+    target_uuid: CubeMelonUUID,
+    entry_points: PluginEntryPoints,
+    host_services: CubeMelonHostServices,
+    request: &CubeMelonTaskRequest,
+    callback: Option<CubeMelonTaskCallback>,
+) -> CubeMelonPluginErrorCode {
+    execute_on_thread(target_uuid, request, callback, move |request, result| {
+        let _caller = enter_plugin(target_uuid);
+        run_single_task(&entry_points, &host_services, request, result)
+    })
+}
+
+/// Run a blocking task on a worker thread and report through the callback
+///
+/// A task that cannot be interrupted: cancelling only tells the caller.
+pub(crate) fn execute_on_thread(
+    target_uuid: CubeMelonUUID,
+    request: &CubeMelonTaskRequest,
+    callback: Option<CubeMelonTaskCallback>,
+    task: impl FnOnce(*const CubeMelonTaskRequest, &mut CubeMelonTaskResult) -> CubeMelonPluginErrorCode + Send + 'static,
+) -> CubeMelonPluginErrorCode {
+    let (host_request, span) = register(target_uuid, request, callback, None);
+    let key = host_request as usize;
+
+    let spawned = std::thread::Builder::new()
+        .name(format!("cubemelon-task-{}", target_uuid))
+        .spawn(move || {
+            let request = key as *mut CubeMelonTaskRequest;
+            let mut result = CubeMelonTaskResult::empty();
+            // Skip the work entirely if the caller gave up before we started
+            if !is_cancelled(key) {
+                let _active = trace::enter(Some(span));
+                let rc = task(request, &mut result);
+                if rc != CubeMelonPluginErrorCode::Success {
+                    result.status = CubeMelonExecutionStatus::Error;
+                    result.error_code = rc;
+                }
+            }
+
+            unsafe { async_task_callback(request, &result as *const _) };
+
+            // The worker produced the result, so the worker releases it
+            free_task_result(&mut result);
+        });
+
+    match spawned {
+        Ok(_) => CubeMelonPluginErrorCode::Success,
+        Err(e) => {
+            runtime_log(CubeMelonLogLevel::Error, &format!("Failed to spawn task worker: {}", e));
+            discard(key, CubeMelonPluginErrorCode::ResourceExhausted);
+            CubeMelonPluginErrorCode::ResourceExhausted
+        }
+    }
+}
+
+/// Cancel a request previously passed to `execute_async_task`
+///
+/// Forwards the cancellation to the owning plugin and reports a `Cancelled`
+/// result to the caller's callback before returning, or once the plugin
+/// reports back if it shares the caller's input. Requests that already
+/// completed (or were already cancelled) are ignored.
+pub(crate) fn cancel(request: *const CubeMelonTaskRequest) -> CubeMelonPluginErrorCode {
+    let caller_request = request as usize;
+    let reg = registry();
+
+    let (key, callback, cancel_target, shares_input) = {
+        let mut tasks = lock_tasks(reg);
+        loop {
+            // Pointers of cancelled requests may be reused by the caller, so
+            // only look at requests the caller has not given up yet
+            let live = tasks
+                .iter()
+                .find(|(_, t)| {
+                    t.caller_request == caller_request
+                        && matches!(t.state, TaskState::InFlight | TaskState::Delivering(_))
+                })
+                .map(|(k, t)| (*k, t.state));
+
+            match live {
+                None => {
+                    runtime_log(CubeMelonLogLevel::Debug, "Cancel ignored: task already completed");
+                    return CubeMelonPluginErrorCode::Success;
+                }
+                Some((_, TaskState::Delivering(thread))) => {
+                    // Cancel from inside the callback itself: the result is already out
+                    if thread == std::thread::current().id() {
+                        return CubeMelonPluginErrorCode::Success;
+                    }
+                    // Otherwise wait for delivery to finish; the entry is gone afterwards.
+                    // The callback may need the runtime meanwhile (see `isolation`).
+                    drop(tasks);
+                    isolation::serve_forwarded_calls();
+                    tasks = lock_tasks(reg);
+                    tasks = reg.changed.wait_timeout(tasks, isolation::CALL_POLL_INTERVAL).unwrap_or_else(|e| e.into_inner()).0;
+                }
+                Some((key, _)) => {
+                    let task = tasks.get_mut(&key).expect("task vanished under lock");
+                    task.state = TaskState::Cancelling { plugin_finished: false };
+                    let (uuid, elapsed) = (task.target_uuid, task.started.elapsed());
+                    metrics::record_call(uuid, CallInterface::AsyncTask, CubeMelonPluginErrorCode::Cancelled, elapsed);
+                    trace::finish_span(task.span, CubeMelonPluginErrorCode::Cancelled);
+                    break (key, task.callback, task.cancel_target, task.host_request.shares_input());
+                }
+            }
+        }
+    };
+
+    // Forward to the owning plugin without holding the registry lock, since the
+    // plugin may report back from another thread meanwhile
+    if let Some(target) = cancel_target {
+        let rc = (target.cancel)(target.instance as *mut CubeMelonPlugin, key as *mut CubeMelonTaskRequest);
+        if rc != CubeMelonPluginErrorCode::Success {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Plugin cancel returned: {:?}", rc));
+        }
+    }
+
+    if shares_input {
+        // The caller must keep its buffers until the plugin lets go of them
+        let mut tasks = lock_tasks(reg);
+        if let Some(task) = tasks.get_mut(&key) {
+            if task.state == (TaskState::Cancelling { plugin_finished: false }) {
+                task.state = TaskState::CancelPending;
+                return CubeMelonPluginErrorCode::Success;
+            }
+        }
+    }
+
+    if let Some(cb) = callback {
+        let result = cancelled_result();
+        unsafe { cb(caller_request as *mut CubeMelonTaskRequest, &result as *const _) };
+    }
+
+    // Release the host copy now if the plugin is already done with it,
+    // otherwise leave that to its (swallowed) callback
+    {
+        let mut tasks = lock_tasks(reg);
+        match tasks.get(&key).map(|t| t.state) {
+            Some(TaskState::Cancelling { plugin_finished: true }) => {
+                tasks.remove(&key);
+            }
+            Some(_) => {
+                if let Some(task) = tasks.get_mut(&key) {
+                    task.state = TaskState::Cancelled;
+                }
+            }
+            None => {}
+        }
+    }
+    reg.changed.notify_all();
+
+    CubeMelonPluginErrorCode::Success
+}
+
+/// Create, initialize, execute, uninitialize and destroy a SingleTask instance
+pub(crate) fn run_single_task(
+    entry_points: &PluginEntryPoints,
+    host_services: &CubeMelonHostServices,
+    request: *const CubeMelonTaskRequest,
+    result: &mut CubeMelonTaskResult,
+) -> CubeMelonPluginErrorCode {
+    let basic = match entry_points.get_interface::<CubeMelonInterface>(CubeMelonPluginType::Basic) {
+        Some(iface) => iface,
+        None => return CubeMelonPluginErrorCode::InterfaceNotSupported,
+    };
+    let single_task = match entry_points.get_interface::<CubeMelonSingleTaskInterfaceImpl>(CubeMelonPluginType::SingleTask) {
+        Some(iface) => iface,
+        None => return CubeMelonPluginErrorCode::InterfaceNotSupported,
+    };
+
+    let instance = unsafe { (entry_points.create_plugin)() };
+    if instance.is_null() {
+        return CubeMelonPluginErrorCode::PluginLoadFailed;
+    }
+
+    let init_rc = (basic.initialize)(instance, host_services as *const _);
+    if init_rc != CubeMelonPluginErrorCode::Success {
+        unsafe { (entry_points.destroy_plugin)(instance) };
+        return init_rc;
+    }
+
+    let exec_rc = (single_task.execute)(instance, request, result as *mut _);
+
+    let _ = (basic.uninitialize)(instance);
+    unsafe { (entry_points.destroy_plugin)(instance) };
+
+    exec_rc
+}
+
+/// Release the buffers of a result produced on the host side
+pub(crate) fn free_task_result(result: &mut CubeMelonTaskResult) {
+    for s in [&mut result.output_json, &mut result.progress_message, &mut result.progress_stage] {
+        if let Some(free_fn) = s.free_string {
+            if !s.str.is_null() {
+                unsafe { free_fn(s.str) };
+            }
+        }
+        *s = CubeMelonString::empty();
+    }
+    if !result.output_data.is_null() {
+        unsafe {
+            if let Some(free_fn) = (*result.output_data).free_value {
+                free_fn(result.output_data);
+            }
+        }
+        result.output_data = std::ptr::null_mut();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::mpsc::{channel, Sender};
+    use std::time::Duration;
+    use cubemelon_sdk::{
+        CubeMelonAsyncTaskInterface, CubeMelonSingleTaskInterface, CubeMelonTaskType, CubeMelonLanguage,
+        create_async_task_interface, create_single_task_interface,
+        create_plugin_instance, destroy_plugin_instance,
+    };
+
+    /// What a test callback observed
+    type Observed = (usize, CubeMelonExecutionStatus, CubeMelonPluginErrorCode, String);
+
+    unsafe extern "C" fn record_callback(request: *mut CubeMelonTaskRequest, result: *const CubeMelonTaskResult) {
+        let tx = &*((*request).user_data as *const Sender<Observed>);
+        let result = &*result;
+        let json = result.output_json.as_str().unwrap_or("").to_string();
+        let _ = tx.send((request as usize, result.status, result.error_code, json));
+    }
+
+    fn make_request(tx: &Sender<Observed>) -> CubeMelonTaskRequest {
+        let mut request = CubeMelonTaskRequest::new(
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            CubeMelonString::empty(),
+            CubeMelonTaskType::Generic,
+            CubeMelonLanguage::EN_US,
+            0,
+            0,
+        );
+        request.user_data = tx as *const Sender<Observed> as *mut std::ffi::c_void;
+        request
+    }
+
+    /// Async plugin that completes every task from its own thread
+    struct ThreadedAsyncPlugin;
+
+    impl CubeMelonAsyncTaskInterface for ThreadedAsyncPlugin {
+        fn execute(
+            &mut self,
+            request: &CubeMelonTaskRequest,
+            callback: Option<CubeMelonTaskCallback>,
+        ) -> CubeMelonPluginErrorCode {
+            let key = request as *const CubeMelonTaskRequest as usize;
+            std::thread::spawn(move || {
+                let mut result = CubeMelonTaskResult::success(
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                    CubeMelonString::from_string("{\"ok\":true}".to_string()),
+                    0,
+                );
+                if let Some(cb) = callback {
+                    unsafe { cb(key as *mut CubeMelonTaskRequest, &result) };
+                }
+                free_task_result(&mut result);
+            });
+            CubeMelonPluginErrorCode::Success
+        }
+
+        fn cancel(&mut self, _request: &mut CubeMelonTaskRequest) -> CubeMelonPluginErrorCode {
+            CubeMelonPluginErrorCode::Success
+        }
+    }
+
+    /// Async plugin that refuses every task
+    struct RefusingAsyncPlugin;
+
+    impl CubeMelonAsyncTaskInterface for RefusingAsyncPlugin {
+        fn execute(
+            &mut self,
+            _request: &CubeMelonTaskRequest,
+            _callback: Option<CubeMelonTaskCallback>,
+        ) -> CubeMelonPluginErrorCode {
+            CubeMelonPluginErrorCode::ResourceBusy
+        }
+
+        fn cancel(&mut self, _request: &mut CubeMelonTaskRequest) -> CubeMelonPluginErrorCode {
+            CubeMelonPluginErrorCode::Success
+        }
+    }
+
+    /// SingleTask plugin used for the worker thread fallback
+    struct SyncPlugin;
+
+    impl CubeMelonSingleTaskInterface for SyncPlugin {
+        fn execute(
+            &mut self,
+            _request: &CubeMelonTaskRequest,
+            result: &mut CubeMelonTaskResult,
+        ) -> CubeMelonPluginErrorCode {
+            *result = CubeMelonTaskResult::success(
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                CubeMelonString::from_string("{\"sync\":true}".to_string()),
+                0,
+            );
+            CubeMelonPluginErrorCode::Success
+        }
+    }
+
+    /// Pending work of a DeferredAsyncPlugin: host request address and callback
+    type Deferred = Arc<Mutex<Vec<(usize, CubeMelonTaskCallback)>>>;
+
+    /// Async plugin that completes tasks only when the test says so
+    struct DeferredAsyncPlugin {
+        pending: Deferred,
+        cancelled: Arc<Mutex<Vec<usize>>>,
+        /// Report Cancelled from inside cancel(), as some plugins do
+        complete_on_cancel: bool,
+    }
+
+    impl DeferredAsyncPlugin {
+        fn new(complete_on_cancel: bool) -> (Self, Deferred, Arc<Mutex<Vec<usize>>>) {
+            let pending: Deferred = Arc::new(Mutex::new(Vec::new()));
+            let cancelled = Arc::new(Mutex::new(Vec::new()));
+            let plugin = Self {
+                pending: pending.clone(),
+                cancelled: cancelled.clone(),
+                complete_on_cancel,
+            };
+            (plugin, pending, cancelled)
+        }
+    }
+
+    impl CubeMelonAsyncTaskInterface for DeferredAsyncPlugin {
+        fn execute(
+            &mut self,
+            request: &CubeMelonTaskRequest,
+            callback: Option<CubeMelonTaskCallback>,
+        ) -> CubeMelonPluginErrorCode {
+            let key = request as *const CubeMelonTaskRequest as usize;
+            self.pending.lock().unwrap().push((key, callback.unwrap()));
+            CubeMelonPluginErrorCode::Success
+        }
+
+        fn cancel(&mut self, request: &mut CubeMelonTaskRequest) -> CubeMelonPluginErrorCode {
+            let key = request as *mut CubeMelonTaskRequest as usize;
+            self.cancelled.lock().unwrap().push(key);
+            if self.complete_on_cancel {
+                let entry = {
+                    let mut pending = self.pending.lock().unwrap();
+                    let pos = pending.iter().position(|(k, _)| *k == key);
+                    pos.map(|i| pending.remove(i))
+                };
+                if let Some((key, cb)) = entry {
+                    complete(key, cb, CubeMelonExecutionStatus::Cancelled, CubeMelonPluginErrorCode::Cancelled);
+                }
+            }
+            CubeMelonPluginErrorCode::Success
+        }
+    }
+
+    fn complete(
+        key: usize,
+        callback: CubeMelonTaskCallback,
+        status: CubeMelonExecutionStatus,
+        code: CubeMelonPluginErrorCode,
+    ) {
+        let result = CubeMelonTaskResult::new(
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            CubeMelonString::empty(),
+            status,
+            code,
+            0,
+        );
+        unsafe { callback(key as *mut CubeMelonTaskRequest, &result) };
+    }
+
+    fn complete_all(pending: &Deferred) {
+        let drained: Vec<_> = pending.lock().unwrap().drain(..).collect();
+        for (key, cb) in drained {
+            complete(key, cb, CubeMelonExecutionStatus::Completed, CubeMelonPluginErrorCode::Success);
+        }
+    }
+
+    /// Wait until the plugin has let go of every request of `uuid`
+    fn wait_until_settled(uuid: CubeMelonUUID) {
+        for _ in 0..500 {
+            if in_flight_count(uuid) == 0 {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        panic!("async tasks for {} never settled", uuid);
+    }
+
+    static BASIC_VTABLE: OnceLock<CubeMelonInterface> = OnceLock::new();
+    static SINGLE_TASK_VTABLE: OnceLock<CubeMelonSingleTaskInterfaceImpl> = OnceLock::new();
+
+    unsafe extern "C" fn sync_get_plugin_interface(
+        plugin_types: u64,
+        _interface_version: u32,
+        interface: *mut *const std::ffi::c_void,
+    ) -> CubeMelonPluginErrorCode {
+        if plugin_types == CubeMelonPluginType::Basic as u64 {
+            *interface = BASIC_VTABLE.get_or_init(CubeMelonInterface::new) as *const _ as *const std::ffi::c_void;
+            CubeMelonPluginErrorCode::Success
+        } else if plugin_types == CubeMelonPluginType::SingleTask as u64 {
+            *interface = SINGLE_TASK_VTABLE.get_or_init(create_single_task_interface::<SyncPlugin>) as *const _ as *const std::ffi::c_void;
+            CubeMelonPluginErrorCode::Success
+        } else {
+            CubeMelonPluginErrorCode::InterfaceNotSupported
+        }
+    }
+
+    unsafe extern "C" fn sync_create_plugin() -> *mut CubeMelonPlugin {
+        create_plugin_instance(SyncPlugin)
+    }
+
+    unsafe extern "C" fn sync_destroy_plugin(plugin: *mut CubeMelonPlugin) {
+        destroy_plugin_instance(plugin);
+    }
+
+    fn sync_entry_points() -> PluginEntryPoints {
+        PluginEntryPoints {
+            get_plugin_interface: sync_get_plugin_interface,
+            create_plugin: sync_create_plugin,
+            destroy_plugin: sync_destroy_plugin,
+        }
+    }
+
+    #[test]
+    fn test_async_interface_delivers_result_to_caller() {
+        let uuid = CubeMelonUUID::from_bytes([0xA1; 16]);
+        let (tx, rx) = channel();
+        let request = make_request(&tx);
+
+        let instance = create_plugin_instance(ThreadedAsyncPlugin);
+        let interface = create_async_task_interface::<ThreadedAsyncPlugin>();
+
+        let rc = execute_with_async_interface(uuid, instance, &interface, &request, Some(record_callback));
+        assert_eq!(rc, CubeMelonPluginErrorCode::Success);
+
+        let (ptr, status, code, json) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(ptr, &request as *const _ as usize); // caller's request, not the host copy
+        assert_eq!(status, CubeMelonExecutionStatus::Completed);
+        assert_eq!(code, CubeMelonPluginErrorCode::Success);
+        assert_eq!(json, "{\"ok\":true}");
+
+        // Exactly one callback, and the registry entry is gone
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(in_flight_count(uuid), 0);
+
+        destroy_plugin_instance(instance);
+    }
+
+    #[test]
+    fn test_refused_task_is_not_tracked() {
+        let uuid = CubeMelonUUID::from_bytes([0xA2; 16]);
+        let (tx, rx) = channel();
+        let request = make_request(&tx);
+
+        let instance = create_plugin_instance(RefusingAsyncPlugin);
+        let interface = create_async_task_interface::<RefusingAsyncPlugin>();
+
+        let rc = execute_with_async_interface(uuid, instance, &interface, &request, Some(record_callback));
+        assert_eq!(rc, CubeMelonPluginErrorCode::ResourceBusy);
+        assert_eq!(in_flight_count(uuid), 0);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        destroy_plugin_instance(instance);
+    }
+
+    #[test]
+    fn test_single_task_fallback_runs_on_worker_thread() {
+        let uuid = CubeMelonUUID::from_bytes([0xA3; 16]);
+        let (tx, rx) = channel();
+        let request = make_request(&tx);
+
+        let rc = execute_on_worker_thread(
+            uuid,
+            sync_entry_points(),
+            CubeMelonHostServices::empty(),
+            &request,
+            Some(record_callback),
+        );
+        assert_eq!(rc, CubeMelonPluginErrorCode::Success);
+
+        let (ptr, status, code, json) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(ptr, &request as *const _ as usize);
+        assert_eq!(status, CubeMelonExecutionStatus::Completed);
+        assert_eq!(code, CubeMelonPluginErrorCode::Success);
+        assert_eq!(json, "{\"sync\":true}");
+
+        // The callback may still be inside send(); keep `tx` alive until it returns
+        wait_until_settled(uuid);
+    }
+
+    #[test]
+    fn test_cancel_reports_cancelled_and_swallows_late_result() {
+        let uuid = CubeMelonUUID::from_bytes([0xB1; 16]);
+        let (tx, rx) = channel();
+        let mut request = make_request(&tx);
+
+        let (plugin, pending, cancelled) = DeferredAsyncPlugin::new(false);
+        let instance = create_plugin_instance(plugin);
+        let interface = create_async_task_interface::<DeferredAsyncPlugin>();
+
+        let rc = execute_with_async_interface(uuid, instance, &interface, &request, Some(record_callback));
+        assert_eq!(rc, CubeMelonPluginErrorCode::Success);
+
+        assert_eq!(cancel(&mut request as *mut _), CubeMelonPluginErrorCode::Success);
+
+        // The caller has been told before cancel returned
+        let (ptr, status, code, _) = rx.try_recv().unwrap();
+        assert_eq!(ptr, &request as *const _ as usize);
+        assert_eq!(status, CubeMelonExecutionStatus::Cancelled);
+        assert_eq!(code, CubeMelonPluginErrorCode::Cancelled);
+
+        // The plugin saw the host copy, not the caller's request
+        let host_key = pending.lock().unwrap()[0].0;
+        assert_eq!(*cancelled.lock().unwrap(), vec![host_key]);
+        assert_ne!(host_key, &request as *const _ as usize);
+
+        // The plugin reporting late must neither reach the caller nor free twice
+        assert_eq!(in_flight_count(uuid), 1);
+        complete_all(&pending);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(in_flight_count(uuid), 0);
+
+        destroy_plugin_instance(instance);
+    }
+
+    #[test]
+    fn test_cancel_after_completion_is_ignored() {
+        let uuid = CubeMelonUUID::from_bytes([0xB2; 16]);
+        let (tx, rx) = channel();
+        let mut request = make_request(&tx);
+
+        let (plugin, pending, cancelled) = DeferredAsyncPlugin::new(false);
+        let instance = create_plugin_instance(plugin);
+        let interface = create_async_task_interface::<DeferredAsyncPlugin>();
+
+        execute_with_async_interface(uuid, instance, &interface, &request, Some(record_callback));
+        complete_all(&pending);
+        let (_, status, _, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(status, CubeMelonExecutionStatus::Completed);
+
+        assert_eq!(cancel(&mut request as *mut _), CubeMelonPluginErrorCode::Success);
+        assert!(cancelled.lock().unwrap().is_empty());
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        destroy_plugin_instance(instance);
+    }
+
+    #[test]
+    fn test_plugin_reporting_inside_cancel_is_delivered_once() {
+        let uuid = CubeMelonUUID::from_bytes([0xB3; 16]);
+        let (tx, rx) = channel();
+        let mut request = make_request(&tx);
+
+        let (plugin, _pending, cancelled) = DeferredAsyncPlugin::new(true);
+        let instance = create_plugin_instance(plugin);
+        let interface = create_async_task_interface::<DeferredAsyncPlugin>();
+
+        execute_with_async_interface(uuid, instance, &interface, &request, Some(record_callback));
+        assert_eq!(cancel(&mut request as *mut _), CubeMelonPluginErrorCode::Success);
+
+        let (_, status, code, _) = rx.try_recv().unwrap();
+        assert_eq!(status, CubeMelonExecutionStatus::Cancelled);
+        assert_eq!(code, CubeMelonPluginErrorCode::Cancelled);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(cancelled.lock().unwrap().len(), 1);
+
+        // The canceller released the host copy itself
+        assert_eq!(in_flight_count(uuid), 0);
+
+        destroy_plugin_instance(instance);
+    }
+
+    #[test]
+    fn test_cancel_racing_completion_delivers_exactly_once() {
+        let uuid = CubeMelonUUID::from_bytes([0xB4; 16]);
+        let instance = create_plugin_instance(ThreadedAsyncPlugin);
+        let interface = create_async_task_interface::<ThreadedAsyncPlugin>();
+
+        for _ in 0..200 {
+            let (tx, rx) = channel();
+            let mut request = Box::new(make_request(&tx));
+
+            execute_with_async_interface(uuid, instance, &interface, &request, Some(record_callback));
+            assert_eq!(cancel(&mut *request as *mut _), CubeMelonPluginErrorCode::Success);
+
+            // Either the result or the cancellation, never both
+            let (_, status, _, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert!(matches!(status, CubeMelonExecutionStatus::Completed | CubeMelonExecutionStatus::Cancelled));
+            wait_until_settled(uuid);
+            assert!(rx.try_recv().is_err());
+
+            // The caller destroys the request; the plugin is done with it
+            drop(request);
+        }
+
+        destroy_plugin_instance(instance);
+    }
+
+    #[test]
+    fn test_cancel_racing_worker_thread_delivers_exactly_once() {
+        let uuid = CubeMelonUUID::from_bytes([0xB5; 16]);
+
+        for _ in 0..50 {
+            let (tx, rx) = channel();
+            let mut request = Box::new(make_request(&tx));
+
+            execute_on_worker_thread(uuid, sync_entry_points(), CubeMelonHostServices::empty(), &request, Some(record_callback));
+            assert_eq!(cancel(&mut *request as *mut _), CubeMelonPluginErrorCode::Success);
+
+            let (_, status, _, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert!(matches!(status, CubeMelonExecutionStatus::Completed | CubeMelonExecutionStatus::Cancelled));
+            wait_until_settled(uuid);
+            assert!(rx.try_recv().is_err());
+            drop(request);
+        }
+    }
+
+    /// Start a worker task that reads the request's input once `go` fires
+    ///
+    /// Returns after the worker has started, so cancelling cannot skip it.
+    fn start_reader(uuid: CubeMelonUUID, request: &CubeMelonTaskRequest) -> (Sender<()>, std::sync::mpsc::Receiver<String>) {
+        let (go, wait) = channel::<()>();
+        let (seen_tx, seen) = channel();
+        let (started_tx, started) = channel::<()>();
+        let rc = execute_on_thread(uuid, request, Some(record_callback), move |request, _result| {
+            let _ = started_tx.send(());
+            let _ = wait.recv();
+            let json = unsafe { (*request).input_json.as_str().unwrap_or("").to_string() };
+            let _ = seen_tx.send(json);
+            CubeMelonPluginErrorCode::Success
+        });
+        assert_eq!(rc, CubeMelonPluginErrorCode::Success);
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+        (go, seen)
+    }
+
+    #[test]
+    fn test_caller_may_free_request_once_cancelled() {
+        let uuid = CubeMelonUUID::from_bytes([0xB7; 16]);
+        let (tx, rx) = channel();
+        let json = std::ffi::CString::new("{\"n\":1}").unwrap();
+        let mut request = Box::new(make_request(&tx));
+        request.input_json = CubeMelonString { str: json.as_ptr() as *const u8, free_string: None };
+        request.input_data = Box::into_raw(Box::new(cubemelon_sdk::CubeMelonValue::string("input".to_string())));
+
+        let (go, seen) = start_reader(uuid, &request);
+        assert_eq!(cancel(&mut *request as *mut _), CubeMelonPluginErrorCode::Success);
+        let (_, status, _, _) = rx.try_recv().unwrap();
+        assert_eq!(status, CubeMelonExecutionStatus::Cancelled);
+
+        // The caller destroys its request while the worker has yet to read it
+        let mut bytes = json.into_bytes_with_nul();
+        bytes.fill(b'x');
+        drop(bytes);
+        unsafe {
+            let mut value = Box::from_raw(request.input_data);
+            if let Some(free_fn) = value.free_value {
+                free_fn(&mut *value);
+            }
+        }
+        drop(request);
+
+        go.send(()).unwrap();
+        assert_eq!(seen.recv_timeout(Duration::from_secs(5)).unwrap(), "{\"n\":1}");
+        wait_until_settled(uuid);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_cancel_with_shared_input_waits_for_plugin() {
+        let uuid = CubeMelonUUID::from_bytes([0xB8; 16]);
+        let (tx, rx) = channel();
+        let mut request = make_request(&tx);
+        // Pointer values cannot be copied, so the worker shares the caller's input
+        let mut value = cubemelon_sdk::CubeMelonValue::pointer(std::ptr::null_mut());
+        request.input_data = &mut value;
+
+        let (go, seen) = start_reader(uuid, &request);
+        assert_eq!(cancel(&mut request as *mut _), CubeMelonPluginErrorCode::Success);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        go.send(()).unwrap();
+        seen.recv_timeout(Duration::from_secs(5)).unwrap();
+        let (ptr, status, code, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(ptr, &request as *const _ as usize);
+        assert_eq!(status, CubeMelonExecutionStatus::Cancelled);
+        assert_eq!(code, CubeMelonPluginErrorCode::Cancelled);
+        wait_until_settled(uuid);
+    }
+
+    unsafe extern "C" fn cancelling_callback(request: *mut CubeMelonTaskRequest, result: *const CubeMelonTaskResult) {
+        // Cancelling from inside the callback must not deadlock
+        assert_eq!(cancel(request), CubeMelonPluginErrorCode::Success);
+        record_callback(request, result);
+    }
+
+    #[test]
+    fn test_cancel_from_inside_callback_is_ignored() {
+        let uuid = CubeMelonUUID::from_bytes([0xB6; 16]);
+        let (tx, rx) = channel();
+        let request = make_request(&tx);
+
+        let (plugin, pending, cancelled) = DeferredAsyncPlugin::new(false);
+        let instance = create_plugin_instance(plugin);
+        let interface = create_async_task_interface::<DeferredAsyncPlugin>();
+
+        execute_with_async_interface(uuid, instance, &interface, &request, Some(cancelling_callback));
+        complete_all(&pending);
+
+        let (_, status, _, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(status, CubeMelonExecutionStatus::Completed);
+        assert!(cancelled.lock().unwrap().is_empty());
+        assert_eq!(in_flight_count(uuid), 0);
+
+        destroy_plugin_instance(instance);
+    }
+
+    #[test]
+    fn test_callback_for_unknown_request_is_ignored() {
+        let mut stray = CubeMelonTaskRequest::empty();
+        let result = CubeMelonTaskResult::empty();
+        unsafe { async_task_callback(&mut stray as *mut _, &result as *const _) };
+    }
+}
@@ -0,0 +1,729 @@
+//! Command Line Interface
+//!
+//! Non-interactive subcommands so the runtime can be scripted:
+//! ```text
+//! cubemelon                                  interactive prompt
+//! cubemelon list [--json]                    list discovered plugins
+//! cubemelon info <id>                        detailed plugin information and metrics
+//! cubemelon run <id>                         load, initialize and release a plugin
+//! cubemelon exec <id> [--input-json <file>] [--task-type <n>] [--timeout <us>] [--trace]
+//! cubemelon pipeline <name> [--input-json <file>]
+//!                                            run a configured task pipeline
+//! cubemelon scan                             scan the plugins directory
+//! ```
+//! `<id>` is a plugin number, name or UUID, as at the prompt.
+//!
+//! Results are printed to stdout as JSON (except plain `list`) and log lines go
+//! to stderr. Failures print `{"error": {...}}` to stderr and exit with the code
+//! `exit_code_for` derives from the CubeMelonPluginErrorCode.
+
+use std::ffi::c_void;
+use std::io::Read;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use cubemelon_sdk::{
+    CubeMelonPluginErrorCode, CubeMelonPluginManagerInterface, CubeMelonTaskRequest, CubeMelonTaskResult,
+    CubeMelonTaskType, CubeMelonExecutionStatus, CubeMelonString,
+};
+
+use crate::{PluginInfo, RuntimeData};
+use crate::async_task;
+use crate::isolation;
+use crate::matcher;
+use crate::metrics;
+use crate::trace;
+use crate::loader::rejection_code;
+
+/// Usage text printed by `help` and on invalid arguments
+pub const USAGE: &str = "\
+Usage: cubemelon [COMMAND]
+
+Commands (interactive prompt when omitted):
+  list [--json]                 List discovered plugins
+  info <id>                     Show detailed plugin information and metrics as JSON
+  run <id>                      Load, initialize and release a plugin
+  exec <id> [options]           Execute a task and print its result as JSON
+      --input-json <file>       Task input JSON ('-' reads stdin)
+      --task-type <n>           CubeMelonTaskType name or value (default: generic)
+      --timeout <us>            Give up after this many microseconds
+      --trace                   Include the task's span tree in the result
+  pipeline <name> [options]     Run a [pipeline] from the configuration file
+      --input-json <file>       Pipeline input JSON ('-' reads stdin)
+  scan                          Scan the plugin search paths
+  help                          Show this help
+
+<id> is a plugin number, name or UUID.
+
+Exit codes:
+  0 success, 1 other error, 2 usage error, 3 plugin or file not found,
+  4 not supported or incompatible, 5 load or initialization failure,
+  6 timeout, 7 cancelled, 8 invalid input, 9 I/O error, 10 plugin panic,
+  11 plugin failed its integrity check";
+
+/// Exit code for invalid command line arguments
+pub const EXIT_USAGE: i32 = 2;
+
+/// Map a plugin error code to a process exit code
+pub fn exit_code_for(code: CubeMelonPluginErrorCode) -> i32 {
+    use CubeMelonPluginErrorCode as E;
+    match code {
+        E::Success => 0,
+        E::PluginNotFound | E::FileNotFound => 3,
+        E::NotSupported | E::InterfaceNotSupported | E::NotImplemented | E::VersionMismatch
+        | E::Incompatible | E::FormatUnsupported => 4,
+        E::PluginLoadFailed | E::PluginUnloadFailed | E::InitializationFailed | E::AlreadyInitialized
+        | E::NotInitialized => 5,
+        E::Timeout => 6,
+        E::Cancelled => 7,
+        E::InvalidParameter | E::NullPointer | E::OutOfBounds | E::Parse | E::Validation | E::Encoding
+        | E::DataCorrupted => 8,
+        E::IO | E::Network | E::ConnectionFailed | E::PermissionDenied | E::FileExists
+        | E::DirectoryNotEmpty | E::DiskFull => 9,
+        E::ThreadPanic => 10,
+        E::IntegrityCheckFailed => 11,
+        _ => 1,
+    }
+}
+
+/// Parsed subcommand
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    List { json: bool },
+    Info { plugin_id: String },
+    Run { plugin_id: String },
+    Exec(ExecOptions),
+    Pipeline { name: String, input_json: Option<String> },
+    Scan,
+    Help,
+}
+
+/// Arguments of `exec`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecOptions {
+    pub plugin_id: String,
+    /// Input JSON file; "-" reads stdin
+    pub input_json: Option<String>,
+    pub task_type: CubeMelonTaskType,
+    /// None waits until the task finishes
+    pub timeout_us: Option<i64>,
+    /// Add the span tree of the task's trace to the report
+    pub trace: bool,
+}
+
+impl Command {
+    /// Parse the arguments following the program name
+    ///
+    /// Returns `Ok(None)` when there are none (interactive mode).
+    pub fn parse(args: &[String]) -> Result<Option<Self>, String> {
+        let Some((name, rest)) = args.split_first() else {
+            return Ok(None);
+        };
+
+        let command = match name.as_str() {
+            "list" | "ls" => {
+                let mut json = false;
+                for arg in rest {
+                    match arg.as_str() {
+                        "--json" => json = true,
+                        other => return Err(format!("unexpected argument for list: '{}'", other)),
+                    }
+                }
+                Command::List { json }
+            }
+            "info" => Command::Info { plugin_id: single_plugin_id("info", rest)? },
+            "run" => Command::Run { plugin_id: single_plugin_id("run", rest)? },
+            "exec" => Command::Exec(ExecOptions::parse(rest)?),
+            "pipeline" => parse_pipeline(rest)?,
+            "scan" => {
+                if let Some(extra) = rest.first() {
+                    return Err(format!("unexpected argument for scan: '{}'", extra));
+                }
+                Command::Scan
+            }
+            "help" | "-h" | "--help" => Command::Help,
+            other => return Err(format!("unknown command: '{}'", other)),
+        };
+        Ok(Some(command))
+    }
+}
+
+impl ExecOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut plugin_id = None;
+        let mut input_json = None;
+        let mut task_type = CubeMelonTaskType::Generic;
+        let mut timeout_us = None;
+        let mut trace = false;
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            // Accept both "--flag value" and "--flag=value"
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| iter.next().cloned())
+                    .ok_or_else(|| format!("missing value for {}", flag))
+            };
+
+            match flag {
+                "--input-json" => input_json = Some(value()?),
+                "--task-type" => {
+                    let raw = value()?;
+                    task_type = matcher::parse_task_type(&raw).ok_or_else(|| format!("unknown task type: '{}'", raw))?;
+                }
+                "--timeout" => {
+                    let raw = value()?;
+                    match raw.parse::<i64>() {
+                        Ok(us) if us > 0 => timeout_us = Some(us),
+                        _ => return Err(format!("invalid timeout (microseconds): '{}'", raw)),
+                    }
+                }
+                "--trace" if inline_value.is_none() => trace = true,
+                other if other.starts_with("--") => return Err(format!("unknown option for exec: '{}'", other)),
+                other => {
+                    if plugin_id.is_some() {
+                        return Err(format!("unexpected argument for exec: '{}'", other));
+                    }
+                    plugin_id = Some(other.to_string());
+                }
+            }
+        }
+
+        Ok(Self {
+            plugin_id: plugin_id.ok_or("exec requires a plugin id")?,
+            input_json,
+            task_type,
+            timeout_us,
+            trace,
+        })
+    }
+}
+
+fn parse_pipeline(args: &[String]) -> Result<Command, String> {
+    let mut name = None;
+    let mut input_json = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--input-json" => input_json = Some(iter.next().ok_or("missing value for --input-json")?.clone()),
+            other if other.starts_with("--input-json=") => input_json = Some(other["--input-json=".len()..].to_string()),
+            other if other.starts_with("--") => return Err(format!("unknown option for pipeline: '{}'", other)),
+            other if name.is_none() => name = Some(other.to_string()),
+            other => return Err(format!("unexpected argument for pipeline: '{}'", other)),
+        }
+    }
+    Ok(Command::Pipeline { name: name.ok_or("pipeline requires a pipeline name")?, input_json })
+}
+
+fn single_plugin_id(command: &str, args: &[String]) -> Result<String, String> {
+    match args {
+        [id] => Ok(id.clone()),
+        [] => Err(format!("{} requires a plugin id", command)),
+        [_, extra, ..] => Err(format!("unexpected argument for {}: '{}'", command, extra)),
+    }
+}
+
+/// Failure reported to the invoking process
+#[derive(Debug)]
+struct CliError {
+    code: CubeMelonPluginErrorCode,
+    message: String,
+}
+
+impl CliError {
+    fn new(code: CubeMelonPluginErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+/// Run a subcommand and return the process exit code
+///
+/// Scans the plugins directory first, as interactive mode does.
+pub fn run(runtime: &mut RuntimeData, command: Command) -> i32 {
+    let outcome = runtime
+        .scan_plugins()
+        .map_err(|e| CliError::new(CubeMelonPluginErrorCode::IO, format!("Failed to scan plugins: {}", e)))
+        .and_then(|()| match command {
+            Command::List { json } => list(runtime, json),
+            Command::Info { plugin_id } => info(runtime, &plugin_id),
+            Command::Run { plugin_id } => run_plugin(runtime, &plugin_id),
+            Command::Exec(options) => exec(runtime, &options),
+            Command::Pipeline { name, input_json } => pipeline(runtime, &name, input_json.as_deref()),
+            Command::Scan => scan(runtime),
+            Command::Help => {
+                println!("{}", USAGE);
+                Ok(0)
+            }
+        });
+
+    match outcome {
+        Ok(exit_code) => exit_code,
+        Err(error) => {
+            let report = json!({
+                "error": {
+                    "code": format!("{:?}", error.code),
+                    "value": error.code as i32,
+                    "message": error.message,
+                }
+            });
+            eprintln!("{}", report);
+            exit_code_for(error.code)
+        }
+    }
+}
+
+fn print_json(value: &Value) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string()));
+}
+
+fn plugin_summary(runtime: &RuntimeData, index: usize, plugin: &PluginInfo) -> Value {
+    json!({
+        "index": index + 1,
+        "uuid": plugin.uuid.to_string(),
+        "name": plugin.name,
+        "description": plugin.description,
+        "version": plugin.version.to_string(),
+        "supported_types": plugin.supported_types,
+        "loaded": runtime.is_loaded(plugin.uuid),
+        "isolated": runtime.is_isolated(plugin),
+        "path": plugin.path.display().to_string(),
+    })
+}
+
+fn list(runtime: &RuntimeData, json: bool) -> Result<i32, CliError> {
+    if !json {
+        runtime.list_plugins();
+        return Ok(0);
+    }
+    let plugins: Vec<Value> = runtime
+        .discovered_plugins
+        .iter()
+        .enumerate()
+        .map(|(i, p)| plugin_summary(runtime, i, p))
+        .collect();
+    print_json(&Value::Array(plugins));
+    Ok(0)
+}
+
+fn scan(runtime: &RuntimeData) -> Result<i32, CliError> {
+    let plugins: Vec<Value> = runtime
+        .discovered_plugins
+        .iter()
+        .enumerate()
+        .map(|(i, p)| plugin_summary(runtime, i, p))
+        .collect();
+    let rejected: Vec<Value> = runtime
+        .rejected_plugins
+        .iter()
+        .map(|r| {
+            json!({
+                "path": r.path.display().to_string(),
+                "code": format!("{:?}", r.code),
+                "value": r.code as i32,
+                "reason": r.reason,
+            })
+        })
+        .collect();
+    let shadowed: Vec<Value> = runtime
+        .shadowed_plugins
+        .iter()
+        .map(|s| {
+            json!({
+                "path": s.path.display().to_string(),
+                "uuid": s.uuid.to_string(),
+                "version": s.version.to_string(),
+                "shadowed_by": s.shadowed_by.display().to_string(),
+            })
+        })
+        .collect();
+    let disabled: Vec<Value> = runtime
+        .disabled_plugins
+        .iter()
+        .map(|d| {
+            json!({
+                "path": d.info.path.display().to_string(),
+                "uuid": d.info.uuid.to_string(),
+                "name": d.info.name,
+                "version": d.info.version.to_string(),
+                "reason": d.reason,
+            })
+        })
+        .collect();
+    let search_paths: Vec<String> = runtime.plugin_search_paths().iter().map(|p| p.display().to_string()).collect();
+    print_json(&json!({
+        "plugins_directory": runtime.get_plugins_directory().display().to_string(),
+        "search_paths": search_paths,
+        "found": plugins.len(),
+        "plugins": plugins,
+        "rejected": rejected,
+        "shadowed": shadowed,
+        "disabled": disabled,
+    }));
+    Ok(0)
+}
+
+fn resolve(runtime: &RuntimeData, plugin_id: &str) -> Result<PluginInfo, CliError> {
+    runtime
+        .resolve_plugin_id(plugin_id)
+        .map_err(|e| CliError::new(CubeMelonPluginErrorCode::PluginNotFound, e.to_string()))
+}
+
+fn load(runtime: &mut RuntimeData, plugin_id: &str) -> Result<PluginInfo, CliError> {
+    let plugin = resolve(runtime, plugin_id)?;
+    runtime
+        .load_plugin(plugin_id)
+        .map_err(|e| CliError::new(rejection_code(&e), format!("{:#}", e)))?;
+    Ok(plugin)
+}
+
+fn info(runtime: &RuntimeData, plugin_id: &str) -> Result<i32, CliError> {
+    let plugin = resolve(runtime, plugin_id)?;
+
+    let mut detailed = CubeMelonString::empty();
+    let rc = CubeMelonPluginManagerInterface::get_plugin_detailed_info(
+        runtime, plugin.uuid, runtime.system_language.clone(), &mut detailed,
+    );
+    let text = detailed.as_str().map(str::to_string).unwrap_or_default();
+    if let Some(free_fn) = detailed.free_string {
+        if !detailed.str.is_null() {
+            unsafe { free_fn(detailed.str) };
+        }
+    }
+    if rc != CubeMelonPluginErrorCode::Success {
+        return Err(CliError::new(rc, format!("No details for plugin: {}", plugin.name)));
+    }
+
+    let value = serde_json::from_str(&text)
+        .map_err(|e| CliError::new(CubeMelonPluginErrorCode::Parse, format!("Invalid plugin details: {}", e)))?;
+    print_json(&value);
+    Ok(0)
+}
+
+fn run_plugin(runtime: &mut RuntimeData, plugin_id: &str) -> Result<i32, CliError> {
+    let plugin = load(runtime, plugin_id)?;
+    runtime
+        .execute_plugin(&plugin)
+        .map_err(|e| CliError::new(CubeMelonPluginErrorCode::InitializationFailed, format!("{:#}", e)))?;
+    print_json(&json!({
+        "uuid": plugin.uuid.to_string(),
+        "name": plugin.name,
+        "description": plugin.description,
+        "status": "ok",
+    }));
+    Ok(0)
+}
+
+/// Owned copy of the fields of a delivered task result
+#[derive(Debug)]
+struct TaskOutcome {
+    status: CubeMelonExecutionStatus,
+    error_code: CubeMelonPluginErrorCode,
+    output_json: String,
+    progress_ratio: f64,
+    progress_stage: String,
+    progress_message: String,
+}
+
+impl TaskOutcome {
+    fn from_result(result: &CubeMelonTaskResult) -> Self {
+        let text = |s: &CubeMelonString| s.as_str().map(str::to_string).unwrap_or_default();
+        Self {
+            status: result.status,
+            error_code: result.error_code,
+            output_json: text(&result.output_json),
+            progress_ratio: result.progress_ratio,
+            progress_stage: text(&result.progress_stage),
+            progress_message: text(&result.progress_message),
+        }
+    }
+
+    /// Error code describing the task as a whole
+    fn effective_code(&self) -> CubeMelonPluginErrorCode {
+        match self.status {
+            CubeMelonExecutionStatus::Cancelled => CubeMelonPluginErrorCode::Cancelled,
+            CubeMelonExecutionStatus::Error if self.error_code == CubeMelonPluginErrorCode::Success => {
+                CubeMelonPluginErrorCode::Unknown
+            }
+            _ => self.error_code,
+        }
+    }
+}
+
+/// Hand-off between the task callback and `exec`
+#[derive(Default)]
+struct Completion {
+    outcome: Mutex<Option<TaskOutcome>>,
+    delivered: Condvar,
+}
+
+impl Completion {
+    /// Wait for the result; None once `timeout` has passed without one
+    ///
+    /// Isolated plugins may need the runtime meanwhile (see `isolation`).
+    fn wait(&self, timeout: Option<Duration>) -> Option<TaskOutcome> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            isolation::serve_forwarded_calls();
+            let mut outcome = self.outcome.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(done) = outcome.take() {
+                return Some(done);
+            }
+            let slice = match deadline {
+                None => isolation::CALL_POLL_INTERVAL,
+                Some(deadline) => deadline.checked_duration_since(Instant::now())?.min(isolation::CALL_POLL_INTERVAL),
+            };
+            let _ = self.delivered.wait_timeout(outcome, slice).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn take(&self) -> Option<TaskOutcome> {
+        self.outcome.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+/// Callback for `exec`; `user_data` of the request points at its Completion
+unsafe extern "C" fn exec_callback(request: *mut CubeMelonTaskRequest, result: *const CubeMelonTaskResult) {
+    if request.is_null() || result.is_null() || (*request).user_data.is_null() {
+        return;
+    }
+    let completion = &*((*request).user_data as *const Completion);
+    let outcome = TaskOutcome::from_result(&*result);
+    *completion.outcome.lock().unwrap_or_else(|e| e.into_inner()) = Some(outcome);
+    completion.delivered.notify_all();
+}
+
+fn read_input_json(path: &str) -> Result<String, CliError> {
+    let read = if path == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text).map(|_| text)
+    } else {
+        std::fs::read_to_string(path)
+    };
+    let text = read.map_err(|e| {
+        let code = if e.kind() == std::io::ErrorKind::NotFound {
+            CubeMelonPluginErrorCode::FileNotFound
+        } else {
+            CubeMelonPluginErrorCode::IO
+        };
+        CliError::new(code, format!("Failed to read {}: {}", path, e))
+    })?;
+
+    serde_json::from_str::<Value>(&text)
+        .map_err(|e| CliError::new(CubeMelonPluginErrorCode::Parse, format!("Invalid input JSON in {}: {}", path, e)))?;
+    Ok(text)
+}
+
+/// Execute a task through the manager's async path so the timeout can be enforced
+fn exec(runtime: &mut RuntimeData, options: &ExecOptions) -> Result<i32, CliError> {
+    let input = match &options.input_json {
+        Some(path) => Some(read_input_json(path)?),
+        None => None,
+    };
+    let plugin = load(runtime, &options.plugin_id)?;
+
+    let completion = Completion::default();
+    let mut request = CubeMelonTaskRequest::new(
+        std::ptr::null(),
+        std::ptr::null_mut(),
+        input.map(CubeMelonString::from_string).unwrap_or_else(CubeMelonString::empty),
+        options.task_type,
+        runtime.system_language.clone(),
+        async_task::now_us(),
+        options.timeout_us.unwrap_or(0),
+    );
+    request.user_data = &completion as *const Completion as *mut c_void;
+    let trace_id = trace::new_id();
+    request.set_trace_id(trace_id);
+
+    let started = Instant::now();
+    let rc = CubeMelonPluginManagerInterface::execute_async_task(runtime, plugin.uuid, &request, Some(exec_callback));
+    if rc != CubeMelonPluginErrorCode::Success {
+        free_input_json(&mut request);
+        return Err(CliError::new(rc, format!("Failed to start task on {}", plugin.name)));
+    }
+
+    let timeout = options.timeout_us.map(|us| Duration::from_micros(us as u64));
+    let outcome = match completion.wait(timeout) {
+        Some(outcome) => outcome,
+        None => {
+            // Cancel delivers synchronously, so nothing touches `completion` afterwards
+            async_task::cancel(&request as *const CubeMelonTaskRequest);
+            match completion.take() {
+                Some(outcome) if outcome.status != CubeMelonExecutionStatus::Cancelled => outcome,
+                _ => {
+                    // The plugin may still be reading the input; the process exits soon anyway
+                    return Err(CliError::new(
+                        CubeMelonPluginErrorCode::Timeout,
+                        format!("{} did not finish within {} us", plugin.name, options.timeout_us.unwrap_or(0)),
+                    ));
+                }
+            }
+        }
+    };
+    free_input_json(&mut request);
+
+    let output = if outcome.output_json.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&outcome.output_json).unwrap_or_else(|_| Value::String(outcome.output_json.clone()))
+    };
+    let mut report = json!({
+        "uuid": plugin.uuid.to_string(),
+        "name": plugin.name,
+        "status": format!("{:?}", outcome.status),
+        "error_code": format!("{:?}", outcome.error_code),
+        "error_code_value": outcome.error_code as i32,
+        "elapsed_us": started.elapsed().as_micros() as u64,
+        "output": output,
+        "metrics": metrics::plugin_metrics(plugin.uuid).to_json(),
+        "trace_id": trace::format_id(trace_id),
+    });
+    if options.trace {
+        report["trace"] = runtime.trace_tree(trace_id).unwrap_or(Value::Null);
+    }
+    if outcome.progress_ratio >= 0.0 || !outcome.progress_stage.is_empty() || !outcome.progress_message.is_empty() {
+        report["progress"] = json!({
+            "ratio": (outcome.progress_ratio >= 0.0).then_some(outcome.progress_ratio),
+            "stage": outcome.progress_stage,
+            "message": outcome.progress_message,
+        });
+    }
+    print_json(&report);
+    Ok(exit_code_for(outcome.effective_code()))
+}
+
+/// Run a pipeline and print its per-stage report
+fn pipeline(runtime: &mut RuntimeData, name: &str, input_json: Option<&str>) -> Result<i32, CliError> {
+    let input = match input_json {
+        Some(path) => Some(read_input_json(path)?),
+        None => None,
+    };
+    let report = runtime
+        .run_pipeline(name, input.as_deref())
+        .map_err(|e| CliError::new(e.code, e.message))?;
+    print_json(&report.to_json());
+    Ok(exit_code_for(report.code()))
+}
+
+fn free_input_json(request: &mut CubeMelonTaskRequest) {
+    if let Some(free_fn) = request.input_json.free_string {
+        if !request.input_json.str.is_null() {
+            unsafe { free_fn(request.input_json.str) };
+        }
+    }
+    request.input_json = CubeMelonString::empty();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_no_arguments_means_interactive() {
+        assert_eq!(Command::parse(&[]), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_simple_commands() {
+        assert_eq!(Command::parse(&args(&["list"])), Ok(Some(Command::List { json: false })));
+        assert_eq!(Command::parse(&args(&["list", "--json"])), Ok(Some(Command::List { json: true })));
+        assert_eq!(Command::parse(&args(&["info", "2"])), Ok(Some(Command::Info { plugin_id: "2".into() })));
+        assert_eq!(Command::parse(&args(&["run", "hello"])), Ok(Some(Command::Run { plugin_id: "hello".into() })));
+        assert_eq!(Command::parse(&args(&["scan"])), Ok(Some(Command::Scan)));
+        assert_eq!(
+            Command::parse(&args(&["pipeline", "greet", "--input-json=in.json"])),
+            Ok(Some(Command::Pipeline { name: "greet".into(), input_json: Some("in.json".into()) }))
+        );
+        assert_eq!(Command::parse(&args(&["--help"])), Ok(Some(Command::Help)));
+    }
+
+    #[test]
+    fn test_parse_exec_options() {
+        let parsed = Command::parse(&args(&[
+            "exec", "3", "--input-json", "in.json", "--task-type=image", "--timeout", "250000", "--trace",
+        ]));
+        assert_eq!(
+            parsed,
+            Ok(Some(Command::Exec(ExecOptions {
+                plugin_id: "3".into(),
+                input_json: Some("in.json".into()),
+                task_type: CubeMelonTaskType::Image,
+                timeout_us: Some(250_000),
+                trace: true,
+            })))
+        );
+
+        let Ok(Some(Command::Exec(defaults))) = Command::parse(&args(&["exec", "3"])) else {
+            panic!("exec without options should parse");
+        };
+        assert_eq!(defaults.task_type, CubeMelonTaskType::Generic);
+        assert_eq!(defaults.timeout_us, None);
+        assert_eq!(defaults.input_json, None);
+        assert!(!defaults.trace);
+
+        let Ok(Some(Command::Exec(numeric))) = Command::parse(&args(&["exec", "3", "--task-type", "20"])) else {
+            panic!("numeric task type should parse");
+        };
+        assert_eq!(numeric.task_type, CubeMelonTaskType::Http);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_arguments() {
+        assert!(Command::parse(&args(&["frobnicate"])).is_err());
+        assert!(Command::parse(&args(&["info"])).is_err());
+        assert!(Command::parse(&args(&["run", "1", "2"])).is_err());
+        assert!(Command::parse(&args(&["list", "--yaml"])).is_err());
+        assert!(Command::parse(&args(&["exec"])).is_err());
+        assert!(Command::parse(&args(&["exec", "1", "--timeout"])).is_err());
+        assert!(Command::parse(&args(&["pipeline"])).is_err());
+        assert!(Command::parse(&args(&["pipeline", "a", "b"])).is_err());
+        assert!(Command::parse(&args(&["exec", "1", "--timeout", "-5"])).is_err());
+        assert!(Command::parse(&args(&["exec", "1", "--task-type", "teleport"])).is_err());
+        assert!(Command::parse(&args(&["exec", "1", "--verbose"])).is_err());
+    }
+
+    #[test]
+    fn test_exit_codes_distinguish_failure_classes() {
+        use CubeMelonPluginErrorCode as E;
+        assert_eq!(exit_code_for(E::Success), 0);
+        assert_eq!(exit_code_for(E::Unknown), 1);
+        assert_eq!(exit_code_for(E::PluginNotFound), 3);
+        assert_eq!(exit_code_for(E::InterfaceNotSupported), 4);
+        assert_eq!(exit_code_for(E::Incompatible), 4);
+        assert_eq!(exit_code_for(E::PluginLoadFailed), 5);
+        assert_eq!(exit_code_for(E::Timeout), 6);
+        assert_eq!(exit_code_for(E::Cancelled), 7);
+        assert_eq!(exit_code_for(E::Parse), 8);
+        assert_eq!(exit_code_for(E::IO), 9);
+        assert_eq!(exit_code_for(E::ThreadPanic), 10);
+        assert_ne!(exit_code_for(E::Unknown), EXIT_USAGE);
+    }
+
+    #[test]
+    fn test_task_outcome_code_reflects_status() {
+        let outcome = |status, error_code| TaskOutcome {
+            status,
+            error_code,
+            output_json: String::new(),
+            progress_ratio: -1.0,
+            progress_stage: String::new(),
+            progress_message: String::new(),
+        };
+        use CubeMelonExecutionStatus as S;
+        use CubeMelonPluginErrorCode as E;
+        assert_eq!(outcome(S::Completed, E::Success).effective_code(), E::Success);
+        assert_eq!(outcome(S::Error, E::Validation).effective_code(), E::Validation);
+        assert_eq!(outcome(S::Error, E::Success).effective_code(), E::Unknown);
+        assert_eq!(outcome(S::Cancelled, E::Success).effective_code(), E::Cancelled);
+    }
+}
@@ -0,0 +1,195 @@
+//! Plugin Dependencies
+//!
+//! Plugins declare the plugins they need through the optional
+//! `get_plugin_dependencies` export (UUID plus minimum version). Loading a
+//! plugin loads its dependencies first, in topological order; unloading a
+//! plugin unloads the plugins that depend on it first, so teardown always
+//! runs in the reverse order of loading.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use cubemelon_sdk::{CubeMelonUUID, CubeMelonVersion, CubeMelonPluginDependency};
+use libloading::Library;
+
+use crate::PluginInfo;
+
+/// C ABI signature of the optional `get_plugin_dependencies` export
+type GetPluginDependenciesFn = unsafe extern "C" fn(*mut usize) -> *const CubeMelonPluginDependency;
+
+/// Why a plugin's dependencies cannot be satisfied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DependencyError {
+    /// No discovered plugin has the required UUID
+    Missing { plugin: String, dependency: CubeMelonPluginDependency },
+    /// The required plugin is installed in a version that is too old
+    VersionTooLow { plugin: String, dependency: CubeMelonPluginDependency, found: String, version: CubeMelonVersion },
+    /// The dependency chain leads back to itself (names in chain order)
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyError::Missing { plugin, dependency } => write!(
+                f,
+                "Plugin '{}' requires {} >= {}, which is not installed",
+                plugin, dependency.uuid, dependency.min_version
+            ),
+            DependencyError::VersionTooLow { plugin, dependency, found, version } => write!(
+                f,
+                "Plugin '{}' requires {} >= {}, but '{}' is version {}",
+                plugin, dependency.uuid, dependency.min_version, found, version
+            ),
+            DependencyError::Cycle(chain) => write!(f, "Dependency cycle: {}", chain.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for DependencyError {}
+
+/// Read the dependency list a plugin library exports (empty if it exports none)
+pub(crate) fn read_dependencies(library: &Library) -> Vec<CubeMelonPluginDependency> {
+    let Ok(get_dependencies) = (unsafe { library.get::<GetPluginDependenciesFn>(b"get_plugin_dependencies") }) else {
+        return Vec::new();
+    };
+    let mut count = 0usize;
+    let ptr = unsafe { get_dependencies(&mut count as *mut usize) };
+    if ptr.is_null() || count == 0 {
+        return Vec::new();
+    }
+    unsafe { std::slice::from_raw_parts(ptr, count) }.to_vec()
+}
+
+/// Plugins to load for `target`, dependencies first and `target` last
+pub fn load_order(plugins: &[PluginInfo], target: CubeMelonUUID) -> Result<Vec<CubeMelonUUID>, DependencyError> {
+    let mut order = Vec::new();
+    let mut visiting = Vec::new();
+    visit(plugins, target, &mut visiting, &mut order)?;
+    Ok(order)
+}
+
+fn visit(
+    plugins: &[PluginInfo],
+    uuid: CubeMelonUUID,
+    visiting: &mut Vec<CubeMelonUUID>,
+    order: &mut Vec<CubeMelonUUID>,
+) -> Result<(), DependencyError> {
+    if order.contains(&uuid) {
+        return Ok(());
+    }
+    let name_of = |uuid: &CubeMelonUUID| {
+        plugins.iter().find(|p| p.uuid == *uuid).map(|p| p.name.clone()).unwrap_or_else(|| uuid.to_string())
+    };
+    if let Some(start) = visiting.iter().position(|v| *v == uuid) {
+        let mut chain: Vec<String> = visiting[start..].iter().map(name_of).collect();
+        chain.push(name_of(&uuid));
+        return Err(DependencyError::Cycle(chain));
+    }
+
+    // The target itself was resolved by the caller; dependencies must exist
+    let Some(plugin) = plugins.iter().find(|p| p.uuid == uuid) else {
+        return Ok(());
+    };
+    visiting.push(uuid);
+    for dependency in &plugin.dependencies {
+        match plugins.iter().find(|p| p.uuid == dependency.uuid) {
+            None => {
+                return Err(DependencyError::Missing { plugin: plugin.name.clone(), dependency: *dependency });
+            }
+            Some(found) if !dependency.is_satisfied_by(found.version) => {
+                return Err(DependencyError::VersionTooLow {
+                    plugin: plugin.name.clone(),
+                    dependency: *dependency,
+                    found: found.name.clone(),
+                    version: found.version,
+                });
+            }
+            Some(_) => visit(plugins, dependency.uuid, visiting, order)?,
+        }
+    }
+    visiting.pop();
+    order.push(uuid);
+    Ok(())
+}
+
+/// Loaded plugins that depend on `uuid`, directly or indirectly, in unload order
+pub fn dependents_to_unload(plugins: &[PluginInfo], loaded: &[CubeMelonUUID], uuid: CubeMelonUUID) -> Vec<CubeMelonUUID> {
+    let mut affected: HashSet<CubeMelonUUID> = HashSet::from([uuid]);
+    loop {
+        let before = affected.len();
+        for plugin in plugins.iter().filter(|p| loaded.contains(&p.uuid)) {
+            if plugin.dependencies.iter().any(|d| affected.contains(&d.uuid)) {
+                affected.insert(plugin.uuid);
+            }
+        }
+        if affected.len() == before {
+            break;
+        }
+    }
+    // Later loads depend on earlier ones, so unload from the back
+    loaded.iter().rev().filter(|u| **u != uuid && affected.contains(u)).copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn uuid(id: u8) -> CubeMelonUUID {
+        CubeMelonUUID::from_bytes([id; 16])
+    }
+
+    fn plugin(id: u8, version: CubeMelonVersion, dependencies: &[(u8, CubeMelonVersion)]) -> PluginInfo {
+        PluginInfo {
+            uuid: uuid(id),
+            version,
+            supported_types: 0,
+            name: format!("plugin-{}", id),
+            description: String::new(),
+            path: PathBuf::new(),
+            capabilities: Default::default(),
+            dependencies: dependencies.iter().map(|(d, v)| CubeMelonPluginDependency::new(uuid(*d), *v)).collect(),
+        }
+    }
+
+    const V1: CubeMelonVersion = CubeMelonVersion::new(1, 0, 0);
+    const V2: CubeMelonVersion = CubeMelonVersion::new(2, 0, 0);
+
+    #[test]
+    fn test_dependencies_load_first() {
+        // 1 -> {2, 3}, 2 -> 3
+        let plugins = vec![plugin(1, V1, &[(2, V1), (3, V1)]), plugin(2, V1, &[(3, V1)]), plugin(3, V2, &[])];
+        assert_eq!(load_order(&plugins, uuid(1)), Ok(vec![uuid(3), uuid(2), uuid(1)]));
+        assert_eq!(load_order(&plugins, uuid(3)), Ok(vec![uuid(3)]));
+    }
+
+    #[test]
+    fn test_missing_and_outdated_dependencies_are_reported() {
+        let plugins = vec![plugin(1, V1, &[(2, V2)]), plugin(2, V1, &[]), plugin(3, V1, &[(9, V1)])];
+
+        let err = load_order(&plugins, uuid(1)).unwrap_err();
+        assert!(matches!(err, DependencyError::VersionTooLow { version, .. } if version == V1));
+        assert!(err.to_string().contains("plugin-2"));
+
+        let err = load_order(&plugins, uuid(3)).unwrap_err();
+        assert!(matches!(err, DependencyError::Missing { ref plugin, .. } if plugin == "plugin-3"));
+    }
+
+    #[test]
+    fn test_cycles_are_reported() {
+        let plugins = vec![plugin(1, V1, &[(2, V1)]), plugin(2, V1, &[(3, V1)]), plugin(3, V1, &[(2, V1)])];
+        let err = load_order(&plugins, uuid(1)).unwrap_err();
+        assert_eq!(err, DependencyError::Cycle(vec!["plugin-2".into(), "plugin-3".into(), "plugin-2".into()]));
+        assert_eq!(err.to_string(), "Dependency cycle: plugin-2 -> plugin-3 -> plugin-2");
+    }
+
+    #[test]
+    fn test_dependents_unload_in_reverse_load_order() {
+        // 2 -> 1, 3 -> 2, 4 independent
+        let plugins = vec![plugin(1, V1, &[]), plugin(2, V1, &[(1, V1)]), plugin(3, V1, &[(2, V1)]), plugin(4, V1, &[])];
+        let loaded = vec![uuid(1), uuid(4), uuid(2), uuid(3)];
+        assert_eq!(dependents_to_unload(&plugins, &loaded, uuid(1)), vec![uuid(3), uuid(2)]);
+        assert!(dependents_to_unload(&plugins, &loaded, uuid(3)).is_empty());
+    }
+}
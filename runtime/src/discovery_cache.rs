@@ -0,0 +1,324 @@
+//! Discovery Cache
+//!
+//! Scanning remembers the metadata of every valid plugin in
+//! `<config name>.cache.json` next to the configuration file, so later scans
+//! neither read manifests nor load libraries for files that did not change.
+//!
+//! An entry is reused while the file's size and modification time match. When
+//! only the modification time differs (the file was copied or touched), the
+//! content hash decides. Entries of files that disappeared are dropped, and the
+//! whole cache is discarded when the host SDK version or the language changes,
+//! since entries hold compatibility results and localized names.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use cubemelon_sdk::{CubeMelonPluginDependency, SDK_VERSION};
+
+use crate::manifest::parse_uuid;
+use crate::matcher::{parse_version, PluginCapabilities};
+use crate::state_store::write_atomically;
+use crate::PluginInfo;
+
+/// Bumped whenever the cache layout changes
+const CACHE_FORMAT: u32 = 1;
+
+/// Identity of a plugin file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileIdentity {
+    size: u64,
+    /// Modification time since the Unix epoch
+    modified: Option<Duration>,
+    /// FNV-1a hash of the contents
+    hash: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedDependency {
+    uuid: String,
+    min_version: String,
+}
+
+/// Cached `PluginInfo` of one file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedPlugin {
+    path: PathBuf,
+    #[serde(flatten)]
+    identity: FileIdentity,
+    uuid: String,
+    version: String,
+    supported_types: u64,
+    name: String,
+    description: String,
+    #[serde(default)]
+    capabilities: PluginCapabilities,
+    #[serde(default)]
+    dependencies: Vec<CachedDependency>,
+}
+
+impl CachedPlugin {
+    fn new(info: &PluginInfo, identity: FileIdentity) -> Self {
+        Self {
+            path: info.path.clone(),
+            identity,
+            uuid: info.uuid.to_string(),
+            version: info.version.to_string(),
+            supported_types: info.supported_types,
+            name: info.name.clone(),
+            description: info.description.clone(),
+            capabilities: info.capabilities.clone(),
+            dependencies: info
+                .dependencies
+                .iter()
+                .map(|d| CachedDependency { uuid: d.uuid.to_string(), min_version: d.min_version.to_string() })
+                .collect(),
+        }
+    }
+
+    /// The cached information, or None if the entry is unreadable
+    fn to_plugin_info(&self) -> Option<PluginInfo> {
+        Some(PluginInfo {
+            uuid: parse_uuid(&self.uuid)?,
+            version: parse_version(&self.version)?,
+            supported_types: self.supported_types,
+            name: self.name.clone(),
+            description: self.description.clone(),
+            path: self.path.clone(),
+            capabilities: self.capabilities.clone(),
+            dependencies: self
+                .dependencies
+                .iter()
+                .map(|d| Some(CubeMelonPluginDependency::new(parse_uuid(&d.uuid)?, parse_version(&d.min_version)?)))
+                .collect::<Option<Vec<_>>>()?,
+        })
+    }
+}
+
+/// On-disk layout
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    format: u32,
+    sdk_version: String,
+    language: String,
+    plugins: Vec<CachedPlugin>,
+}
+
+/// Plugin metadata remembered across scans
+#[derive(Debug)]
+pub struct DiscoveryCache {
+    path: PathBuf,
+    language: String,
+    entries: HashMap<PathBuf, CachedPlugin>,
+    /// Whether `entries` differ from the file
+    dirty: bool,
+}
+
+impl DiscoveryCache {
+    /// Cache file belonging to a configuration file
+    pub fn path_for_config(config_path: &Path) -> PathBuf {
+        config_path.with_extension("cache.json")
+    }
+
+    /// Read the cache; a missing, corrupted or outdated file yields an empty cache
+    pub fn load(path: PathBuf, language: &str) -> Self {
+        let file = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<CacheFile>(&content).ok())
+            .filter(|file| {
+                file.format == CACHE_FORMAT && file.sdk_version == SDK_VERSION.to_string() && file.language == language
+            });
+        let dirty = file.is_none() && path.exists();
+        let entries = file
+            .map(|file| file.plugins.into_iter().map(|p| (p.path.clone(), p)).collect())
+            .unwrap_or_default();
+        Self { path, language: language.to_string(), entries, dirty }
+    }
+
+    /// Cached information for a file, if the file is unchanged
+    pub fn lookup(&mut self, plugin_path: &Path) -> Option<PluginInfo> {
+        let entry = self.entries.get(plugin_path)?;
+        let metadata = fs::metadata(plugin_path).ok();
+        let unchanged = match &metadata {
+            Some(metadata) if metadata.len() == entry.identity.size => {
+                modified_since_epoch(metadata) == entry.identity.modified
+                    || file_hash(plugin_path).is_ok_and(|hash| hash == entry.identity.hash)
+            }
+            _ => false,
+        };
+        let info = if unchanged { entry.to_plugin_info() } else { None };
+
+        match (&info, metadata) {
+            (Some(_), Some(metadata)) => {
+                // Same contents under a new timestamp: remember the timestamp
+                let modified = modified_since_epoch(&metadata);
+                if let Some(entry) = self.entries.get_mut(plugin_path) {
+                    if entry.identity.modified != modified {
+                        entry.identity.modified = modified;
+                        self.dirty = true;
+                    }
+                }
+            }
+            _ => {
+                self.entries.remove(plugin_path);
+                self.dirty = true;
+            }
+        }
+        info
+    }
+
+    /// Remember a freshly validated plugin
+    pub fn insert(&mut self, info: &PluginInfo) -> io::Result<()> {
+        let metadata = fs::metadata(&info.path)?;
+        let identity = FileIdentity {
+            size: metadata.len(),
+            modified: modified_since_epoch(&metadata),
+            hash: file_hash(&info.path)?,
+        };
+        self.entries.insert(info.path.clone(), CachedPlugin::new(info, identity));
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Drop entries of files that were not seen by the scan
+    pub fn retain(&mut self, seen: &HashSet<PathBuf>) {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| seen.contains(path));
+        self.dirty |= self.entries.len() != before;
+    }
+
+    /// Write the cache if it changed
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut plugins: Vec<CachedPlugin> = self.entries.values().cloned().collect();
+        plugins.sort_by(|a, b| a.path.cmp(&b.path));
+        let file = CacheFile {
+            format: CACHE_FORMAT,
+            sdk_version: SDK_VERSION.to_string(),
+            language: self.language.clone(),
+            plugins,
+        };
+        let content = serde_json::to_string_pretty(&file).map_err(io::Error::other)?;
+        write_atomically(&self.path, content.as_bytes())?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn modified_since_epoch(metadata: &fs::Metadata) -> Option<Duration> {
+    metadata.modified().ok()?.duration_since(SystemTime::UNIX_EPOCH).ok()
+}
+
+/// 64-bit FNV-1a hash of a file's contents
+fn file_hash(path: &Path) -> io::Result<u64> {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut file = fs::File::open(path)?;
+    let mut buffer = [0u8; 64 * 1024];
+    let mut hash = OFFSET_BASIS;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hash);
+        }
+        for byte in &buffer[..read] {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cubemelon_sdk::{CubeMelonUUID, CubeMelonVersion};
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("cubemelon-cache-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn plugin(path: &Path) -> PluginInfo {
+        PluginInfo {
+            uuid: CubeMelonUUID::from_bytes([5; 16]),
+            version: CubeMelonVersion::new(1, 4, 2),
+            supported_types: u64::MAX,
+            name: "Cached".into(),
+            description: "Cached plugin".into(),
+            path: path.to_path_buf(),
+            capabilities: PluginCapabilities { input_formats: vec!["json".into()], ..Default::default() },
+            dependencies: vec![CubeMelonPluginDependency::new(CubeMelonUUID::from_bytes([6; 16]), CubeMelonVersion::new(2, 0, 0))],
+        }
+    }
+
+    fn set_modified(path: &Path, secs: u64) {
+        let file = fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+    }
+
+    #[test]
+    fn test_entries_survive_a_restart() {
+        let dir = TempDir::new("restart");
+        let library = dir.0.join("libplugin.so");
+        fs::write(&library, b"library v1").unwrap();
+        let cache_path = dir.0.join("cubemelon.cache.json");
+
+        let mut cache = DiscoveryCache::load(cache_path.clone(), "en-US");
+        cache.insert(&plugin(&library)).unwrap();
+        cache.save().unwrap();
+
+        let mut cache = DiscoveryCache::load(cache_path.clone(), "en-US");
+        let info = cache.lookup(&library).expect("cached");
+        assert_eq!(info.uuid, plugin(&library).uuid);
+        assert_eq!(info.version, CubeMelonVersion::new(1, 4, 2));
+        assert_eq!(info.supported_types, u64::MAX);
+        assert_eq!(info.capabilities.input_formats, vec!["json".to_string()]);
+        assert_eq!(info.dependencies, plugin(&library).dependencies);
+
+        // Names are localized, so another language starts from scratch
+        assert!(DiscoveryCache::load(cache_path, "ja-JP").lookup(&library).is_none());
+    }
+
+    #[test]
+    fn test_changed_files_are_invalidated() {
+        let dir = TempDir::new("invalidate");
+        let library = dir.0.join("libplugin.so");
+        fs::write(&library, b"library v1").unwrap();
+        set_modified(&library, 1_000);
+
+        let mut cache = DiscoveryCache::load(dir.0.join("cubemelon.cache.json"), "en-US");
+        cache.insert(&plugin(&library)).unwrap();
+
+        // Touched but identical: the hash keeps the entry
+        set_modified(&library, 2_000);
+        assert!(cache.lookup(&library).is_some());
+
+        // Same size, new contents
+        fs::write(&library, b"library v2").unwrap();
+        set_modified(&library, 3_000);
+        assert!(cache.lookup(&library).is_none());
+
+        // Entries of vanished files are dropped
+        cache.insert(&plugin(&library)).unwrap();
+        cache.retain(&HashSet::new());
+        assert!(cache.lookup(&library).is_none());
+    }
+}
@@ -3,23 +3,23 @@
 //! This module implements all host services provided to plugins,
 //! including logging, system language detection, and utility functions.
 
-use chrono::Local;
-
 use cubemelon_sdk::{
-    CubeMelonLanguage, CubeMelonLogLevel, CubeMelonPluginErrorCode, CubeMelonPluginType,
+    CubeMelonUUID, CubeMelonLanguage, CubeMelonLogLevel, CubeMelonPluginErrorCode, CubeMelonPluginType,
     CubeMelonPlugin, CubeMelonPluginManagerInterfaceImpl, CubeMelonPluginStateInterfaceImpl,
     create_plugin_instance, create_plugin_manager_interface, create_plugin_state_interface,
 };
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::ffi::c_void;
-use std::sync::OnceLock;
+use std::sync::{Mutex, OnceLock};
+use std::thread::ThreadId;
 
 use crate::RuntimeData;
+use crate::logging;
 
 /// Custom logging function for runtime to match plugin log format
 pub fn runtime_log(level: CubeMelonLogLevel, message: &str) {
-    let now = Local::now();
-    let timestamp = now.format("%Y-%m-%d %H:%M:%S:%.3f");
-    println!("{}> [{}] Runtime: {}", timestamp, level, message);
+    logging::log(level, "Runtime", message);
 }
 
 /// System language callback function
@@ -66,7 +66,7 @@ pub unsafe extern "C" fn get_system_language_callback() -> CubeMelonLanguage {
 }
 
 /// Plugin log callback function
-/// This function receives log messages from plugins and outputs them to standard output
+/// This function receives log messages from plugins and hands them to the runtime logger
 pub unsafe extern "C" fn plugin_log_callback(
     level: CubeMelonLogLevel,
     plugin_name: *const u8,
@@ -91,23 +91,55 @@ pub unsafe extern "C" fn plugin_log_callback(
         }
     };
     
-    // Format timestamp as YYYY-MM-DD hh:mm:ss:xxx.xxx> [CubeMelonLogLevel]
-    let now = Local::now();
-    let timestamp = now.format("%Y-%m-%d %H:%M:%S:%.3f");
-    
-    // Output log message with timestamp and level formatting in requested format
-    match level {
-        CubeMelonLogLevel::Error => println!("{}> [ERROR] {}: {}", timestamp, plugin_name_str, message_str),
-        CubeMelonLogLevel::Warn => println!("{}> [WARN] {}: {}", timestamp, plugin_name_str, message_str),
-        CubeMelonLogLevel::Info => println!("{}> [INFO] {}: {}", timestamp, plugin_name_str, message_str),
-        CubeMelonLogLevel::Debug => println!("{}> [DEBUG] {}: {}", timestamp, plugin_name_str, message_str),
-        CubeMelonLogLevel::Trace => println!("{}> [TRACE] {}: {}", timestamp, plugin_name_str, message_str),
-    }
+    logging::log(level, plugin_name_str, message_str);
 }
 
 /// Host proxy type used to expose manager/state interfaces via SDK wrappers.
+///
+/// Each plugin receives its own proxy so calls made through it can be
+/// attributed to that plugin (e.g. for the Local state scope).
 #[derive(Debug)]
-pub struct HostRuntimeProxy;
+pub struct HostRuntimeProxy {
+    /// Plugin the proxy was handed to (None if requested outside any plugin call)
+    pub caller: Option<CubeMelonUUID>,
+}
+
+impl HostRuntimeProxy {
+    /// Attribute host calls on the current thread to this proxy's plugin
+    pub(crate) fn enter(&self) -> Option<CallerScope> {
+        self.caller.map(enter_plugin)
+    }
+}
+
+thread_local! {
+    /// Plugin whose code the runtime is currently running on this thread
+    static CURRENT_CALLER: Cell<Option<CubeMelonUUID>> = const { Cell::new(None) };
+}
+
+/// Marks the current thread as running plugin code until dropped
+pub(crate) struct CallerScope {
+    previous: Option<CubeMelonUUID>,
+}
+
+impl Drop for CallerScope {
+    fn drop(&mut self) {
+        CURRENT_CALLER.with(|c| c.set(self.previous));
+    }
+}
+
+/// Record that the runtime is calling into a plugin on this thread
+///
+/// Scopes nest, so a plugin calling another plugin through the manager is
+/// attributed correctly on both sides.
+pub(crate) fn enter_plugin(uuid: CubeMelonUUID) -> CallerScope {
+    let previous = CURRENT_CALLER.with(|c| c.replace(Some(uuid)));
+    CallerScope { previous }
+}
+
+/// Plugin whose code is running on this thread, if any
+pub(crate) fn current_caller() -> Option<CubeMelonUUID> {
+    CURRENT_CALLER.with(|c| c.get())
+}
 
 unsafe impl Send for HostRuntimeProxy {}
 unsafe impl Sync for HostRuntimeProxy {}
@@ -115,34 +147,59 @@ unsafe impl Sync for HostRuntimeProxy {}
 // Global pointer to active RuntimeData. Set on startup from main.
 static mut RUNTIME_SINGLETON: *mut RuntimeData = std::ptr::null_mut();
 
+/// Thread that registered the runtime and owns it
+static RUNTIME_OWNER: OnceLock<ThreadId> = OnceLock::new();
+
 /// Set the global runtime pointer. Called from main after creating runtime.
 pub fn set_runtime_singleton(runtime: *mut RuntimeData) {
     unsafe { RUNTIME_SINGLETON = runtime; }
+    let _ = RUNTIME_OWNER.set(std::thread::current().id());
+}
+
+/// Whether this is the thread owning the registered runtime
+pub(crate) fn is_runtime_owner() -> bool {
+    RUNTIME_OWNER.get() == Some(&std::thread::current().id())
 }
 
 /// Execute closure with immutable runtime reference, if available.
+///
+/// Calls from other threads run on the owning thread (see `isolation`).
 pub(crate) fn with_runtime<R>(f: impl FnOnce(&RuntimeData) -> R) -> Option<R> {
     let ptr = unsafe { RUNTIME_SINGLETON };
     if ptr.is_null() { return None; }
+    if !is_runtime_owner() {
+        return crate::isolation::run_on_runtime_thread(|| with_runtime(f)).flatten();
+    }
     Some(unsafe { f(&*ptr) })
 }
 
 /// Execute closure with mutable runtime reference, if available.
+///
+/// Calls from other threads run on the owning thread (see `isolation`).
 pub(crate) fn with_runtime_mut<R>(f: impl FnOnce(&mut RuntimeData) -> R) -> Option<R> {
     let ptr = unsafe { RUNTIME_SINGLETON };
     if ptr.is_null() { return None; }
+    if !is_runtime_owner() {
+        return crate::isolation::run_on_runtime_thread(|| with_runtime_mut(f)).flatten();
+    }
     Some(unsafe { f(&mut *ptr) })
 }
 
-// Lazily created proxy plugin instance and interface tables
-// Store pointer address as usize to satisfy Send + Sync bounds for OnceLock
-static HOST_PROXY_PLUGIN: OnceLock<usize> = OnceLock::new();
+// Lazily created proxy plugin instances (one per caller) and interface tables
+// Store pointer addresses as usize to satisfy Send + Sync bounds for OnceLock
+static HOST_PROXY_PLUGINS: OnceLock<Mutex<HashMap<Option<CubeMelonUUID>, usize>>> = OnceLock::new();
 static MANAGER_VTABLE: OnceLock<CubeMelonPluginManagerInterfaceImpl> = OnceLock::new();
 static STATE_VTABLE: OnceLock<CubeMelonPluginStateInterfaceImpl> = OnceLock::new();
 
 fn ensure_proxy_plugin() -> *const CubeMelonPlugin {
-    let addr = HOST_PROXY_PLUGIN
-        .get_or_init(|| create_plugin_instance(HostRuntimeProxy) as usize);
+    let caller = current_caller();
+    let mut proxies = HOST_PROXY_PLUGINS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let addr = proxies
+        .entry(caller)
+        .or_insert_with(|| create_plugin_instance(HostRuntimeProxy { caller }) as usize);
     *addr as *const CubeMelonPlugin
 }
 
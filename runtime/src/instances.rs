@@ -0,0 +1,462 @@
+//! Plugin Instance Management
+//!
+//! Long-lived, initialized plugin instances owned by RuntimeData.
+//!
+//! Each plugin has at most one default instance, created on first use by the
+//! manager interface, plus any number of named instances created on request.
+//! Instances stay alive until they are destroyed explicitly or the runtime
+//! shuts down, so plugins can keep caches and connections between calls.
+
+use std::fmt;
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
+use cubemelon_sdk::{
+    CubeMelonUUID, CubeMelonPlugin, CubeMelonPluginErrorCode, CubeMelonLogLevel, CubeMelonPluginType,
+    CubeMelonInterface, CubeMelonSingleTaskInterfaceImpl, CubeMelonTaskRequest, CubeMelonTaskResult,
+    CubeMelonResidentInterfaceImpl,
+};
+
+use crate::RuntimeData;
+use crate::async_task;
+use crate::host_services::{runtime_log, enter_plugin};
+use crate::loader::PluginEntryPoints;
+use crate::metrics::{self, CallInterface};
+use crate::resident;
+use crate::trace;
+
+/// Identifies a live instance: the plugin and an optional instance name
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InstanceKey {
+    pub uuid: CubeMelonUUID,
+    /// None for the default instance
+    pub name: Option<String>,
+}
+
+impl InstanceKey {
+    pub fn new(uuid: CubeMelonUUID, name: Option<&str>) -> Self {
+        Self { uuid, name: name.map(str::to_string) }
+    }
+}
+
+impl fmt::Display for InstanceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}#{}", self.uuid, name),
+            None => write!(f, "{}", self.uuid),
+        }
+    }
+}
+
+/// Initialized plugin instance
+pub struct PluginInstance {
+    instance: *mut CubeMelonPlugin,
+    basic: &'static CubeMelonInterface,
+    entry_points: PluginEntryPoints,
+    created_at: DateTime<Local>,
+}
+
+impl PluginInstance {
+    /// Raw instance pointer handed to interface functions
+    pub(crate) fn handle(&self) -> *mut CubeMelonPlugin {
+        self.instance
+    }
+
+    /// Uninitialize and destroy the instance
+    pub(crate) fn teardown(self, key: &InstanceKey) {
+        let pending = async_task::in_flight_count(key.uuid);
+        if pending > 0 {
+            runtime_log(CubeMelonLogLevel::Info, &format!("Stopping {} with {} tasks in flight", key, pending));
+        }
+        let _caller = enter_plugin(key.uuid);
+        // A resident service still running on this instance is stopped first
+        if let Some(resident) = self.entry_points.get_interface::<CubeMelonResidentInterfaceImpl>(CubeMelonPluginType::Resident) {
+            if resident::is_active((resident.get_status)(self.instance)) {
+                let rc = (resident.stop)(self.instance);
+                runtime_log(CubeMelonLogLevel::Info, &format!("Stopped resident service of {}: {:?}", key, rc));
+            }
+        }
+        // Plugins are expected to finish or abandon their work in uninitialize
+        let rc = (self.basic.uninitialize)(self.instance);
+        if rc != CubeMelonPluginErrorCode::Success {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Instance uninitialize failed for {}: {:?}", key, rc));
+        }
+        unsafe { (self.entry_points.destroy_plugin)(self.instance) };
+
+        // The instance can no longer report back; drop what it left behind
+        let abandoned = async_task::discard_all_for_instance(self.instance);
+        if abandoned > 0 {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Abandoned {} pending async tasks of {}", abandoned, key));
+        }
+        metrics::record_instance_destroyed(key.uuid);
+        runtime_log(CubeMelonLogLevel::Info, &format!("Destroyed plugin instance: {}", key));
+    }
+}
+
+impl RuntimeData {
+    /// Get a live instance, creating and initializing it on first use
+    pub(crate) fn get_or_create_instance(
+        &mut self,
+        uuid: CubeMelonUUID,
+        name: Option<&str>,
+    ) -> Result<*mut CubeMelonPlugin, CubeMelonPluginErrorCode> {
+        let key = InstanceKey::new(uuid, name);
+        if let Some(existing) = self.instances.get(&key) {
+            return Ok(existing.handle());
+        }
+        self.create_instance(uuid, name)
+    }
+
+    /// Create and initialize a new instance
+    ///
+    /// Fails with `AlreadyInitialized` if the instance exists. Instances of an
+    /// isolated plugin live in its plugin host, so the handle is null for them.
+    pub fn create_instance(
+        &mut self,
+        uuid: CubeMelonUUID,
+        name: Option<&str>,
+    ) -> Result<*mut CubeMelonPlugin, CubeMelonPluginErrorCode> {
+        if let Some(isolated) = self.isolated_plugin(uuid) {
+            return match isolated.create_instance(name) {
+                CubeMelonPluginErrorCode::Success => {
+                    metrics::record_instance_created(uuid);
+                    Ok(std::ptr::null_mut())
+                }
+                code => Err(code),
+            };
+        }
+        let key = InstanceKey::new(uuid, name);
+        if self.instances.contains_key(&key) {
+            return Err(CubeMelonPluginErrorCode::AlreadyInitialized);
+        }
+
+        let entry_points = self.entry_points(uuid)?;
+        let basic = entry_points
+            .get_interface::<CubeMelonInterface>(CubeMelonPluginType::Basic)
+            .ok_or(CubeMelonPluginErrorCode::InterfaceNotSupported)?;
+
+        let instance = unsafe { (entry_points.create_plugin)() };
+        if instance.is_null() {
+            return Err(CubeMelonPluginErrorCode::PluginLoadFailed);
+        }
+
+        let init_rc = {
+            let _caller = enter_plugin(uuid);
+            (basic.initialize)(instance, &self.host_services as *const _)
+        };
+        if init_rc != CubeMelonPluginErrorCode::Success {
+            unsafe { (entry_points.destroy_plugin)(instance) };
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Instance initialize failed for {}: {:?}", key, init_rc));
+            return Err(init_rc);
+        }
+
+        metrics::record_instance_created(uuid);
+        runtime_log(CubeMelonLogLevel::Info, &format!("Created plugin instance: {}", key));
+        self.instances.insert(key, PluginInstance {
+            instance,
+            basic,
+            entry_points,
+            created_at: Local::now(),
+        });
+        Ok(instance)
+    }
+
+    /// Uninitialize and destroy an instance
+    pub fn destroy_instance(
+        &mut self,
+        uuid: CubeMelonUUID,
+        name: Option<&str>,
+    ) -> Result<(), CubeMelonPluginErrorCode> {
+        if let Some(isolated) = self.isolated_plugin(uuid) {
+            return match isolated.destroy_instance(name) {
+                CubeMelonPluginErrorCode::Success => {
+                    metrics::record_instance_destroyed(uuid);
+                    Ok(())
+                }
+                code => Err(code),
+            };
+        }
+        let key = InstanceKey::new(uuid, name);
+        match self.instances.remove(&key) {
+            Some(instance) => {
+                instance.teardown(&key);
+                Ok(())
+            }
+            None => Err(CubeMelonPluginErrorCode::PluginNotFound),
+        }
+    }
+
+    /// Uninitialize and destroy every instance of a plugin
+    pub fn destroy_plugin_instances(&mut self, uuid: CubeMelonUUID) {
+        let keys: Vec<InstanceKey> = self.instances.keys().filter(|k| k.uuid == uuid).cloned().collect();
+        for key in keys {
+            if let Some(instance) = self.instances.remove(&key) {
+                instance.teardown(&key);
+            }
+        }
+    }
+
+    /// Uninitialize and destroy every instance
+    ///
+    /// Instances of dependent plugins go before those of their dependencies.
+    pub fn destroy_all_instances(&mut self) {
+        let mut instances: Vec<_> = self.instances.drain().collect();
+        let load_position = |uuid: &CubeMelonUUID| self.load_order.iter().position(|loaded| loaded == uuid);
+        instances.sort_by_key(|(key, _)| std::cmp::Reverse(load_position(&key.uuid)));
+        for (key, instance) in instances {
+            instance.teardown(&key);
+        }
+    }
+
+    /// Execute a synchronous task on a live instance
+    ///
+    /// A request with a timeout is watched (see `watchdog`). Every call is
+    /// counted in the plugin's metrics and runs in a span of its trace.
+    pub fn execute_task_on(
+        &mut self,
+        uuid: CubeMelonUUID,
+        name: Option<&str>,
+        request: &CubeMelonTaskRequest,
+        result: &mut CubeMelonTaskResult,
+    ) -> CubeMelonPluginErrorCode {
+        let started = Instant::now();
+        let span = trace::start_span(request.trace_id(), uuid, CallInterface::SingleTask);
+        let traced = trace::traced_request(request, span.trace_id);
+        let rc = {
+            let _active = trace::enter(Some(span));
+            self.dispatch_task(uuid, name, &traced, result)
+        };
+        let outcome = metrics::call_outcome(rc, result);
+        metrics::record_call(uuid, CallInterface::SingleTask, outcome, started.elapsed());
+        trace::finish_span(span, outcome);
+        rc
+    }
+
+    /// Run a synchronous task on the instance chosen by `execute_task_on`
+    fn dispatch_task(
+        &mut self,
+        uuid: CubeMelonUUID,
+        name: Option<&str>,
+        request: &CubeMelonTaskRequest,
+        result: &mut CubeMelonTaskResult,
+    ) -> CubeMelonPluginErrorCode {
+        if let Some(isolated) = self.isolated_plugin(uuid) {
+            return isolated.execute(name, false, request, result);
+        }
+        let entry_points = match self.entry_points(uuid) {
+            Ok(entry_points) => entry_points,
+            Err(code) => return code,
+        };
+        let single_task = match entry_points.get_interface::<CubeMelonSingleTaskInterfaceImpl>(CubeMelonPluginType::SingleTask) {
+            Some(iface) => iface,
+            None => return CubeMelonPluginErrorCode::InterfaceNotSupported,
+        };
+        let instance = match self.get_or_create_instance(uuid, name) {
+            Ok(instance) => instance,
+            Err(code) => return code,
+        };
+
+        if request.timeout_us > 0 {
+            return self.execute_with_timeout(InstanceKey::new(uuid, name), single_task, instance, request, result);
+        }
+
+        let _caller = enter_plugin(uuid);
+        (single_task.execute)(instance, request as *const _, result as *mut _)
+    }
+
+    /// List live plugin instances
+    pub fn list_instances(&self) {
+        if self.instances.is_empty() {
+            println!("No live instances.");
+            return;
+        }
+
+        let mut keys: Vec<&InstanceKey> = self.instances.keys().collect();
+        keys.sort_by_key(|k| (k.uuid.to_string(), k.name.clone()));
+
+        println!("Live instances:");
+        for key in keys {
+            let instance = &self.instances[key];
+            let plugin_name = self
+                .discovered_plugins
+                .iter()
+                .find(|p| p.uuid == key.uuid)
+                .map(|p| p.name.as_str())
+                .unwrap_or("?");
+            println!(
+                "  {} [{}] created {}",
+                plugin_name,
+                key.name.as_deref().unwrap_or("default"),
+                instance.created_at.format("%Y-%m-%d %H:%M:%S"),
+            );
+            println!("     UUID: {}", key.uuid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+    use cubemelon_sdk::{
+        CubeMelonLanguage, CubeMelonSingleTaskInterface, CubeMelonString, CubeMelonTaskType,
+        create_single_task_interface, create_plugin_instance, destroy_plugin_instance,
+    };
+    use crate::loader::{PluginLibrary, UnloadOutcome};
+    use crate::metrics::plugin_metrics;
+
+    /// SingleTask plugin that reports how many tasks its instance has run
+    struct CountingPlugin {
+        calls: u32,
+    }
+
+    impl CubeMelonSingleTaskInterface for CountingPlugin {
+        fn execute(
+            &mut self,
+            _request: &CubeMelonTaskRequest,
+            result: &mut CubeMelonTaskResult,
+        ) -> CubeMelonPluginErrorCode {
+            self.calls += 1;
+            *result = CubeMelonTaskResult::success(
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                CubeMelonString::from_string(format!("{{\"calls\":{}}}", self.calls)),
+                0,
+            );
+            CubeMelonPluginErrorCode::Success
+        }
+    }
+
+    static BASIC_VTABLE: OnceLock<CubeMelonInterface> = OnceLock::new();
+    static SINGLE_TASK_VTABLE: OnceLock<CubeMelonSingleTaskInterfaceImpl> = OnceLock::new();
+
+    unsafe extern "C" fn counting_get_plugin_interface(
+        plugin_types: u64,
+        _interface_version: u32,
+        interface: *mut *const std::ffi::c_void,
+    ) -> CubeMelonPluginErrorCode {
+        if plugin_types == CubeMelonPluginType::Basic as u64 {
+            *interface = BASIC_VTABLE.get_or_init(CubeMelonInterface::new) as *const _ as *const std::ffi::c_void;
+            CubeMelonPluginErrorCode::Success
+        } else if plugin_types == CubeMelonPluginType::SingleTask as u64 {
+            *interface = SINGLE_TASK_VTABLE.get_or_init(create_single_task_interface::<CountingPlugin>) as *const _ as *const std::ffi::c_void;
+            CubeMelonPluginErrorCode::Success
+        } else {
+            CubeMelonPluginErrorCode::InterfaceNotSupported
+        }
+    }
+
+    unsafe extern "C" fn counting_create_plugin() -> *mut CubeMelonPlugin {
+        create_plugin_instance(CountingPlugin { calls: 0 })
+    }
+
+    unsafe extern "C" fn counting_destroy_plugin(plugin: *mut CubeMelonPlugin) {
+        destroy_plugin_instance(plugin);
+    }
+
+    /// Runtime with the counting plugin loaded as `uuid`
+    fn runtime_with_counting_plugin(uuid: CubeMelonUUID) -> RuntimeData {
+        let entry_points = PluginEntryPoints {
+            get_plugin_interface: counting_get_plugin_interface,
+            create_plugin: counting_create_plugin,
+            destroy_plugin: counting_destroy_plugin,
+        };
+        let mut runtime = RuntimeData::for_tests();
+        runtime.load_fake_plugin(uuid, PluginLibrary::fake(entry_points, None));
+        runtime
+    }
+
+    /// Run a task on an instance and return the output JSON
+    fn run(runtime: &mut RuntimeData, uuid: CubeMelonUUID, name: Option<&str>) -> String {
+        let request = CubeMelonTaskRequest::new(
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            CubeMelonString::empty(),
+            CubeMelonTaskType::Generic,
+            CubeMelonLanguage::EN_US,
+            0,
+            0,
+        );
+        let mut result = CubeMelonTaskResult::empty();
+        let rc = runtime.execute_task_on(uuid, name, &request, &mut result);
+        assert_eq!(rc, CubeMelonPluginErrorCode::Success);
+        let json = result.output_json.as_str().unwrap().to_string();
+        async_task::free_task_result(&mut result);
+        json
+    }
+
+    #[test]
+    fn test_create_and_destroy_named_instances() {
+        let uuid = CubeMelonUUID::from_bytes([0xE1; 16]);
+        let mut runtime = runtime_with_counting_plugin(uuid);
+
+        assert!(runtime.create_instance(uuid, Some("a")).is_ok());
+        assert_eq!(runtime.create_instance(uuid, Some("a")), Err(CubeMelonPluginErrorCode::AlreadyInitialized));
+        assert!(runtime.create_instance(uuid, Some("b")).is_ok());
+        assert_eq!(runtime.instances.len(), 2);
+
+        assert_eq!(runtime.destroy_instance(uuid, Some("c")), Err(CubeMelonPluginErrorCode::PluginNotFound));
+        assert_eq!(runtime.destroy_instance(uuid, None), Err(CubeMelonPluginErrorCode::PluginNotFound));
+        let unknown = CubeMelonUUID::from_bytes([0xEF; 16]);
+        assert_eq!(runtime.destroy_instance(unknown, Some("a")), Err(CubeMelonPluginErrorCode::PluginNotFound));
+
+        assert_eq!(runtime.destroy_instance(uuid, Some("a")), Ok(()));
+        assert_eq!(runtime.destroy_instance(uuid, Some("a")), Err(CubeMelonPluginErrorCode::PluginNotFound));
+        assert_eq!(runtime.instances.len(), 1);
+        runtime.destroy_all_instances();
+    }
+
+    #[test]
+    fn test_tasks_run_on_the_named_instance() {
+        let uuid = CubeMelonUUID::from_bytes([0xE2; 16]);
+        let mut runtime = runtime_with_counting_plugin(uuid);
+
+        assert_eq!(run(&mut runtime, uuid, Some("a")), "{\"calls\":1}");
+        assert_eq!(run(&mut runtime, uuid, Some("a")), "{\"calls\":2}");
+        assert_eq!(run(&mut runtime, uuid, Some("b")), "{\"calls\":1}");
+        assert_eq!(run(&mut runtime, uuid, None), "{\"calls\":1}");
+        assert_eq!(run(&mut runtime, uuid, Some("a")), "{\"calls\":3}");
+        assert_eq!(runtime.instances.len(), 3);
+
+        // A recreated instance starts over
+        runtime.destroy_instance(uuid, Some("a")).unwrap();
+        assert_eq!(run(&mut runtime, uuid, Some("a")), "{\"calls\":1}");
+        runtime.destroy_all_instances();
+    }
+
+    #[test]
+    fn test_destroy_plugin_instances_spares_other_plugins() {
+        let uuid = CubeMelonUUID::from_bytes([0xE3; 16]);
+        let other = CubeMelonUUID::from_bytes([0xE4; 16]);
+        let mut runtime = runtime_with_counting_plugin(uuid);
+        runtime.load_fake_plugin(other, PluginLibrary::fake(runtime.entry_points(uuid).unwrap(), None));
+        run(&mut runtime, uuid, None);
+        runtime.create_instance(uuid, Some("a")).unwrap();
+        runtime.create_instance(other, Some("a")).unwrap();
+
+        runtime.destroy_plugin_instances(uuid);
+        assert_eq!(runtime.instances.len(), 1);
+        assert!(runtime.instances.keys().all(|key| key.uuid == other));
+
+        // The plugin's instances start over when used again
+        assert_eq!(run(&mut runtime, uuid, None), "{\"calls\":1}");
+        runtime.destroy_all_instances();
+    }
+
+    #[test]
+    fn test_unload_destroys_instances() {
+        let uuid = CubeMelonUUID::from_bytes([0xE5; 16]);
+        let mut runtime = runtime_with_counting_plugin(uuid);
+        run(&mut runtime, uuid, None);
+        runtime.create_instance(uuid, Some("a")).unwrap();
+
+        assert_eq!(runtime.unload_plugin(uuid), Ok(UnloadOutcome::Unloaded));
+        assert!(runtime.instances.is_empty());
+        let metrics = plugin_metrics(uuid);
+        assert_eq!(metrics.instances_created, 2);
+        assert_eq!(metrics.instances_destroyed, 2);
+
+        // Nothing is left to run tasks on
+        assert_eq!(runtime.create_instance(uuid, Some("a")), Err(CubeMelonPluginErrorCode::PluginNotFound));
+    }
+}
@@ -0,0 +1,346 @@
+//! Plugin Integrity Checks
+//!
+//! Before a plugin file is read for metadata or opened with `Library::new`,
+//! the runtime can verify it against the `[integrity]` section:
+//!
+//! ```toml
+//! [integrity]
+//! required = true                      # refuse files that pass neither check
+//! trusted_keys = ["d75a9801...511a"]   # hex Ed25519 public keys
+//! audit_log = "logs/audit.log"         # default: <config name>.audit.log
+//!
+//! [integrity.sha256]
+//! "libhello_world.so" = "9f86d081...0f00a08"
+//! "tools/libtools.so" = "60303ae2...2efd7d3"
+//! ```
+//!
+//! Hashes are pinned by the file's path relative to the search path it was
+//! found in (with `/` separators), or by its full path. A file with a pinned
+//! SHA-256 must match it. When trusted keys are configured, every file needs a
+//! detached signature next to it (`<file>.sig`, 64 raw bytes or 128 hex
+//! digits) that is a valid Ed25519 signature of the file by one of them.
+//! Files failing a check are rejected with
+//! `IntegrityCheckFailed`, and each failure is appended to the audit log as a
+//! JSON line. Without any pins, keys or `required`, nothing is checked.
+//!
+//! The file is hashed right before it is opened, so keep plugin directories
+//! writable only by whoever deploys the plugins.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{Local, SecondsFormat};
+use cubemelon_sdk::{CubeMelonLogLevel, CubeMelonPluginErrorCode};
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::host_services::runtime_log;
+use crate::loader::PluginRejection;
+use crate::{IntegrityConfig, RuntimeData};
+
+/// Extension appended to a plugin file name to find its detached signature
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+impl IntegrityConfig {
+    /// Whether any check is configured
+    pub fn is_active(&self) -> bool {
+        self.required || !self.trusted_keys.is_empty() || !self.sha256.is_empty()
+    }
+}
+
+/// Lowercase hex SHA-256 of some bytes
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    // An odd trailing digit makes `get` fail
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Detached signature file of a plugin file
+pub fn signature_path(plugin_path: &Path) -> PathBuf {
+    let mut name = plugin_path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(SIGNATURE_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// Check a plugin file's contents
+///
+/// `pin_names` are the keys the file's SHA-256 may be pinned under; the first
+/// one pinned is used. Returns whether the file was verified by a pin or
+/// signature, or the reason it must be refused.
+pub fn check_contents(
+    config: &IntegrityConfig,
+    pin_names: &[String],
+    contents: &[u8],
+    signature: Option<&[u8]>,
+) -> Result<bool, String> {
+    let mut verified = false;
+
+    if let Some(expected) = pin_names.iter().find_map(|name| config.sha256.get(name)) {
+        let actual = sha256_hex(contents);
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(format!("SHA-256 {} does not match the pinned {}", actual, expected.trim()));
+        }
+        verified = true;
+    }
+
+    if !config.trusted_keys.is_empty() {
+        let signature = signature.ok_or_else(|| "missing signature".to_string())?;
+        verify_signature(&config.trusted_keys, contents, signature)?;
+        verified = true;
+    }
+
+    if config.required && !verified {
+        return Err("no pinned SHA-256 or trusted signature".to_string());
+    }
+    Ok(verified)
+}
+
+/// Accept a detached signature made by any of the trusted keys
+fn verify_signature(trusted_keys: &[String], contents: &[u8], signature: &[u8]) -> Result<(), String> {
+    let bytes: [u8; 64] = match signature.try_into() {
+        Ok(raw) => raw,
+        Err(_) => std::str::from_utf8(signature)
+            .ok()
+            .and_then(decode_hex)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| "malformed signature file".to_string())?,
+    };
+    let signature = Signature::from_bytes(&bytes);
+
+    for key in trusted_keys {
+        let verifying_key = decode_hex(key)
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+            .ok_or_else(|| format!("invalid trusted key '{}'", key))?;
+        if verifying_key.verify_strict(contents, &signature).is_ok() {
+            return Ok(());
+        }
+    }
+    Err("signature does not match any trusted key".to_string())
+}
+
+/// The [integrity] checks, detached from the runtime so plugin hosts can be
+/// checked again when they are restarted
+#[derive(Debug, Clone)]
+pub struct IntegrityCheck {
+    config: IntegrityConfig,
+    search_paths: Vec<PathBuf>,
+    audit_log: Option<PathBuf>,
+}
+
+impl IntegrityCheck {
+    /// Verify a plugin file before using it
+    ///
+    /// Fails with a `PluginRejection` carrying `IntegrityCheckFailed`.
+    pub fn verify(&self, plugin_path: &Path) -> Result<()> {
+        let config = &self.config;
+        if !config.is_active() {
+            return Ok(());
+        }
+
+        let contents = fs::read(plugin_path);
+        let checked = match &contents {
+            Ok(contents) => {
+                let signature = fs::read(signature_path(plugin_path)).ok();
+                check_contents(config, &self.pin_names(plugin_path), contents, signature.as_deref())
+            }
+            Err(e) => Err(format!("cannot read file: {}", e)),
+        };
+
+        match checked {
+            Ok(verified) => {
+                if verified {
+                    runtime_log(CubeMelonLogLevel::Debug, &format!("Verified integrity of {:?}", plugin_path));
+                }
+                Ok(())
+            }
+            Err(reason) => {
+                let sha256 = contents.as_deref().ok().map(sha256_hex);
+                self.audit_failure(plugin_path, sha256.as_deref(), &reason);
+                Err(PluginRejection {
+                    code: CubeMelonPluginErrorCode::IntegrityCheckFailed,
+                    message: format!("Integrity check failed for {:?}: {}", plugin_path, reason),
+                }
+                .into())
+            }
+        }
+    }
+
+    /// Keys a plugin file's SHA-256 may be pinned under
+    ///
+    /// Its path relative to the search path holding it, then its full path.
+    fn pin_names(&self, plugin_path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .search_paths
+            .iter()
+            .filter_map(|root| plugin_path.strip_prefix(root).ok())
+            .map(|relative| {
+                let components: Vec<_> = relative.iter().map(|c| c.to_string_lossy()).collect();
+                components.join("/")
+            })
+            .collect();
+        names.push(plugin_path.display().to_string());
+        names
+    }
+
+    /// Record a refused plugin file in the log and the audit log
+    fn audit_failure(&self, plugin_path: &Path, sha256: Option<&str>, reason: &str) {
+        runtime_log(
+            CubeMelonLogLevel::Error,
+            &format!("Refusing plugin {:?}: integrity check failed: {}", plugin_path, reason),
+        );
+
+        let Some(audit_path) = &self.audit_log else {
+            return;
+        };
+        let entry = serde_json::json!({
+            "timestamp": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            "event": "integrity_check_failed",
+            "path": plugin_path.display().to_string(),
+            "sha256": sha256,
+            "reason": reason,
+            "code": CubeMelonPluginErrorCode::IntegrityCheckFailed as i32,
+        });
+        let written = audit_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| OpenOptions::new().create(true).append(true).open(audit_path))
+            .and_then(|mut file| writeln!(file, "{}", entry));
+        if let Err(e) = written {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Failed to write audit log {:?}: {}", audit_path, e));
+        }
+    }
+}
+
+impl RuntimeData {
+    /// The [integrity] checks as currently configured
+    pub(crate) fn integrity_check(&self) -> IntegrityCheck {
+        IntegrityCheck {
+            config: self.config.integrity.clone(),
+            search_paths: self.plugin_search_paths(),
+            audit_log: self.audit_log_path(),
+        }
+    }
+
+    /// Verify a plugin file against the [integrity] section before using it
+    ///
+    /// Fails with a `PluginRejection` carrying `IntegrityCheckFailed`.
+    pub(crate) fn verify_plugin_file(&self, plugin_path: &Path) -> Result<()> {
+        self.integrity_check().verify(plugin_path)
+    }
+
+    /// Audit log file (none without a config path or explicit setting)
+    fn audit_log_path(&self) -> Option<PathBuf> {
+        match &self.config.integrity.audit_log {
+            Some(file) => {
+                let base_dir = self.config_path.parent().map(Path::to_path_buf).unwrap_or_default();
+                Some(base_dir.join(file))
+            }
+            None => Some(&self.config_path)
+                .filter(|config_path| !config_path.as_os_str().is_empty())
+                .map(|config_path| config_path.with_extension("audit.log")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const LIBRARY: &[u8] = b"plugin library contents";
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_pinned_hash() {
+        assert_eq!(sha256_hex(b"test"), "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08");
+
+        let mut config = IntegrityConfig::default();
+        config.sha256.insert("libplugin.so".into(), sha256_hex(LIBRARY).to_uppercase());
+        let plugin = names(&["libplugin.so", "/plugins/libplugin.so"]);
+        assert_eq!(check_contents(&config, &plugin, LIBRARY, None), Ok(true));
+        assert!(check_contents(&config, &plugin, b"tampered", None).unwrap_err().contains("does not match"));
+
+        // A file with the same name in a subdirectory is a different file
+        let nested = names(&["tools/libplugin.so", "/plugins/tools/libplugin.so"]);
+        assert_eq!(check_contents(&config, &nested, b"anything", None), Ok(false));
+        config.sha256.insert("/plugins/tools/libplugin.so".into(), sha256_hex(LIBRARY));
+        assert!(check_contents(&config, &nested, b"anything", None).is_err());
+        assert_eq!(check_contents(&config, &nested, LIBRARY, None), Ok(true));
+
+        // Unpinned files pass unless verification is required
+        let other = names(&["libother.so"]);
+        assert_eq!(check_contents(&config, &other, b"anything", None), Ok(false));
+        config.required = true;
+        assert!(check_contents(&config, &other, b"anything", None).is_err());
+    }
+
+    #[test]
+    fn test_pin_names_are_relative_to_the_search_path() {
+        let root = std::env::temp_dir().join("cubemelon-integrity-root");
+        let mut runtime = RuntimeData::for_tests();
+        runtime.config.search.paths = vec![root.display().to_string()];
+        let path = root.join("tools").join("libtools.so");
+
+        let pin_names = runtime.integrity_check().pin_names(&path);
+        assert!(pin_names.contains(&"tools/libtools.so".to_string()));
+        assert_eq!(pin_names.last(), Some(&path.display().to_string()));
+    }
+
+    #[test]
+    fn test_detached_signature() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        let config = IntegrityConfig {
+            required: true,
+            trusted_keys: vec![hex(other_key.verifying_key().as_bytes()), hex(signing_key.verifying_key().as_bytes())],
+            ..Default::default()
+        };
+        let signature = signing_key.sign(LIBRARY).to_bytes();
+        let plugin = names(&["libplugin.so"]);
+
+        assert_eq!(check_contents(&config, &plugin, LIBRARY, Some(&signature)), Ok(true));
+        let hex_signature = format!("{}\n", hex(&signature));
+        assert_eq!(check_contents(&config, &plugin, LIBRARY, Some(hex_signature.as_bytes())), Ok(true));
+
+        assert!(check_contents(&config, &plugin, b"tampered", Some(&signature)).is_err());
+        let untrusted = SigningKey::from_bytes(&[9; 32]).sign(LIBRARY).to_bytes();
+        assert!(check_contents(&config, &plugin, LIBRARY, Some(&untrusted)).is_err());
+        assert!(check_contents(&config, &plugin, LIBRARY, Some(b"garbage")).is_err());
+        assert!(check_contents(&config, &plugin, LIBRARY, None).is_err());
+    }
+
+    #[test]
+    fn test_trusted_keys_require_a_signature() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let mut config = IntegrityConfig {
+            trusted_keys: vec![hex(signing_key.verifying_key().as_bytes())],
+            ..Default::default()
+        };
+        let plugin = names(&["libplugin.so"]);
+        assert_eq!(check_contents(&config, &plugin, LIBRARY, None), Err("missing signature".to_string()));
+
+        // A pinned hash does not stand in for the signature
+        config.sha256.insert("libplugin.so".into(), sha256_hex(LIBRARY));
+        assert_eq!(check_contents(&config, &plugin, LIBRARY, None), Err("missing signature".to_string()));
+        let signature = signing_key.sign(LIBRARY).to_bytes();
+        assert_eq!(check_contents(&config, &plugin, LIBRARY, Some(&signature)), Ok(true));
+    }
+}
@@ -0,0 +1,470 @@
+//! Plugin Host Protocol
+//!
+//! Messages exchanged between the runtime and a plugin host process (see
+//! `isolation` and `plugin_host`), one JSON object per line over the child's
+//! stdin and stdout.
+//!
+//! Task requests, results and `CubeMelonValue` trees are copied into owned
+//! wire types on one side and rebuilt on the other. Raw pointers (`Pointer`
+//! and `Custom` values, `caller`, `user_data`) mean nothing in another process
+//! and do not cross; neither do non-finite floats, which JSON cannot hold.
+
+use std::io::{self, BufRead, Write};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use cubemelon_sdk::{
+    CubeMelonExecutionStatus, CubeMelonPluginErrorCode, CubeMelonString, CubeMelonTaskRequest, CubeMelonTaskResult,
+    CubeMelonTaskType, CubeMelonValue, CubeMelonValueTag,
+};
+
+use crate::host_services::parse_language;
+use crate::matcher::task_type_from_value;
+
+/// Owned copy of a `CubeMelonValue` tree
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum WireValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Buffer(Vec<u8>),
+    Array(Vec<WireValue>),
+}
+
+impl WireValue {
+    /// Copy a value tree
+    ///
+    /// # Safety
+    ///
+    /// The value must be well-formed: its tag matches the active union member.
+    pub unsafe fn from_value(value: &CubeMelonValue) -> Result<Self, CubeMelonPluginErrorCode> {
+        Ok(match value.tag {
+            CubeMelonValueTag::Null => WireValue::Null,
+            CubeMelonValueTag::Bool => WireValue::Bool(value.as_bool()),
+            CubeMelonValueTag::Int => WireValue::Int(value.as_int() as i64),
+            CubeMelonValueTag::UInt => WireValue::UInt(value.as_uint() as u64),
+            CubeMelonValueTag::Float if value.as_float().is_finite() => WireValue::Float(value.as_float()),
+            CubeMelonValueTag::String => {
+                WireValue::String(value.as_str().map_err(|_| CubeMelonPluginErrorCode::Encoding)?.to_string())
+            }
+            CubeMelonValueTag::Buffer => WireValue::Buffer(value.as_buffer().to_vec()),
+            CubeMelonValueTag::Array => WireValue::Array(
+                value.as_array().iter().map(|item| Self::from_value(item)).collect::<Result<_, _>>()?,
+            ),
+            CubeMelonValueTag::Float | CubeMelonValueTag::Pointer | CubeMelonValueTag::Custom => {
+                return Err(CubeMelonPluginErrorCode::FormatUnsupported)
+            }
+        })
+    }
+
+    /// Build an owned value tree, released through its `free_value`
+    pub fn to_value(&self) -> CubeMelonValue {
+        match self {
+            WireValue::Null => CubeMelonValue::null(),
+            WireValue::Bool(b) => CubeMelonValue::bool(*b),
+            WireValue::Int(i) => CubeMelonValue::int(*i as isize),
+            WireValue::UInt(u) => CubeMelonValue::uint(*u as usize),
+            WireValue::Float(f) => CubeMelonValue::float(*f),
+            WireValue::String(s) => CubeMelonValue::string(s.clone()),
+            WireValue::Buffer(bytes) => CubeMelonValue::buffer(bytes.clone()),
+            WireValue::Array(items) => CubeMelonValue::array(items.iter().map(WireValue::to_value).collect()),
+        }
+    }
+}
+
+fn text(s: &CubeMelonString) -> String {
+    s.as_str().map(str::to_string).unwrap_or_default()
+}
+
+/// Owned copy of the marshallable fields of a `CubeMelonTaskRequest`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WireRequest {
+    pub input_json: String,
+    pub input_data: Option<WireValue>,
+    pub task_type: u16,
+    pub language: String,
+    pub request_time_us: i64,
+    pub timeout_us: i64,
+    /// See `trace`; 0 = none
+    #[serde(default)]
+    pub trace_id: u64,
+}
+
+impl WireRequest {
+    /// Copy a request
+    pub fn from_request(request: &CubeMelonTaskRequest) -> Result<Self, CubeMelonPluginErrorCode> {
+        let input_data = match request.input_data.is_null() {
+            true => None,
+            false => Some(unsafe { WireValue::from_value(&*request.input_data)? }),
+        };
+        Ok(Self {
+            input_json: text(&request.input_json),
+            input_data,
+            task_type: request.task_type as u16,
+            language: request.language.as_str().to_string(),
+            request_time_us: request.request_time_us,
+            timeout_us: request.timeout_us,
+            trace_id: request.trace_id(),
+        })
+    }
+
+    /// Rebuild the request; `caller` and `user_data` stay null
+    pub fn to_request(&self) -> OwnedRequest {
+        let input_data = match &self.input_data {
+            Some(value) => Box::into_raw(Box::new(value.to_value())),
+            None => std::ptr::null_mut(),
+        };
+        let mut request = CubeMelonTaskRequest::new(
+            std::ptr::null(),
+            input_data,
+            CubeMelonString::from_string(self.input_json.clone()),
+            task_type_from_value(self.task_type.into()).unwrap_or(CubeMelonTaskType::None),
+            parse_language(&self.language),
+            self.request_time_us,
+            self.timeout_us,
+        );
+        request.set_trace_id(self.trace_id);
+        OwnedRequest(request)
+    }
+}
+
+/// Request rebuilt from the wire; its buffers are released on drop
+pub struct OwnedRequest(pub CubeMelonTaskRequest);
+
+impl Drop for OwnedRequest {
+    fn drop(&mut self) {
+        let request = &mut self.0;
+        if let Some(free_fn) = request.input_json.free_string {
+            if !request.input_json.str.is_null() {
+                unsafe { free_fn(request.input_json.str) };
+            }
+        }
+        if !request.input_data.is_null() {
+            unsafe {
+                let mut value = Box::from_raw(request.input_data);
+                if let Some(free_fn) = value.free_value {
+                    free_fn(&mut *value);
+                }
+            }
+        }
+    }
+}
+
+/// Owned copy of the marshallable fields of a `CubeMelonTaskResult`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WireResult {
+    pub output_json: String,
+    pub output_data: Option<WireValue>,
+    pub status: u32,
+    pub error_code: i32,
+    pub completion_time_us: i64,
+    pub progress_ratio: f64,
+    pub progress_message: String,
+    pub progress_stage: String,
+    pub estimated_remaining_us: u64,
+}
+
+impl WireResult {
+    /// Copy a result
+    pub fn from_result(result: &CubeMelonTaskResult) -> Result<Self, CubeMelonPluginErrorCode> {
+        let output_data = match result.output_data.is_null() {
+            true => None,
+            false => Some(unsafe { WireValue::from_value(&*result.output_data)? }),
+        };
+        Ok(Self {
+            output_json: text(&result.output_json),
+            output_data,
+            status: result.status as u32,
+            error_code: result.error_code as i32,
+            completion_time_us: result.completion_time_us,
+            progress_ratio: if result.progress_ratio.is_finite() { result.progress_ratio } else { -1.0 },
+            progress_message: text(&result.progress_message),
+            progress_stage: text(&result.progress_stage),
+            estimated_remaining_us: result.estimated_remaining_us,
+        })
+    }
+
+    /// Fill a caller's result with host-allocated copies
+    ///
+    /// The caller releases them as it would a plugin's result.
+    pub fn fill(&self, result: &mut CubeMelonTaskResult) {
+        let string = |s: &str| match s.is_empty() {
+            true => CubeMelonString::empty(),
+            false => CubeMelonString::from_string(s.to_string()),
+        };
+        result.output_json = string(&self.output_json);
+        result.output_data = match &self.output_data {
+            Some(value) => Box::into_raw(Box::new(value.to_value())),
+            None => std::ptr::null_mut(),
+        };
+        result.status = execution_status_from_u32(self.status);
+        result.error_code = error_code_from_i32(self.error_code);
+        result.completion_time_us = self.completion_time_us;
+        result.progress_ratio = self.progress_ratio;
+        result.progress_message = string(&self.progress_message);
+        result.progress_stage = string(&self.progress_stage);
+        result.estimated_remaining_us = self.estimated_remaining_us;
+    }
+}
+
+/// Basic plugin information as returned by `get_all_plugins_basic_info`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WirePluginInfo {
+    pub uuid: String,
+    pub version: String,
+    pub supported_types: u64,
+    pub name: String,
+    pub description: String,
+}
+
+/// Runtime to plugin host
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum HostMessage {
+    /// Create and initialize an instance
+    Create { id: u64, instance: Option<String> },
+    /// Uninitialize and destroy an instance
+    Destroy { id: u64, instance: Option<String> },
+    /// Initialize and release a throwaway instance (the `run` command)
+    Run { id: u64 },
+    /// Execute a task, through the AsyncTask interface if `asynchronous`
+    Execute { id: u64, instance: Option<String>, asynchronous: bool, request: WireRequest },
+    /// Answer to a `PluginMessage::Call`
+    Reply { id: u64, code: i32, data: ReplyData },
+    /// Destroy every instance and exit
+    Shutdown,
+}
+
+/// Plugin host to runtime
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PluginMessage {
+    /// The library is loaded and requests can be sent
+    Ready,
+    /// The library could not be loaded; the host exits
+    Failed { code: i32, message: String },
+    /// Log record of the plugin, made in the trace `trace_id` (0 = none)
+    Log {
+        level: String,
+        source: String,
+        message: String,
+        #[serde(default)]
+        trace_id: u64,
+    },
+    /// Manager interface call made by the plugin
+    Call { id: u64, call: HostCall },
+    /// Answer to a `HostMessage`
+    Done { id: u64, code: i32, result: Option<WireResult> },
+}
+
+/// Manager interface methods a plugin host forwards to the runtime
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum HostCall {
+    GetAllPluginsBasicInfo { language: String },
+    GetPluginDetailedInfo { uuid: String, language: String },
+    FindPluginsForTask { task_json: String },
+    IsPluginAlive { uuid: String },
+    ExecuteTask { uuid: String, request: WireRequest },
+}
+
+/// Output of a forwarded call
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum ReplyData {
+    #[default]
+    None,
+    Plugins(Vec<WirePluginInfo>),
+    Text(String),
+    Uuids(Vec<String>),
+    Alive(bool),
+    Result(WireResult),
+}
+
+/// One line read from the other side
+#[derive(Debug, PartialEq)]
+pub enum Frame<M> {
+    Message(M),
+    /// Anything else the plugin printed to stdout
+    Text(String),
+}
+
+/// Write a message as one line
+pub fn write_message<W: Write, M: Serialize>(writer: &mut W, message: &M) -> io::Result<()> {
+    let mut line = serde_json::to_string(message).map_err(io::Error::other)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    writer.flush()
+}
+
+/// Read the next line; None at end of stream
+pub fn read_frame<R: BufRead, M: DeserializeOwned>(reader: &mut R) -> io::Result<Option<Frame<M>>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let line = line.trim_end_matches(['\r', '\n']);
+    Ok(Some(match serde_json::from_str(line) {
+        Ok(message) => Frame::Message(message),
+        Err(_) => Frame::Text(line.to_string()),
+    }))
+}
+
+/// CubeMelonPluginErrorCode by numeric value (`Unknown` if there is none)
+pub(crate) fn error_code_from_i32(code: i32) -> CubeMelonPluginErrorCode {
+    use CubeMelonPluginErrorCode as E;
+    match code {
+        0 => E::Success,
+        -2 => E::InvalidParameter,
+        -3 => E::NotSupported,
+        -4 => E::MemoryAllocation,
+        -5 => E::NullPointer,
+        -6 => E::OutOfBounds,
+        -7 => E::InvalidState,
+        -8 => E::PermissionDenied,
+        -9 => E::ResourceBusy,
+        -10 => E::ResourceExhausted,
+        -20 => E::InitializationFailed,
+        -21 => E::AlreadyInitialized,
+        -22 => E::NotInitialized,
+        -23 => E::VersionMismatch,
+        -24 => E::Incompatible,
+        -30 => E::PluginNotFound,
+        -31 => E::InterfaceNotSupported,
+        -32 => E::NotImplemented,
+        -33 => E::PluginLoadFailed,
+        -34 => E::PluginUnloadFailed,
+        -35 => E::IntegrityCheckFailed,
+        -40 => E::ConnectionFailed,
+        -41 => E::Timeout,
+        -42 => E::IO,
+        -43 => E::Network,
+        -44 => E::Cancelled,
+        -50 => E::Parse,
+        -51 => E::Validation,
+        -52 => E::Encoding,
+        -53 => E::DataCorrupted,
+        -54 => E::FormatUnsupported,
+        -60 => E::LockFailed,
+        -61 => E::Deadlock,
+        -62 => E::State,
+        -63 => E::ThreadPanic,
+        -70 => E::FileNotFound,
+        -71 => E::FileExists,
+        -72 => E::DirectoryNotEmpty,
+        -73 => E::DiskFull,
+        _ => E::Unknown,
+    }
+}
+
+fn execution_status_from_u32(status: u32) -> CubeMelonExecutionStatus {
+    use CubeMelonExecutionStatus as S;
+    match status {
+        0 => S::Idle,
+        1 => S::Running,
+        2 => S::Suspended,
+        3 => S::Completed,
+        5 => S::Cancelled,
+        _ => S::Error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cubemelon_sdk::CubeMelonLanguage;
+
+    #[test]
+    fn test_task_round_trip() {
+        let nested = CubeMelonValue::array(vec![
+            CubeMelonValue::null(),
+            CubeMelonValue::bool(true),
+            CubeMelonValue::int(-7),
+            CubeMelonValue::uint(7),
+            CubeMelonValue::float(0.5),
+            CubeMelonValue::string("テキスト".to_string()),
+            CubeMelonValue::buffer(vec![0, 1, 255]),
+        ]);
+        let mut input = CubeMelonValue::array(vec![nested]);
+        let request = CubeMelonTaskRequest::new(
+            std::ptr::null(),
+            &mut input,
+            CubeMelonString::from_static_str("{\"a\":1}\0"),
+            CubeMelonTaskType::Image,
+            CubeMelonLanguage::JA_JP,
+            10,
+            20,
+        );
+
+        let wire = WireRequest::from_request(&request).unwrap();
+        let json = serde_json::to_string(&wire).unwrap();
+        let rebuilt = serde_json::from_str::<WireRequest>(&json).unwrap().to_request();
+        assert_eq!(WireRequest::from_request(&rebuilt.0).unwrap(), wire);
+        assert_eq!(rebuilt.0.task_type, CubeMelonTaskType::Image);
+        assert_eq!(rebuilt.0.language.as_str(), "ja-JP");
+        unsafe { input.free_value.unwrap()(&mut input) };
+
+        let mut output = CubeMelonValue::pointer(std::ptr::null_mut());
+        let mut result = CubeMelonTaskResult::empty();
+        result.output_data = &mut output;
+        assert_eq!(WireResult::from_result(&result), Err(CubeMelonPluginErrorCode::FormatUnsupported));
+
+        let wire = WireResult {
+            output_json: "{}".into(),
+            output_data: Some(WireValue::String("done".into())),
+            status: CubeMelonExecutionStatus::Error as u32,
+            error_code: CubeMelonPluginErrorCode::Timeout as i32,
+            completion_time_us: 30,
+            progress_ratio: 1.0,
+            progress_message: String::new(),
+            progress_stage: "finished".into(),
+            estimated_remaining_us: 0,
+        };
+        let mut result = CubeMelonTaskResult::empty();
+        wire.fill(&mut result);
+        assert_eq!(result.status, CubeMelonExecutionStatus::Error);
+        assert_eq!(result.error_code, CubeMelonPluginErrorCode::Timeout);
+        assert_eq!(WireResult::from_result(&result).unwrap(), wire);
+        crate::async_task::free_task_result(&mut result);
+    }
+
+    #[test]
+    fn test_message_framing() {
+        let messages = [
+            PluginMessage::Log {
+                level: "INFO".into(),
+                source: "Example".into(),
+                message: "two\nlines".into(),
+                trace_id: 7,
+            },
+            PluginMessage::Call { id: 4, call: HostCall::IsPluginAlive { uuid: "x".into() } },
+            PluginMessage::Done { id: 5, code: -63, result: None },
+        ];
+        let mut stream = Vec::new();
+        write_message(&mut stream, &messages[0]).unwrap();
+        stream.extend_from_slice(b"printed by the plugin\n");
+        write_message(&mut stream, &messages[1]).unwrap();
+        write_message(&mut stream, &messages[2]).unwrap();
+
+        let mut reader = io::Cursor::new(stream);
+        let mut frames = Vec::new();
+        while let Some(frame) = read_frame::<_, PluginMessage>(&mut reader).unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(
+            frames,
+            vec![
+                Frame::Message(messages[0].clone()),
+                Frame::Text("printed by the plugin".into()),
+                Frame::Message(messages[1].clone()),
+                Frame::Message(messages[2].clone()),
+            ]
+        );
+        assert_eq!(error_code_from_i32(-63), CubeMelonPluginErrorCode::ThreadPanic);
+        assert_eq!(error_code_from_i32(12345), CubeMelonPluginErrorCode::Unknown);
+    }
+}
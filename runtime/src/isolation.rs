@@ -0,0 +1,735 @@
+//! Plugin Isolation
+//!
+//! Plugins listed in the `[isolation]` section run in a plugin host process
+//! (see `plugin_host`) instead of being loaded into the runtime, so a crash
+//! takes down only that process:
+//!
+//! ```toml
+//! [isolation]
+//! plugins = ["Single Task Plugin"]   # UUID or name
+//! max_restarts = 3                   # consecutive crashes before giving up
+//! ```
+//!
+//! The manager interface treats isolated plugins like any other: tasks, async
+//! tasks and instances are forwarded to the plugin host, and the plugin's own
+//! log and manager calls come back as messages. When the host process dies it
+//! is restarted for the next call; the call that was running fails with
+//! `ThreadPanic`, and the plugin's instances start over. A synchronous task
+//! that overruns its timeout gets `Timeout`, and its host process is killed
+//! and restarted the same way. The plugin file is verified (see `integrity`)
+//! before every restart; a file that fails the check is not started again,
+//! and later calls get `IntegrityCheckFailed`. Resident services and the State
+//! interface are only available to plugins loaded in process, so a plugin
+//! providing either cannot be isolated and fails to load until it is removed
+//! from `[isolation]`.
+//!
+//! A plugin host serves one request at a time. A plugin calling back into an
+//! isolated plugin that is busy serving it gets `ResourceBusy` instead of a
+//! deadlock. Manager calls from a plugin host are run on the thread owning
+//! the runtime, whenever it waits: for a plugin host's answer, for a task
+//! with a timeout, for an `exec` result, or at the prompt. In-process plugin
+//! code running on other threads reaches the runtime the same way (see
+//! `run_on_runtime_thread`).
+
+use std::cell::Cell;
+use std::io::BufReader;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, TryLockError};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use cubemelon_sdk::{
+    CubeMelonExecutionStatus, CubeMelonLogLevel, CubeMelonPluginErrorCode, CubeMelonPluginManagerInterface,
+    CubeMelonPluginType, CubeMelonString, CubeMelonTaskRequest, CubeMelonTaskResult, CubeMelonUUID, CubeMelonUUIDArray, CubeMelonPluginBasicInfoArray,
+};
+
+use crate::async_task::{free_task_result, now_us};
+use crate::host_services::{
+    current_caller, enter_plugin, is_runtime_owner, parse_language, runtime_log, with_runtime, with_runtime_mut,
+};
+use crate::ipc::{
+    error_code_from_i32, read_frame, write_message, Frame, HostCall, HostMessage, PluginMessage, ReplyData,
+    WirePluginInfo, WireRequest, WireResult,
+};
+use crate::integrity::IntegrityCheck;
+use crate::loader::{poll_until, PluginRejection};
+use crate::logging;
+use crate::manifest::parse_uuid;
+use crate::plugin_host::PLUGIN_HOST_ARG;
+use crate::policy::names_plugin;
+use crate::trace;
+use crate::{PluginInfo, RuntimeData};
+
+/// How long a plugin host may take to exit after `Shutdown`
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often a waiting runtime thread serves forwarded calls
+pub(crate) const CALL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Plugin types a plugin host cannot serve
+const IN_PROCESS_ONLY_TYPES: u64 = CubeMelonPluginType::Resident as u64 | CubeMelonPluginType::State as u64;
+
+thread_local! {
+    /// Set while serving a call made by a plugin host
+    static SERVING_PLUGIN_HOST: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Manager call made by a plugin host, waiting for the runtime's thread
+struct ForwardedCall {
+    uuid: CubeMelonUUID,
+    id: u64,
+    call: HostCall,
+    stdin: Arc<Mutex<ChildStdin>>,
+}
+
+/// Work waiting for the thread owning the runtime
+type Job = Box<dyn FnOnce() + Send>;
+
+type CallQueue = (Sender<Job>, Mutex<Receiver<Job>>);
+
+fn forwarded_calls() -> &'static CallQueue {
+    static QUEUE: OnceLock<CallQueue> = OnceLock::new();
+    QUEUE.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        (tx, Mutex::new(rx))
+    })
+}
+
+impl ForwardedCall {
+    /// Run the call and send the plugin host its reply
+    fn serve(self) {
+        let serving = SERVING_PLUGIN_HOST.with(|serving| serving.replace(true));
+        let (code, data) = {
+            let _caller = enter_plugin(self.uuid);
+            handle_call(self.call)
+        };
+        SERVING_PLUGIN_HOST.with(|flag| flag.set(serving));
+        let reply = HostMessage::Reply { id: self.id, code: code as i32, data };
+        let _ = write_message(&mut *self.stdin.lock().unwrap_or_else(|e| e.into_inner()), &reply);
+    }
+}
+
+/// Serve the calls plugin hosts and worker threads are waiting on
+///
+/// Does nothing except on the thread owning the runtime.
+pub(crate) fn serve_forwarded_calls() {
+    if !is_runtime_owner() {
+        return;
+    }
+    loop {
+        let next = forwarded_calls().1.lock().unwrap_or_else(|e| e.into_inner()).try_recv();
+        match next {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+/// Run `f` on the thread owning the runtime and wait for its result
+///
+/// The calling plugin and trace span carry over. None if the call was
+/// dropped without running.
+pub(crate) fn run_on_runtime_thread<R>(f: impl FnOnce() -> R) -> Option<R> {
+    let (done, result) = mpsc::channel();
+    let caller = current_caller();
+    let span = trace::current();
+    let job: Box<dyn FnOnce() + '_> = Box::new(move || {
+        let _caller = caller.map(enter_plugin);
+        let _active = trace::enter(span);
+        let _ = done.send(f());
+    });
+    // SAFETY: this thread blocks until the job has run or been dropped (which
+    // disconnects `result`), so nothing it borrows is used meanwhile
+    let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + '_>, Job>(job) };
+    forwarded_calls().0.send(job).ok()?;
+    result.recv().ok()
+}
+
+/// Receive from a channel until `deadline`, serving forwarded calls meanwhile
+pub(crate) fn recv_serving_calls<T>(rx: &Receiver<T>, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+    if !is_runtime_owner() {
+        return match deadline {
+            Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+    }
+    loop {
+        serve_forwarded_calls();
+        let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match rx.recv_timeout(left.map_or(CALL_POLL_INTERVAL, |left| left.min(CALL_POLL_INTERVAL))) {
+            Err(RecvTimeoutError::Timeout) if left.is_none_or(|left| left > CALL_POLL_INTERVAL) => continue,
+            received => return received,
+        }
+    }
+}
+
+/// Running plugin host process
+struct Connection {
+    child: Child,
+    stdin: Arc<Mutex<ChildStdin>>,
+    /// `Ready`, `Failed` and `Done` messages, in order
+    replies: Receiver<PluginMessage>,
+    next_id: u64,
+}
+
+impl Connection {
+    /// Spawn a plugin host and wait until it has loaded the library
+    fn spawn(plugin_info: &PluginInfo, language: &str) -> Result<Self> {
+        let exe = std::env::current_exe().context("Cannot locate the runtime executable")?;
+        let mut child = Command::new(exe)
+            .arg(PLUGIN_HOST_ARG)
+            .arg(&plugin_info.path)
+            .arg(language)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to start plugin host process")?;
+        let stdin = Arc::new(Mutex::new(child.stdin.take().expect("piped stdin")));
+        let stdout = child.stdout.take().expect("piped stdout");
+
+        let (tx, replies) = mpsc::channel();
+        let reader_stdin = Arc::clone(&stdin);
+        let (uuid, name) = (plugin_info.uuid, plugin_info.name.clone());
+        std::thread::Builder::new()
+            .name(format!("cubemelon-host-{}", uuid))
+            .spawn(move || serve_plugin_host(uuid, &name, BufReader::new(stdout), reader_stdin, tx))
+            .context("Failed to start plugin host reader")?;
+
+        let mut connection = Self { child, stdin, replies, next_id: 1 };
+        match recv_serving_calls(&connection.replies, None) {
+            Ok(PluginMessage::Ready) => Ok(connection),
+            Ok(PluginMessage::Failed { code, message }) => {
+                let _ = connection.child.wait();
+                Err(PluginRejection { code: error_code_from_i32(code), message }.into())
+            }
+            _ => {
+                let status = connection.child.wait().context("Plugin host vanished")?;
+                Err(anyhow!("Plugin host exited during startup ({})", status))
+            }
+        }
+    }
+
+    /// Send a request and wait for its answer, at most `timeout`
+    fn request(
+        &mut self,
+        timeout: Option<Duration>,
+        message: impl FnOnce(u64) -> HostMessage,
+    ) -> Result<(CubeMelonPluginErrorCode, Option<WireResult>), Interrupted> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let written = write_message(&mut *self.stdin.lock().unwrap_or_else(|e| e.into_inner()), &message(id));
+        if written.is_err() {
+            return Err(Interrupted::Died);
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let reply = recv_serving_calls(&self.replies, deadline).map_err(|e| match e {
+                RecvTimeoutError::Timeout => Interrupted::TimedOut,
+                RecvTimeoutError::Disconnected => Interrupted::Died,
+            })?;
+            if let PluginMessage::Done { id: done, code, result } = reply {
+                if done == id {
+                    return Ok((error_code_from_i32(code), result));
+                }
+            }
+        }
+    }
+
+    /// Exit status once the process has died
+    fn exit_status(&mut self) -> Option<ExitStatus> {
+        self.child.try_wait().ok().flatten()
+    }
+
+    /// Ask the process to exit, killing it if it does not
+    fn shutdown(mut self) {
+        let _ = write_message(&mut *self.stdin.lock().unwrap_or_else(|e| e.into_inner()), &HostMessage::Shutdown);
+        if !poll_until(SHUTDOWN_TIMEOUT, || self.exit_status().is_some()) {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// Why a request got no answer
+enum Interrupted {
+    Died,
+    TimedOut,
+}
+
+/// Plugin running in a plugin host process
+pub struct IsolatedPlugin {
+    info: PluginInfo,
+    language: String,
+    max_restarts: u32,
+    /// Checks the plugin file passes before each restart
+    integrity: IntegrityCheck,
+    /// None once the plugin crashed too often or failed the integrity check
+    connection: Mutex<Option<Connection>>,
+    /// Crashes since the last successful request
+    crashes: AtomicU32,
+    /// Set once the plugin file failed the integrity check on a restart
+    refused: AtomicBool,
+}
+
+impl IsolatedPlugin {
+    /// Start the plugin host for a plugin whose file was just verified
+    pub fn spawn(info: &PluginInfo, language: &str, max_restarts: u32, integrity: IntegrityCheck) -> Result<Self> {
+        let connection = Connection::spawn(info, language)?;
+        runtime_log(
+            CubeMelonLogLevel::Info,
+            &format!("Started plugin host for {} (pid {})", info.name, connection.child.id()),
+        );
+        Ok(Self {
+            info: info.clone(),
+            language: language.to_string(),
+            max_restarts,
+            integrity,
+            connection: Mutex::new(Some(connection)),
+            crashes: AtomicU32::new(0),
+            refused: AtomicBool::new(false),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Option<Connection>>, CubeMelonPluginErrorCode> {
+        if !SERVING_PLUGIN_HOST.with(Cell::get) {
+            return Ok(self.wait_for_connection());
+        }
+        // Waiting here could wait on the very plugin host asking
+        match self.connection.try_lock() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(e)) => Ok(e.into_inner()),
+            Err(TryLockError::WouldBlock) => {
+                runtime_log(CubeMelonLogLevel::Warn, &format!("Isolated plugin {} is busy", self.info.name));
+                Err(CubeMelonPluginErrorCode::ResourceBusy)
+            }
+        }
+    }
+
+    /// Lock the connection, waiting for the request holding it
+    ///
+    /// That request may wait on a forwarded call, so the runtime's thread
+    /// keeps serving them meanwhile.
+    fn wait_for_connection(&self) -> MutexGuard<'_, Option<Connection>> {
+        loop {
+            match self.connection.try_lock() {
+                Ok(guard) => return guard,
+                Err(TryLockError::Poisoned(e)) => return e.into_inner(),
+                Err(TryLockError::WouldBlock) if is_runtime_owner() => {
+                    serve_forwarded_calls();
+                    std::thread::sleep(CALL_POLL_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => return self.connection.lock().unwrap_or_else(|e| e.into_inner()),
+            }
+        }
+    }
+
+    /// Send a request, restarting the plugin host if it died or overran `timeout`
+    fn request(
+        &self,
+        timeout: Option<Duration>,
+        message: impl FnOnce(u64) -> HostMessage,
+    ) -> (CubeMelonPluginErrorCode, Option<WireResult>) {
+        let mut guard = match self.lock() {
+            Ok(guard) => guard,
+            Err(code) => return (code, None),
+        };
+        // The process may have died between requests
+        if let Some(status) = guard.as_mut().and_then(Connection::exit_status) {
+            self.recover(&mut guard, status);
+        }
+        let Some(connection) = guard.as_mut() else {
+            if self.refused.load(Ordering::Relaxed) {
+                return (CubeMelonPluginErrorCode::IntegrityCheckFailed, None);
+            }
+            return (CubeMelonPluginErrorCode::PluginLoadFailed, None);
+        };
+
+        match connection.request(timeout, message) {
+            Ok(answer) => {
+                self.crashes.store(0, Ordering::Relaxed);
+                answer
+            }
+            Err(Interrupted::TimedOut) => {
+                self.restart(&mut guard);
+                (CubeMelonPluginErrorCode::Timeout, None)
+            }
+            Err(Interrupted::Died) => {
+                let status = connection.child.wait();
+                match status {
+                    Ok(status) => self.recover(&mut guard, status),
+                    Err(_) => *guard = None,
+                }
+                (CubeMelonPluginErrorCode::ThreadPanic, None)
+            }
+        }
+    }
+
+    /// Kill a plugin host that overran a timeout and start a fresh one
+    ///
+    /// Unlike a crash, this does not count towards `max_restarts`.
+    fn restart(&self, connection: &mut Option<Connection>) {
+        if let Some(mut stuck) = connection.take() {
+            let _ = stuck.child.kill();
+            let _ = stuck.child.wait();
+        }
+        runtime_log(
+            CubeMelonLogLevel::Warn,
+            &format!("Plugin host for {} timed out; its instances are lost", self.info.name),
+        );
+        self.respawn(connection);
+    }
+
+    /// Replace a dead plugin host, unless it crashed too often
+    fn recover(&self, connection: &mut Option<Connection>, status: ExitStatus) {
+        *connection = None;
+        let crashes = self.crashes.fetch_add(1, Ordering::Relaxed) + 1;
+        runtime_log(
+            CubeMelonLogLevel::Error,
+            &format!("Plugin host for {} crashed ({}); its instances are lost", self.info.name, status),
+        );
+        if crashes > self.max_restarts {
+            runtime_log(
+                CubeMelonLogLevel::Error,
+                &format!("Giving up on {} after {} crashes in a row", self.info.name, crashes),
+            );
+            return;
+        }
+        self.respawn(connection);
+    }
+
+    /// Verify the plugin file again and start a fresh plugin host
+    ///
+    /// A file that fails the check is given up on.
+    fn respawn(&self, connection: &mut Option<Connection>) {
+        if let Err(e) = self.integrity.verify(&self.info.path) {
+            self.refused.store(true, Ordering::Relaxed);
+            runtime_log(
+                CubeMelonLogLevel::Error,
+                &format!("Not restarting plugin host for {}: {:#}", self.info.name, e),
+            );
+            return;
+        }
+        match Connection::spawn(&self.info, &self.language) {
+            Ok(restarted) => {
+                runtime_log(
+                    CubeMelonLogLevel::Info,
+                    &format!("Restarted plugin host for {} (pid {})", self.info.name, restarted.child.id()),
+                );
+                *connection = Some(restarted);
+            }
+            Err(e) => runtime_log(
+                CubeMelonLogLevel::Error,
+                &format!("Failed to restart plugin host for {}: {:#}", self.info.name, e),
+            ),
+        }
+    }
+
+    /// Whether the plugin host is running
+    pub fn is_alive(&self) -> bool {
+        match self.connection.try_lock() {
+            Ok(mut guard) => guard.as_mut().is_some_and(|c| c.exit_status().is_none()),
+            // Busy serving a request
+            Err(TryLockError::WouldBlock) => true,
+            Err(TryLockError::Poisoned(_)) => false,
+        }
+    }
+
+    /// Execute a task on an instance in the plugin host
+    ///
+    /// `asynchronous` picks the plugin's AsyncTask interface (default instance)
+    /// or a dedicated SingleTask instance, as `execute_async_task` does.
+    pub fn execute(
+        &self,
+        instance: Option<&str>,
+        asynchronous: bool,
+        request: &CubeMelonTaskRequest,
+        result: &mut CubeMelonTaskResult,
+    ) -> CubeMelonPluginErrorCode {
+        let mut request = match WireRequest::from_request(request) {
+            Ok(request) => request,
+            Err(code) => return code,
+        };
+        // Synchronous calls are bounded by their timeout (see `watchdog`)
+        let timeout = (!asynchronous && request.timeout_us > 0).then(|| {
+            if request.request_time_us <= 0 {
+                request.request_time_us = now_us();
+            }
+            Duration::from_micros(request.timeout_us as u64)
+        });
+        let instance = instance.map(str::to_string);
+        let (code, wire) = self.request(timeout, |id| HostMessage::Execute { id, instance, asynchronous, request });
+        if code == CubeMelonPluginErrorCode::Timeout && wire.is_none() {
+            result.status = CubeMelonExecutionStatus::Error;
+            result.error_code = code;
+        }
+        if let Some(wire) = wire {
+            wire.fill(result);
+        }
+        code
+    }
+
+    pub fn create_instance(&self, name: Option<&str>) -> CubeMelonPluginErrorCode {
+        let instance = name.map(str::to_string);
+        self.request(None, |id| HostMessage::Create { id, instance }).0
+    }
+
+    pub fn destroy_instance(&self, name: Option<&str>) -> CubeMelonPluginErrorCode {
+        let instance = name.map(str::to_string);
+        self.request(None, |id| HostMessage::Destroy { id, instance }).0
+    }
+
+    /// Initialize and release a throwaway instance
+    pub fn run_lifecycle(&self) -> CubeMelonPluginErrorCode {
+        self.request(None, |id| HostMessage::Run { id }).0
+    }
+
+    /// Stop the plugin host
+    pub fn shutdown(&self) {
+        let connection = self.wait_for_connection().take();
+        if let Some(connection) = connection {
+            connection.shutdown();
+            runtime_log(CubeMelonLogLevel::Info, &format!("Stopped plugin host for {}", self.info.name));
+        }
+    }
+}
+
+/// Read a plugin host's output until it exits
+///
+/// Log records are handled right here. Manager calls need the runtime, so
+/// they are queued for its thread (see `serve_forwarded_calls`).
+fn serve_plugin_host(
+    uuid: CubeMelonUUID,
+    name: &str,
+    mut stdout: BufReader<std::process::ChildStdout>,
+    stdin: Arc<Mutex<ChildStdin>>,
+    replies: Sender<PluginMessage>,
+) {
+    while let Ok(Some(frame)) = read_frame::<_, PluginMessage>(&mut stdout) {
+        match frame {
+            Frame::Message(PluginMessage::Log { level, source, message, trace_id }) => {
+                let level = logging::parse_level(&level).unwrap_or(CubeMelonLogLevel::Info);
+                logging::log_traced(level, &source, &message, trace_id);
+            }
+            Frame::Message(PluginMessage::Call { id, call }) => {
+                let forwarded = ForwardedCall { uuid, id, call, stdin: Arc::clone(&stdin) };
+                let _ = forwarded_calls().0.send(Box::new(move || forwarded.serve()));
+            }
+            Frame::Message(message) => {
+                let _ = replies.send(message);
+            }
+            Frame::Text(line) => logging::log(CubeMelonLogLevel::Info, name, &line),
+        }
+    }
+}
+
+/// Run a manager call forwarded by a plugin host
+fn handle_call(call: HostCall) -> (CubeMelonPluginErrorCode, ReplyData) {
+    let not_initialized = (CubeMelonPluginErrorCode::NotInitialized, ReplyData::None);
+    match call {
+        HostCall::GetAllPluginsBasicInfo { language } => with_runtime(|runtime| {
+            let mut infos = CubeMelonPluginBasicInfoArray::empty();
+            let code = runtime.get_all_plugins_basic_info(parse_language(&language), &mut infos);
+            let plugins = unsafe { infos.as_slice() }
+                .iter()
+                .map(|info| WirePluginInfo {
+                    uuid: info.uuid.to_string(),
+                    version: info.version.to_string(),
+                    supported_types: info.supported_types,
+                    name: info.name.as_str().unwrap_or_default().to_string(),
+                    description: info.description.as_str().unwrap_or_default().to_string(),
+                })
+                .collect();
+            if let Some(free_fn) = infos.free_info_array {
+                unsafe { free_fn(infos.infos, infos.count) };
+            }
+            (code, ReplyData::Plugins(plugins))
+        }),
+        HostCall::GetPluginDetailedInfo { uuid, language } => with_runtime(|runtime| {
+            let Some(uuid) = parse_uuid(&uuid) else {
+                return (CubeMelonPluginErrorCode::InvalidParameter, ReplyData::None);
+            };
+            let mut json = CubeMelonString::empty();
+            let code = runtime.get_plugin_detailed_info(uuid, parse_language(&language), &mut json);
+            let text = json.as_str().unwrap_or_default().to_string();
+            if let (Some(free_fn), false) = (json.free_string, json.str.is_null()) {
+                unsafe { free_fn(json.str) };
+            }
+            (code, ReplyData::Text(text))
+        }),
+        HostCall::FindPluginsForTask { task_json } => with_runtime(|runtime| {
+            let task_json = format!("{}\0", task_json);
+            let mut uuids = CubeMelonUUIDArray::empty();
+            let code = runtime.find_plugins_for_task(task_json.as_ptr(), &mut uuids);
+            let found = unsafe { uuids.as_slice() }.iter().map(|uuid| uuid.to_string()).collect();
+            if let Some(free_fn) = uuids.free_uuid_array {
+                unsafe { free_fn(uuids.uuids, uuids.count) };
+            }
+            (code, ReplyData::Uuids(found))
+        }),
+        HostCall::IsPluginAlive { uuid } => with_runtime(|runtime| {
+            let alive = parse_uuid(&uuid).is_some_and(|uuid| runtime.is_plugin_alive(uuid));
+            (CubeMelonPluginErrorCode::Success, ReplyData::Alive(alive))
+        }),
+        HostCall::ExecuteTask { uuid, request } => with_runtime_mut(|runtime| {
+            let Some(uuid) = parse_uuid(&uuid) else {
+                return (CubeMelonPluginErrorCode::InvalidParameter, ReplyData::None);
+            };
+            let request = request.to_request();
+            let mut result = CubeMelonTaskResult::empty();
+            let code = runtime.execute_task(uuid, &request.0, &mut result);
+            let wire = WireResult::from_result(&result);
+            free_task_result(&mut result);
+            match wire {
+                Ok(wire) => (code, ReplyData::Result(wire)),
+                Err(marshal_code) if code == CubeMelonPluginErrorCode::Success => (marshal_code, ReplyData::None),
+                Err(_) => (code, ReplyData::None),
+            }
+        }),
+    }
+    .unwrap_or(not_initialized)
+}
+
+impl RuntimeData {
+    /// Whether the [isolation] section names a plugin
+    pub(crate) fn is_isolated(&self, plugin_info: &PluginInfo) -> bool {
+        self.config.isolation.plugins.iter().any(|entry| names_plugin(entry, plugin_info))
+    }
+
+    /// Loaded plugin running in a plugin host, if it is one
+    pub(crate) fn isolated_plugin(&self, uuid: CubeMelonUUID) -> Option<Arc<IsolatedPlugin>> {
+        self.isolated_plugins.get(&uuid).cloned()
+    }
+
+    /// Whether a plugin is loaded, in process or in a plugin host
+    pub fn is_loaded(&self, uuid: CubeMelonUUID) -> bool {
+        self.loaded_libraries.contains_key(&uuid) || self.isolated_plugins.contains_key(&uuid)
+    }
+
+    /// Start a plugin host for an isolated plugin
+    pub(crate) fn spawn_isolated(&mut self, plugin_info: &PluginInfo) -> Result<()> {
+        if plugin_info.supported_types & IN_PROCESS_ONLY_TYPES != 0 {
+            let message = format!(
+                "Plugin '{}' provides a Resident service or the State interface and cannot run in a plugin host; \
+                 remove it from [isolation] to load it in process",
+                plugin_info.name
+            );
+            runtime_log(CubeMelonLogLevel::Error, &message);
+            return Err(anyhow!(message));
+        }
+        let language = self.system_language.as_str().to_string();
+        let integrity = self.integrity_check();
+        let plugin = IsolatedPlugin::spawn(plugin_info, &language, self.config.isolation.max_restarts, integrity)?;
+        self.isolated_plugins.insert(plugin_info.uuid, Arc::new(plugin));
+        self.load_order.push(plugin_info.uuid);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_selects_isolated_plugins() {
+        let config: crate::RuntimeConfig = toml::from_str(
+            r#"
+            [settings]
+            plugins_directory = "plugins"
+            language = "auto"
+
+            [isolation]
+            plugins = ["Fragile", "02020202-0202-0202-0202-020202020202"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.isolation.max_restarts, 3);
+
+        let plugin = |id: u8, name: &str| PluginInfo {
+            uuid: CubeMelonUUID::from_bytes([id; 16]),
+            version: cubemelon_sdk::CubeMelonVersion::new(1, 0, 0),
+            supported_types: 0,
+            name: name.to_string(),
+            description: String::new(),
+            path: std::path::PathBuf::from(name),
+            capabilities: Default::default(),
+            dependencies: Vec::new(),
+        };
+        let isolated = |info: &PluginInfo| config.isolation.plugins.iter().any(|entry| names_plugin(entry, info));
+        assert!(isolated(&plugin(1, "Fragile")));
+        assert!(isolated(&plugin(2, "Other")));
+        assert!(!isolated(&plugin(3, "Sturdy")));
+    }
+
+    #[test]
+    fn test_worker_calls_run_on_runtime_thread() {
+        // This test's thread plays the one owning the runtime
+        crate::host_services::set_runtime_singleton(std::ptr::null_mut());
+        assert!(is_runtime_owner());
+        let uuid = CubeMelonUUID::from_bytes([7; 16]);
+
+        let worker = std::thread::spawn(move || {
+            let _caller = enter_plugin(uuid);
+            run_on_runtime_thread(|| (std::thread::current().id(), current_caller()))
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !worker.is_finished() && Instant::now() < deadline {
+            serve_forwarded_calls();
+            std::thread::sleep(CALL_POLL_INTERVAL);
+        }
+        assert_eq!(worker.join().unwrap(), Some((std::thread::current().id(), Some(uuid))));
+    }
+
+    #[test]
+    fn test_resident_and_state_plugins_are_not_isolated() {
+        let mut runtime = RuntimeData::for_tests();
+        for (id, plugin_type) in [(5, CubeMelonPluginType::Resident), (6, CubeMelonPluginType::State)] {
+            let info = PluginInfo {
+                uuid: CubeMelonUUID::from_bytes([id; 16]),
+                version: cubemelon_sdk::CubeMelonVersion::new(1, 0, 0),
+                supported_types: CubeMelonPluginType::Basic as u64 | plugin_type as u64,
+                name: "Service".to_string(),
+                description: String::new(),
+                path: std::path::PathBuf::from("service"),
+                capabilities: Default::default(),
+                dependencies: Vec::new(),
+            };
+            let error = runtime.spawn_isolated(&info).unwrap_err();
+            assert!(error.to_string().contains("[isolation]"));
+            assert!(!runtime.is_loaded(info.uuid));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tampered_plugin_is_not_restarted() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let path = std::env::temp_dir().join(format!("cubemelon-isolation-{}.so", std::process::id()));
+        std::fs::write(&path, b"tampered").unwrap();
+        let mut runtime = RuntimeData::for_tests();
+        runtime.config.integrity.sha256.insert(path.display().to_string(), crate::integrity::sha256_hex(b"original"));
+
+        let plugin = IsolatedPlugin {
+            info: PluginInfo {
+                uuid: CubeMelonUUID::from_bytes([4; 16]),
+                version: cubemelon_sdk::CubeMelonVersion::new(1, 0, 0),
+                supported_types: 0,
+                name: "Fragile".to_string(),
+                description: String::new(),
+                path: path.clone(),
+                capabilities: Default::default(),
+                dependencies: Vec::new(),
+            },
+            language: "en-US".to_string(),
+            max_restarts: 3,
+            integrity: runtime.integrity_check(),
+            connection: Mutex::new(None),
+            crashes: AtomicU32::new(0),
+            refused: AtomicBool::new(false),
+        };
+        plugin.recover(&mut plugin.connection.lock().unwrap(), ExitStatus::from_raw(139));
+        assert!(plugin.connection.lock().unwrap().is_none());
+        assert_eq!(plugin.create_instance(None), CubeMelonPluginErrorCode::IntegrityCheckFailed);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -2,76 +2,290 @@
 // Note: Keep comments in English per repository guidelines.
 
 use anyhow::{anyhow, Context, Result};
+use std::fmt;
 use libloading::Library;
-use std::path::{PathBuf};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use cubemelon_sdk::{
-    CubeMelonInterface, CubeMelonPlugin, CubeMelonPluginErrorCode, CubeMelonLogLevel,
+    CubeMelonUUID, CubeMelonInterface, CubeMelonPlugin, CubeMelonPluginErrorCode, CubeMelonLogLevel, CubeMelonPluginType,
+    CubeMelonVersion, SDK_VERSION, check_plugin_compatibility,
 };
 
-use crate::host_services::runtime_log;
+use crate::async_task;
+use crate::dependency;
+use crate::discovery_cache::DiscoveryCache;
+use crate::isolation;
+use crate::manifest;
+use crate::metrics;
+use crate::host_services::{runtime_log, enter_plugin};
+use crate::matcher::PluginCapabilities;
+use crate::search;
 use crate::{PluginInfo, RuntimeData};
 
-impl RuntimeData {
-    /// Scan plugins directory for valid plugins
-    pub fn scan_plugins(&mut self) -> Result<()> {
-        let plugins_dir = self.get_plugins_directory();
-        runtime_log(CubeMelonLogLevel::Info, &format!("Scanning plugins directory: {:?}", plugins_dir));
+/// C ABI signature of the exported `get_plugin_interface` function
+pub(crate) type GetPluginInterfaceFn =
+    unsafe extern "C" fn(u64, u32, *mut *const std::ffi::c_void) -> CubeMelonPluginErrorCode;
+/// C ABI signature of the exported `create_plugin` function
+pub(crate) type CreatePluginFn = unsafe extern "C" fn() -> *mut CubeMelonPlugin;
+/// C ABI signature of the exported `destroy_plugin` function
+pub(crate) type DestroyPluginFn = unsafe extern "C" fn(*mut CubeMelonPlugin);
+/// C ABI signature of the optional `get_plugin_capabilities` export
+type GetPluginCapabilitiesFn = unsafe extern "C" fn() -> *const u8;
+/// C ABI signature of the exported `can_unload_now` function
+type CanUnloadNowFn = unsafe extern "C" fn() -> bool;
+
+/// How long `unload_plugin` waits for a plugin to report it can be unloaded
+const UNLOAD_POLL_TIMEOUT: Duration = Duration::from_millis(500);
+/// Interval between `can_unload_now` polls
+const UNLOAD_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Result of an unload request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnloadOutcome {
+    /// The library has been closed
+    Unloaded,
+    /// The plugin is still in use; the library is closed once it reports it can be unloaded
+    Deferred,
+}
 
-        if !plugins_dir.exists() {
-            runtime_log(
-                CubeMelonLogLevel::Warn,
-                &format!(
-                    "Plugins directory does not exist, creating: {:?}",
-                    plugins_dir
-                ),
-            );
-            std::fs::create_dir_all(&plugins_dir)?;
-            return Ok(());
+/// Plugin file refused during validation
+#[derive(Debug, Clone)]
+pub struct PluginRejection {
+    pub code: CubeMelonPluginErrorCode,
+    pub message: String,
+}
+
+impl fmt::Display for PluginRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for PluginRejection {}
+
+/// Plugin file skipped by the last scan
+#[derive(Debug, Clone)]
+pub struct RejectedPlugin {
+    pub path: PathBuf,
+    pub code: CubeMelonPluginErrorCode,
+    pub reason: String,
+}
+
+/// Error code for a validation or load failure
+pub(crate) fn rejection_code(error: &anyhow::Error) -> CubeMelonPluginErrorCode {
+    error
+        .downcast_ref::<PluginRejection>()
+        .map(|rejection| rejection.code)
+        .unwrap_or(CubeMelonPluginErrorCode::PluginLoadFailed)
+}
+
+/// C ABI signature of the exported `get_plugin_sdk_version` function
+type GetPluginSdkVersionFn = unsafe extern "C" fn() -> CubeMelonVersion;
+
+/// Check the SDK version a loaded library reports, before calling anything else
+pub(crate) fn check_sdk_version(library: &Library, plugin_path: &Path) -> Result<()> {
+    let get_sdk_version = unsafe {
+        library
+            .get::<GetPluginSdkVersionFn>(b"get_plugin_sdk_version")
+            .context("Plugin missing get_plugin_sdk_version function")?
+    };
+    check_sdk_compatibility(unsafe { get_sdk_version() }, plugin_path)
+}
+
+/// Reject plugins built against an SDK whose ABI the host does not speak
+pub(crate) fn check_sdk_compatibility(plugin_sdk: CubeMelonVersion, plugin_path: &Path) -> Result<()> {
+    if !check_plugin_compatibility(plugin_sdk, SDK_VERSION) {
+        return Err(PluginRejection {
+            code: CubeMelonPluginErrorCode::Incompatible,
+            message: format!(
+                "Plugin {:?} was built with SDK {}, which is incompatible with host SDK {} (major versions must match)",
+                plugin_path, plugin_sdk, SDK_VERSION
+            ),
         }
+        .into());
+    }
+    Ok(())
+}
 
-        let entries = std::fs::read_dir(&plugins_dir)?;
+/// Raw entry points exported by a plugin library
+///
+/// The function pointers stay valid for as long as the owning `Library` is loaded.
+#[derive(Clone, Copy)]
+pub(crate) struct PluginEntryPoints {
+    pub get_plugin_interface: GetPluginInterfaceFn,
+    pub create_plugin: CreatePluginFn,
+    pub destroy_plugin: DestroyPluginFn,
+}
 
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
+impl PluginEntryPoints {
+    /// Resolve the required exports from a loaded library
+    pub fn resolve(library: &Library) -> Result<Self, CubeMelonPluginErrorCode> {
+        unsafe {
+            let get_plugin_interface = *library
+                .get::<GetPluginInterfaceFn>(b"get_plugin_interface")
+                .map_err(|_| CubeMelonPluginErrorCode::InterfaceNotSupported)?;
+            let create_plugin = *library
+                .get::<CreatePluginFn>(b"create_plugin")
+                .map_err(|_| CubeMelonPluginErrorCode::PluginLoadFailed)?;
+            let destroy_plugin = *library
+                .get::<DestroyPluginFn>(b"destroy_plugin")
+                .map_err(|_| CubeMelonPluginErrorCode::PluginUnloadFailed)?;
+            Ok(Self { get_plugin_interface, create_plugin, destroy_plugin })
+        }
+    }
 
-            if !path.is_file() {
-                continue;
-            }
+    /// Query an interface table by plugin type
+    ///
+    /// Returns `None` if the plugin does not provide the interface. Tables are
+    /// static in the plugin, so querying them on every call does not allocate.
+    pub fn get_interface<T>(&self, interface_type: CubeMelonPluginType) -> Option<&'static T> {
+        let mut ptr: *const std::ffi::c_void = std::ptr::null();
+        let rc = unsafe {
+            (self.get_plugin_interface)(interface_type as u64, 1, &mut ptr as *mut *const std::ffi::c_void)
+        };
+        if rc != CubeMelonPluginErrorCode::Success || ptr.is_null() {
+            return None;
+        }
+        Some(unsafe { &*(ptr as *const T) })
+    }
+}
 
-            // Check file extension (platform-specific)
-            #[cfg(windows)]
-            let is_library = path.extension() == Some(std::ffi::OsStr::new("dll"));
-            #[cfg(target_os = "macos")]
-            let is_library = path.extension() == Some(std::ffi::OsStr::new("dylib"));
-            #[cfg(all(unix, not(target_os = "macos")))]
-            let is_library = path.extension() == Some(std::ffi::OsStr::new("so"));
+/// Plugin library held open by the runtime, with its entry points resolved
+pub struct PluginLibrary {
+    library: Library,
+    entry_points: PluginEntryPoints,
+    /// None if the plugin was built without the export
+    can_unload_now: Option<CanUnloadNowFn>,
+}
+
+impl PluginLibrary {
+    /// Resolve the entry points of an opened library
+    fn open(library: Library) -> Result<Self, CubeMelonPluginErrorCode> {
+        let entry_points = PluginEntryPoints::resolve(&library)?;
+        let can_unload_now = unsafe { library.get::<CanUnloadNowFn>(b"can_unload_now") }.ok().map(|f| *f);
+        Ok(Self { library, entry_points, can_unload_now })
+    }
+
+    /// Stand-in for a plugin library: the test binary, with the given entry points
+    #[cfg(test)]
+    pub(crate) fn fake(entry_points: PluginEntryPoints, can_unload_now: Option<CanUnloadNowFn>) -> Self {
+        #[cfg(unix)]
+        let library = libloading::os::unix::Library::this().into();
+        #[cfg(windows)]
+        let library = libloading::os::windows::Library::this().expect("test binary handle").into();
+        Self { library, entry_points, can_unload_now }
+    }
+}
 
-            if !is_library {
+impl RuntimeData {
+    /// Scan the plugin search paths for valid plugins
+    pub fn scan_plugins(&mut self) -> Result<()> {
+        let plugins_dir = self.get_plugins_directory();
+        let search_paths = self.plugin_search_paths();
+        self.rejected_plugins.clear();
+        self.shadowed_plugins.clear();
+        self.disabled_plugins.clear();
+
+        for dir in &search_paths {
+            runtime_log(CubeMelonLogLevel::Info, &format!("Scanning plugins directory: {:?}", dir));
+            if dir.exists() {
                 continue;
             }
+            if *dir == plugins_dir {
+                runtime_log(
+                    CubeMelonLogLevel::Warn,
+                    &format!(
+                        "Plugins directory does not exist, creating: {:?}",
+                        plugins_dir
+                    ),
+                );
+                std::fs::create_dir_all(&plugins_dir)?;
+            } else {
+                runtime_log(CubeMelonLogLevel::Warn, &format!("Plugin search path does not exist: {:?}", dir));
+            }
+        }
 
-            match self.validate_and_extract_info(&path) {
-                Ok(plugin_info) => {
-                    runtime_log(
-                        CubeMelonLogLevel::Info,
-                        &format!(
-                            "Found valid plugin: {} ({})",
-                            plugin_info.name, plugin_info.uuid
-                        ),
-                    );
-                    self.discovered_plugins.push(plugin_info);
+        let files = search::find_plugin_files(&search_paths, self.plugin_search_depth());
+        // The cache lives next to the configuration file (none without one)
+        let mut cache = Some(&self.config_path)
+            .filter(|config_path| !config_path.as_os_str().is_empty())
+            .map(|config_path| DiscoveryCache::load(DiscoveryCache::path_for_config(config_path), self.system_language.as_str()));
+        let seen: HashSet<PathBuf> = files.iter().cloned().collect();
+        let mut reused = 0;
+        let mut candidates = Vec::new();
+
+        for path in files {
+            let cached = cache.as_mut().and_then(|cache| cache.lookup(&path));
+            let validated = match cached {
+                Some(plugin_info) => {
+                    reused += 1;
+                    self.verify_plugin_file(&path).map(|()| plugin_info)
                 }
+                None => self.validate_and_extract_info(&path).inspect(|plugin_info| {
+                    if let Some(Err(e)) = cache.as_mut().map(|cache| cache.insert(plugin_info)) {
+                        runtime_log(CubeMelonLogLevel::Warn, &format!("Failed to cache {:?}: {}", path, e));
+                    }
+                }),
+            };
+
+            match validated {
+                Ok(plugin_info) => candidates.push(plugin_info),
                 Err(e) => {
                     runtime_log(
                         CubeMelonLogLevel::Warn,
                         &format!("Invalid plugin at {:?}: {}", path, e),
                     );
+                    self.rejected_plugins.push(RejectedPlugin { code: rejection_code(&e), reason: e.to_string(), path });
                 }
             }
         }
 
+        let (allowed, disabled) = self.config.plugins.apply(candidates);
+        for excluded in &disabled {
+            runtime_log(
+                CubeMelonLogLevel::Info,
+                &format!(
+                    "Skipping plugin {} v{} at {:?}: {}",
+                    excluded.info.name, excluded.info.version, excluded.info.path, excluded.reason
+                ),
+            );
+        }
+        let (selected, shadowed) = search::select_plugins(allowed);
+        for plugin_info in &selected {
+            runtime_log(
+                CubeMelonLogLevel::Info,
+                &format!(
+                    "Found valid plugin: {} ({})",
+                    plugin_info.name, plugin_info.uuid
+                ),
+            );
+        }
+        for hidden in &shadowed {
+            runtime_log(
+                CubeMelonLogLevel::Warn,
+                &format!(
+                    "Plugin {} v{} at {:?} is shadowed by {:?}",
+                    hidden.uuid, hidden.version, hidden.path, hidden.shadowed_by
+                ),
+            );
+        }
+        self.discovered_plugins = selected;
+        self.shadowed_plugins = shadowed;
+        self.disabled_plugins = disabled;
+
+        if let Some(cache) = cache.as_mut() {
+            cache.retain(&seen);
+            if let Err(e) = cache.save() {
+                runtime_log(CubeMelonLogLevel::Warn, &format!("Failed to save discovery cache: {}", e));
+            }
+            runtime_log(
+                CubeMelonLogLevel::Debug,
+                &format!("Reused cached metadata for {} of {} plugin files", reused, seen.len()),
+            );
+        }
+
         runtime_log(
             CubeMelonLogLevel::Info,
             &format!("Found {} valid plugins", self.discovered_plugins.len()),
@@ -80,10 +294,34 @@ impl RuntimeData {
     }
 
     /// Validate plugin and extract basic information
+    ///
+    /// The file must pass the integrity checks first. The embedded manifest
+    /// is used when present; otherwise the library is loaded and queried.
     pub fn validate_and_extract_info(&self, plugin_path: &PathBuf) -> Result<PluginInfo> {
+        self.verify_plugin_file(plugin_path)?;
+
+        match manifest::read_manifest(plugin_path, self.system_language.as_str()) {
+            Ok(Some(plugin_info)) => {
+                runtime_log(CubeMelonLogLevel::Debug, &format!("Read manifest of {:?}", plugin_path));
+                return Ok(plugin_info);
+            }
+            Ok(None) => runtime_log(
+                CubeMelonLogLevel::Debug,
+                &format!("No manifest in {:?}, loading it to read metadata", plugin_path),
+            ),
+            Err(e) if e.downcast_ref::<PluginRejection>().is_some() => return Err(e),
+            Err(e) => runtime_log(
+                CubeMelonLogLevel::Warn,
+                &format!("Ignoring manifest of {:?}: {:#}", plugin_path, e),
+            ),
+        }
+
         // Load library temporarily
         let library = unsafe { Library::new(plugin_path).context("Failed to load plugin library")? };
 
+        // Nothing else may be called before the ABI handshake succeeds
+        check_sdk_version(&library, plugin_path)?;
+
         // Get required functions
         let get_plugin_interface: libloading::Symbol<
             unsafe extern "C" fn(u64, u32, *mut *const std::ffi::c_void) -> CubeMelonPluginErrorCode,
@@ -151,6 +389,9 @@ impl RuntimeData {
         // Clean up
         unsafe { destroy_plugin(plugin) };
 
+        let capabilities = declared_capabilities(&library).context("Invalid capability declaration")?;
+        let dependencies = dependency::read_dependencies(&library);
+
         Ok(PluginInfo {
             uuid,
             version,
@@ -158,13 +399,15 @@ impl RuntimeData {
             description,
             supported_types,
             path: plugin_path.clone(),
+            capabilities,
+            dependencies,
         })
     }
 
-    /// Load a plugin by name, UUID, or number
-    pub fn load_plugin(&mut self, plugin_id: &str) -> Result<&PluginInfo> {
+    /// Find a discovered plugin by name, UUID, or number
+    pub fn resolve_plugin_id(&self, plugin_id: &str) -> Result<PluginInfo> {
         // Try to parse as number first
-        let plugin_info = if let Ok(index) = plugin_id.parse::<usize>() {
+        if let Ok(index) = plugin_id.parse::<usize>() {
             if index == 0 || index > self.discovered_plugins.len() {
                 return Err(anyhow!(
                     "Invalid plugin number: {}. Valid range: 1-{}",
@@ -172,30 +415,91 @@ impl RuntimeData {
                     self.discovered_plugins.len()
                 ));
             }
-            self.discovered_plugins[index - 1].clone()
+            Ok(self.discovered_plugins[index - 1].clone())
         } else {
             // Find plugin by name or UUID
-            self.discovered_plugins
+            Ok(self.discovered_plugins
                 .iter()
                 .find(|p| p.name == plugin_id || p.uuid.to_string() == plugin_id)
                 .ok_or_else(|| anyhow!("Plugin not found: {}", plugin_id))?
-                .clone()
-        };
+                .clone())
+        }
+    }
+
+    /// Resolve the entry points of a loaded plugin
+    pub(crate) fn entry_points(&self, uuid: CubeMelonUUID) -> Result<PluginEntryPoints, CubeMelonPluginErrorCode> {
+        match self.loaded_libraries.get(&uuid) {
+            Some(library) => Ok(library.entry_points),
+            None if self.isolated_plugins.contains_key(&uuid) => {
+                // Only the manager interface reaches into a plugin host
+                runtime_log(CubeMelonLogLevel::Debug, &format!("Plugin runs in a plugin host: {}", uuid));
+                Err(CubeMelonPluginErrorCode::NotSupported)
+            }
+            None => {
+                runtime_log(CubeMelonLogLevel::Error, &format!("Plugin not loaded: {}", uuid));
+                Err(CubeMelonPluginErrorCode::PluginNotFound)
+            }
+        }
+    }
+
+    /// Load a plugin by name, UUID, or number
+    ///
+    /// Declared dependencies are loaded first.
+    pub fn load_plugin(&mut self, plugin_id: &str) -> Result<&PluginInfo> {
+        let plugin_info = self.resolve_plugin_id(plugin_id)?;
+
+        let order = dependency::load_order(&self.discovered_plugins, plugin_info.uuid)?;
+        for uuid in order {
+            if uuid == plugin_info.uuid {
+                self.load_library(&plugin_info)?;
+                continue;
+            }
+            let required = self.discovered_plugins.iter().find(|p| p.uuid == uuid).cloned();
+            if let Some(required) = required {
+                self.load_library(&required)
+                    .with_context(|| format!("Failed to load dependency '{}' of '{}'", required.name, plugin_info.name))?;
+            }
+        }
+
+        Ok(self
+            .discovered_plugins
+            .iter()
+            .find(|p| p.uuid == plugin_info.uuid)
+            .unwrap())
+    }
 
-        if self.loaded_libraries.contains_key(&plugin_info.uuid) {
+    /// Load a single plugin library
+    fn load_library(&mut self, plugin_info: &PluginInfo) -> Result<()> {
+        if self.is_loaded(plugin_info.uuid) {
             runtime_log(CubeMelonLogLevel::Info, &format!("Plugin already loaded: {}", plugin_info.name));
-            return Ok(self
-                .discovered_plugins
-                .iter()
-                .find(|p| p.uuid == plugin_info.uuid)
-                .unwrap());
+            return Ok(());
+        }
+
+        // A deferred unload has not closed the library yet; take it back
+        if let Some(library) = self.pending_unloads.remove(&plugin_info.uuid) {
+            runtime_log(CubeMelonLogLevel::Info, &format!("Cancelled deferred unload: {}", plugin_info.name));
+            self.loaded_libraries.insert(plugin_info.uuid, library);
+            self.load_order.push(plugin_info.uuid);
+            return Ok(());
         }
 
         runtime_log(CubeMelonLogLevel::Info, &format!("Loading plugin: {}", plugin_info.name));
         runtime_log(CubeMelonLogLevel::Info, &format!("Plugin path: {:?}", plugin_info.path));
 
+        let started = Instant::now();
+        // Check the file again; it may have changed since the scan
+        self.verify_plugin_file(&plugin_info.path)?;
+
+        if self.is_isolated(plugin_info) {
+            self.spawn_isolated(plugin_info)?;
+            metrics::record_load(plugin_info.uuid, started.elapsed());
+            runtime_log(CubeMelonLogLevel::Info, &format!("Plugin loaded in a plugin host: {}", plugin_info.name));
+            return Ok(());
+        }
+
         // Load library
         let library = unsafe { Library::new(&plugin_info.path).context("Failed to load plugin library")? };
+        check_sdk_version(&library, &plugin_info.path)?;
         runtime_log(CubeMelonLogLevel::Info, "Plugin library loaded successfully");
 
         // Get interface
@@ -214,24 +518,33 @@ impl RuntimeData {
             return Err(anyhow!("Failed to get plugin interface: {:?}", result));
         }
 
+        let library = PluginLibrary::open(library)
+            .map_err(|code| anyhow!("Plugin missing required exports: {:?}", code))?;
+
         // Store loaded library
         self.loaded_libraries.insert(plugin_info.uuid, library);
+        self.load_order.push(plugin_info.uuid);
+        metrics::record_load(plugin_info.uuid, started.elapsed());
 
         runtime_log(CubeMelonLogLevel::Info, &format!("Plugin loaded successfully: {}", plugin_info.name));
-
-        Ok(self
-            .discovered_plugins
-            .iter()
-            .find(|p| p.uuid == plugin_info.uuid)
-            .unwrap())
+        Ok(())
     }
 
     /// Execute a plugin
     pub fn execute_plugin(&self, plugin_info: &PluginInfo) -> Result<()> {
-        let library = self
+        if let Some(isolated) = self.isolated_plugin(plugin_info.uuid) {
+            runtime_log(CubeMelonLogLevel::Info, &format!("Executing isolated plugin: {}", plugin_info.name));
+            return match isolated.run_lifecycle() {
+                CubeMelonPluginErrorCode::Success => Ok(()),
+                code => Err(anyhow!("Plugin initialization failed: {:?}", code)),
+            };
+        }
+
+        let library = &self
             .loaded_libraries
             .get(&plugin_info.uuid)
-            .ok_or_else(|| anyhow!("Plugin not loaded: {}", plugin_info.name))?;
+            .ok_or_else(|| anyhow!("Plugin not loaded: {}", plugin_info.name))?
+            .library;
 
         runtime_log(CubeMelonLogLevel::Info, &format!("Executing plugin: {}", plugin_info.name));
 
@@ -265,6 +578,7 @@ impl RuntimeData {
         };
 
         // Create instance
+        let _caller = enter_plugin(plugin_info.uuid);
         let instance = unsafe { create_plugin() };
         if instance.is_null() {
             return Err(anyhow!("Failed to create plugin instance"));
@@ -279,9 +593,6 @@ impl RuntimeData {
         }
         runtime_log(CubeMelonLogLevel::Info, "Plugin initialization completed successfully");
 
-        println!("Plugin '{}' executed successfully!", plugin_info.name);
-        println!("Description: {}", plugin_info.description);
-
         // Uninitialize
         runtime_log(CubeMelonLogLevel::Info, "Uninitializing plugin instance...");
         let uninit_result = (interface.uninitialize)(instance);
@@ -300,9 +611,16 @@ impl RuntimeData {
         Ok(())
     }
 
+    /// Register a fake plugin library as loaded
+    #[cfg(test)]
+    pub(crate) fn load_fake_plugin(&mut self, uuid: CubeMelonUUID, library: PluginLibrary) {
+        self.loaded_libraries.insert(uuid, library);
+        self.load_order.push(uuid);
+    }
+
     /// List all discovered plugins
     pub fn list_plugins(&self) {
-        if self.discovered_plugins.is_empty() {
+        if self.discovered_plugins.is_empty() && self.disabled_plugins.is_empty() {
             println!("No plugins found.");
             return;
         }
@@ -311,6 +629,10 @@ impl RuntimeData {
         for (i, plugin) in self.discovered_plugins.iter().enumerate() {
             let status = if self.loaded_libraries.contains_key(&plugin.uuid) {
                 "loaded"
+            } else if self.isolated_plugins.contains_key(&plugin.uuid) {
+                "isolated"
+            } else if self.pending_unloads.contains_key(&plugin.uuid) {
+                "unloading"
             } else {
                 "discovered"
             };
@@ -320,5 +642,303 @@ impl RuntimeData {
             println!("     Version: {}", plugin.version);
             println!("     UUID: {}", plugin.uuid);
         }
+
+        for disabled in &self.disabled_plugins {
+            println!("  -. {} v{} [{}]", disabled.info.name, disabled.info.version, disabled.reason);
+        }
+    }
+
+    /// Unload a plugin library
+    ///
+    /// Loaded plugins that depend on it are unloaded first. Destroys every
+    /// live instance of the plugin, then polls `can_unload_now`. If the plugin
+    /// still reports live objects (or host worker threads still run its code),
+    /// the library is kept open and closed later by `retry_pending_unloads`.
+    /// An isolated plugin's plugin host is stopped instead.
+    pub fn unload_plugin(&mut self, uuid: CubeMelonUUID) -> Result<UnloadOutcome, CubeMelonPluginErrorCode> {
+        if !self.is_loaded(uuid) {
+            return Err(CubeMelonPluginErrorCode::PluginNotFound);
+        }
+        for dependent in dependency::dependents_to_unload(&self.discovered_plugins, &self.load_order, uuid) {
+            runtime_log(CubeMelonLogLevel::Info, &format!("Unloading {} first; it depends on {}", dependent, uuid));
+            let _ = self.unload_plugin(dependent);
+        }
+
+        if let Some(isolated) = self.isolated_plugins.remove(&uuid) {
+            self.load_order.retain(|loaded| *loaded != uuid);
+            runtime_log(CubeMelonLogLevel::Info, &format!("Unloading plugin: {}", uuid));
+            // The plugin host destroys its instances on the way out
+            isolated.shutdown();
+            runtime_log(CubeMelonLogLevel::Info, &format!("Plugin unloaded: {}", uuid));
+            return Ok(UnloadOutcome::Unloaded);
+        }
+
+        let library = match self.loaded_libraries.remove(&uuid) {
+            Some(library) => library,
+            None => return Err(CubeMelonPluginErrorCode::PluginNotFound),
+        };
+        self.load_order.retain(|loaded| *loaded != uuid);
+
+        runtime_log(CubeMelonLogLevel::Info, &format!("Unloading plugin: {}", uuid));
+        self.destroy_plugin_instances(uuid);
+
+        if wait_until_unloadable(uuid, &library, UNLOAD_POLL_TIMEOUT) {
+            drop(library);
+            runtime_log(CubeMelonLogLevel::Info, &format!("Plugin unloaded: {}", uuid));
+            Ok(UnloadOutcome::Unloaded)
+        } else {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Plugin still in use, unload deferred: {}", uuid));
+            self.pending_unloads.insert(uuid, library);
+            Ok(UnloadOutcome::Deferred)
+        }
+    }
+
+    /// Close libraries whose deferred unload can complete now
+    pub fn retry_pending_unloads(&mut self) {
+        let ready: Vec<CubeMelonUUID> = self
+            .pending_unloads
+            .iter()
+            .filter(|(uuid, library)| is_unloadable(**uuid, library))
+            .map(|(uuid, _)| *uuid)
+            .collect();
+        for uuid in ready {
+            self.pending_unloads.remove(&uuid);
+            runtime_log(CubeMelonLogLevel::Info, &format!("Deferred unload completed: {}", uuid));
+        }
+    }
+}
+
+/// Capabilities declared through the optional `get_plugin_capabilities` export
+fn declared_capabilities(library: &Library) -> Result<PluginCapabilities> {
+    let toml = match unsafe { library.get::<GetPluginCapabilitiesFn>(b"get_plugin_capabilities") } {
+        Ok(get_plugin_capabilities) => unsafe { get_plugin_capabilities() },
+        Err(_) => std::ptr::null(),
+    };
+    if toml.is_null() {
+        return Ok(PluginCapabilities::default());
+    }
+    let toml = unsafe { std::ffi::CStr::from_ptr(toml as *const std::ffi::c_char) };
+    PluginCapabilities::from_toml(&toml.to_string_lossy())
+}
+
+/// Check file extension (platform-specific)
+pub(crate) fn is_plugin_library(path: &Path) -> bool {
+    path.extension() == Some(std::ffi::OsStr::new(library_extension()))
+}
+
+/// File extension of dynamic libraries on this platform
+pub(crate) fn library_extension() -> &'static str {
+    #[cfg(windows)]
+    let extension = "dll";
+    #[cfg(target_os = "macos")]
+    let extension = "dylib";
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let extension = "so";
+    extension
+}
+
+/// Whether nothing references the plugin's code any more
+fn is_unloadable(uuid: CubeMelonUUID, library: &PluginLibrary) -> bool {
+    if async_task::in_flight_count(uuid) > 0 {
+        return false;
+    }
+    // Plugins built without the export cannot tell; trust instance teardown
+    match library.can_unload_now {
+        Some(can_unload_now) => unsafe { can_unload_now() },
+        None => true,
+    }
+}
+
+/// Poll `is_unloadable` until it holds or the timeout expires
+fn wait_until_unloadable(uuid: CubeMelonUUID, library: &PluginLibrary, timeout: Duration) -> bool {
+    poll_until(timeout, || is_unloadable(uuid, library))
+}
+
+/// Evaluate `condition` every `UNLOAD_POLL_INTERVAL` until it holds or `timeout` expires
+pub(crate) fn poll_until(timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if condition() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        // Tasks being waited for may need the runtime (see `isolation`)
+        isolation::serve_forwarded_calls();
+        std::thread::sleep(UNLOAD_POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::OnceLock;
+    use cubemelon_sdk::{
+        create_plugin_instance, create_single_task_interface, destroy_plugin_instance, CubeMelonLanguage,
+        CubeMelonSingleTaskInterface, CubeMelonSingleTaskInterfaceImpl, CubeMelonString, CubeMelonTaskRequest,
+        CubeMelonTaskResult, CubeMelonTaskType,
+    };
+
+    unsafe extern "C" fn no_interface(
+        _plugin_types: u64,
+        _interface_version: u32,
+        _interface: *mut *const std::ffi::c_void,
+    ) -> CubeMelonPluginErrorCode {
+        CubeMelonPluginErrorCode::InterfaceNotSupported
+    }
+
+    unsafe extern "C" fn no_instance() -> *mut CubeMelonPlugin {
+        std::ptr::null_mut()
+    }
+
+    unsafe extern "C" fn ignore_destroy(_plugin: *mut CubeMelonPlugin) {}
+
+    /// Plugin with no interfaces, only `can_unload_now`
+    fn fake_library(can_unload_now: Option<CanUnloadNowFn>) -> PluginLibrary {
+        let entry_points = PluginEntryPoints {
+            get_plugin_interface: no_interface,
+            create_plugin: no_instance,
+            destroy_plugin: ignore_destroy,
+        };
+        PluginLibrary::fake(entry_points, can_unload_now)
+    }
+
+    static OBJECTS_RELEASED: AtomicBool = AtomicBool::new(false);
+
+    unsafe extern "C" fn objects_released() -> bool {
+        OBJECTS_RELEASED.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_unload_waits_for_can_unload_now() {
+        let uuid = CubeMelonUUID::from_bytes([0xD1; 16]);
+        let mut runtime = RuntimeData::for_tests();
+        assert_eq!(runtime.unload_plugin(uuid), Err(CubeMelonPluginErrorCode::PluginNotFound));
+
+        runtime.load_fake_plugin(uuid, fake_library(Some(objects_released)));
+        assert_eq!(runtime.unload_plugin(uuid), Ok(UnloadOutcome::Deferred));
+        assert!(!runtime.loaded_libraries.contains_key(&uuid));
+        assert!(runtime.load_order.is_empty());
+        assert!(runtime.pending_unloads.contains_key(&uuid));
+
+        runtime.retry_pending_unloads();
+        assert!(runtime.pending_unloads.contains_key(&uuid));
+
+        OBJECTS_RELEASED.store(true, Ordering::SeqCst);
+        runtime.retry_pending_unloads();
+        assert!(runtime.pending_unloads.is_empty());
+
+        // Nothing holds the plugin now, so the next unload completes at once
+        runtime.load_fake_plugin(uuid, fake_library(Some(objects_released)));
+        assert_eq!(runtime.unload_plugin(uuid), Ok(UnloadOutcome::Unloaded));
+        assert!(runtime.pending_unloads.is_empty());
+    }
+
+    static TASK_RELEASED: AtomicBool = AtomicBool::new(false);
+
+    /// SingleTask plugin whose task runs until `TASK_RELEASED` is set
+    struct BlockingPlugin;
+
+    impl CubeMelonSingleTaskInterface for BlockingPlugin {
+        fn execute(
+            &mut self,
+            _request: &CubeMelonTaskRequest,
+            _result: &mut CubeMelonTaskResult,
+        ) -> CubeMelonPluginErrorCode {
+            while !TASK_RELEASED.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            CubeMelonPluginErrorCode::Success
+        }
+    }
+
+    static BASIC_VTABLE: OnceLock<CubeMelonInterface> = OnceLock::new();
+    static SINGLE_TASK_VTABLE: OnceLock<CubeMelonSingleTaskInterfaceImpl> = OnceLock::new();
+
+    unsafe extern "C" fn blocking_get_plugin_interface(
+        plugin_types: u64,
+        _interface_version: u32,
+        interface: *mut *const std::ffi::c_void,
+    ) -> CubeMelonPluginErrorCode {
+        if plugin_types == CubeMelonPluginType::Basic as u64 {
+            *interface = BASIC_VTABLE.get_or_init(CubeMelonInterface::new) as *const _ as *const std::ffi::c_void;
+            CubeMelonPluginErrorCode::Success
+        } else if plugin_types == CubeMelonPluginType::SingleTask as u64 {
+            *interface = SINGLE_TASK_VTABLE.get_or_init(create_single_task_interface::<BlockingPlugin>) as *const _ as *const std::ffi::c_void;
+            CubeMelonPluginErrorCode::Success
+        } else {
+            CubeMelonPluginErrorCode::InterfaceNotSupported
+        }
+    }
+
+    unsafe extern "C" fn blocking_create_plugin() -> *mut CubeMelonPlugin {
+        create_plugin_instance(BlockingPlugin)
+    }
+
+    unsafe extern "C" fn blocking_destroy_plugin(plugin: *mut CubeMelonPlugin) {
+        destroy_plugin_instance(plugin);
+    }
+
+    #[test]
+    fn test_unload_waits_for_tasks_in_flight() {
+        let uuid = CubeMelonUUID::from_bytes([0xD2; 16]);
+        let mut runtime = RuntimeData::for_tests();
+        let entry_points = PluginEntryPoints {
+            get_plugin_interface: blocking_get_plugin_interface,
+            create_plugin: blocking_create_plugin,
+            destroy_plugin: blocking_destroy_plugin,
+        };
+        // Without `can_unload_now` only the host's own bookkeeping counts
+        runtime.load_fake_plugin(uuid, PluginLibrary::fake(entry_points, None));
+
+        let request = CubeMelonTaskRequest::new(
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            CubeMelonString::empty(),
+            CubeMelonTaskType::Generic,
+            CubeMelonLanguage::EN_US,
+            0,
+            0,
+        );
+        let rc = async_task::execute_on_worker_thread(uuid, entry_points, runtime.host_services, &request, None);
+        assert_eq!(rc, CubeMelonPluginErrorCode::Success);
+
+        assert_eq!(runtime.unload_plugin(uuid), Ok(UnloadOutcome::Deferred));
+        runtime.retry_pending_unloads();
+        assert!(runtime.pending_unloads.contains_key(&uuid));
+
+        TASK_RELEASED.store(true, Ordering::SeqCst);
+        assert!(poll_until(Duration::from_secs(5), || async_task::in_flight_count(uuid) == 0));
+        runtime.retry_pending_unloads();
+        assert!(runtime.pending_unloads.is_empty());
+    }
+
+    #[test]
+    fn test_poll_until_succeeds_once_condition_holds() {
+        let mut calls = 0;
+        assert!(poll_until(Duration::from_secs(5), || {
+            calls += 1;
+            calls >= 3
+        }));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_poll_until_gives_up_after_timeout() {
+        let started = Instant::now();
+        assert!(!poll_until(Duration::from_millis(30), || false));
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_rejection_code_survives_context() {
+        let rejection = PluginRejection { code: CubeMelonPluginErrorCode::Incompatible, message: "old SDK".to_string() };
+        let error = anyhow::Error::from(rejection).context("Failed to load dependency");
+        assert_eq!(rejection_code(&error), CubeMelonPluginErrorCode::Incompatible);
+
+        let error = anyhow!("Plugin missing create_plugin function");
+        assert_eq!(rejection_code(&error), CubeMelonPluginErrorCode::PluginLoadFailed);
     }
 }
@@ -0,0 +1,411 @@
+//! Runtime Logging
+//!
+//! Collects log records from the runtime and from plugins (through the host
+//! log callback) and routes them to the configured sinks:
+//! - the console (stdout, or stderr in CLI mode)
+//! - an optional log file with size-based rotation
+//! - an in-memory ring buffer read by the `logs` REPL command
+//!
+//! Configured by the `[log]` section:
+//! ```toml
+//! [log]
+//! level = "info"                 # trace, debug, info, warn, error
+//! format = "json"                # "text" (default) or "json" (one object per line)
+//! file = "logs/cubemelon.log"    # relative to the executable directory
+//! max_file_size = 1048576        # rotate to cubemelon.log.1, .2, ... past this size
+//! max_files = 3
+//!
+//! [log.plugins]
+//! ResidentPlugin = "debug"       # per source; the runtime itself is "Runtime"
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use cubemelon_sdk::CubeMelonLogLevel;
+
+use crate::trace;
+use crate::{LogConfig, LogFormat};
+
+/// Send log lines to stderr instead of stdout (keeps CLI output machine-readable)
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Route console log lines to stderr
+pub fn set_log_to_stderr(enabled: bool) {
+    LOG_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Parse a level name such as "info" or "WARN"
+pub fn parse_level(name: &str) -> Option<CubeMelonLogLevel> {
+    match name.to_ascii_lowercase().as_str() {
+        "trace" => Some(CubeMelonLogLevel::Trace),
+        "debug" => Some(CubeMelonLogLevel::Debug),
+        "info" => Some(CubeMelonLogLevel::Info),
+        "warn" | "warning" => Some(CubeMelonLogLevel::Warn),
+        "error" => Some(CubeMelonLogLevel::Error),
+        _ => None,
+    }
+}
+
+/// One log message
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub timestamp: DateTime<Local>,
+    pub level: CubeMelonLogLevel,
+    /// "Runtime" or the name a plugin logs under
+    pub source: String,
+    pub message: String,
+    /// Trace of the call the record was made in (see `trace`)
+    pub trace_id: Option<u64>,
+}
+
+impl LogRecord {
+    pub fn new(level: CubeMelonLogLevel, source: &str, message: &str) -> Self {
+        Self { timestamp: Local::now(), level, source: source.to_string(), message: message.to_string(), trace_id: None }
+    }
+
+    /// Human-readable line
+    pub fn to_text(&self) -> String {
+        let trace = match self.trace_id {
+            Some(id) => format!(" (trace {})", trace::format_id(id)),
+            None => String::new(),
+        };
+        format!(
+            "{}> [{}] {}{}: {}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S:%.3f"),
+            self.level,
+            self.source,
+            trace,
+            self.message
+        )
+    }
+
+    /// Single-line JSON object
+    pub fn to_json(&self) -> String {
+        let mut value = serde_json::json!({
+            "timestamp": self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, false),
+            "level": self.level.to_string(),
+            "source": self.source,
+            "message": self.message,
+        });
+        if let Some(id) = self.trace_id {
+            value["trace_id"] = trace::format_id(id).into();
+        }
+        value.to_string()
+    }
+}
+
+/// Path of the `index`-th rotated log file (`name.log.1` is the newest)
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Log file that rotates once it would grow past `max_size`
+struct FileSink {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl FileSink {
+    fn open(path: PathBuf, max_size: u64, max_files: u32) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size, max_size, max_files })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Shift `name.N` to `name.N+1`, drop the oldest and start a new file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated_path(&self.path, self.max_files));
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Filters records and fans them out to the sinks
+pub struct Logger {
+    level: CubeMelonLogLevel,
+    source_levels: HashMap<String, CubeMelonLogLevel>,
+    format: LogFormat,
+    console: bool,
+    file: Option<FileSink>,
+    buffer: VecDeque<LogRecord>,
+    buffer_size: usize,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::from_config(&LogConfig::default(), Path::new(".")).0
+    }
+}
+
+impl Logger {
+    /// Build a logger; problems with the configuration are returned as warnings
+    pub fn from_config(config: &LogConfig, base_dir: &Path) -> (Self, Vec<String>) {
+        let mut warnings = Vec::new();
+
+        let level = parse_level(&config.level).unwrap_or_else(|| {
+            warnings.push(format!("Unknown log level '{}', using trace", config.level));
+            CubeMelonLogLevel::Trace
+        });
+        let mut source_levels = HashMap::new();
+        for (source, name) in &config.plugins {
+            match parse_level(name) {
+                Some(level) => {
+                    source_levels.insert(source.clone(), level);
+                }
+                None => warnings.push(format!("Unknown log level '{}' for {}, ignored", name, source)),
+            }
+        }
+
+        let file = config.file.as_ref().and_then(|file| {
+            let path = base_dir.join(file);
+            match FileSink::open(path.clone(), config.max_file_size, config.max_files) {
+                Ok(sink) => Some(sink),
+                Err(e) => {
+                    warnings.push(format!("Failed to open log file {:?}: {}", path, e));
+                    None
+                }
+            }
+        });
+
+        let logger = Self {
+            level,
+            source_levels,
+            format: config.format,
+            console: config.console,
+            file,
+            buffer: VecDeque::with_capacity(config.buffer_size.min(4096)),
+            buffer_size: config.buffer_size,
+        };
+        (logger, warnings)
+    }
+
+    /// Whether a record from `source` at `level` passes the filters
+    pub fn enabled(&self, level: CubeMelonLogLevel, source: &str) -> bool {
+        level >= self.source_levels.get(source).copied().unwrap_or(self.level)
+    }
+
+    /// Filter a record and write it to every sink
+    pub fn record(&mut self, record: LogRecord) {
+        if !self.enabled(record.level, &record.source) {
+            return;
+        }
+
+        let line = match self.format {
+            LogFormat::Text => record.to_text(),
+            LogFormat::Json => record.to_json(),
+        };
+        if self.console {
+            if LOG_TO_STDERR.load(Ordering::Relaxed) {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
+        }
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = file.write_line(&line) {
+                // Report once on the console and stop writing to the broken file
+                eprintln!("Failed to write log file {:?}: {}", file.path, e);
+                self.file = None;
+            }
+        }
+
+        self.buffer_record(record);
+    }
+
+    fn buffer_record(&mut self, record: LogRecord) {
+        if self.buffer_size == 0 {
+            return;
+        }
+        if self.buffer.len() >= self.buffer_size {
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back(record);
+    }
+
+    /// The last `count` buffered records, optionally from one source only
+    pub fn recent(&self, count: usize, source: Option<&str>) -> Vec<LogRecord> {
+        let mut records: Vec<LogRecord> = self
+            .buffer
+            .iter()
+            .rev()
+            .filter(|r| source.is_none_or(|s| r.source == s))
+            .take(count)
+            .cloned()
+            .collect();
+        records.reverse();
+        records
+    }
+}
+
+fn logger() -> &'static Mutex<Logger> {
+    static LOGGER: OnceLock<Mutex<Logger>> = OnceLock::new();
+    LOGGER.get_or_init(|| Mutex::new(Logger::default()))
+}
+
+/// Replace the active logger with one built from the configuration
+///
+/// Relative file paths are resolved against `base_dir`.
+pub fn configure(config: &LogConfig, base_dir: &Path) {
+    let (new_logger, warnings) = Logger::from_config(config, base_dir);
+    {
+        let mut active = logger().lock().unwrap_or_else(|e| e.into_inner());
+        let buffered = std::mem::take(&mut active.buffer);
+        *active = new_logger;
+        // Keep what was logged during startup visible to `logs`
+        for record in buffered {
+            active.buffer_record(record);
+        }
+    }
+    for warning in warnings {
+        log(CubeMelonLogLevel::Warn, "Runtime", &warning);
+    }
+}
+
+/// Log a message from `source`, in the trace active on this thread
+pub fn log(level: CubeMelonLogLevel, source: &str, message: &str) {
+    log_traced(level, source, message, trace::current_trace_id());
+}
+
+/// Log a message made in the trace `trace_id` (0 = none)
+pub fn log_traced(level: CubeMelonLogLevel, source: &str, message: &str, trace_id: u64) {
+    let mut active = logger().lock().unwrap_or_else(|e| e.into_inner());
+    if active.enabled(level, source) {
+        let mut record = LogRecord::new(level, source, message);
+        record.trace_id = (trace_id != 0).then_some(trace_id);
+        active.record(record);
+    }
+}
+
+/// The last `count` buffered records, optionally from one source only
+pub fn recent(count: usize, source: Option<&str>) -> Vec<LogRecord> {
+    logger().lock().unwrap_or_else(|e| e.into_inner()).recent(count, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet_config() -> LogConfig {
+        LogConfig { console: false, ..LogConfig::default() }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cubemelon-log-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_levels_filter_globally_and_per_source() {
+        let mut config = quiet_config();
+        config.level = "warn".to_string();
+        config.plugins.insert("Chatty".to_string(), "trace".to_string());
+        config.plugins.insert("Broken".to_string(), "loud".to_string());
+        let (logger, warnings) = Logger::from_config(&config, Path::new("."));
+
+        assert_eq!(warnings.len(), 1);
+        assert!(!logger.enabled(CubeMelonLogLevel::Info, "Runtime"));
+        assert!(logger.enabled(CubeMelonLogLevel::Error, "Runtime"));
+        assert!(logger.enabled(CubeMelonLogLevel::Trace, "Chatty"));
+        assert!(!logger.enabled(CubeMelonLogLevel::Info, "Broken"));
+        assert_eq!(parse_level("WARNING"), Some(CubeMelonLogLevel::Warn));
+        assert_eq!(parse_level("verbose"), None);
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_latest_records() {
+        let mut config = quiet_config();
+        config.buffer_size = 3;
+        let (mut logger, _) = Logger::from_config(&config, Path::new("."));
+        for i in 0..5 {
+            let source = if i % 2 == 0 { "Even" } else { "Odd" };
+            logger.record(LogRecord::new(CubeMelonLogLevel::Info, source, &i.to_string()));
+        }
+
+        let messages: Vec<String> = logger.recent(10, None).into_iter().map(|r| r.message).collect();
+        assert_eq!(messages, vec!["2", "3", "4"]);
+        let messages: Vec<String> = logger.recent(10, Some("Even")).into_iter().map(|r| r.message).collect();
+        assert_eq!(messages, vec!["2", "4"]);
+        assert_eq!(logger.recent(1, None)[0].message, "4");
+    }
+
+    #[test]
+    fn test_json_lines_are_valid_json() {
+        let record = LogRecord::new(CubeMelonLogLevel::Warn, "Plugin \"X\"", "line\nbreak");
+        let line = record.to_json();
+        assert!(!line.contains('\n'));
+
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["source"], "Plugin \"X\"");
+        assert_eq!(value["message"], "line\nbreak");
+        assert!(value.get("trace_id").is_none());
+
+        let mut traced = LogRecord::new(CubeMelonLogLevel::Info, "Runtime", "nested");
+        traced.trace_id = Some(0xabc);
+        let value: serde_json::Value = serde_json::from_str(&traced.to_json()).unwrap();
+        assert_eq!(value["trace_id"], "0000000000000abc");
+        assert!(traced.to_text().contains("Runtime (trace 0000000000000abc): nested"));
+    }
+
+    #[test]
+    fn test_file_sink_rotates_by_size() {
+        let dir = temp_dir("rotation");
+        let mut config = quiet_config();
+        config.file = Some("runtime.log".to_string());
+        config.max_file_size = 100;
+        config.max_files = 2;
+        let (mut logger, warnings) = Logger::from_config(&config, &dir);
+        assert!(warnings.is_empty());
+
+        for i in 0..12 {
+            logger.record(LogRecord::new(CubeMelonLogLevel::Info, "Runtime", &format!("message {}", i)));
+        }
+
+        let path = dir.join("runtime.log");
+        assert!(fs::metadata(&path).unwrap().len() <= 100);
+        assert!(rotated_path(&path, 1).exists());
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+        assert!(fs::read_to_string(&path).unwrap().contains("message 11"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -3,18 +3,19 @@
 //! A simple host application for loading and executing CubeMelon plugins.
 
 use anyhow::{Context, Result};
-use libloading::Library;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
 
 use cubemelon_sdk::{
     CubeMelonUUID, CubeMelonVersion, CubeMelonLanguage, CubeMelonHostServices, CubeMelonLogLevel,
     CubeMelonTaskRequest, CubeMelonTaskResult, CubeMelonTaskType, CubeMelonString, CubeMelonPluginErrorCode,
+    CubeMelonPluginDependency,
 };
-use cubemelon_sdk::CubeMelonPluginManagerInterface; // bring trait methods (execute_task, etc.) into scope
 
 mod host_services;
 use host_services::{
@@ -23,13 +24,66 @@ use host_services::{
 
 mod manager;
 mod state;
+mod state_store;
 mod loader;
+mod async_task;
+mod instances;
+mod matcher;
+mod reload;
+mod dependency;
+mod discovery_cache;
+mod manifest;
+mod logging;
+mod resident;
+mod search;
+mod policy;
+mod integrity;
+mod ipc;
+mod plugin_host;
+mod isolation;
+mod watchdog;
+mod metrics;
+mod trace;
+mod pipeline;
+mod cli;
 
 /// Top-level runtime configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuntimeConfig {
     /// Settings section containing host-level options
     pub settings: Settings,
+
+    /// Additional plugin search paths
+    #[serde(default)]
+    pub search: SearchConfig,
+
+    /// Which plugins may be used
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+
+    /// Hash pinning and signature verification of plugin files
+    #[serde(default)]
+    pub integrity: IntegrityConfig,
+
+    /// Plugins run in a separate plugin host process
+    #[serde(default)]
+    pub isolation: IsolationConfig,
+
+    /// Plugin directory watch mode
+    #[serde(default)]
+    pub watch: WatchConfig,
+
+    /// Resident services keyed by plugin UUID or name
+    #[serde(default)]
+    pub resident: BTreeMap<String, ResidentConfig>,
+
+    /// Log filtering and sinks
+    #[serde(default)]
+    pub log: LogConfig,
+
+    /// Task pipelines keyed by name
+    #[serde(default)]
+    pub pipeline: BTreeMap<String, PipelineConfig>,
 }
 
 /// [settings] section
@@ -56,9 +110,261 @@ impl Default for Settings {
     }
 }
 
+/// [search] section
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Directories searched after `settings.plugins_directory` (relative to executable)
+    pub paths: Vec<String>,
+
+    /// Levels of subdirectories scanned below each search path
+    pub max_depth: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            max_depth: 2,
+        }
+    }
+}
+
+/// [plugins] section
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginsConfig {
+    /// Whether plugins must be listed in `enabled` to be used
+    pub mode: PluginsMode,
+
+    /// Plugins allowed in allowlist mode (UUID or name)
+    pub enabled: Vec<String>,
+
+    /// Plugins never used (UUID or name)
+    pub disabled: Vec<String>,
+
+    /// Exact versions to use, keyed by plugin UUID or name
+    pub pinned: BTreeMap<String, String>,
+}
+
+/// Selection mode of the [plugins] section
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginsMode {
+    /// Every plugin not listed in `disabled`
+    #[default]
+    All,
+    /// Only plugins listed in `enabled`
+    Allowlist,
+}
+
+/// [integrity] section
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntegrityConfig {
+    /// Refuse plugin files that have neither a pinned hash nor a valid signature
+    pub required: bool,
+
+    /// Hex-encoded Ed25519 public keys accepted for `<file>.sig` signatures
+    pub trusted_keys: Vec<String>,
+
+    /// Expected SHA-256 (hex) keyed by plugin file path, relative to its search path or absolute
+    pub sha256: BTreeMap<String, String>,
+
+    /// Audit log of failed checks (relative to executable; next to the config file if omitted)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<String>,
+}
+
+/// [isolation] section
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IsolationConfig {
+    /// Plugins (UUID or name) loaded in their own plugin host process
+    pub plugins: Vec<String>,
+
+    /// Consecutive crashes after which a plugin host is no longer restarted
+    pub max_restarts: u32,
+}
+
+impl Default for IsolationConfig {
+    fn default() -> Self {
+        Self { plugins: Vec::new(), max_restarts: 3 }
+    }
+}
+
+/// [watch] section
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchConfig {
+    /// Reload plugins when their files change
+    pub enabled: bool,
+
+    /// Polling interval in milliseconds
+    pub interval_ms: u64,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: 1000,
+        }
+    }
+}
+
+/// [resident."<plugin>"] section
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResidentConfig {
+    /// Start the service when the interactive runtime starts
+    pub autostart: bool,
+
+    /// Configuration passed to `start`: a JSON string or a TOML table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<toml::Value>,
+
+    /// What to do when the service fails or stops on its own
+    pub restart: RestartPolicy,
+
+    /// Consecutive restarts allowed by the `on-failure` policy
+    pub max_retries: u32,
+
+    /// Delay before the first restart in milliseconds (doubled per attempt)
+    pub backoff_ms: u64,
+
+    /// Upper bound of the restart delay in milliseconds
+    pub max_backoff_ms: u64,
+}
+
+impl Default for ResidentConfig {
+    fn default() -> Self {
+        Self {
+            autostart: true,
+            config: None,
+            restart: RestartPolicy::Never,
+            max_retries: 3,
+            backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+        }
+    }
+}
+
+/// Restart policy of a resident service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave a failed service stopped
+    Never,
+    /// Restart after an error, up to `max_retries` times in a row
+    OnFailure,
+    /// Restart whenever the service is no longer running
+    Always,
+}
+
+/// [pipeline."<name>"] section
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Shown when listing pipelines
+    pub description: String,
+
+    /// Stages, run in order
+    pub stages: Vec<PipelineStage>,
+}
+
+/// [[pipeline."<name>".stages]] entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineStage {
+    /// Plugin UUID (or name)
+    pub plugin: String,
+
+    /// Label in reports and `{{stages.<name>}}` references (1-based position if omitted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// CubeMelonTaskType name or value (generic if omitted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_type: Option<String>,
+
+    /// Template of the stage's input JSON: a JSON string or a TOML table
+    /// (the previous stage's output if omitted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<toml::Value>,
+
+    /// Give up after this many microseconds (0 = no limit)
+    #[serde(default)]
+    pub timeout_us: i64,
+}
+
+/// [log] section
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Minimum level: trace, debug, info, warn or error
+    pub level: String,
+
+    /// Minimum level per log source (plugin log name, or "Runtime")
+    pub plugins: BTreeMap<String, String>,
+
+    /// Output format of console and file lines
+    pub format: LogFormat,
+
+    /// Write to the console (stderr in CLI mode)
+    pub console: bool,
+
+    /// Log file (relative to executable)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+
+    /// Rotate the log file before it exceeds this many bytes
+    pub max_file_size: u64,
+
+    /// Rotated log files to keep
+    pub max_files: u32,
+
+    /// Records kept in memory for the `logs` command
+    pub buffer_size: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "trace".to_string(),
+            plugins: BTreeMap::new(),
+            format: LogFormat::Text,
+            console: true,
+            file: None,
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 3,
+            buffer_size: 1000,
+        }
+    }
+}
+
+/// Log line format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `timestamp> [LEVEL] source: message`
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
 impl Default for RuntimeConfig {
     fn default() -> Self {
-        Self { settings: Settings::default() }
+        Self {
+            settings: Settings::default(),
+            search: SearchConfig::default(),
+            plugins: PluginsConfig::default(),
+            integrity: IntegrityConfig::default(),
+            isolation: IsolationConfig::default(),
+            watch: WatchConfig::default(),
+            resident: BTreeMap::new(),
+            log: LogConfig::default(),
+            pipeline: BTreeMap::new(),
+        }
     }
 }
 
@@ -69,7 +375,31 @@ pub struct RuntimeData {
     pub discovered_plugins: Vec<PluginInfo>,
     
     /// Loaded plugin libraries
-    pub loaded_libraries: HashMap<CubeMelonUUID, Library>,
+    pub loaded_libraries: HashMap<CubeMelonUUID, loader::PluginLibrary>,
+
+    /// Plugins loaded in a plugin host process instead of this one
+    pub isolated_plugins: HashMap<CubeMelonUUID, Arc<isolation::IsolatedPlugin>>,
+
+    /// Plugin files skipped by the last scan
+    pub rejected_plugins: Vec<loader::RejectedPlugin>,
+
+    /// Plugin files hidden by another file with the same UUID
+    pub shadowed_plugins: Vec<search::ShadowedPlugin>,
+
+    /// Valid plugin files excluded by the [plugins] section
+    pub disabled_plugins: Vec<policy::DisabledPlugin>,
+
+    /// Loaded plugins in the order they were loaded (dependencies first)
+    pub load_order: Vec<CubeMelonUUID>,
+
+    /// Libraries whose unload waits for the plugin to release its objects
+    pub pending_unloads: HashMap<CubeMelonUUID, loader::PluginLibrary>,
+
+    /// Plugins directory watcher (watch mode only)
+    pub watcher: Option<reload::PluginWatcher>,
+
+    /// Reloads waiting for the old library to be released
+    pub pending_reloads: Vec<reload::PendingReload>,
     
     /// Current system language (resolved from config)
     pub system_language: CubeMelonLanguage,
@@ -82,6 +412,15 @@ pub struct RuntimeData {
     
     /// Host services for plugins
     pub host_services: CubeMelonHostServices,
+
+    /// Live, initialized plugin instances
+    pub instances: HashMap<instances::InstanceKey, instances::PluginInstance>,
+
+    /// Storage for the Local and Shared state scopes (None without a config path)
+    pub state_store: Option<state_store::StateStore>,
+
+    /// Resident services started by the supervisor
+    pub residents: HashMap<CubeMelonUUID, resident::ResidentService>,
 }
 
 /// Basic plugin information
@@ -93,6 +432,9 @@ pub struct PluginInfo {
     name: String,
     description: String,
     path: PathBuf,
+    capabilities: matcher::PluginCapabilities,
+    /// Plugins that must be loaded first
+    dependencies: Vec<CubeMelonPluginDependency>,
 }
 
 impl RuntimeData {
@@ -138,6 +480,14 @@ impl RuntimeData {
             );
             (PathBuf::new(), RuntimeConfig::default())
         };
+        Self::with_config(config_path, config)
+    }
+
+    /// Create a RuntimeData from an already loaded configuration
+    fn with_config(config_path: PathBuf, config: RuntimeConfig) -> Self {
+        // Apply the [log] section before anything else is logged
+        let base_dir = config_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        logging::configure(&config.log, &base_dir);
 
         // Determine effective language: config > system (strict BCP 47, case-sensitive)
         let system_language = if config.settings.language != "auto" {
@@ -163,16 +513,39 @@ impl RuntimeData {
             Some(host_services::get_host_interface_callback), // Host interface provider
         );
 
+        // Plugin state lives next to the configuration file
+        let state_store = config_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| state_store::StateStore::new(dir.join("state")));
+
         Self {
             discovered_plugins: Vec::new(),
             loaded_libraries: HashMap::new(),
+            isolated_plugins: HashMap::new(),
+            load_order: Vec::new(),
+            rejected_plugins: Vec::new(),
+            shadowed_plugins: Vec::new(),
+            disabled_plugins: Vec::new(),
+            pending_unloads: HashMap::new(),
+            watcher: None,
+            pending_reloads: Vec::new(),
             system_language,
             config_path,
             config,
             host_services,
+            instances: HashMap::new(),
+            state_store,
+            residents: HashMap::new(),
         }
     }
     
+    /// Runtime with the default configuration and no configuration file
+    #[cfg(test)]
+    pub(crate) fn for_tests() -> Self {
+        Self::with_config(PathBuf::new(), RuntimeConfig::default())
+    }
+
     /// Get the path to the configuration file
     fn get_config_file_path() -> Option<PathBuf> {
         // Derive from current executable; if unavailable, return None
@@ -203,6 +576,23 @@ impl RuntimeData {
         Ok(())
     }
     
+    /// Write the configuration after `setting` changed
+    fn persist_config(&self, setting: &str) {
+        if !self.config_path.as_os_str().is_empty() {
+            if let Err(e) = Self::save_config(&self.config_path, &self.config) {
+                runtime_log(
+                    CubeMelonLogLevel::Warn,
+                    &format!("Failed to persist config ({}): {}", setting, e),
+                );
+            }
+        } else {
+            runtime_log(
+                CubeMelonLogLevel::Info,
+                "Config path unavailable; new setting kept in-memory only",
+            );
+        }
+    }
+
     /// Get the current plugins directory path (absolute)
     pub fn get_plugins_directory(&self) -> PathBuf {
         if Path::new(&self.config.settings.plugins_directory).is_absolute() {
@@ -223,19 +613,7 @@ impl RuntimeData {
     /// Set plugins directory
     pub fn set_plugins_directory(&mut self, directory: String) -> Result<()> {
         self.config.settings.plugins_directory = directory;
-        if !self.config_path.as_os_str().is_empty() {
-            if let Err(e) = Self::save_config(&self.config_path, &self.config) {
-                runtime_log(
-                    CubeMelonLogLevel::Warn,
-                    &format!("Failed to persist config (plugins_directory): {}", e),
-                );
-            }
-        } else {
-            runtime_log(
-                CubeMelonLogLevel::Info,
-                "Config path unavailable; new setting kept in-memory only",
-            );
-        }
+        self.persist_config("plugins_directory");
         runtime_log(CubeMelonLogLevel::Info, "Plugins directory updated in configuration");
         Ok(())
     }
@@ -243,53 +621,92 @@ impl RuntimeData {
     /// Set language setting
     pub fn set_language(&mut self, language: String) -> Result<()> {
         self.config.settings.language = language;
-        if !self.config_path.as_os_str().is_empty() {
-            if let Err(e) = Self::save_config(&self.config_path, &self.config) {
-                runtime_log(
-                    CubeMelonLogLevel::Warn,
-                    &format!("Failed to persist config (language): {}", e),
-                );
-            }
-        } else {
-            runtime_log(
-                CubeMelonLogLevel::Info,
-                "Config path unavailable; new setting kept in-memory only",
-            );
-        }
+        self.persist_config("language");
         runtime_log(CubeMelonLogLevel::Info, "Language setting updated in configuration");
         Ok(())
     }
     
     // Loader-related methods are implemented in `loader.rs`.
     
+    /// How long the prompt waits for input before watch mode polls again
+    fn watch_poll_interval(&self) -> Duration {
+        let interval = Duration::from_millis(self.config.watch.interval_ms.max(100));
+        if self.residents.is_empty() {
+            interval
+        } else {
+            interval.min(resident::SUPERVISE_INTERVAL)
+        }
+    }
+    
+    /// Release plugin instances held by the runtime
+    pub fn shutdown(&mut self) {
+        self.stop_all_residents();
+        self.destroy_all_instances();
+
+        // Dependents go before the plugins they depend on
+        for uuid in self.load_order.clone().into_iter().rev() {
+            let _ = self.unload_plugin(uuid);
+        }
+
+        self.retry_pending_unloads();
+        for (uuid, library) in self.pending_unloads.drain() {
+            // Closing a library whose code may still run would crash; let the OS reclaim it
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Plugin still in use at exit, leaving it mapped: {}", uuid));
+            std::mem::forget(library);
+        }
+    }
+    
     /// Interactive prompt loop
     pub fn run_interactive(&mut self) -> Result<()> {
         runtime_log(CubeMelonLogLevel::Info, "Starting interactive mode");
         println!("CubeMelon Plugin Runtime v{}", env!("CARGO_PKG_VERSION"));
         println!("Type 'help' for commands, 'quit' to exit");
         println!();
+
+        if self.config.watch.enabled {
+            self.watcher = Some(reload::PluginWatcher::new(&self.find_plugin_files()));
+            println!("Watching plugin search paths for changes.");
+        }
+
+        self.start_configured_residents();
+
+        // Read stdin on its own thread so watch mode can poll while the prompt waits
+        let input_lines = spawn_stdin_reader();
         
         loop {
+            self.retry_pending_unloads();
+
             print!("cubemelon> ");
             io::stdout().flush().unwrap();
             
-            let mut input = String::new();
-            match io::stdin().read_line(&mut input) {
-                Ok(0) => {
+            let read = loop {
+                let deadline = Instant::now() + self.watch_poll_interval();
+                match isolation::recv_serving_calls(&input_lines, Some(deadline)) {
+                    Ok(read) => break read,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        let restarted = self.supervise_residents();
+                        if self.poll_plugin_changes() || restarted {
+                            print!("cubemelon> ");
+                            io::stdout().flush().unwrap();
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break Ok(String::new()),
+                }
+            };
+            let input = match read {
+                Ok(line) if line.is_empty() => {
                     // EOF reached, exit gracefully
                     runtime_log(CubeMelonLogLevel::Info, "EOF reached, exiting interactive mode");
                     println!("Goodbye!");
                     break;
                 }
-                Ok(_) => {
-                    // Successfully read input
-                }
+                Ok(line) => line,
                 Err(e) => {
                     runtime_log(CubeMelonLogLevel::Warn, &format!("Error reading input: {}", e));
                     println!("Error reading input: {}", e);
                     continue;
                 }
-            }
+            };
             
             let input = input.trim();
             if input.is_empty() {
@@ -302,13 +719,30 @@ impl RuntimeData {
             match command {
                 "help" | "h" => {
                     println!("Available commands:");
-                    println!("  help, h              - Show this help");
-                    println!("  list, ls             - List all plugins");
-                    println!("  scan                 - Rescan plugins directory");
-                    println!("  load <name|number>   - Load a plugin by name or number");
-                    println!("  run <name|number>    - Run a plugin by name or number");
-                    println!("  host-exec <id>       - Execute via host manager (index|uuid|name)");
-                    println!("  quit, exit, q        - Exit the runtime");
+                    println!("  help, h                - Show this help");
+                    println!("  list, ls               - List all plugins");
+                    println!("  scan                   - Rescan plugins directory");
+                    println!("  load <name|number>     - Load a plugin by name or number");
+                    println!("  run <name|number>      - Run a plugin by name or number");
+                    println!("  host-exec <id> [inst]  - Execute via host manager (index|uuid|name)");
+                    println!("  instances              - List live plugin instances");
+                    println!("  create <id> [name]     - Create a plugin instance (default or named)");
+                    println!("  destroy <id> [name]    - Destroy a plugin instance (default or named)");
+                    println!("  unload <id>            - Destroy instances and unload a plugin");
+                    println!("  enable <id>            - Enable a plugin and save the choice");
+                    println!("  disable <id>           - Unload and disable a plugin and save the choice");
+                    println!("  pin <id> [version]     - Use only this version of a plugin (unpin if omitted)");
+                    println!("  watch [on|off]         - Show or toggle reloading of changed plugin files");
+                    println!("  start <id> [json]      - Start a resident service (config JSON overrides [resident])");
+                    println!("  stop <id>              - Stop a resident service");
+                    println!("  suspend <id>           - Suspend a running resident service");
+                    println!("  resume <id>            - Resume a suspended resident service");
+                    println!("  status [id]            - Show resident service status");
+                    println!("  logs [n] [plugin]      - Show the last n log records (default 20)");
+                    println!("  stats [id]             - Show call, latency, load and instance metrics");
+                    println!("  trace [trace_id]       - List recent call traces or show one as a JSON span tree");
+                    println!("  pipeline [name] [json] - List pipelines, or run one with the given input JSON");
+                    println!("  quit, exit, q          - Exit the runtime");
                     println!();
                 }
                 "list" | "ls" => {
@@ -368,6 +802,8 @@ impl RuntimeData {
                     // Execute plugin
                     match self.execute_plugin(&plugin_info) {
                         Ok(()) => {
+                            println!("Plugin '{}' executed successfully!", plugin_info.name);
+                            println!("Description: {}", plugin_info.description);
                             runtime_log(CubeMelonLogLevel::Info, &format!("Plugin execution completed successfully: {}", plugin_info.name));
                         }
                         Err(e) => {
@@ -379,10 +815,12 @@ impl RuntimeData {
                 }
                 "host-exec" => {
                     if parts.len() < 2 {
-                        println!("Usage: host-exec <plugin_id>");
+                        println!("Usage: host-exec <plugin_id> [instance]");
                         println!("  plugin_id: index|uuid|name");
+                        println!("  instance:  named instance (default instance if omitted)");
                         continue;
                     }
+                    let instance_name = parts.get(2).copied();
 
                     // Load plugin if needed and get its info
                     let plugin_info = match self.load_plugin(parts[1]) {
@@ -407,7 +845,7 @@ impl RuntimeData {
                     );
 
                     // Execute through host manager path
-                    let rc = self.execute_task(plugin_info.uuid, &request, &mut result);
+                    let rc = self.execute_task_on(plugin_info.uuid, instance_name, &request, &mut result);
                     match rc {
                         CubeMelonPluginErrorCode::Success => {
                             println!("host-exec: Success. status={:?}, code={:?}", result.status, result.error_code);
@@ -449,6 +887,200 @@ impl RuntimeData {
                     }
                     println!();
                 }
+                "instances" => {
+                    self.list_instances();
+                    println!();
+                }
+                "create" => {
+                    if parts.len() < 2 {
+                        println!("Usage: create <plugin_id> [name]");
+                        continue;
+                    }
+
+                    let plugin_info = match self.load_plugin(parts[1]) {
+                        Ok(info) => info.clone(),
+                        Err(e) => {
+                            runtime_log(CubeMelonLogLevel::Warn, &format!("Failed to load plugin '{}': {}", parts[1], e));
+                            println!("Failed to load plugin: {}", e);
+                            continue;
+                        }
+                    };
+                    let instance_name = parts.get(2).copied();
+                    match self.create_instance(plugin_info.uuid, instance_name) {
+                        Ok(_) => println!("Instance '{}' of '{}' created.", instance_name.unwrap_or("default"), plugin_info.name),
+                        Err(code) => println!("Failed to create instance: {:?}", code),
+                    }
+                    println!();
+                }
+                "destroy" => {
+                    if parts.len() < 2 {
+                        println!("Usage: destroy <plugin_id> [name]");
+                        continue;
+                    }
+
+                    let plugin_info = match self.resolve_plugin_id(parts[1]) {
+                        Ok(info) => info,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    };
+                    let instance_name = parts.get(2).copied();
+                    match self.destroy_instance(plugin_info.uuid, instance_name) {
+                        Ok(()) => println!("Instance '{}' of '{}' destroyed.", instance_name.unwrap_or("default"), plugin_info.name),
+                        Err(_) => println!("No such instance: '{}' of '{}'", instance_name.unwrap_or("default"), plugin_info.name),
+                    }
+                    println!();
+                }
+                "unload" => {
+                    if parts.len() < 2 {
+                        println!("Usage: unload <plugin_id>");
+                        continue;
+                    }
+
+                    let plugin_info = match self.resolve_plugin_id(parts[1]) {
+                        Ok(info) => info,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    };
+                    match self.unload_plugin(plugin_info.uuid) {
+                        Ok(loader::UnloadOutcome::Unloaded) => println!("Plugin '{}' unloaded.", plugin_info.name),
+                        Ok(loader::UnloadOutcome::Deferred) => {
+                            println!("Plugin '{}' is still in use; it will be unloaded once released.", plugin_info.name)
+                        }
+                        Err(_) => println!("Plugin '{}' is not loaded.", plugin_info.name),
+                    }
+                    println!();
+                }
+                "enable" | "disable" => {
+                    if parts.len() < 2 {
+                        println!("Usage: {} <plugin_id>", command);
+                        continue;
+                    }
+                    let enabled = command == "enable";
+                    match self.set_plugin_enabled(parts[1], enabled) {
+                        Ok(plugin_info) => println!("Plugin '{}' {}.", plugin_info.name, if enabled { "enabled" } else { "disabled" }),
+                        Err(e) => println!("Failed to {} plugin: {}", command, e),
+                    }
+                    println!();
+                }
+                "pin" => {
+                    if parts.len() < 2 {
+                        println!("Usage: pin <plugin_id> [version]");
+                        continue;
+                    }
+                    let version = parts.get(2).copied();
+                    match self.set_plugin_pin(parts[1], version) {
+                        Ok(plugin_info) => match version {
+                            Some(version) => println!("Plugin '{}' pinned to version {}.", plugin_info.name, version),
+                            None => println!("Plugin '{}' unpinned.", plugin_info.name),
+                        },
+                        Err(e) => println!("Failed to pin plugin: {}", e),
+                    }
+                    println!();
+                }
+                "watch" => {
+                    match parts.get(1).copied() {
+                        Some("on") => {
+                            self.set_watch_enabled(true);
+                            println!("Watch mode enabled.");
+                        }
+                        Some("off") => {
+                            self.set_watch_enabled(false);
+                            println!("Watch mode disabled.");
+                        }
+                        Some(_) => println!("Usage: watch [on|off]"),
+                        None => println!(
+                            "Watch mode is {} (interval {} ms).",
+                            if self.watcher.is_some() { "on" } else { "off" },
+                            self.config.watch.interval_ms
+                        ),
+                    }
+                    println!();
+                }
+                "start" => {
+                    if parts.len() < 2 {
+                        println!("Usage: start <plugin_id> [config_json]");
+                        continue;
+                    }
+                    // The configuration may contain spaces; take the rest of the line
+                    let config_json = input
+                        .splitn(3, char::is_whitespace)
+                        .nth(2)
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty());
+                    match self.start_resident(parts[1], config_json) {
+                        Ok(plugin_info) => println!("Resident service '{}' started.", plugin_info.name),
+                        Err(code) => println!("Failed to start resident service: {:?}", code),
+                    }
+                    println!();
+                }
+                "stop" | "suspend" | "resume" => {
+                    if parts.len() < 2 {
+                        println!("Usage: {} <plugin_id>", command);
+                        continue;
+                    }
+                    let outcome = match command {
+                        "stop" => self.stop_resident(parts[1]),
+                        "suspend" => self.suspend_resident(parts[1]),
+                        _ => self.resume_resident(parts[1]),
+                    };
+                    match outcome {
+                        Ok(plugin_info) => println!("Resident service '{}': {} done.", plugin_info.name, command),
+                        Err(code) => println!("Failed to {} resident service: {:?}", command, code),
+                    }
+                    println!();
+                }
+                "status" => {
+                    self.print_resident_status(parts.get(1).copied());
+                    println!();
+                }
+                "logs" => {
+                    let mut count = 20;
+                    let mut source = None;
+                    for arg in &parts[1..] {
+                        match arg.parse::<usize>() {
+                            Ok(n) if source.is_none() => count = n,
+                            _ => source = Some(*arg),
+                        }
+                    }
+                    for record in logging::recent(count, source) {
+                        println!("{}", record.to_text());
+                    }
+                    println!();
+                }
+                "stats" => {
+                    if let Err(e) = self.print_stats(parts.get(1).copied()) {
+                        println!("{}", e);
+                    }
+                    println!();
+                }
+                "trace" => {
+                    if let Err(e) = self.print_traces(parts.get(1).copied()) {
+                        println!("{}", e);
+                    }
+                    println!();
+                }
+                "pipeline" => {
+                    match parts.get(1).copied() {
+                        Some(name) => {
+                            // The input may contain spaces; take the rest of the line
+                            let input_json = input
+                                .splitn(3, char::is_whitespace)
+                                .nth(2)
+                                .map(str::trim)
+                                .filter(|s| !s.is_empty());
+                            match self.run_pipeline(name, input_json) {
+                                Ok(report) => report.print(),
+                                Err(e) => println!("{}", e),
+                            }
+                        }
+                        None => self.list_pipelines(),
+                    }
+                    println!();
+                }
                 "quit" | "exit" | "q" => {
                     runtime_log(CubeMelonLogLevel::Info, "User requested exit");
                     println!("Goodbye!");
@@ -465,10 +1097,45 @@ impl RuntimeData {
     }
 }
 
-
-
+/// Forward stdin lines to a channel; an empty string signals EOF
+fn spawn_stdin_reader() -> mpsc::Receiver<io::Result<String>> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || loop {
+        let mut line = String::new();
+        let read = io::stdin().read_line(&mut line);
+        let eof = matches!(read, Ok(0));
+        if tx.send(read.map(|_| line)).is_err() || eof {
+            break;
+        }
+    });
+    rx
+}
 
 fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // Child process serving an isolated plugin; stdout carries the protocol
+    if args.first().map(String::as_str) == Some(plugin_host::PLUGIN_HOST_ARG) {
+        logging::set_log_to_stderr(true);
+        std::process::exit(plugin_host::run(&args[1..]));
+    }
+    let command = match cli::Command::parse(&args) {
+        Ok(Some(cli::Command::Help)) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("cubemelon: {}", message);
+            eprintln!();
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(cli::EXIT_USAGE);
+        }
+    };
+    // Keep stdout for command output
+    if command.is_some() {
+        logging::set_log_to_stderr(true);
+    }
+
     runtime_log(CubeMelonLogLevel::Info, &format!("Starting CubeMelon Plugin Runtime v{}", env!("CARGO_PKG_VERSION")));
     
     // Create runtime data (always succeeds, falls back to defaults)
@@ -483,6 +1150,14 @@ fn main() -> Result<()> {
         ),
     );
     
+    if let Some(command) = command {
+        let exit_code = cli::run(&mut runtime, command);
+        runtime.shutdown();
+        runtime_log(CubeMelonLogLevel::Info, "CubeMelon Plugin Runtime shutting down");
+        // Plugins may still run abandoned tasks; exit without unmapping their libraries
+        std::process::exit(exit_code);
+    }
+    
     // Scan for plugins
     runtime.scan_plugins()
         .context("Failed to scan plugins")?;
@@ -491,6 +1166,7 @@ fn main() -> Result<()> {
     runtime.run_interactive()
         .context("Interactive mode failed")?;
     
+    runtime.shutdown();
     runtime_log(CubeMelonLogLevel::Info, "CubeMelon Plugin Runtime shutting down");
     Ok(())
 }
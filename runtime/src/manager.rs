@@ -2,21 +2,69 @@
 //! 
 //! This module implements CubeMelonPluginManagerInterface for RuntimeData.
 
+use std::collections::HashSet;
+use std::time::Instant;
+
 use cubemelon_sdk::{
     CubeMelonUUID, CubeMelonLanguage, CubeMelonPluginErrorCode, CubeMelonLogLevel,
     CubeMelonPluginBasicInfo, CubeMelonPluginBasicInfoArray, CubeMelonUUIDArray, CubeMelonString,
-    CubeMelonTaskRequest, CubeMelonTaskResult, CubeMelonTaskCallback,
+    CubeMelonTaskRequest, CubeMelonTaskResult, CubeMelonTaskCallback, CubeMelonPluginType,
+    CubeMelonAsyncTaskInterfaceImpl, CubeMelonSingleTaskInterfaceImpl,
     CubeMelonPluginManagerInterface, CubeMelonPluginManagerInterfaceImpl,
     create_plugin_manager_interface,
 };
 
 use crate::{RuntimeData, host_services::{runtime_log, HostRuntimeProxy, with_runtime}};
+use crate::async_task;
+use crate::matcher::{self, TaskQuery};
+use crate::metrics::{self, CallInterface};
 
 impl RuntimeData {
     /// Create the C ABI interface implementation for plugin manager
     pub fn create_manager_interface() -> CubeMelonPluginManagerInterfaceImpl {
         create_plugin_manager_interface::<Self>()
     }
+
+    /// Hand an async task to the plugin, or to a worker thread
+    fn start_async_task(
+        &mut self,
+        target_uuid: CubeMelonUUID,
+        request: &CubeMelonTaskRequest,
+        callback: Option<CubeMelonTaskCallback>,
+    ) -> CubeMelonPluginErrorCode {
+        // The plugin host picks the interface; wait for it on a worker thread
+        if let Some(isolated) = self.isolated_plugin(target_uuid) {
+            return async_task::execute_on_thread(target_uuid, request, callback, move |request, result| {
+                isolated.execute(None, true, unsafe { &*request }, result)
+            });
+        }
+
+        let entry_points = match self.entry_points(target_uuid) {
+            Ok(entry_points) => entry_points,
+            Err(code) => return code,
+        };
+
+        // Prefer the plugin's own AsyncTask interface
+        if let Some(interface) = entry_points.get_interface::<CubeMelonAsyncTaskInterfaceImpl>(CubeMelonPluginType::AsyncTask) {
+            let instance = match self.get_or_create_instance(target_uuid, None) {
+                Ok(instance) => instance,
+                Err(code) => {
+                    runtime_log(CubeMelonLogLevel::Error, &format!("Failed to prepare async task instance: {:?}", code));
+                    return code;
+                }
+            };
+            return async_task::execute_with_async_interface(target_uuid, instance, interface, request, callback);
+        }
+
+        // Fall back to running SingleTask on a worker thread
+        if entry_points.get_interface::<CubeMelonSingleTaskInterfaceImpl>(CubeMelonPluginType::SingleTask).is_some() {
+            runtime_log(CubeMelonLogLevel::Info, "Plugin has no AsyncTask interface; running SingleTask on a worker thread");
+            return async_task::execute_on_worker_thread(target_uuid, entry_points, self.host_services, request, callback);
+        }
+
+        runtime_log(CubeMelonLogLevel::Error, "Plugin supports neither AsyncTask nor SingleTask");
+        CubeMelonPluginErrorCode::InterfaceNotSupported
+    }
 }
 
 #[allow(unused_variables)]
@@ -81,9 +129,9 @@ impl CubeMelonPluginManagerInterface for RuntimeData {
                 out
             }
 
-            let loaded = self.loaded_libraries.contains_key(&plugin_info.uuid);
+            let loaded = self.is_loaded(plugin_info.uuid);
             let json = format!(
-                "{{\n  \"uuid\": \"{}\",\n  \"version\": \"{}\",\n  \"supported_types\": {},\n  \"name\": \"{}\",\n  \"description\": \"{}\",\n  \"loaded\": {},\n  \"path\": \"{}\"\n}}",
+                "{{\n  \"uuid\": \"{}\",\n  \"version\": \"{}\",\n  \"supported_types\": {},\n  \"name\": \"{}\",\n  \"description\": \"{}\",\n  \"loaded\": {},\n  \"path\": \"{}\",\n  \"metrics\": {}\n}}",
                 plugin_info.uuid,
                 plugin_info.version,
                 plugin_info.supported_types,
@@ -91,6 +139,7 @@ impl CubeMelonPluginManagerInterface for RuntimeData {
                 esc(&plugin_info.description),
                 if loaded { "true" } else { "false" },
                 esc(&plugin_info.path.display().to_string()),
+                metrics::plugin_metrics(plugin_info.uuid).to_json(),
             );
 
             *out_detailed_json = CubeMelonString::from_string(json);
@@ -125,10 +174,22 @@ impl CubeMelonPluginManagerInterface for RuntimeData {
         match task_json_str {
             Ok(json_str) => {
                 runtime_log(CubeMelonLogLevel::Info, &format!("Searching plugins for task: {}", json_str));
-                
-                // For now, return all loaded plugins as potential candidates
-                // TODO: Implement actual task matching logic
-                
+
+                let query = match TaskQuery::parse(json_str) {
+                    Ok(query) => query,
+                    Err(code) => {
+                        runtime_log(CubeMelonLogLevel::Error, &format!("Invalid task JSON: {:?}", code));
+                        *out_uuids = CubeMelonUUIDArray::empty();
+                        return code;
+                    }
+                };
+
+                let loaded: HashSet<CubeMelonUUID> =
+                    self.loaded_libraries.keys().chain(self.isolated_plugins.keys()).copied().collect();
+                let ranked = matcher::rank_plugins(&query, &self.discovered_plugins, &loaded);
+                runtime_log(CubeMelonLogLevel::Info, &format!("Found {} matching plugins", ranked.len()));
+                *out_uuids = CubeMelonUUIDArray::from_vec(ranked);
+
                 CubeMelonPluginErrorCode::Success
             }
             Err(_) => {
@@ -140,7 +201,10 @@ impl CubeMelonPluginManagerInterface for RuntimeData {
 
     /// Check plugin liveness
     fn is_plugin_alive(&self, target_uuid: CubeMelonUUID) -> bool {
-        let is_alive = self.loaded_libraries.contains_key(&target_uuid);
+        let is_alive = match self.isolated_plugin(target_uuid) {
+            Some(isolated) => isolated.is_alive(),
+            None => self.loaded_libraries.contains_key(&target_uuid),
+        };
         runtime_log(CubeMelonLogLevel::Debug, &format!("Plugin {} is_alive: {}", target_uuid, is_alive));
         is_alive
     }
@@ -154,75 +218,8 @@ impl CubeMelonPluginManagerInterface for RuntimeData {
     ) -> CubeMelonPluginErrorCode {
         runtime_log(CubeMelonLogLevel::Info, &format!("execute_task called for plugin: {}", target_uuid));
 
-        // Check if plugin is loaded
-        let library = match self.loaded_libraries.get(&target_uuid) {
-            Some(lib) => lib,
-            None => {
-                runtime_log(CubeMelonLogLevel::Error, &format!("Plugin not loaded: {}", target_uuid));
-                return CubeMelonPluginErrorCode::PluginNotFound;
-            }
-        };
-
-        // Resolve exported functions
-        let get_plugin_interface: libloading::Symbol<unsafe extern "C" fn(u64, u32, *mut *const std::ffi::c_void) -> CubeMelonPluginErrorCode> = unsafe {
-            match library.get(b"get_plugin_interface") {
-                Ok(sym) => sym,
-                Err(_) => return CubeMelonPluginErrorCode::InterfaceNotSupported,
-            }
-        };
-        let create_plugin: libloading::Symbol<unsafe extern "C" fn() -> *mut cubemelon_sdk::instance::CubeMelonPlugin> = unsafe {
-            match library.get(b"create_plugin") {
-                Ok(sym) => sym,
-                Err(_) => return CubeMelonPluginErrorCode::PluginLoadFailed,
-            }
-        };
-        let destroy_plugin: libloading::Symbol<unsafe extern "C" fn(*mut cubemelon_sdk::instance::CubeMelonPlugin)> = unsafe {
-            match library.get(b"destroy_plugin") {
-                Ok(sym) => sym,
-                Err(_) => return CubeMelonPluginErrorCode::PluginUnloadFailed,
-            }
-        };
-
-        // Get basic interface (for initialize/uninitialize)
-        let mut basic_ptr: *const std::ffi::c_void = std::ptr::null();
-        let rc = unsafe {
-            get_plugin_interface(cubemelon_sdk::types::CubeMelonPluginType::Basic as u64, 1, &mut basic_ptr as *mut *const std::ffi::c_void)
-        };
-        if rc != CubeMelonPluginErrorCode::Success || basic_ptr.is_null() {
-            return rc;
-        }
-        let basic = unsafe { &*(basic_ptr as *const cubemelon_sdk::interfaces::CubeMelonInterface) };
-
-        // Get single_task interface
-        let mut st_ptr: *const std::ffi::c_void = std::ptr::null();
-        let rc = unsafe {
-            get_plugin_interface(cubemelon_sdk::types::CubeMelonPluginType::SingleTask as u64, 1, &mut st_ptr as *mut *const std::ffi::c_void)
-        };
-        if rc != CubeMelonPluginErrorCode::Success || st_ptr.is_null() {
-            return rc;
-        }
-        let single_task = unsafe { &*(st_ptr as *const cubemelon_sdk::interfaces::CubeMelonSingleTaskInterfaceImpl) };
-
-        // Create and initialize instance
-        let instance = unsafe { create_plugin() };
-        if instance.is_null() {
-            return CubeMelonPluginErrorCode::PluginLoadFailed;
-        }
-
-        let init_rc = (basic.initialize)(instance, &self.host_services as *const _);
-        if init_rc != CubeMelonPluginErrorCode::Success {
-            unsafe { destroy_plugin(instance) };
-            return init_rc;
-        }
-
-        // Execute the task
-        let exec_rc = (single_task.execute)(instance, request as *const _, result as *mut _);
-
-        // Uninitialize and destroy instance
-        let _ = (basic.uninitialize)(instance);
-        unsafe { destroy_plugin(instance) };
-
-        exec_rc
+        // Route to the plugin's default instance, created on first use
+        self.execute_task_on(target_uuid, None, request, result)
     }
 
     /// Execute asynchronous task
@@ -233,16 +230,14 @@ impl CubeMelonPluginManagerInterface for RuntimeData {
         callback: Option<CubeMelonTaskCallback>,
     ) -> CubeMelonPluginErrorCode {
         runtime_log(CubeMelonLogLevel::Info, &format!("execute_async_task called for plugin: {}", target_uuid));
-        
-        // Check if plugin is loaded
-        if !self.loaded_libraries.contains_key(&target_uuid) {
-            runtime_log(CubeMelonLogLevel::Error, &format!("Plugin not loaded: {}", target_uuid));
-            return CubeMelonPluginErrorCode::PluginNotFound;
+
+        let started = Instant::now();
+        let rc = self.start_async_task(target_uuid, request, callback);
+        if rc != CubeMelonPluginErrorCode::Success {
+            // Started tasks are counted once their result is delivered
+            metrics::record_call(target_uuid, CallInterface::AsyncTask, rc, started.elapsed());
         }
-        
-        // TODO: Implement actual async task execution
-        runtime_log(CubeMelonLogLevel::Warn, "Async task execution not fully implemented yet");
-        CubeMelonPluginErrorCode::NotSupported
+        rc
     }
 
     /// Cancel asynchronous task
@@ -251,10 +246,10 @@ impl CubeMelonPluginManagerInterface for RuntimeData {
         request: &mut CubeMelonTaskRequest,
     ) -> CubeMelonPluginErrorCode {
         runtime_log(CubeMelonLogLevel::Info, "cancel_async_task called");
-        
-        // TODO: Implement task cancellation logic
-        runtime_log(CubeMelonLogLevel::Warn, "Task cancellation not fully implemented yet");
-        CubeMelonPluginErrorCode::NotSupported
+
+        // The callback has seen a Cancelled result by the time this returns;
+        // the caller destroys the request afterwards
+        async_task::cancel(request as *const CubeMelonTaskRequest)
     }
 }
 
@@ -266,12 +261,12 @@ impl CubeMelonPluginManagerInterface for HostRuntimeProxy {
         language: cubemelon_sdk::CubeMelonLanguage,
         out_infos: &mut cubemelon_sdk::CubeMelonPluginBasicInfoArray,
     ) -> CubeMelonPluginErrorCode {
-        if let Some(rt) = with_runtime(|r| r as *const RuntimeData) {
-            // SAFETY: rt came from &RuntimeData above
-            let r = unsafe { &*rt };
+        if let Some(code) = with_runtime(|r| {
             cubemelon_sdk::CubeMelonPluginManagerInterface::get_all_plugins_basic_info(
                 r, language, out_infos,
             )
+        }) {
+            code
         } else {
             CubeMelonPluginErrorCode::NotInitialized
         }
@@ -283,11 +278,12 @@ impl CubeMelonPluginManagerInterface for HostRuntimeProxy {
         language: cubemelon_sdk::CubeMelonLanguage,
         out_detailed_json: &mut cubemelon_sdk::CubeMelonString,
     ) -> CubeMelonPluginErrorCode {
-        if let Some(rt) = with_runtime(|r| r as *const RuntimeData) {
-            let r = unsafe { &*rt };
+        if let Some(code) = with_runtime(|r| {
             cubemelon_sdk::CubeMelonPluginManagerInterface::get_plugin_detailed_info(
                 r, target_uuid, language, out_detailed_json,
             )
+        }) {
+            code
         } else {
             CubeMelonPluginErrorCode::NotInitialized
         }
@@ -298,23 +294,22 @@ impl CubeMelonPluginManagerInterface for HostRuntimeProxy {
         task_json: *const u8,
         out_uuids: &mut cubemelon_sdk::CubeMelonUUIDArray,
     ) -> CubeMelonPluginErrorCode {
-        if let Some(rt) = with_runtime(|r| r as *const RuntimeData) {
-            let r = unsafe { &*rt };
+        if let Some(code) = with_runtime(|r| {
             cubemelon_sdk::CubeMelonPluginManagerInterface::find_plugins_for_task(
                 r, task_json, out_uuids,
             )
+        }) {
+            code
         } else {
             CubeMelonPluginErrorCode::NotInitialized
         }
     }
 
     fn is_plugin_alive(&self, target_uuid: cubemelon_sdk::CubeMelonUUID) -> bool {
-        if let Some(rt) = with_runtime(|r| r as *const RuntimeData) {
-            let r = unsafe { &*rt };
+        with_runtime(|r| {
             cubemelon_sdk::CubeMelonPluginManagerInterface::is_plugin_alive(r, target_uuid)
-        } else {
-            false
-        }
+        })
+        .unwrap_or(false)
     }
 
     fn execute_task(
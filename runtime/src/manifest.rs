@@ -0,0 +1,208 @@
+//! Plugin Manifests
+//!
+//! Plugins built with the SDK macros embed a manifest (see
+//! `cubemelon_sdk::manifest`) holding their UUID, version, supported types,
+//! localized names, dependencies and declared capabilities. Scanning reads it
+//! straight from the file, so discovering a plugin runs none of its code;
+//! plugins without a manifest are still loaded to query them.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use cubemelon_sdk::manifest::find_embedded_manifest;
+use cubemelon_sdk::{CubeMelonPluginDependency, CubeMelonUUID};
+
+use crate::loader::check_sdk_compatibility;
+use crate::matcher::{parse_version, DeclaredCapabilities};
+use crate::PluginInfo;
+
+/// Texts the SDK macros use when a plugin does not implement the method
+const DEFAULT_NAME: &str = "Unnamed Plugin";
+const DEFAULT_DESCRIPTION: &str = "No description";
+
+/// TOML part of the manifest
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ManifestMetadata {
+    /// Language code (or "default") to name
+    name: HashMap<String, String>,
+    /// Language code (or "default") to description
+    description: HashMap<String, String>,
+    dependencies: Vec<ManifestDependency>,
+    capabilities: DeclaredCapabilities,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestDependency {
+    uuid: String,
+    min_version: String,
+}
+
+/// Read plugin information from the manifest embedded in a plugin file
+///
+/// Returns `Ok(None)` if the file has no manifest. Fails with a
+/// `PluginRejection` if the manifest names an incompatible SDK.
+pub fn read_manifest(path: &Path, language: &str) -> Result<Option<PluginInfo>> {
+    let file = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let Some(manifest) = find_embedded_manifest(&file) else {
+        return Ok(None);
+    };
+    check_sdk_compatibility(manifest.sdk_version, path)?;
+
+    let metadata: ManifestMetadata = toml::from_str(manifest.metadata).context("Malformed plugin manifest")?;
+    let dependencies = metadata
+        .dependencies
+        .iter()
+        .map(|d| {
+            let uuid = parse_uuid(&d.uuid).ok_or_else(|| anyhow!("Invalid dependency UUID in manifest: {}", d.uuid))?;
+            let min_version = parse_version(&d.min_version)
+                .ok_or_else(|| anyhow!("Invalid dependency version in manifest: {}", d.min_version))?;
+            Ok(CubeMelonPluginDependency::new(uuid, min_version))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let capabilities = metadata.capabilities.into_capabilities().context("Invalid capabilities in manifest")?;
+
+    Ok(Some(PluginInfo {
+        uuid: manifest.uuid,
+        version: manifest.version,
+        supported_types: manifest.supported_types,
+        name: localized(&metadata.name, language).unwrap_or(DEFAULT_NAME).to_string(),
+        description: localized(&metadata.description, language).unwrap_or(DEFAULT_DESCRIPTION).to_string(),
+        path: path.to_path_buf(),
+        capabilities,
+        dependencies,
+    }))
+}
+
+/// Text for a language, matched exactly like `multilang_map!` does
+fn localized<'a>(texts: &'a HashMap<String, String>, language: &str) -> Option<&'a str> {
+    texts.get(language).or_else(|| texts.get("default")).map(String::as_str)
+}
+
+/// Parse a hyphenated (or plain) hexadecimal UUID
+pub(crate) fn parse_uuid(text: &str) -> Option<CubeMelonUUID> {
+    let hex: Vec<u8> = text.bytes().filter(|b| *b != b'-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(CubeMelonUUID::from_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cubemelon_sdk::manifest::CubeMelonPluginManifest;
+    use cubemelon_sdk::{CubeMelonVersion, CubeMelonPluginErrorCode};
+
+    const METADATA: &[u8; 129] = b"[name]\ndefault = \"Example\"\n\"ja-JP\" = \"\xe4\xbe\x8b\"\n\n\
+[[dependencies]]\nuuid = \"d6090f56-26a4-420c-9e25-a37dd8ebae2e\"\nmin_version = \"1.2.0\"\n";
+
+    fn write_plugin<const N: usize>(name: &str, manifest: &CubeMelonPluginManifest<N>) -> std::path::PathBuf {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                manifest as *const _ as *const u8,
+                std::mem::size_of::<CubeMelonPluginManifest<N>>(),
+            )
+        };
+        let mut file = b"\x7fELF padding".to_vec();
+        file.extend_from_slice(bytes);
+        let path = std::env::temp_dir().join(format!("cubemelon-manifest-{}-{}.so", std::process::id(), name));
+        std::fs::write(&path, file).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_manifest_without_loading() {
+        let uuid = CubeMelonUUID::from_bytes([3; 16]);
+        let manifest = CubeMelonPluginManifest::new(uuid, CubeMelonVersion::new(2, 0, 1), 0x10, *METADATA);
+        let path = write_plugin("ok", &manifest);
+
+        let info = read_manifest(&path, "ja-JP").unwrap().unwrap();
+        assert_eq!(info.uuid, uuid);
+        assert_eq!(info.version, CubeMelonVersion::new(2, 0, 1));
+        assert_eq!(info.supported_types, 0x10);
+        assert_eq!(info.name, "例");
+        assert_eq!(info.description, DEFAULT_DESCRIPTION);
+        assert_eq!(info.dependencies.len(), 1);
+        assert_eq!(info.dependencies[0].uuid.to_string(), "d6090f56-26a4-420c-9e25-a37dd8ebae2e");
+        assert_eq!(info.dependencies[0].min_version, CubeMelonVersion::new(1, 2, 0));
+        assert_eq!(read_manifest(&path, "en-US").unwrap().unwrap().name, "Example");
+
+        std::fs::write(&path, b"no manifest").unwrap();
+        assert!(read_manifest(&path, "en-US").unwrap().is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Metadata padded with blank lines to the manifest's fixed size
+    fn padded_metadata<const N: usize>(toml: &str) -> [u8; N] {
+        let mut metadata = [b'\n'; N];
+        metadata[..toml.len()].copy_from_slice(toml.as_bytes());
+        metadata
+    }
+
+    #[test]
+    fn test_declared_capabilities_rank_plugins() {
+        use crate::matcher::{rank_plugins, TaskQuery};
+        use std::collections::HashSet;
+
+        let png_plugin = CubeMelonUUID::from_bytes([4; 16]);
+        let image_plugin = CubeMelonUUID::from_bytes([5; 16]);
+        let single_task = cubemelon_sdk::CubeMelonPluginType::SingleTask as u64;
+        let manifests = [
+            CubeMelonPluginManifest::new(
+                png_plugin,
+                CubeMelonVersion::new(1, 0, 0),
+                single_task,
+                padded_metadata::<256>(
+                    "[capabilities]\ntask_types = [\"image\"]\ninput_formats = [\"png\"]\nlanguages = [\"ja\"]\n",
+                ),
+            ),
+            CubeMelonPluginManifest::new(
+                image_plugin,
+                CubeMelonVersion::new(2, 0, 0),
+                single_task,
+                padded_metadata::<256>(
+                    "[capabilities]\ntask_types = [\"image\", 4]\ninput_formats = [\"png\", \"jpeg\"]\n",
+                ),
+            ),
+        ];
+        let plugins: Vec<PluginInfo> = manifests
+            .iter()
+            .enumerate()
+            .map(|(i, manifest)| {
+                let path = write_plugin(&format!("caps{}", i), manifest);
+                let info = read_manifest(&path, "en-US").unwrap().unwrap();
+                let _ = std::fs::remove_file(&path);
+                info
+            })
+            .collect();
+        assert_eq!(plugins[0].capabilities.task_types, vec![6]);
+        assert_eq!(plugins[1].capabilities.task_types, vec![6, 4]);
+
+        // The declared language outweighs the other plugin's newer version
+        let loaded = HashSet::new();
+        let q = TaskQuery::parse(r#"{"task_type":"image","input_format":"png","language":"ja-JP"}"#).unwrap();
+        assert_eq!(rank_plugins(&q, &plugins, &loaded), vec![png_plugin, image_plugin]);
+
+        let q = TaskQuery::parse(r#"{"task_type":"computation","input_format":"jpeg"}"#).unwrap();
+        assert_eq!(rank_plugins(&q, &plugins, &loaded), vec![image_plugin]);
+    }
+
+    #[test]
+    fn test_incompatible_sdk_in_manifest_is_rejected() {
+        let mut manifest =
+            CubeMelonPluginManifest::new(CubeMelonUUID::zero(), CubeMelonVersion::new(1, 0, 0), 0, *METADATA);
+        manifest.sdk_version = CubeMelonVersion::new(99, 0, 0);
+        let path = write_plugin("sdk", &manifest);
+
+        let err = read_manifest(&path, "en-US").unwrap_err();
+        assert_eq!(crate::loader::rejection_code(&err), CubeMelonPluginErrorCode::Incompatible);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,539 @@
+//! Task Matching
+//!
+//! This module implements the search behind `find_plugins_for_task`.
+//!
+//! A task is described in JSON; every field is optional:
+//! ```json
+//! {
+//!   "task_type": "image",              // name or CubeMelonTaskType value
+//!   "required_types": ["single_task"], // names, or a raw CubeMelonPluginType bit mask
+//!   "input_format": "png",
+//!   "output_format": "json",
+//!   "language": "ja-JP",
+//!   "min_version": "1.2.0"
+//! }
+//! ```
+//!
+//! Hard requirements (type flags, minimum version, declared capabilities that
+//! contradict the task) exclude a plugin; everything else only affects ranking.
+//!
+//! Plugins declare capabilities as a TOML table returned by the optional
+//! `get_plugin_capabilities` export:
+//! ```toml
+//! task_types = ["image", 4]   # names or CubeMelonTaskType values
+//! input_formats = ["png"]
+//! output_formats = ["json"]
+//! languages = ["ja", "en-US"]
+//! ```
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use cubemelon_sdk::{CubeMelonUUID, CubeMelonVersion, CubeMelonPluginType, CubeMelonPluginErrorCode, CubeMelonTaskType};
+
+use crate::PluginInfo;
+
+/// Capabilities a plugin declares beyond its type flags
+///
+/// Empty lists mean "not declared"; such plugins are neither excluded nor
+/// preferred for the corresponding field.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginCapabilities {
+    /// Supported CubeMelonTaskType values
+    pub task_types: Vec<u32>,
+    /// Accepted input formats (e.g. "json", "png")
+    pub input_formats: Vec<String>,
+    /// Produced output formats
+    pub output_formats: Vec<String>,
+    /// Supported languages (BCP 47)
+    pub languages: Vec<String>,
+}
+
+/// Capabilities as a plugin declares them
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct DeclaredCapabilities {
+    task_types: Vec<NameOrNumber>,
+    input_formats: Vec<String>,
+    output_formats: Vec<String>,
+    languages: Vec<String>,
+}
+
+impl DeclaredCapabilities {
+    /// Resolve task type names; fails on unknown names
+    pub(crate) fn into_capabilities(self) -> Result<PluginCapabilities> {
+        let task_types = self
+            .task_types
+            .into_iter()
+            .map(|task_type| match task_type {
+                NameOrNumber::Number(n) => u32::try_from(n).map_err(|_| anyhow!("Invalid task type: {}", n)),
+                NameOrNumber::Name(name) => {
+                    task_type_from_name(&name).ok_or_else(|| anyhow!("Unknown task type: {}", name))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PluginCapabilities {
+            task_types,
+            input_formats: self.input_formats,
+            output_formats: self.output_formats,
+            languages: self.languages,
+        })
+    }
+}
+
+impl PluginCapabilities {
+    /// Parse a plugin's capability declaration
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        toml::from_str::<DeclaredCapabilities>(toml)?.into_capabilities()
+    }
+}
+
+/// Name or numeric value in task JSON
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum NameOrNumber {
+    Number(u64),
+    Name(String),
+}
+
+/// Plugin type flags in task JSON
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TypeFlags {
+    Mask(u64),
+    Names(Vec<String>),
+    Name(String),
+}
+
+/// Raw task JSON
+#[derive(Debug, Default, Deserialize)]
+struct TaskJson {
+    task_type: Option<NameOrNumber>,
+    required_types: Option<TypeFlags>,
+    input_format: Option<String>,
+    output_format: Option<String>,
+    language: Option<String>,
+    min_version: Option<String>,
+}
+
+/// Validated task description
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TaskQuery {
+    pub task_type: Option<u32>,
+    pub required_types: u64,
+    pub input_format: Option<String>,
+    pub output_format: Option<String>,
+    pub language: Option<String>,
+    pub min_version: Option<CubeMelonVersion>,
+}
+
+/// Plugins must be able to run tasks to be of any use here
+const EXECUTABLE_TYPES: u64 = CubeMelonPluginType::SingleTask as u64 | CubeMelonPluginType::AsyncTask as u64;
+
+impl TaskQuery {
+    /// Parse a task description
+    pub fn parse(json: &str) -> Result<Self, CubeMelonPluginErrorCode> {
+        let raw: TaskJson = if json.trim().is_empty() {
+            TaskJson::default()
+        } else {
+            serde_json::from_str(json).map_err(|_| CubeMelonPluginErrorCode::Parse)?
+        };
+
+        let task_type = match raw.task_type {
+            None => None,
+            Some(NameOrNumber::Number(n)) => {
+                Some(u32::try_from(n).map_err(|_| CubeMelonPluginErrorCode::Validation)?)
+            }
+            Some(NameOrNumber::Name(name)) => {
+                Some(task_type_from_name(&name).ok_or(CubeMelonPluginErrorCode::Validation)?)
+            }
+        };
+
+        let required_types = match raw.required_types {
+            None => 0,
+            Some(TypeFlags::Mask(mask)) => mask,
+            Some(TypeFlags::Name(name)) => {
+                plugin_type_from_name(&name).ok_or(CubeMelonPluginErrorCode::Validation)?
+            }
+            Some(TypeFlags::Names(names)) => {
+                let mut mask = 0;
+                for name in &names {
+                    mask |= plugin_type_from_name(name).ok_or(CubeMelonPluginErrorCode::Validation)?;
+                }
+                mask
+            }
+        };
+
+        let min_version = match raw.min_version {
+            None => None,
+            Some(v) => Some(parse_version(&v).ok_or(CubeMelonPluginErrorCode::Validation)?),
+        };
+
+        Ok(Self {
+            task_type,
+            required_types,
+            input_format: raw.input_format,
+            output_format: raw.output_format,
+            language: raw.language,
+            min_version,
+        })
+    }
+
+    /// Score a plugin against this query, or None if it cannot serve the task
+    fn score(&self, plugin: &PluginInfo) -> Option<u32> {
+        let caps = &plugin.capabilities;
+        let mut score = 0;
+
+        if plugin.supported_types & EXECUTABLE_TYPES == 0 {
+            return None;
+        }
+        if plugin.supported_types & self.required_types != self.required_types {
+            return None;
+        }
+        if let Some(min) = self.min_version {
+            if plugin.version < min {
+                return None;
+            }
+        }
+
+        if let Some(task_type) = self.task_type {
+            if !caps.task_types.is_empty() {
+                if !caps.task_types.contains(&task_type) {
+                    return None;
+                }
+                score += 4;
+            }
+            // Type flags hint at the kind of work a plugin does
+            let implied = implied_plugin_types(task_type);
+            if implied != 0 && plugin.supported_types & implied != 0 {
+                score += 2;
+            }
+        }
+
+        for (wanted, declared) in [
+            (&self.input_format, &caps.input_formats),
+            (&self.output_format, &caps.output_formats),
+        ] {
+            if let (Some(wanted), false) = (wanted, declared.is_empty()) {
+                if !declared.iter().any(|d| d.eq_ignore_ascii_case(wanted)) {
+                    return None;
+                }
+                score += 2;
+            }
+        }
+
+        if let (Some(wanted), false) = (&self.language, caps.languages.is_empty()) {
+            if !caps.languages.iter().any(|l| language_matches(l, wanted)) {
+                return None;
+            }
+            score += 1;
+        }
+
+        Some(score)
+    }
+}
+
+/// Rank discovered plugins for a task, best match first
+///
+/// Ties prefer plugins that are already loaded, then newer versions.
+pub fn rank_plugins(
+    query: &TaskQuery,
+    plugins: &[PluginInfo],
+    loaded: &HashSet<CubeMelonUUID>,
+) -> Vec<CubeMelonUUID> {
+    let mut candidates: Vec<(u32, bool, &PluginInfo)> = plugins
+        .iter()
+        .filter_map(|p| query.score(p).map(|s| (s, loaded.contains(&p.uuid), p)))
+        .collect();
+
+    candidates.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then(b.1.cmp(&a.1))
+            .then(b.2.version.cmp(&a.2.version))
+            .then(a.2.name.cmp(&b.2.name))
+    });
+
+    let mut seen = HashSet::new();
+    candidates
+        .into_iter()
+        .map(|(_, _, p)| p.uuid)
+        .filter(|uuid| seen.insert(*uuid))
+        .collect()
+}
+
+/// Parse "1", "1.2" or "1.2.3"
+pub fn parse_version(s: &str) -> Option<CubeMelonVersion> {
+    let mut parts = s.trim().split('.');
+    let major = parts.next()?.parse::<u16>().ok()?;
+    let minor = parts.next().map_or(Some(0), |p| p.parse::<u8>().ok())?;
+    let patch = parts.next().map_or(Some(0), |p| p.parse::<u8>().ok())?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(CubeMelonVersion::new(major, minor, patch))
+}
+
+/// "en" matches "en-US"; otherwise a case-insensitive full match
+fn language_matches(declared: &str, wanted: &str) -> bool {
+    if declared.eq_ignore_ascii_case(wanted) {
+        return true;
+    }
+    let primary = |tag: &str| tag.split('-').next().unwrap_or("").to_ascii_lowercase();
+    (!declared.contains('-') || !wanted.contains('-')) && primary(declared) == primary(wanted)
+}
+
+/// CubeMelonTaskType by numeric value
+pub(crate) fn task_type_from_value(value: u32) -> Option<CubeMelonTaskType> {
+    use CubeMelonTaskType as T;
+    let task_type = match value {
+        0 => T::None,
+        1 => T::Generic,
+        2 => T::FileIO,
+        3 => T::Database,
+        4 => T::Computation,
+        5 => T::Window,
+        6 => T::Image,
+        7 => T::Audio,
+        8 => T::Video,
+        20 => T::Http,
+        21 => T::Tcp,
+        22 => T::Udp,
+        23 => T::WebSocket,
+        24 => T::FileSharing,
+        25 => T::ServiceDiscovery,
+        26 => T::GRPC,
+        27 => T::MQTT,
+        28 => T::GraphQL,
+        _ => return None,
+    };
+    Some(task_type)
+}
+
+/// CubeMelonTaskType by snake_case name
+pub(crate) fn task_type_from_name(name: &str) -> Option<u32> {
+    let value = match name.to_ascii_lowercase().as_str() {
+        "generic" => 1,
+        "file_io" => 2,
+        "database" => 3,
+        "computation" => 4,
+        "window" => 5,
+        "image" => 6,
+        "audio" => 7,
+        "video" => 8,
+        "http" => 20,
+        "tcp" => 21,
+        "udp" => 22,
+        "websocket" => 23,
+        "file_sharing" => 24,
+        "service_discovery" => 25,
+        "grpc" => 26,
+        "mqtt" => 27,
+        "graphql" => 28,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// CubeMelonTaskType by snake_case name or numeric value
+pub(crate) fn parse_task_type(raw: &str) -> Option<CubeMelonTaskType> {
+    let value = match raw.parse::<u32>() {
+        Ok(value) => value,
+        Err(_) => task_type_from_name(raw)?,
+    };
+    task_type_from_value(value)
+}
+
+/// CubeMelonPluginType flag by snake_case name
+pub fn plugin_type_from_name(name: &str) -> Option<u64> {
+    use CubeMelonPluginType as T;
+    let flag = match name.to_ascii_lowercase().as_str() {
+        "single_task" => T::SingleTask,
+        "async_task" => T::AsyncTask,
+        "resident" => T::Resident,
+        "state" => T::State,
+        "manager" => T::Manager,
+        "data_input" => T::DataInput,
+        "data_output" => T::DataOutput,
+        "window" => T::Window,
+        "image" => T::Image,
+        "audio" => T::Audio,
+        "video" => T::Video,
+        "file_system" => T::FileSystem,
+        "database" => T::Database,
+        "encryption" => T::Encryption,
+        "http_client" => T::HttpClient,
+        "http_server" => T::HttpServer,
+        "tcp_client" => T::TcpClient,
+        "tcp_server" => T::TcpServer,
+        "udp_socket" => T::UdpSocket,
+        "websocket" => T::WebSocket,
+        "file_sharing" => T::FileSharing,
+        "service_discovery" => T::ServiceDiscovery,
+        "streaming" => T::Streaming,
+        "messaging" => T::Messaging,
+        "blockchain" => T::Blockchain,
+        "iot" => T::IoT,
+        _ => return None,
+    };
+    Some(flag as u64)
+}
+
+/// Plugin type flags typically present on plugins serving a task type
+fn implied_plugin_types(task_type: u32) -> u64 {
+    use CubeMelonPluginType as T;
+    match task_type {
+        2 => T::FileSystem as u64,
+        3 => T::Database as u64,
+        5 => T::Window as u64,
+        6 => T::Image as u64,
+        7 => T::Audio as u64,
+        8 => T::Video as u64,
+        20 => T::HttpClient | T::HttpServer,
+        21 => T::TcpClient | T::TcpServer,
+        22 => T::UdpSocket as u64,
+        23 => T::WebSocket as u64,
+        24 => T::FileSharing as u64,
+        25 => T::ServiceDiscovery as u64,
+        27 => T::Messaging as u64,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn plugin(id: u8, version: CubeMelonVersion, supported_types: u64, capabilities: PluginCapabilities) -> PluginInfo {
+        PluginInfo {
+            uuid: CubeMelonUUID::from_bytes([id; 16]),
+            version,
+            supported_types,
+            name: format!("plugin-{}", id),
+            description: String::new(),
+            path: PathBuf::new(),
+            capabilities,
+            dependencies: Vec::new(),
+        }
+    }
+
+    fn uuid(id: u8) -> CubeMelonUUID {
+        CubeMelonUUID::from_bytes([id; 16])
+    }
+
+    const SINGLE: u64 = CubeMelonPluginType::SingleTask as u64;
+    const ASYNC: u64 = CubeMelonPluginType::AsyncTask as u64;
+    const IMAGE: u64 = CubeMelonPluginType::Image as u64;
+
+    #[test]
+    fn test_parse_names_and_numbers() {
+        let q = TaskQuery::parse(r#"{"task_type":"image","required_types":["single_task","image"],"min_version":"1.2"}"#).unwrap();
+        assert_eq!(q.task_type, Some(6));
+        assert_eq!(q.required_types, SINGLE | IMAGE);
+        assert_eq!(q.min_version, Some(CubeMelonVersion::new(1, 2, 0)));
+
+        let q = TaskQuery::parse(r#"{"task_type":150,"required_types":3}"#).unwrap();
+        assert_eq!(q.task_type, Some(150));
+        assert_eq!(q.required_types, SINGLE | ASYNC);
+
+        assert_eq!(TaskQuery::parse("").unwrap(), TaskQuery::default());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(TaskQuery::parse("{not json"), Err(CubeMelonPluginErrorCode::Parse));
+        assert_eq!(TaskQuery::parse(r#"{"task_type":"teleport"}"#), Err(CubeMelonPluginErrorCode::Validation));
+        assert_eq!(TaskQuery::parse(r#"{"required_types":["warp_drive"]}"#), Err(CubeMelonPluginErrorCode::Validation));
+        assert_eq!(TaskQuery::parse(r#"{"min_version":"one"}"#), Err(CubeMelonPluginErrorCode::Validation));
+    }
+
+    #[test]
+    fn test_hard_requirements_exclude() {
+        let plugins = vec![
+            plugin(1, CubeMelonVersion::new(1, 0, 0), SINGLE, PluginCapabilities::default()),
+            plugin(2, CubeMelonVersion::new(2, 0, 0), ASYNC, PluginCapabilities::default()),
+            plugin(3, CubeMelonVersion::new(3, 0, 0), CubeMelonPluginType::Resident as u64, PluginCapabilities::default()),
+        ];
+        let loaded = HashSet::new();
+
+        let q = TaskQuery::parse(r#"{"required_types":"async_task"}"#).unwrap();
+        assert_eq!(rank_plugins(&q, &plugins, &loaded), vec![uuid(2)]);
+
+        let q = TaskQuery::parse(r#"{"min_version":"1.5.0"}"#).unwrap();
+        assert_eq!(rank_plugins(&q, &plugins, &loaded), vec![uuid(2)]);
+
+        // Non-executable plugins are never candidates
+        let q = TaskQuery::default();
+        assert_eq!(rank_plugins(&q, &plugins, &loaded), vec![uuid(2), uuid(1)]);
+    }
+
+    #[test]
+    fn test_declared_capabilities_rank_and_filter() {
+        let declared = PluginCapabilities {
+            task_types: vec![6],
+            input_formats: vec!["PNG".to_string()],
+            output_formats: vec!["json".to_string()],
+            languages: vec!["ja".to_string()],
+        };
+        let plugins = vec![
+            plugin(1, CubeMelonVersion::new(1, 0, 0), SINGLE, PluginCapabilities::default()),
+            plugin(2, CubeMelonVersion::new(1, 0, 0), SINGLE | IMAGE, PluginCapabilities::default()),
+            plugin(3, CubeMelonVersion::new(1, 0, 0), SINGLE | IMAGE, declared),
+            plugin(4, CubeMelonVersion::new(1, 0, 0), SINGLE, PluginCapabilities {
+                input_formats: vec!["wav".to_string()],
+                ..Default::default()
+            }),
+        ];
+        let loaded = HashSet::new();
+
+        let q = TaskQuery::parse(r#"{"task_type":"image","input_format":"png","output_format":"json","language":"ja-JP"}"#).unwrap();
+        assert_eq!(rank_plugins(&q, &plugins, &loaded), vec![uuid(3), uuid(2), uuid(1)]);
+    }
+
+    #[test]
+    fn test_parse_declared_capabilities() {
+        let caps = PluginCapabilities::from_toml(
+            "task_types = [\"image\", 4]\ninput_formats = [\"png\"]\nlanguages = [\"ja\"]\n",
+        )
+        .unwrap();
+        assert_eq!(caps.task_types, vec![6, 4]);
+        assert_eq!(caps.input_formats, vec!["png".to_string()]);
+        assert!(caps.output_formats.is_empty());
+        assert_eq!(caps.languages, vec!["ja".to_string()]);
+
+        assert_eq!(PluginCapabilities::from_toml("").unwrap(), PluginCapabilities::default());
+        assert!(PluginCapabilities::from_toml("task_types = [\"teleport\"]").is_err());
+        assert!(PluginCapabilities::from_toml("task_types = \"image\"").is_err());
+
+        // Declared capabilities outweigh a newer version
+        let plugins = vec![
+            plugin(1, CubeMelonVersion::new(2, 0, 0), SINGLE, PluginCapabilities::default()),
+            plugin(2, CubeMelonVersion::new(1, 0, 0), SINGLE, caps),
+        ];
+        let q = TaskQuery::parse(r#"{"task_type":"image","input_format":"png","language":"ja-JP"}"#).unwrap();
+        assert_eq!(rank_plugins(&q, &plugins, &HashSet::new()), vec![uuid(2), uuid(1)]);
+    }
+
+    #[test]
+    fn test_ties_prefer_loaded_then_newer() {
+        let plugins = vec![
+            plugin(1, CubeMelonVersion::new(1, 0, 0), SINGLE, PluginCapabilities::default()),
+            plugin(2, CubeMelonVersion::new(2, 0, 0), SINGLE, PluginCapabilities::default()),
+            plugin(3, CubeMelonVersion::new(1, 0, 0), SINGLE, PluginCapabilities::default()),
+        ];
+        let loaded: HashSet<_> = [uuid(3)].into_iter().collect();
+        let q = TaskQuery::default();
+        assert_eq!(rank_plugins(&q, &plugins, &loaded), vec![uuid(3), uuid(2), uuid(1)]);
+    }
+
+    #[test]
+    fn test_language_matching() {
+        assert!(language_matches("en-US", "en-us"));
+        assert!(language_matches("en", "en-GB"));
+        assert!(language_matches("ja-JP", "ja"));
+        assert!(!language_matches("en-US", "en-GB"));
+        assert!(!language_matches("fr", "en"));
+    }
+}
@@ -0,0 +1,333 @@
+//! Per-Plugin Execution Metrics
+//!
+//! The runtime counts, for every plugin UUID and execution interface, the
+//! calls made, their errors by `CubeMelonPluginErrorCode` and a latency
+//! histogram. It also records how often and how fast each plugin was loaded
+//! and how many instances were created and destroyed.
+//!
+//! Async results are delivered on plugin threads, so the figures live in a
+//! process-wide store rather than in RuntimeData. They are shown by the
+//! `stats` command, in `exec` reports and in `get_plugin_detailed_info`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use cubemelon_sdk::{CubeMelonExecutionStatus, CubeMelonPluginErrorCode, CubeMelonTaskResult, CubeMelonUUID};
+
+use crate::RuntimeData;
+
+/// Interface a task call went through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CallInterface {
+    SingleTask,
+    AsyncTask,
+}
+
+impl CallInterface {
+    pub fn name(self) -> &'static str {
+        match self {
+            CallInterface::SingleTask => "single_task",
+            CallInterface::AsyncTask => "async_task",
+        }
+    }
+}
+
+/// Upper bounds of the latency buckets in microseconds; one more bucket holds the rest
+const LATENCY_BOUNDS_US: [u64; 6] = [100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+/// Latency histogram with logarithmic buckets
+#[derive(Debug, Clone, Default)]
+pub struct Latency {
+    buckets: [u64; LATENCY_BOUNDS_US.len() + 1],
+    total_us: u64,
+    max_us: u64,
+}
+
+impl Latency {
+    fn record(&mut self, elapsed: Duration) {
+        let us = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BOUNDS_US.iter().position(|bound| us <= *bound).unwrap_or(LATENCY_BOUNDS_US.len());
+        self.buckets[bucket] += 1;
+        self.total_us = self.total_us.saturating_add(us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn mean_us(&self) -> u64 {
+        self.total_us.checked_div(self.count()).unwrap_or(0)
+    }
+
+    pub fn max_us(&self) -> u64 {
+        self.max_us
+    }
+
+    /// Bucket labels ("<=100us", ..., ">10s") and counts
+    pub fn buckets(&self) -> impl Iterator<Item = (String, u64)> + '_ {
+        let labels = LATENCY_BOUNDS_US
+            .iter()
+            .map(|bound| format!("<={}", format_us(*bound)))
+            .chain(std::iter::once(format!(">{}", format_us(LATENCY_BOUNDS_US[LATENCY_BOUNDS_US.len() - 1]))));
+        labels.zip(self.buckets.iter().copied())
+    }
+
+    fn to_json(&self) -> Value {
+        let buckets: Vec<Value> = LATENCY_BOUNDS_US
+            .iter()
+            .map(|bound| Value::from(*bound))
+            .chain(std::iter::once(Value::Null))
+            .zip(self.buckets)
+            .map(|(le_us, count)| json!({ "le_us": le_us, "count": count }))
+            .collect();
+        json!({
+            "mean_us": self.mean_us(),
+            "max_us": self.max_us,
+            "buckets": buckets,
+        })
+    }
+}
+
+/// Calls made through one interface
+#[derive(Debug, Clone, Default)]
+pub struct CallStats {
+    pub calls: u64,
+    /// Failed calls by error code name
+    pub errors: BTreeMap<String, u64>,
+    pub latency: Latency,
+}
+
+impl CallStats {
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "calls": self.calls,
+            "errors": self.error_count(),
+            "errors_by_code": self.errors,
+            "latency": self.latency.to_json(),
+        })
+    }
+}
+
+/// Everything recorded for one plugin
+#[derive(Debug, Clone, Default)]
+pub struct PluginMetrics {
+    pub calls: BTreeMap<CallInterface, CallStats>,
+    pub loads: u64,
+    pub last_load: Option<Duration>,
+    pub total_load: Duration,
+    pub instances_created: u64,
+    pub instances_destroyed: u64,
+}
+
+impl PluginMetrics {
+    pub fn live_instances(&self) -> u64 {
+        self.instances_created.saturating_sub(self.instances_destroyed)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let calls: serde_json::Map<String, Value> =
+            self.calls.iter().map(|(interface, stats)| (interface.name().to_string(), stats.to_json())).collect();
+        json!({
+            "calls": calls,
+            "loads": self.loads,
+            "last_load_us": self.last_load.map(|d| d.as_micros() as u64),
+            "total_load_us": self.total_load.as_micros() as u64,
+            "instances": {
+                "live": self.live_instances(),
+                "created": self.instances_created,
+                "destroyed": self.instances_destroyed,
+            },
+        })
+    }
+}
+
+static METRICS: OnceLock<Mutex<HashMap<CubeMelonUUID, PluginMetrics>>> = OnceLock::new();
+
+fn lock_metrics() -> MutexGuard<'static, HashMap<CubeMelonUUID, PluginMetrics>> {
+    METRICS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Error code describing a finished call: the return code, or the result's code
+pub(crate) fn call_outcome(rc: CubeMelonPluginErrorCode, result: &CubeMelonTaskResult) -> CubeMelonPluginErrorCode {
+    if rc != CubeMelonPluginErrorCode::Success {
+        return rc;
+    }
+    match result.status {
+        CubeMelonExecutionStatus::Cancelled => CubeMelonPluginErrorCode::Cancelled,
+        CubeMelonExecutionStatus::Error if result.error_code == CubeMelonPluginErrorCode::Success => {
+            CubeMelonPluginErrorCode::Unknown
+        }
+        CubeMelonExecutionStatus::Error => result.error_code,
+        _ => CubeMelonPluginErrorCode::Success,
+    }
+}
+
+/// Count a finished task call
+pub(crate) fn record_call(
+    uuid: CubeMelonUUID,
+    interface: CallInterface,
+    outcome: CubeMelonPluginErrorCode,
+    elapsed: Duration,
+) {
+    let mut metrics = lock_metrics();
+    let stats = metrics.entry(uuid).or_default().calls.entry(interface).or_default();
+    stats.calls += 1;
+    if outcome != CubeMelonPluginErrorCode::Success {
+        *stats.errors.entry(format!("{:?}", outcome)).or_default() += 1;
+    }
+    stats.latency.record(elapsed);
+}
+
+/// Count a successful plugin load
+pub(crate) fn record_load(uuid: CubeMelonUUID, elapsed: Duration) {
+    let mut metrics = lock_metrics();
+    let plugin = metrics.entry(uuid).or_default();
+    plugin.loads += 1;
+    plugin.last_load = Some(elapsed);
+    plugin.total_load += elapsed;
+}
+
+pub(crate) fn record_instance_created(uuid: CubeMelonUUID) {
+    lock_metrics().entry(uuid).or_default().instances_created += 1;
+}
+
+pub(crate) fn record_instance_destroyed(uuid: CubeMelonUUID) {
+    lock_metrics().entry(uuid).or_default().instances_destroyed += 1;
+}
+
+/// Figures recorded for a plugin so far
+pub fn plugin_metrics(uuid: CubeMelonUUID) -> PluginMetrics {
+    lock_metrics().get(&uuid).cloned().unwrap_or_default()
+}
+
+fn format_us(us: u64) -> String {
+    match us {
+        0..=999 => format!("{}us", us),
+        1_000..=999_999 => format!("{}ms", us as f64 / 1_000.0),
+        _ => format!("{}s", us as f64 / 1_000_000.0),
+    }
+}
+
+impl RuntimeData {
+    /// Print the metrics of one plugin, or of every plugin with any
+    pub fn print_stats(&self, plugin_id: Option<&str>) -> anyhow::Result<()> {
+        let plugins = match plugin_id {
+            Some(plugin_id) => vec![self.resolve_plugin_id(plugin_id)?],
+            None => {
+                let recorded = lock_metrics();
+                self.discovered_plugins.iter().filter(|p| recorded.contains_key(&p.uuid)).cloned().collect()
+            }
+        };
+        if plugins.is_empty() {
+            println!("No plugin metrics recorded yet.");
+            return Ok(());
+        }
+
+        println!("Plugin metrics:");
+        for plugin in plugins {
+            let metrics = plugin_metrics(plugin.uuid);
+            println!("  {}", plugin.name);
+            println!("     UUID: {}", plugin.uuid);
+            match metrics.last_load {
+                Some(last) => println!(
+                    "     Loads: {} (last {}, total {})",
+                    metrics.loads,
+                    format_us(last.as_micros() as u64),
+                    format_us(metrics.total_load.as_micros() as u64),
+                ),
+                None => println!("     Loads: 0"),
+            }
+            println!(
+                "     Instances: {} live ({} created, {} destroyed)",
+                metrics.live_instances(),
+                metrics.instances_created,
+                metrics.instances_destroyed,
+            );
+            for (interface, stats) in &metrics.calls {
+                let errors: Vec<String> = stats.errors.iter().map(|(code, n)| format!("{} {}", code, n)).collect();
+                println!(
+                    "     {}: {} calls, {} errors{}",
+                    interface.name(),
+                    stats.calls,
+                    stats.error_count(),
+                    if errors.is_empty() { String::new() } else { format!(" ({})", errors.join(", ")) },
+                );
+                println!(
+                    "       latency: mean {}, max {}",
+                    format_us(stats.latency.mean_us()),
+                    format_us(stats.latency.max_us()),
+                );
+                let histogram: Vec<String> = stats
+                    .latency
+                    .buckets()
+                    .filter(|(_, count)| *count > 0)
+                    .map(|(label, count)| format!("{} {}", label, count))
+                    .collect();
+                println!("       histogram: {}", histogram.join(", "));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram_buckets() {
+        let mut latency = Latency::default();
+        for us in [50, 100, 101, 5_000, 20_000_000] {
+            latency.record(Duration::from_micros(us));
+        }
+        let buckets: Vec<(String, u64)> = latency.buckets().collect();
+        assert_eq!(buckets[0], ("<=100us".to_string(), 2));
+        assert_eq!(buckets[1], ("<=1ms".to_string(), 1));
+        assert_eq!(buckets[2], ("<=10ms".to_string(), 1));
+        assert_eq!(buckets[6], (">10s".to_string(), 1));
+        assert_eq!(latency.max_us(), 20_000_000);
+        assert_eq!(latency.mean_us(), (50 + 100 + 101 + 5_000 + 20_000_000) / 5);
+
+        let json = latency.to_json();
+        assert_eq!(json["buckets"][6]["le_us"], Value::Null);
+        assert_eq!(json["buckets"][0]["count"], 2);
+    }
+
+    #[test]
+    fn test_calls_errors_and_instances_are_counted() {
+        let uuid = CubeMelonUUID::from_bytes([0x5A; 16]);
+        let mut failed = CubeMelonTaskResult::empty();
+        failed.status = CubeMelonExecutionStatus::Error;
+        failed.error_code = CubeMelonPluginErrorCode::Parse;
+        let outcome = call_outcome(CubeMelonPluginErrorCode::Success, &failed);
+        assert_eq!(outcome, CubeMelonPluginErrorCode::Parse);
+
+        record_call(uuid, CallInterface::SingleTask, CubeMelonPluginErrorCode::Success, Duration::from_millis(2));
+        record_call(uuid, CallInterface::SingleTask, outcome, Duration::from_millis(4));
+        record_call(uuid, CallInterface::AsyncTask, CubeMelonPluginErrorCode::Timeout, Duration::from_secs(1));
+        record_load(uuid, Duration::from_millis(3));
+        record_instance_created(uuid);
+        record_instance_created(uuid);
+        record_instance_destroyed(uuid);
+
+        let metrics = plugin_metrics(uuid);
+        let single = &metrics.calls[&CallInterface::SingleTask];
+        assert_eq!((single.calls, single.error_count()), (2, 1));
+        assert_eq!(single.errors["Parse"], 1);
+        assert_eq!(single.latency.mean_us(), 3_000);
+        assert_eq!(metrics.calls[&CallInterface::AsyncTask].errors["Timeout"], 1);
+        assert_eq!(metrics.live_instances(), 1);
+
+        let json = metrics.to_json();
+        assert_eq!(json["calls"]["single_task"]["errors_by_code"]["Parse"], 1);
+        assert_eq!(json["last_load_us"], 3_000);
+        assert_eq!(json["instances"]["created"], 2);
+    }
+}
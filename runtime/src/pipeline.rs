@@ -0,0 +1,553 @@
+//! Task Pipelines
+//!
+//! A pipeline runs plugins one after another through `execute_task`, each
+//! stage getting its input from the stages before it:
+//! ```toml
+//! [pipeline.greet]
+//! description = "Detect the language, then greet in it"
+//!
+//! [[pipeline.greet.stages]]
+//! plugin = "6ccc639d-b240-44ec-9c83-a006a66a590b"
+//! name = "detect"
+//! task_type = "computation"
+//! input = { text = "{{input.text}}" }
+//!
+//! [[pipeline.greet.stages]]
+//! plugin = "Greeter Plugin"
+//! input = '{"language": "{{previous.language}}", "who": "{{input.name}}"}'
+//! timeout_us = 500000
+//! ```
+//! `input` is a JSON template: a JSON string or a TOML table. String values
+//! of the form `{{ref}}` are replaced by the referenced value, and references
+//! inside longer strings by its text. A reference starts at `input` (the
+//! pipeline's input), `previous` (the previous stage's output) or
+//! `stages.<name>`, followed by object keys or array indices. Without a
+//! template a stage gets the previous stage's output JSON as is. Each stage
+//! also gets the previous stage's `output_data` as its `input_data`.
+//!
+//! The pipeline stops at the first stage that fails. Stages run in one trace,
+//! and each one is logged as it starts and finishes.
+
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use cubemelon_sdk::{
+    CubeMelonExecutionStatus, CubeMelonLogLevel, CubeMelonPluginErrorCode, CubeMelonPluginManagerInterface,
+    CubeMelonString, CubeMelonTaskResult, CubeMelonUUID,
+};
+
+use crate::async_task::{free_task_result, now_us};
+use crate::host_services::runtime_log;
+use crate::ipc::{WireRequest, WireValue};
+use crate::loader::rejection_code;
+use crate::matcher;
+use crate::metrics;
+use crate::trace;
+use crate::{PipelineStage, RuntimeData};
+
+/// Why a pipeline could not be started
+#[derive(Debug)]
+pub struct PipelineError {
+    pub code: CubeMelonPluginErrorCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// What became of a stage
+#[derive(Debug)]
+pub struct StageReport {
+    pub name: String,
+    /// Plugin as configured
+    pub plugin: String,
+    /// Resolved plugin, once loaded
+    pub target: Option<(CubeMelonUUID, String)>,
+    /// None for stages that never ran because an earlier one failed
+    pub status: Option<CubeMelonExecutionStatus>,
+    /// Error code describing the stage as a whole
+    pub code: CubeMelonPluginErrorCode,
+    pub error: Option<String>,
+    pub elapsed: Duration,
+    pub output_json: String,
+    pub output_data: Option<WireValue>,
+    pub progress_ratio: f64,
+    pub progress_stage: String,
+    pub progress_message: String,
+}
+
+impl StageReport {
+    fn new(name: String, plugin: &str) -> Self {
+        Self {
+            name,
+            plugin: plugin.to_string(),
+            target: None,
+            status: None,
+            code: CubeMelonPluginErrorCode::Success,
+            error: None,
+            elapsed: Duration::ZERO,
+            output_json: String::new(),
+            output_data: None,
+            progress_ratio: -1.0,
+            progress_stage: String::new(),
+            progress_message: String::new(),
+        }
+    }
+
+    /// Mark the stage as failed before or instead of running it
+    fn fail(&mut self, code: CubeMelonPluginErrorCode, error: String) {
+        self.status = Some(CubeMelonExecutionStatus::Error);
+        self.code = code;
+        self.error = Some(error);
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.status.is_some() && self.code == CubeMelonPluginErrorCode::Success
+    }
+
+    /// Output JSON as a value: null when empty, a string when not JSON
+    pub fn output(&self) -> Value {
+        output_value(&self.output_json)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({
+            "name": self.name,
+            "plugin": self.plugin,
+            "uuid": self.target.as_ref().map(|(uuid, _)| uuid.to_string()),
+            "plugin_name": self.target.as_ref().map(|(_, name)| name.clone()),
+            "status": self.status.map_or("Skipped".to_string(), |status| format!("{:?}", status)),
+            "error_code": self.status.map(|_| format!("{:?}", self.code)),
+            "error_code_value": self.status.map(|_| self.code as i32),
+            "elapsed_us": self.elapsed.as_micros() as u64,
+            "output": self.output(),
+        });
+        if let Some(error) = &self.error {
+            value["error"] = error.clone().into();
+        }
+        if let Some(data) = &self.output_data {
+            value["output_data"] = serde_json::to_value(data).unwrap_or(Value::Null);
+        }
+        if self.progress_ratio >= 0.0 || !self.progress_stage.is_empty() || !self.progress_message.is_empty() {
+            value["progress"] = json!({
+                "ratio": (self.progress_ratio >= 0.0).then_some(self.progress_ratio),
+                "stage": self.progress_stage,
+                "message": self.progress_message,
+            });
+        }
+        value
+    }
+}
+
+/// Outcome of a pipeline run
+#[derive(Debug)]
+pub struct PipelineReport {
+    pub name: String,
+    pub trace_id: u64,
+    pub stages: Vec<StageReport>,
+    pub elapsed: Duration,
+}
+
+impl PipelineReport {
+    /// The failed stage, if any
+    pub fn failed_stage(&self) -> Option<(usize, &StageReport)> {
+        self.stages.iter().enumerate().find(|(_, stage)| stage.status.is_some() && !stage.succeeded())
+    }
+
+    /// Error code describing the run as a whole
+    pub fn code(&self) -> CubeMelonPluginErrorCode {
+        self.failed_stage().map_or(CubeMelonPluginErrorCode::Success, |(_, stage)| stage.code)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let failed = self.failed_stage();
+        json!({
+            "pipeline": self.name,
+            "trace_id": trace::format_id(self.trace_id),
+            "status": if failed.is_some() { "Failed" } else { "Completed" },
+            "error_code": format!("{:?}", self.code()),
+            "error_code_value": self.code() as i32,
+            "failed_stage": failed.map(|(index, _)| index + 1),
+            "elapsed_us": self.elapsed.as_micros() as u64,
+            "stages": self.stages.iter().map(StageReport::to_json).collect::<Vec<_>>(),
+            "output": self.stages.last().filter(|_| failed.is_none()).map_or(Value::Null, StageReport::output),
+        })
+    }
+
+    /// Print a line per stage and the pipeline's output
+    pub fn print(&self) {
+        println!("Pipeline '{}' (trace {}):", self.name, trace::format_id(self.trace_id));
+        for (index, stage) in self.stages.iter().enumerate() {
+            let plugin = stage.target.as_ref().map_or(stage.plugin.as_str(), |(_, name)| name.as_str());
+            match stage.status {
+                None => println!("  {}. {} ({}): skipped", index + 1, stage.name, plugin),
+                Some(status) => println!(
+                    "  {}. {} ({}): status={:?}, code={:?}, {} us",
+                    index + 1,
+                    stage.name,
+                    plugin,
+                    status,
+                    stage.code,
+                    stage.elapsed.as_micros()
+                ),
+            }
+            if let Some(error) = &stage.error {
+                println!("     error: {}", error);
+            }
+        }
+        match self.failed_stage() {
+            Some((index, _)) => println!("Failed at stage {}: {:?}", index + 1, self.code()),
+            None => {
+                let output = self.stages.last().map(|stage| stage.output_json.as_str()).unwrap_or_default();
+                println!("Completed in {} us. output: {}", self.elapsed.as_micros(), output);
+            }
+        }
+    }
+}
+
+fn output_value(text: &str) -> Value {
+    if text.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+}
+
+/// Values a template can refer to
+struct Scope<'a> {
+    input: &'a Value,
+    previous: &'a Value,
+    /// Outputs of the stages run so far, by name
+    stages: &'a [(String, Value)],
+}
+
+impl Scope<'_> {
+    fn resolve(&self, reference: &str) -> Result<&Value, String> {
+        let mut path = reference.split('.');
+        let mut value = match path.next() {
+            Some("input") => self.input,
+            Some("previous") => self.previous,
+            Some("stages") => {
+                let name = path.next().ok_or_else(|| format!("{{{{{}}}}}: missing stage name", reference))?;
+                self.stages
+                    .iter()
+                    .find(|(stage, _)| stage == name)
+                    .map(|(_, output)| output)
+                    .ok_or_else(|| format!("{{{{{}}}}}: no earlier stage '{}'", reference, name))?
+            }
+            _ => return Err(format!("{{{{{}}}}}: unknown reference", reference)),
+        };
+        for key in path {
+            let next = match value {
+                Value::Array(items) => key.parse::<usize>().ok().and_then(|index| items.get(index)),
+                other => other.get(key),
+            };
+            value = next.ok_or_else(|| format!("{{{{{}}}}}: no '{}' in the referenced value", reference, key))?;
+        }
+        Ok(value)
+    }
+}
+
+/// Fill in the references of a template
+fn render(template: &Value, scope: &Scope) -> Result<Value, String> {
+    Ok(match template {
+        Value::String(text) => render_string(text, scope)?,
+        Value::Array(items) => Value::Array(items.iter().map(|item| render(item, scope)).collect::<Result<_, _>>()?),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), render(value, scope)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+fn render_string(text: &str, scope: &Scope) -> Result<Value, String> {
+    // A lone reference keeps the referenced value's type
+    if let Some(reference) = text.strip_prefix("{{").and_then(|rest| rest.strip_suffix("}}")) {
+        if !reference.contains("{{") {
+            return scope.resolve(reference.trim()).cloned();
+        }
+    }
+
+    let mut rendered = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find("}}").ok_or_else(|| format!("unterminated reference in '{}'", text))?;
+        match scope.resolve(rest[start + 2..start + end].trim())? {
+            Value::String(s) => rendered.push_str(s),
+            other => rendered.push_str(&other.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(Value::String(rendered))
+}
+
+/// Template of a stage's input as JSON
+fn input_template(stage: &PipelineStage) -> Result<Option<Value>, String> {
+    match &stage.input {
+        None => Ok(None),
+        Some(toml::Value::String(text)) => {
+            serde_json::from_str(text).map(Some).map_err(|e| format!("input template is not valid JSON: {}", e))
+        }
+        Some(table) => serde_json::to_value(table).map(Some).map_err(|e| format!("invalid input template: {}", e)),
+    }
+}
+
+impl RuntimeData {
+    /// Print the configured pipelines
+    pub fn list_pipelines(&self) {
+        if self.config.pipeline.is_empty() {
+            println!("No pipelines configured.");
+            return;
+        }
+        println!("Pipelines:");
+        for (name, pipeline) in &self.config.pipeline {
+            let plugins: Vec<&str> = pipeline.stages.iter().map(|stage| stage.plugin.as_str()).collect();
+            println!("  {} - {} stage(s): {}", name, pipeline.stages.len(), plugins.join(" -> "));
+            if !pipeline.description.is_empty() {
+                println!("      {}", pipeline.description);
+            }
+        }
+    }
+
+    /// Run a configured pipeline with `input_json` as its input
+    pub fn run_pipeline(&mut self, name: &str, input_json: Option<&str>) -> Result<PipelineReport, PipelineError> {
+        let stages = match self.config.pipeline.get(name) {
+            Some(pipeline) if !pipeline.stages.is_empty() => pipeline.stages.clone(),
+            Some(_) => {
+                return Err(PipelineError {
+                    code: CubeMelonPluginErrorCode::InvalidParameter,
+                    message: format!("Pipeline '{}' has no stages", name),
+                })
+            }
+            None => {
+                return Err(PipelineError {
+                    code: CubeMelonPluginErrorCode::InvalidParameter,
+                    message: format!("Pipeline not found: {}", name),
+                })
+            }
+        };
+        let input_text = input_json.unwrap_or_default();
+        let input = match input_text {
+            "" => Value::Null,
+            text => serde_json::from_str(text).map_err(|e| PipelineError {
+                code: CubeMelonPluginErrorCode::Parse,
+                message: format!("Invalid pipeline input JSON: {}", e),
+            })?,
+        };
+
+        let started = Instant::now();
+        let trace_id = trace::new_id();
+        runtime_log(
+            CubeMelonLogLevel::Info,
+            &format!("Pipeline '{}' started ({} stages, trace {})", name, stages.len(), trace::format_id(trace_id)),
+        );
+
+        let mut reports: Vec<StageReport> = Vec::new();
+        let mut outputs: Vec<(String, Value)> = Vec::new();
+        let mut previous_json = input_text.to_string();
+        let mut previous_data = None;
+        for (index, stage) in stages.iter().enumerate() {
+            let stage_name = stage.name.clone().unwrap_or_else(|| (index + 1).to_string());
+            let mut report = StageReport::new(stage_name, &stage.plugin);
+            if reports.last().is_some_and(|last| !last.succeeded()) {
+                reports.push(report);
+                continue;
+            }
+
+            runtime_log(
+                CubeMelonLogLevel::Info,
+                &format!("Pipeline '{}' stage {}/{} '{}' ({}) started", name, index + 1, stages.len(), report.name, stage.plugin),
+            );
+            let previous = outputs.last().map_or(&input, |(_, output)| output);
+            let scope = Scope { input: &input, previous, stages: &outputs };
+            let stage_started = Instant::now();
+            self.run_stage(stage, &scope, &previous_json, previous_data.take(), trace_id, &mut report);
+            report.elapsed = stage_started.elapsed();
+
+            if report.succeeded() {
+                runtime_log(
+                    CubeMelonLogLevel::Info,
+                    &format!(
+                        "Pipeline '{}' stage {}/{} '{}' finished in {} us",
+                        name,
+                        index + 1,
+                        stages.len(),
+                        report.name,
+                        report.elapsed.as_micros()
+                    ),
+                );
+                outputs.push((report.name.clone(), report.output()));
+                previous_json = report.output_json.clone();
+                previous_data = report.output_data.clone();
+            } else {
+                runtime_log(
+                    CubeMelonLogLevel::Warn,
+                    &format!(
+                        "Pipeline '{}' stage {}/{} '{}' failed: {:?}{}",
+                        name,
+                        index + 1,
+                        stages.len(),
+                        report.name,
+                        report.code,
+                        report.error.as_ref().map(|e| format!(" ({})", e)).unwrap_or_default()
+                    ),
+                );
+            }
+            reports.push(report);
+        }
+
+        let report = PipelineReport { name: name.to_string(), trace_id, stages: reports, elapsed: started.elapsed() };
+        runtime_log(
+            CubeMelonLogLevel::Info,
+            &format!("Pipeline '{}' finished: {:?} in {} us", name, report.code(), report.elapsed.as_micros()),
+        );
+        Ok(report)
+    }
+
+    /// Load a stage's plugin, build its request and execute it
+    fn run_stage(
+        &mut self,
+        stage: &PipelineStage,
+        scope: &Scope,
+        previous_json: &str,
+        previous_data: Option<WireValue>,
+        trace_id: u64,
+        report: &mut StageReport,
+    ) {
+        let task_type = stage.task_type.as_deref().unwrap_or("generic");
+        let Some(task_type) = matcher::parse_task_type(task_type) else {
+            report.fail(CubeMelonPluginErrorCode::InvalidParameter, format!("unknown task type: '{}'", task_type));
+            return;
+        };
+        let input_json = match input_template(stage).and_then(|template| template.map(|t| render(&t, scope)).transpose()) {
+            Ok(Some(rendered)) => rendered.to_string(),
+            Ok(None) => previous_json.to_string(),
+            Err(error) => {
+                report.fail(CubeMelonPluginErrorCode::Validation, error);
+                return;
+            }
+        };
+        let uuid = match self.load_plugin(&stage.plugin) {
+            Ok(plugin) => {
+                report.target = Some((plugin.uuid, plugin.name.clone()));
+                plugin.uuid
+            }
+            Err(e) => {
+                report.fail(rejection_code(&e), format!("{:#}", e));
+                return;
+            }
+        };
+
+        let wire = WireRequest {
+            input_json,
+            input_data: previous_data,
+            task_type: task_type as u16,
+            language: self.system_language.as_str().to_string(),
+            request_time_us: now_us(),
+            timeout_us: stage.timeout_us.max(0),
+            trace_id,
+        };
+        let request = wire.to_request();
+        let mut result = CubeMelonTaskResult::empty();
+        let rc = CubeMelonPluginManagerInterface::execute_task(self, uuid, &request.0, &mut result);
+
+        let text = |s: &CubeMelonString| s.as_str().map(str::to_string).unwrap_or_default();
+        report.status = Some(result.status);
+        report.output_json = text(&result.output_json);
+        report.progress_ratio = result.progress_ratio;
+        report.progress_stage = text(&result.progress_stage);
+        report.progress_message = text(&result.progress_message);
+        if !result.output_data.is_null() {
+            match unsafe { WireValue::from_value(&*result.output_data) } {
+                Ok(data) => report.output_data = Some(data),
+                Err(code) => runtime_log(
+                    CubeMelonLogLevel::Warn,
+                    &format!("Output data of stage '{}' cannot be passed on: {:?}", report.name, code),
+                ),
+            }
+        }
+        report.code = metrics::call_outcome(rc, &result);
+        if rc != CubeMelonPluginErrorCode::Success {
+            report.status = Some(CubeMelonExecutionStatus::Error);
+        }
+        free_task_result(&mut result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RuntimeConfig;
+
+    #[test]
+    fn test_templates_reference_earlier_outputs() {
+        let input = json!({ "name": "Ada", "sizes": [16, 32] });
+        let previous = json!({ "language": "en-US", "score": 0.5 });
+        let stages = vec![("detect".to_string(), previous.clone())];
+        let scope = Scope { input: &input, previous: &previous, stages: &stages };
+
+        let template = json!({
+            "who": "{{input.name}}",
+            "size": "{{ input.sizes.1 }}",
+            "greeting": "Hello {{input.name}} ({{stages.detect.language}}, {{previous.score}})",
+            "all": "{{previous}}",
+            "fixed": [1, true],
+        });
+        let rendered = render(&template, &scope).unwrap();
+        assert_eq!(rendered["who"], "Ada");
+        assert_eq!(rendered["size"], 32);
+        assert_eq!(rendered["greeting"], "Hello Ada (en-US, 0.5)");
+        assert_eq!(rendered["all"], previous);
+        assert_eq!(rendered["fixed"], json!([1, true]));
+
+        assert!(render(&json!("{{input.missing}}"), &scope).unwrap_err().contains("missing"));
+        assert!(render(&json!("{{stages.later}}"), &scope).unwrap_err().contains("later"));
+        assert!(render(&json!("{{output}}"), &scope).is_err());
+        assert!(render(&json!("open {{input"), &scope).is_err());
+    }
+
+    #[test]
+    fn test_pipeline_section_parses() {
+        let config: RuntimeConfig = toml::from_str(
+            r#"
+            [settings]
+            plugins_directory = "plugins"
+            language = "en-US"
+
+            [pipeline.greet]
+            description = "Detect, then greet"
+
+            [[pipeline.greet.stages]]
+            plugin = "6ccc639d-b240-44ec-9c83-a006a66a590b"
+            name = "detect"
+            task_type = "computation"
+            input = { text = "{{input.text}}" }
+
+            [[pipeline.greet.stages]]
+            plugin = "Greeter Plugin"
+            input = '{"language": "{{previous.language}}"}'
+            timeout_us = 500000
+            "#,
+        )
+        .unwrap();
+
+        let stages = &config.pipeline["greet"].stages;
+        assert_eq!(stages.len(), 2);
+        assert_eq!(stages[0].name.as_deref(), Some("detect"));
+        assert_eq!(input_template(&stages[0]).unwrap(), Some(json!({ "text": "{{input.text}}" })));
+        assert_eq!(stages[1].task_type, None);
+        assert_eq!(stages[1].timeout_us, 500_000);
+        assert_eq!(input_template(&stages[1]).unwrap(), Some(json!({ "language": "{{previous.language}}" })));
+
+        let mut broken = stages[1].clone();
+        broken.input = Some(toml::Value::String("{not json".into()));
+        assert!(input_template(&broken).is_err());
+    }
+}
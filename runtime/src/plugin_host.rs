@@ -0,0 +1,492 @@
+//! Plugin Host Process
+//!
+//! `cubemelon --plugin-host <file> <language>` loads one plugin library and
+//! serves the runtime that spawned it (see `isolation`) over stdin and stdout,
+//! using the messages in `ipc`. The runtime checks the file before spawning.
+//!
+//! Plugin log records are forwarded to the runtime, and the manager interface
+//! handed out by `get_host_interface` forwards every call to it. Both carry
+//! the trace ID of the request being executed (see `trace`). The State
+//! interface and `cancel_async_task` are not available to isolated plugins.
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+
+use libloading::Library;
+use cubemelon_sdk::{
+    CubeMelonAsyncTaskInterfaceImpl, CubeMelonHostServices, CubeMelonInterface, CubeMelonLanguage, CubeMelonLogLevel,
+    CubeMelonPlugin, CubeMelonPluginBasicInfo, CubeMelonPluginBasicInfoArray, CubeMelonPluginErrorCode,
+    CubeMelonPluginManagerInterface, CubeMelonPluginManagerInterfaceImpl, CubeMelonPluginType,
+    CubeMelonSingleTaskInterfaceImpl, CubeMelonString, CubeMelonTaskCallback, CubeMelonTaskRequest,
+    CubeMelonTaskResult, CubeMelonUUID, CubeMelonUUIDArray, create_plugin_instance, create_plugin_manager_interface,
+};
+
+use crate::async_task::{free_task_result, run_single_task};
+use crate::host_services::parse_language;
+use crate::ipc::{
+    error_code_from_i32, read_frame, write_message, Frame, HostCall, HostMessage, PluginMessage, ReplyData,
+    WireRequest, WireResult,
+};
+use crate::loader::{check_sdk_version, rejection_code, PluginEntryPoints};
+use crate::manifest::parse_uuid;
+use crate::matcher::parse_version;
+use crate::trace::{self, SpanContext};
+
+/// First argument that starts the runtime as a plugin host
+pub const PLUGIN_HOST_ARG: &str = "--plugin-host";
+
+/// Language reported by `get_system_language`
+static LANGUAGE: OnceLock<String> = OnceLock::new();
+
+/// Reply to a forwarded call: error code and data
+type Reply = (i32, ReplyData);
+
+/// Forwarded calls waiting for the runtime's reply, by call id
+static PENDING_CALLS: OnceLock<Mutex<HashMap<u64, Sender<Reply>>>> = OnceLock::new();
+static NEXT_CALL_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Async task results the host waits for, keyed by request address
+static ASYNC_RESULTS: OnceLock<Mutex<HashMap<usize, Sender<Option<WireResult>>>>> = OnceLock::new();
+
+fn pending_calls() -> &'static Mutex<HashMap<u64, Sender<Reply>>> {
+    PENDING_CALLS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn async_results() -> &'static Mutex<HashMap<usize, Sender<Option<WireResult>>>> {
+    ASYNC_RESULTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Send a message to the runtime
+///
+/// Stdout is locked per line, so plugin threads may send concurrently.
+fn send(message: &PluginMessage) {
+    // A vanished runtime surfaces as EOF on stdin
+    let _ = write_message(&mut io::stdout().lock(), message);
+}
+
+/// Run the plugin host; returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let [path, language] = args else {
+        eprintln!("usage: cubemelon {} <file> <language>", PLUGIN_HOST_ARG);
+        return 2;
+    };
+    let _ = LANGUAGE.set(language.clone());
+
+    let (library, entry_points) = match open_library(Path::new(path)) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            send(&PluginMessage::Failed { code: rejection_code(&e) as i32, message: format!("{:#}", e) });
+            return 1;
+        }
+    };
+    let Some(basic) = entry_points.get_interface::<CubeMelonInterface>(CubeMelonPluginType::Basic) else {
+        send(&PluginMessage::Failed {
+            code: CubeMelonPluginErrorCode::InterfaceNotSupported as i32,
+            message: format!("Plugin {:?} has no Basic interface", path),
+        });
+        return 1;
+    };
+    send(&PluginMessage::Ready);
+
+    let mut host = PluginHost {
+        entry_points,
+        basic,
+        host_services: CubeMelonHostServices::new(
+            Some(log_callback),
+            Some(get_system_language_callback),
+            Some(get_host_interface_callback),
+        ),
+        instances: HashMap::new(),
+    };
+    for message in spawn_stdin_reader() {
+        let outcome = |rc: Result<_, CubeMelonPluginErrorCode>| (rc.err().unwrap_or(CubeMelonPluginErrorCode::Success), None);
+        let (id, (code, result)) = match message {
+            HostMessage::Create { id, instance } => (id, outcome(host.create_instance(instance).map(drop))),
+            HostMessage::Destroy { id, instance } => (id, outcome(host.destroy_instance(&instance))),
+            HostMessage::Run { id } => (id, outcome(host.run_lifecycle())),
+            HostMessage::Execute { id, instance, asynchronous, request } => {
+                (id, host.execute(instance, asynchronous, &request))
+            }
+            HostMessage::Shutdown => break,
+            // Replies are routed by the reader thread
+            HostMessage::Reply { .. } => continue,
+        };
+        send(&PluginMessage::Done { id, code: code as i32, result });
+    }
+
+    host.destroy_all_instances();
+    // Plugin threads may still be running; exit without unmapping the library
+    std::mem::forget(library);
+    0
+}
+
+fn open_library(path: &Path) -> anyhow::Result<(Library, PluginEntryPoints)> {
+    let library = unsafe { Library::new(path) }
+        .map_err(|e| anyhow::anyhow!("Failed to load plugin library {:?}: {}", path, e))?;
+    check_sdk_version(&library, path)?;
+    let entry_points = PluginEntryPoints::resolve(&library)
+        .map_err(|code| anyhow::anyhow!("Plugin {:?} is missing required exports: {:?}", path, code))?;
+    Ok((library, entry_points))
+}
+
+/// Read runtime messages on a thread of their own
+///
+/// Replies to forwarded calls go straight to the waiting plugin thread, so a
+/// plugin blocked in a call never needs the main loop. Everything else is
+/// handed to the main loop; the channel closes at EOF.
+fn spawn_stdin_reader() -> Receiver<HostMessage> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut stdin = BufReader::new(io::stdin());
+        while let Ok(Some(frame)) = read_frame::<_, HostMessage>(&mut stdin) {
+            match frame {
+                Frame::Message(HostMessage::Reply { id, code, data }) => {
+                    let waiting = pending_calls().lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+                    if let Some(waiting) = waiting {
+                        let _ = waiting.send((code, data));
+                    }
+                }
+                Frame::Message(message) => {
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                }
+                Frame::Text(_) => {}
+            }
+        }
+        // Wake plugin threads still waiting for a reply
+        pending_calls().lock().unwrap_or_else(|e| e.into_inner()).clear();
+    });
+    rx
+}
+
+/// The loaded plugin and its live instances
+struct PluginHost {
+    entry_points: PluginEntryPoints,
+    basic: &'static CubeMelonInterface,
+    host_services: CubeMelonHostServices,
+    instances: HashMap<Option<String>, *mut CubeMelonPlugin>,
+}
+
+impl PluginHost {
+    /// Create and initialize an instance that lives until destroyed
+    fn create_instance(&mut self, name: Option<String>) -> Result<*mut CubeMelonPlugin, CubeMelonPluginErrorCode> {
+        if self.instances.contains_key(&name) {
+            return Err(CubeMelonPluginErrorCode::AlreadyInitialized);
+        }
+        let instance = self.new_instance()?;
+        self.instances.insert(name, instance);
+        Ok(instance)
+    }
+
+    fn get_or_create_instance(&mut self, name: Option<String>) -> Result<*mut CubeMelonPlugin, CubeMelonPluginErrorCode> {
+        match self.instances.get(&name) {
+            Some(instance) => Ok(*instance),
+            None => self.create_instance(name),
+        }
+    }
+
+    fn new_instance(&self) -> Result<*mut CubeMelonPlugin, CubeMelonPluginErrorCode> {
+        let instance = unsafe { (self.entry_points.create_plugin)() };
+        if instance.is_null() {
+            return Err(CubeMelonPluginErrorCode::PluginLoadFailed);
+        }
+        let rc = (self.basic.initialize)(instance, &self.host_services as *const _);
+        if rc != CubeMelonPluginErrorCode::Success {
+            unsafe { (self.entry_points.destroy_plugin)(instance) };
+            return Err(rc);
+        }
+        Ok(instance)
+    }
+
+    fn release_instance(&self, instance: *mut CubeMelonPlugin) {
+        let _ = (self.basic.uninitialize)(instance);
+        unsafe { (self.entry_points.destroy_plugin)(instance) };
+    }
+
+    fn destroy_instance(&mut self, name: &Option<String>) -> Result<(), CubeMelonPluginErrorCode> {
+        let instance = self.instances.remove(name).ok_or(CubeMelonPluginErrorCode::PluginNotFound)?;
+        self.release_instance(instance);
+        Ok(())
+    }
+
+    fn destroy_all_instances(&mut self) {
+        for (_, instance) in std::mem::take(&mut self.instances) {
+            self.release_instance(instance);
+        }
+    }
+
+    fn run_lifecycle(&self) -> Result<(), CubeMelonPluginErrorCode> {
+        let instance = self.new_instance()?;
+        self.release_instance(instance);
+        Ok(())
+    }
+
+    /// Execute a task as `execute_task` or `execute_async_task` would in process
+    ///
+    /// The result is returned along with a failure code whenever the plugin
+    /// produced one, as the caller's result struct would hold it.
+    fn execute(
+        &mut self,
+        name: Option<String>,
+        asynchronous: bool,
+        request: &WireRequest,
+    ) -> (CubeMelonPluginErrorCode, Option<WireResult>) {
+        let single_task =
+            self.entry_points.get_interface::<CubeMelonSingleTaskInterfaceImpl>(CubeMelonPluginType::SingleTask);
+        let async_task =
+            self.entry_points.get_interface::<CubeMelonAsyncTaskInterfaceImpl>(CubeMelonPluginType::AsyncTask);
+        let mut request = request.to_request();
+        let mut result = CubeMelonTaskResult::empty();
+        // The runtime holds the span; join its trace for logs and nested calls
+        let trace_id = request.0.trace_id();
+        let joined = (trace_id != 0).then_some(SpanContext { trace_id, span_id: 0 });
+        let _active = trace::enter(joined);
+
+        let rc = match (asynchronous, single_task, async_task) {
+            (false, Some(single_task), _) => match self.get_or_create_instance(name) {
+                Ok(instance) => (single_task.execute)(instance, &request.0, &mut result),
+                Err(code) => return (code, None),
+            },
+            (true, _, Some(async_task)) => {
+                return match self.get_or_create_instance(None) {
+                    Ok(instance) => execute_async(instance, async_task, &mut request.0),
+                    Err(code) => (code, None),
+                };
+            }
+            // Same fallback as in process: a dedicated instance for the task
+            (true, Some(_), None) => run_single_task(&self.entry_points, &self.host_services, &request.0, &mut result),
+            _ => return (CubeMelonPluginErrorCode::InterfaceNotSupported, None),
+        };
+
+        let wire = WireResult::from_result(&result);
+        free_task_result(&mut result);
+        match (rc, wire) {
+            (rc, Ok(wire)) => (rc, Some(wire)),
+            (CubeMelonPluginErrorCode::Success, Err(code)) => (code, None),
+            (rc, Err(_)) => (rc, None),
+        }
+    }
+}
+
+/// Start a task through the AsyncTask interface and wait for its callback
+fn execute_async(
+    instance: *mut CubeMelonPlugin,
+    interface: &CubeMelonAsyncTaskInterfaceImpl,
+    request: &mut CubeMelonTaskRequest,
+) -> (CubeMelonPluginErrorCode, Option<WireResult>) {
+    let key = request as *mut CubeMelonTaskRequest as usize;
+    let (tx, rx) = mpsc::channel();
+    async_results().lock().unwrap_or_else(|e| e.into_inner()).insert(key, tx);
+
+    let rc = (interface.execute)(instance, request, async_result_callback);
+    if rc != CubeMelonPluginErrorCode::Success {
+        async_results().lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+        return (rc, None);
+    }
+    match rx.recv() {
+        Ok(Some(result)) => (CubeMelonPluginErrorCode::Success, Some(result)),
+        Ok(None) => (CubeMelonPluginErrorCode::FormatUnsupported, None),
+        Err(_) => (CubeMelonPluginErrorCode::Unknown, None),
+    }
+}
+
+unsafe extern "C" fn async_result_callback(request: *mut CubeMelonTaskRequest, result: *const CubeMelonTaskResult) {
+    let waiting = async_results().lock().unwrap_or_else(|e| e.into_inner()).remove(&(request as usize));
+    if let (Some(waiting), false) = (waiting, result.is_null()) {
+        // The plugin releases its result once this returns
+        let _ = waiting.send(WireResult::from_result(&*result).ok());
+    }
+}
+
+unsafe extern "C" fn log_callback(level: CubeMelonLogLevel, plugin_name: *const u8, message: *const u8) {
+    let text = |ptr: *const u8, fallback: &str| match ptr.is_null() {
+        true => fallback.to_string(),
+        false => std::ffi::CStr::from_ptr(ptr as *const i8).to_string_lossy().into_owned(),
+    };
+    send(&PluginMessage::Log {
+        level: level.to_string(),
+        source: text(plugin_name, "Unknown Plugin"),
+        message: text(message, "Empty message"),
+        trace_id: trace::current_trace_id(),
+    });
+}
+
+unsafe extern "C" fn get_system_language_callback() -> CubeMelonLanguage {
+    parse_language(LANGUAGE.get().map(String::as_str).unwrap_or_default())
+}
+
+static MANAGER_PROXY: OnceLock<usize> = OnceLock::new();
+static MANAGER_VTABLE: OnceLock<CubeMelonPluginManagerInterfaceImpl> = OnceLock::new();
+
+unsafe extern "C" fn get_host_interface_callback(
+    interface_type: CubeMelonPluginType,
+    interface_version: u32,
+    plugin_out: *mut *const CubeMelonPlugin,
+    interface_out: *mut *const c_void,
+) -> CubeMelonPluginErrorCode {
+    if plugin_out.is_null() || interface_out.is_null() {
+        return CubeMelonPluginErrorCode::NullPointer;
+    }
+    if interface_version != 1 {
+        return CubeMelonPluginErrorCode::VersionMismatch;
+    }
+    match interface_type {
+        CubeMelonPluginType::Manager => {
+            let vtbl = MANAGER_VTABLE.get_or_init(create_plugin_manager_interface::<RuntimeManagerProxy>);
+            let proxy = MANAGER_PROXY.get_or_init(|| create_plugin_instance(RuntimeManagerProxy) as usize);
+            *plugin_out = *proxy as *const CubeMelonPlugin;
+            *interface_out = (vtbl as *const _) as *const c_void;
+            CubeMelonPluginErrorCode::Success
+        }
+        _ => CubeMelonPluginErrorCode::InterfaceNotSupported,
+    }
+}
+
+/// Forward a manager call to the runtime and wait for the reply
+fn call_runtime(call: HostCall) -> (CubeMelonPluginErrorCode, ReplyData) {
+    let id = NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = mpsc::channel();
+    pending_calls().lock().unwrap_or_else(|e| e.into_inner()).insert(id, tx);
+    send(&PluginMessage::Call { id, call });
+    match rx.recv() {
+        Ok((code, data)) => (error_code_from_i32(code), data),
+        Err(_) => (CubeMelonPluginErrorCode::ConnectionFailed, ReplyData::None),
+    }
+}
+
+/// Copy a request the plugin makes, in the trace of the task calling it
+fn wire_request(request: &CubeMelonTaskRequest) -> Result<WireRequest, CubeMelonPluginErrorCode> {
+    let mut wire = WireRequest::from_request(request)?;
+    if wire.trace_id == 0 {
+        wire.trace_id = trace::current_trace_id();
+    }
+    Ok(wire)
+}
+
+/// Manager interface handed to the plugin; every call goes to the runtime
+struct RuntimeManagerProxy;
+
+impl CubeMelonPluginManagerInterface for RuntimeManagerProxy {
+    fn get_all_plugins_basic_info(
+        &self,
+        language: CubeMelonLanguage,
+        out_infos: &mut CubeMelonPluginBasicInfoArray,
+    ) -> CubeMelonPluginErrorCode {
+        let (code, data) = call_runtime(HostCall::GetAllPluginsBasicInfo { language: language.as_str().to_string() });
+        let ReplyData::Plugins(plugins) = data else {
+            *out_infos = CubeMelonPluginBasicInfoArray::empty();
+            return code;
+        };
+        let infos = plugins
+            .into_iter()
+            .filter_map(|p| {
+                Some(CubeMelonPluginBasicInfo::new(
+                    parse_uuid(&p.uuid)?,
+                    parse_version(&p.version)?,
+                    p.supported_types,
+                    CubeMelonString::from_string(p.name),
+                    CubeMelonString::from_string(p.description),
+                ))
+            })
+            .collect();
+        *out_infos = CubeMelonPluginBasicInfoArray::from_vec(infos);
+        code
+    }
+
+    fn get_plugin_detailed_info(
+        &self,
+        target_uuid: CubeMelonUUID,
+        language: CubeMelonLanguage,
+        out_detailed_json: &mut CubeMelonString,
+    ) -> CubeMelonPluginErrorCode {
+        let (code, data) = call_runtime(HostCall::GetPluginDetailedInfo {
+            uuid: target_uuid.to_string(),
+            language: language.as_str().to_string(),
+        });
+        *out_detailed_json = match data {
+            ReplyData::Text(text) => CubeMelonString::from_string(text),
+            _ => CubeMelonString::empty(),
+        };
+        code
+    }
+
+    fn find_plugins_for_task(&self, task_json: *const u8, out_uuids: &mut CubeMelonUUIDArray) -> CubeMelonPluginErrorCode {
+        if task_json.is_null() {
+            return CubeMelonPluginErrorCode::NullPointer;
+        }
+        let Ok(task_json) = unsafe { std::ffi::CStr::from_ptr(task_json as *const i8) }.to_str() else {
+            return CubeMelonPluginErrorCode::Encoding;
+        };
+        let (code, data) = call_runtime(HostCall::FindPluginsForTask { task_json: task_json.to_string() });
+        *out_uuids = match data {
+            ReplyData::Uuids(uuids) => CubeMelonUUIDArray::from_vec(uuids.iter().filter_map(|u| parse_uuid(u)).collect()),
+            _ => CubeMelonUUIDArray::empty(),
+        };
+        code
+    }
+
+    fn is_plugin_alive(&self, target_uuid: CubeMelonUUID) -> bool {
+        let (_, data) = call_runtime(HostCall::IsPluginAlive { uuid: target_uuid.to_string() });
+        data == ReplyData::Alive(true)
+    }
+
+    fn execute_task(
+        &mut self,
+        target_uuid: CubeMelonUUID,
+        request: &CubeMelonTaskRequest,
+        result: &mut CubeMelonTaskResult,
+    ) -> CubeMelonPluginErrorCode {
+        let request = match wire_request(request) {
+            Ok(request) => request,
+            Err(code) => return code,
+        };
+        let (code, data) = call_runtime(HostCall::ExecuteTask { uuid: target_uuid.to_string(), request });
+        if let ReplyData::Result(wire) = data {
+            wire.fill(result);
+        }
+        code
+    }
+
+    fn execute_async_task(
+        &mut self,
+        target_uuid: CubeMelonUUID,
+        request: &CubeMelonTaskRequest,
+        callback: Option<CubeMelonTaskCallback>,
+    ) -> CubeMelonPluginErrorCode {
+        // The runtime sees a synchronous call made from a thread of our own
+        let wire = match wire_request(request) {
+            Ok(wire) => wire,
+            Err(code) => return code,
+        };
+        let caller_request = request as *const CubeMelonTaskRequest as usize;
+        let spawned = std::thread::Builder::new().name(format!("cubemelon-task-{}", target_uuid)).spawn(move || {
+            let (code, data) = call_runtime(HostCall::ExecuteTask { uuid: target_uuid.to_string(), request: wire });
+            let mut result = CubeMelonTaskResult::empty();
+            match data {
+                ReplyData::Result(wire) => wire.fill(&mut result),
+                _ => {
+                    result.status = cubemelon_sdk::CubeMelonExecutionStatus::Error;
+                    result.error_code = code;
+                }
+            }
+            if let Some(cb) = callback {
+                unsafe { cb(caller_request as *mut CubeMelonTaskRequest, &result) };
+            }
+            free_task_result(&mut result);
+        });
+        match spawned {
+            Ok(_) => CubeMelonPluginErrorCode::Success,
+            Err(_) => CubeMelonPluginErrorCode::ResourceExhausted,
+        }
+    }
+
+    fn cancel_async_task(&mut self, _request: &mut CubeMelonTaskRequest) -> CubeMelonPluginErrorCode {
+        CubeMelonPluginErrorCode::NotSupported
+    }
+}
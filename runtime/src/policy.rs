@@ -0,0 +1,245 @@
+//! Plugin Enable/Disable Policy
+//!
+//! The `[plugins]` section decides which valid plugin files may be used.
+//! Entries name a plugin by UUID or by name, like `[resident]` keys:
+//!
+//! ```toml
+//! [plugins]
+//! mode = "allowlist"   # or "all" (default)
+//! enabled = ["Hello World Plugin", "b7e4c2a1-5d3f-4e8a-9c61-2f7d8e0a4b19"]
+//! disabled = ["Async Task Plugin"]
+//!
+//! [plugins.pinned]
+//! "Hello World Plugin" = "1.0.0"
+//! ```
+//!
+//! `disabled` always wins. In allowlist mode only plugins listed in `enabled`
+//! are used. A pinned plugin is only used in exactly that version, so a pin
+//! also chooses between files that share a UUID. Excluded files are left out
+//! of discovery and cannot be loaded.
+
+use anyhow::{anyhow, Result};
+use cubemelon_sdk::{CubeMelonLogLevel, CubeMelonVersion};
+
+use crate::host_services::runtime_log;
+use crate::matcher::parse_version;
+use crate::{PluginInfo, PluginsConfig, PluginsMode, RuntimeData};
+
+/// Valid plugin file excluded by the [plugins] section
+#[derive(Debug, Clone)]
+pub struct DisabledPlugin {
+    pub info: PluginInfo,
+    pub reason: String,
+}
+
+/// Whether a config entry names the plugin (by UUID, case-insensitively, or by name)
+pub(crate) fn names_plugin(entry: &str, plugin_info: &PluginInfo) -> bool {
+    entry == plugin_info.name || entry.eq_ignore_ascii_case(&plugin_info.uuid.to_string())
+}
+
+impl PluginsConfig {
+    /// Why a plugin file may not be used, or None if it may
+    pub fn exclusion(&self, plugin_info: &PluginInfo) -> Option<String> {
+        if self.disabled.iter().any(|entry| names_plugin(entry, plugin_info)) {
+            return Some("disabled".to_string());
+        }
+        if self.mode == PluginsMode::Allowlist && !self.enabled.iter().any(|entry| names_plugin(entry, plugin_info)) {
+            return Some("not in the allowlist".to_string());
+        }
+        let (_, pinned) = self.pinned.iter().find(|(entry, _)| names_plugin(entry, plugin_info))?;
+        match parse_version(pinned) {
+            Some(version) if version == plugin_info.version => None,
+            Some(version) => Some(format!("pinned to version {}", version)),
+            None => Some(format!("invalid pinned version '{}'", pinned)),
+        }
+    }
+
+    /// Split plugin files into usable ones and excluded ones
+    pub fn apply(&self, candidates: Vec<PluginInfo>) -> (Vec<PluginInfo>, Vec<DisabledPlugin>) {
+        let mut allowed = Vec::new();
+        let mut disabled = Vec::new();
+        for info in candidates {
+            match self.exclusion(&info) {
+                Some(reason) => disabled.push(DisabledPlugin { info, reason }),
+                None => allowed.push(info),
+            }
+        }
+        (allowed, disabled)
+    }
+
+    /// Allow a plugin again
+    fn enable(&mut self, plugin_info: &PluginInfo) {
+        self.disabled.retain(|entry| !names_plugin(entry, plugin_info));
+        if self.mode == PluginsMode::Allowlist && !self.enabled.iter().any(|entry| names_plugin(entry, plugin_info)) {
+            self.enabled.push(plugin_info.uuid.to_string());
+        }
+    }
+
+    /// Exclude a plugin
+    fn disable(&mut self, plugin_info: &PluginInfo) {
+        self.enabled.retain(|entry| !names_plugin(entry, plugin_info));
+        if !self.disabled.iter().any(|entry| names_plugin(entry, plugin_info)) {
+            self.disabled.push(plugin_info.uuid.to_string());
+        }
+    }
+
+    /// Pin a plugin to a version, or remove its pin
+    fn pin(&mut self, plugin_info: &PluginInfo, version: Option<CubeMelonVersion>) {
+        self.pinned.retain(|entry, _| !names_plugin(entry, plugin_info));
+        if let Some(version) = version {
+            self.pinned.insert(plugin_info.uuid.to_string(), version.to_string());
+        }
+    }
+}
+
+impl RuntimeData {
+    /// Find a plugin by number, name or UUID, including disabled ones
+    fn resolve_any_plugin(&self, plugin_id: &str) -> Result<PluginInfo> {
+        if let Ok(plugin_info) = self.resolve_plugin_id(plugin_id) {
+            return Ok(plugin_info);
+        }
+        self.disabled_plugins
+            .iter()
+            .map(|disabled| &disabled.info)
+            .find(|info| names_plugin(plugin_id, info))
+            .cloned()
+            .ok_or_else(|| anyhow!("Plugin not found: {}", plugin_id))
+    }
+
+    /// Enable or disable a plugin, persist the choice and rescan
+    ///
+    /// A disabled plugin is stopped and unloaded first.
+    pub fn set_plugin_enabled(&mut self, plugin_id: &str, enabled: bool) -> Result<PluginInfo> {
+        let plugin_info = self.resolve_any_plugin(plugin_id)?;
+        if enabled {
+            self.config.plugins.enable(&plugin_info);
+        } else {
+            self.release_plugin(&plugin_info);
+            self.config.plugins.disable(&plugin_info);
+        }
+        self.persist_config("plugins");
+        runtime_log(
+            CubeMelonLogLevel::Info,
+            &format!("Plugin {} {}", plugin_info.name, if enabled { "enabled" } else { "disabled" }),
+        );
+        self.scan_plugins()?;
+        Ok(plugin_info)
+    }
+
+    /// Pin a plugin to a version (or unpin it), persist the choice and rescan
+    pub fn set_plugin_pin(&mut self, plugin_id: &str, version: Option<&str>) -> Result<PluginInfo> {
+        let plugin_info = self.resolve_any_plugin(plugin_id)?;
+        let version = version
+            .map(|v| parse_version(v).ok_or_else(|| anyhow!("Invalid version: {}", v)))
+            .transpose()?;
+        if version.is_some_and(|v| v != plugin_info.version) {
+            // Another build is about to replace the one in use
+            self.release_plugin(&plugin_info);
+        }
+        self.config.plugins.pin(&plugin_info, version);
+        self.persist_config("plugins");
+        match version {
+            Some(version) => runtime_log(
+                CubeMelonLogLevel::Info,
+                &format!("Plugin {} pinned to version {}", plugin_info.name, version),
+            ),
+            None => runtime_log(CubeMelonLogLevel::Info, &format!("Plugin {} unpinned", plugin_info.name)),
+        }
+        self.scan_plugins()?;
+        Ok(plugin_info)
+    }
+
+    /// Stop a plugin's resident service and unload it
+    fn release_plugin(&mut self, plugin_info: &PluginInfo) {
+        if self.residents.contains_key(&plugin_info.uuid) {
+            let _ = self.stop_resident(&plugin_info.uuid.to_string());
+        }
+        if self.is_loaded(plugin_info.uuid) {
+            let _ = self.unload_plugin(plugin_info.uuid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cubemelon_sdk::CubeMelonUUID;
+    use std::path::PathBuf;
+
+    fn plugin(id: u8, name: &str, version: CubeMelonVersion) -> PluginInfo {
+        PluginInfo {
+            uuid: CubeMelonUUID::from_bytes([id; 16]),
+            version,
+            supported_types: 0,
+            name: name.to_string(),
+            description: String::new(),
+            path: PathBuf::from(format!("{}-{}", name, version)),
+            capabilities: Default::default(),
+            dependencies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_config_excludes_plugins() {
+        let config: crate::RuntimeConfig = toml::from_str(
+            r#"
+            [settings]
+            plugins_directory = "plugins"
+            language = "auto"
+
+            [plugins]
+            mode = "allowlist"
+            enabled = ["Alpha", "02020202-0202-0202-0202-020202020202", "Gamma"]
+            disabled = ["Gamma"]
+
+            [plugins.pinned]
+            "02020202-0202-0202-0202-020202020202" = "1.1.0"
+            "#,
+        )
+        .unwrap();
+        let v1 = CubeMelonVersion::new(1, 0, 0);
+        let v11 = CubeMelonVersion::new(1, 1, 0);
+        let candidates = vec![
+            plugin(1, "Alpha", v1),
+            plugin(2, "Beta", v1),
+            plugin(2, "Beta", v11),
+            plugin(3, "Gamma", v1),
+            plugin(4, "Delta", v1),
+        ];
+
+        let (allowed, disabled) = config.plugins.apply(candidates);
+        let allowed: Vec<_> = allowed.iter().map(|p| (p.name.as_str(), p.version)).collect();
+        assert_eq!(allowed, [("Alpha", v1), ("Beta", v11)]);
+        let reasons: Vec<_> = disabled.iter().map(|d| (d.info.name.as_str(), d.reason.as_str())).collect();
+        assert_eq!(
+            reasons,
+            [("Beta", "pinned to version 1.1.0"), ("Gamma", "disabled"), ("Delta", "not in the allowlist")]
+        );
+    }
+
+    #[test]
+    fn test_enable_disable_and_pin_edit_the_lists() {
+        let alpha = plugin(1, "Alpha", CubeMelonVersion::new(1, 0, 0));
+        let mut config = PluginsConfig { disabled: vec!["alpha".into(), "Alpha".into()], ..Default::default() };
+
+        config.enable(&alpha);
+        assert_eq!(config.disabled, ["alpha"]);
+        assert!(config.enabled.is_empty());
+        assert_eq!(config.exclusion(&alpha), None);
+
+        config.mode = PluginsMode::Allowlist;
+        config.enable(&alpha);
+        assert_eq!(config.enabled, [alpha.uuid.to_string()]);
+
+        config.disable(&alpha);
+        assert!(config.enabled.is_empty());
+        assert_eq!(config.disabled, ["alpha".to_string(), alpha.uuid.to_string()]);
+        assert_eq!(config.exclusion(&alpha).as_deref(), Some("disabled"));
+
+        config.pin(&alpha, Some(CubeMelonVersion::new(2, 0, 0)));
+        config.pin(&alpha, Some(CubeMelonVersion::new(1, 0, 0)));
+        assert_eq!(config.pinned.len(), 1);
+        config.pin(&alpha, None);
+        assert!(config.pinned.is_empty());
+    }
+}
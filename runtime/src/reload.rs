@@ -0,0 +1,417 @@
+//! Plugin Hot Reload
+//!
+//! Opt-in watch mode for the plugin search paths.
+//!
+//! The search paths are polled for added or changed library files. A file is only
+//! picked up once its size and modification time stop changing between two
+//! polls, so half-written build outputs are never loaded.
+//! Replace files by renaming a finished build over them: overwriting a library
+//! that is still mapped crashes the process on most platforms.
+//!
+//! Reloading a plugin that is in use:
+//! 1. Every live instance that implements the State interface hands over its
+//!    `Local` state through `load_state`.
+//! 2. The old library is unloaded as by `unload` (deferred while in use).
+//! 3. The new file goes through `validate_and_extract_info` and is loaded.
+//! 4. The instances are recreated under the same names and receive their
+//!    state back through `save_state`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use cubemelon_sdk::{
+    CubeMelonUUID, CubeMelonPlugin, CubeMelonPluginErrorCode, CubeMelonLogLevel, CubeMelonPluginType,
+    CubeMelonPluginStateInterfaceImpl, CubeMelonPluginStateScope, CubeMelonValue, CubeMelonValueTag,
+};
+
+use crate::RuntimeData;
+use crate::host_services::runtime_log;
+use crate::loader::UnloadOutcome;
+
+/// Size and modification time of a plugin file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Polling watcher for plugin library files
+pub struct PluginWatcher {
+    /// Files as last reported
+    known: HashMap<PathBuf, FileStamp>,
+    /// Changed files waiting to settle
+    settling: HashMap<PathBuf, FileStamp>,
+}
+
+impl PluginWatcher {
+    /// Start watching; files already present are not reported
+    pub fn new(files: &[PathBuf]) -> Self {
+        Self {
+            known: stamp_files(files),
+            settling: HashMap::new(),
+        }
+    }
+
+    /// Report files that were added or changed and have stopped changing
+    pub fn poll(&mut self, files: &[PathBuf]) -> Vec<PathBuf> {
+        let current = stamp_files(files);
+        let mut ready = Vec::new();
+
+        for (path, stamp) in &current {
+            if self.known.get(path) == Some(stamp) {
+                self.settling.remove(path);
+                continue;
+            }
+            if self.settling.get(path) == Some(stamp) {
+                self.settling.remove(path);
+                self.known.insert(path.clone(), *stamp);
+                ready.push(path.clone());
+            } else {
+                self.settling.insert(path.clone(), *stamp);
+            }
+        }
+
+        // Deleted files are forgotten; a loaded library stays mapped until unloaded
+        self.known.retain(|path, _| current.contains_key(path));
+        self.settling.retain(|path, _| current.contains_key(path));
+
+        ready.sort();
+        ready
+    }
+}
+
+fn stamp_files(files: &[PathBuf]) -> HashMap<PathBuf, FileStamp> {
+    files
+        .iter()
+        .filter_map(|path| {
+            let meta = std::fs::metadata(path).ok()?;
+            Some((path.clone(), FileStamp { len: meta.len(), modified: meta.modified().ok() }))
+        })
+        .collect()
+}
+
+/// Instance to recreate after a swap
+struct CarriedInstance {
+    name: Option<String>,
+    /// `Local` state handed over by the old instance, if it has any
+    state: Option<Vec<u8>>,
+}
+
+/// Reload waiting for a deferred unload to complete
+pub struct PendingReload {
+    path: PathBuf,
+    uuid: CubeMelonUUID,
+    /// Instances to recreate; `None` if the plugin was not loaded
+    instances: Option<Vec<CarriedInstance>>,
+}
+
+/// Ask an instance for its `Local` state
+///
+/// String and Buffer values are supported; anything else is not carried.
+fn take_local_state(
+    state: &CubeMelonPluginStateInterfaceImpl,
+    instance: *mut CubeMelonPlugin,
+) -> Option<Vec<u8>> {
+    let mut value = CubeMelonValue::null();
+    let rc = (state.load_state)(instance, CubeMelonPluginStateScope::Local, &mut value);
+
+    let bytes = if rc != CubeMelonPluginErrorCode::Success {
+        None
+    } else {
+        match value.tag {
+            CubeMelonValueTag::String => unsafe { value.as_str().ok().map(|s| s.as_bytes().to_vec()) },
+            CubeMelonValueTag::Buffer => Some(unsafe { value.as_buffer() }.to_vec()),
+            _ => None,
+        }
+    };
+
+    if let Some(free_fn) = value.free_value {
+        unsafe { free_fn(&mut value) };
+    }
+    bytes
+}
+
+/// Hand carried `Local` state to a fresh instance
+fn restore_local_state(
+    state: &CubeMelonPluginStateInterfaceImpl,
+    instance: *mut CubeMelonPlugin,
+    data: &[u8],
+) -> CubeMelonPluginErrorCode {
+    (state.save_state)(instance, CubeMelonPluginStateScope::Local, data.as_ptr(), data.len())
+}
+
+impl RuntimeData {
+    /// Turn watch mode on or off and persist the choice
+    pub fn set_watch_enabled(&mut self, enabled: bool) {
+        self.watcher = if enabled {
+            Some(PluginWatcher::new(&self.find_plugin_files()))
+        } else {
+            None
+        };
+
+        self.config.watch.enabled = enabled;
+        if !self.config_path.as_os_str().is_empty() {
+            if let Err(e) = Self::save_config(&self.config_path, &self.config) {
+                runtime_log(CubeMelonLogLevel::Warn, &format!("Failed to persist config (watch): {}", e));
+            }
+        }
+        runtime_log(CubeMelonLogLevel::Info, &format!("Watch mode {}", if enabled { "enabled" } else { "disabled" }));
+    }
+
+    /// Pick up changed plugin files; returns true if anything was reloaded
+    pub fn poll_plugin_changes(&mut self) -> bool {
+        if self.watcher.is_none() {
+            return false;
+        }
+
+        self.retry_pending_unloads();
+        let mut reloaded = self.finish_pending_reloads();
+
+        let files = self.find_plugin_files();
+        let changed = match self.watcher.as_mut() {
+            Some(watcher) => watcher.poll(&files),
+            None => Vec::new(),
+        };
+        for path in changed {
+            runtime_log(CubeMelonLogLevel::Info, &format!("Plugin file changed: {:?}", path));
+            match self.reload_plugin_file(&path) {
+                Ok(()) => reloaded = true,
+                Err(e) => {
+                    runtime_log(CubeMelonLogLevel::Warn, &format!("Reload failed for {:?}: {}", path, e));
+                    println!("Reload failed for {}: {}", path.display(), e);
+                    reloaded = true;
+                }
+            }
+        }
+        reloaded
+    }
+
+    /// Swap in a new build of a plugin file
+    pub fn reload_plugin_file(&mut self, path: &Path) -> Result<()> {
+        if self.shadowed_plugins.iter().any(|p| p.path == path) {
+            // Another file provides this UUID; a rescan decides which one wins
+            runtime_log(CubeMelonLogLevel::Info, &format!("Ignoring change to shadowed plugin file {:?}", path));
+            return Ok(());
+        }
+        if self.disabled_plugins.iter().any(|p| p.info.path == path) {
+            runtime_log(CubeMelonLogLevel::Info, &format!("Ignoring change to disabled plugin file {:?}", path));
+            return Ok(());
+        }
+
+        let previous = self.discovered_plugins.iter().find(|p| p.path == path).map(|p| p.uuid);
+
+        if let Some(uuid) = previous {
+            if self.is_loaded(uuid) {
+                let instances = self.take_instances_for_reload(uuid);
+                if self.unload_plugin(uuid) == Ok(UnloadOutcome::Deferred) {
+                    // The old code is still mapped; loading now would just return it again
+                    println!("Plugin {} is still in use; reload deferred.", uuid);
+                    self.pending_reloads.push(PendingReload { path: path.to_path_buf(), uuid, instances: Some(instances) });
+                    return Ok(());
+                }
+                return self.swap_in(path, Some(instances));
+            }
+            if self.pending_unloads.contains_key(&uuid) {
+                self.pending_reloads.push(PendingReload { path: path.to_path_buf(), uuid, instances: None });
+                return Ok(());
+            }
+        }
+
+        self.swap_in(path, None)
+    }
+
+    /// Complete reloads whose old library has been closed meanwhile
+    fn finish_pending_reloads(&mut self) -> bool {
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_reloads)
+            .into_iter()
+            .partition(|r| !self.pending_unloads.contains_key(&r.uuid));
+        self.pending_reloads = waiting;
+
+        let any = !ready.is_empty();
+        for reload in ready {
+            if let Err(e) = self.swap_in(&reload.path, reload.instances) {
+                runtime_log(CubeMelonLogLevel::Warn, &format!("Deferred reload failed for {:?}: {}", reload.path, e));
+                println!("Reload failed for {}: {}", reload.path.display(), e);
+            }
+        }
+        any
+    }
+
+    /// Capture the instances of a plugin (and their state) before unloading it
+    fn take_instances_for_reload(&mut self, uuid: CubeMelonUUID) -> Vec<CarriedInstance> {
+        let state_iface = self
+            .entry_points(uuid)
+            .ok()
+            .and_then(|e| e.get_interface::<CubeMelonPluginStateInterfaceImpl>(CubeMelonPluginType::State));
+
+        self.instances
+            .iter()
+            .filter(|(key, _)| key.uuid == uuid)
+            .map(|(key, instance)| CarriedInstance {
+                name: key.name.clone(),
+                state: state_iface.and_then(|s| take_local_state(s, instance.handle())),
+            })
+            .collect()
+    }
+
+    /// Validate and register a plugin file, then reload it and its instances
+    fn swap_in(&mut self, path: &Path, carried: Option<Vec<CarriedInstance>>) -> Result<()> {
+        let info = self.validate_and_extract_info(&path.to_path_buf())?;
+
+        match self.discovered_plugins.iter_mut().find(|p| p.path == info.path || p.uuid == info.uuid) {
+            Some(slot) => *slot = info.clone(),
+            None => self.discovered_plugins.push(info.clone()),
+        }
+
+        let Some(carried) = carried else {
+            runtime_log(CubeMelonLogLevel::Info, &format!("Plugin updated: {} v{}", info.name, info.version));
+            println!("Plugin '{}' v{} updated.", info.name, info.version);
+            return Ok(());
+        };
+
+        self.load_plugin(&info.uuid.to_string())?;
+        let state_iface = self
+            .entry_points(info.uuid)
+            .ok()
+            .and_then(|e| e.get_interface::<CubeMelonPluginStateInterfaceImpl>(CubeMelonPluginType::State));
+
+        for instance in carried {
+            let handle = match self.create_instance(info.uuid, instance.name.as_deref()) {
+                Ok(handle) => handle,
+                Err(code) => {
+                    runtime_log(CubeMelonLogLevel::Warn, &format!("Failed to recreate instance after reload: {:?}", code));
+                    continue;
+                }
+            };
+            if let (Some(state), Some(iface)) = (&instance.state, state_iface) {
+                let rc = restore_local_state(iface, handle, state);
+                if rc != CubeMelonPluginErrorCode::Success {
+                    runtime_log(CubeMelonLogLevel::Warn, &format!("Plugin rejected carried state: {:?}", rc));
+                }
+            }
+        }
+
+        runtime_log(CubeMelonLogLevel::Info, &format!("Plugin reloaded: {} v{}", info.name, info.version));
+        println!("Plugin '{}' v{} reloaded.", info.name, info.version);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use cubemelon_sdk::{
+        CubeMelonPluginStateInterface, create_plugin_state_interface,
+        create_plugin_instance, destroy_plugin_instance,
+    };
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "cubemelon-reload-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn library_name(stem: &str) -> String {
+        #[cfg(windows)]
+        return format!("{}.dll", stem);
+        #[cfg(target_os = "macos")]
+        return format!("lib{}.dylib", stem);
+        #[cfg(all(unix, not(target_os = "macos")))]
+        return format!("lib{}.so", stem);
+    }
+
+    #[test]
+    fn test_watcher_reports_settled_changes_only() {
+        let dir = temp_dir();
+        let existing = dir.join(library_name("existing"));
+        std::fs::write(&existing, b"v1").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+
+        let files = || crate::search::find_plugin_files(std::slice::from_ref(&dir), 0);
+        let mut watcher = PluginWatcher::new(&files());
+        assert!(watcher.poll(&files()).is_empty());
+
+        // A new file is reported once it stops changing
+        let added = dir.join(library_name("added"));
+        std::fs::write(&added, b"partial").unwrap();
+        assert!(watcher.poll(&files()).is_empty());
+        std::fs::write(&added, b"complete build").unwrap();
+        assert!(watcher.poll(&files()).is_empty());
+        assert_eq!(watcher.poll(&files()), vec![added.clone()]);
+        assert!(watcher.poll(&files()).is_empty());
+
+        // A rewrite of an existing file is reported too
+        std::fs::write(&existing, b"version two").unwrap();
+        assert!(watcher.poll(&files()).is_empty());
+        assert_eq!(watcher.poll(&files()), vec![existing]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Plugin keeping a counter as its Local state
+    struct CounterPlugin {
+        count: String,
+    }
+
+    impl CubeMelonPluginStateInterface for CounterPlugin {
+        fn load_state(&self, scope: CubeMelonPluginStateScope, data: &mut CubeMelonValue) -> CubeMelonPluginErrorCode {
+            if scope != CubeMelonPluginStateScope::Local {
+                return CubeMelonPluginErrorCode::NotSupported;
+            }
+            *data = CubeMelonValue::string(self.count.clone());
+            CubeMelonPluginErrorCode::Success
+        }
+
+        fn save_state(&mut self, scope: CubeMelonPluginStateScope, data: *const u8, size: usize) -> CubeMelonPluginErrorCode {
+            if scope != CubeMelonPluginStateScope::Local {
+                return CubeMelonPluginErrorCode::NotSupported;
+            }
+            let bytes = unsafe { std::slice::from_raw_parts(data, size) };
+            self.count = String::from_utf8_lossy(bytes).into_owned();
+            CubeMelonPluginErrorCode::Success
+        }
+
+        fn get_format_name(&self, _scope: CubeMelonPluginStateScope) -> *const u8 {
+            c"text".as_ptr() as *const u8
+        }
+
+        fn get_state_value(&self, _: CubeMelonPluginStateScope, _: *const u8, _: &mut CubeMelonValue) -> CubeMelonPluginErrorCode {
+            CubeMelonPluginErrorCode::NotSupported
+        }
+
+        fn set_state_value(&mut self, _: CubeMelonPluginStateScope, _: *const u8, _: *const u8, _: usize) -> CubeMelonPluginErrorCode {
+            CubeMelonPluginErrorCode::NotSupported
+        }
+
+        fn list_state_keys(&self, _: CubeMelonPluginStateScope, _: &mut CubeMelonValue) -> CubeMelonPluginErrorCode {
+            CubeMelonPluginErrorCode::NotSupported
+        }
+
+        fn clear_state_value(&mut self, _: CubeMelonPluginStateScope, _: *const u8) -> CubeMelonPluginErrorCode {
+            CubeMelonPluginErrorCode::NotSupported
+        }
+    }
+
+    #[test]
+    fn test_local_state_survives_swap() {
+        let iface = create_plugin_state_interface::<CounterPlugin>();
+        let old = create_plugin_instance(CounterPlugin { count: "42".to_string() });
+        let new = create_plugin_instance(CounterPlugin { count: "0".to_string() });
+
+        let carried = take_local_state(&iface, old).unwrap();
+        destroy_plugin_instance(old);
+        assert_eq!(carried, b"42");
+
+        assert_eq!(restore_local_state(&iface, new, &carried), CubeMelonPluginErrorCode::Success);
+        assert_eq!(take_local_state(&iface, new).unwrap(), b"42");
+        destroy_plugin_instance(new);
+    }
+}
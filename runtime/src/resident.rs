@@ -0,0 +1,503 @@
+//! Resident Service Supervisor
+//!
+//! Starts, controls and stops plugins that implement the Resident interface.
+//!
+//! Each service runs on a dedicated named instance (`<uuid>#resident`), so it
+//! never shares state with the default instance used for task execution.
+//! Services listed in the `[resident]` configuration section are started when
+//! the interactive runtime starts:
+//! ```toml
+//! [resident."Resident Plugin"]
+//! autostart = true
+//! config = { interval_ms = 500 }
+//! restart = "on-failure"   # "never" (default), "on-failure" or "always"
+//! max_retries = 3
+//! backoff_ms = 1000
+//! ```
+//! `config` may also be a JSON string. Every running service is stopped
+//! before the runtime shuts down.
+//!
+//! ## Restarts
+//! The supervisor polls running services. When one reports `Error` (or, with
+//! `always`, any inactive status) it is restarted by `reset` followed by
+//! `start` with the configuration the plugin last reported. Restarts are
+//! delayed by an exponential backoff; a service that stays up for
+//! `STABLE_AFTER` has its retry count cleared.
+
+use std::ffi::CString;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use cubemelon_sdk::{
+    CubeMelonUUID, CubeMelonPlugin, CubeMelonPluginErrorCode, CubeMelonLogLevel, CubeMelonPluginType,
+    CubeMelonExecutionStatus, CubeMelonResidentInterfaceImpl,
+};
+
+use crate::host_services::{runtime_log, enter_plugin};
+use crate::instances::InstanceKey;
+use crate::{PluginInfo, ResidentConfig, RestartPolicy, RuntimeData};
+
+/// Name of the instance a resident service runs on
+pub const RESIDENT_INSTANCE: &str = "resident";
+
+/// Configuration passed to `start` when none is given
+const EMPTY_CONFIG: &str = "{}";
+
+/// How often the interactive runtime checks running services
+pub const SUPERVISE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Uptime after which a restarted service counts as healthy again
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// A service started by the supervisor
+pub struct ResidentService {
+    interface: &'static CubeMelonResidentInterfaceImpl,
+    instance: *mut CubeMelonPlugin,
+    started_at: DateTime<Local>,
+    /// Restart policy and backoff settings in effect
+    settings: ResidentConfig,
+    /// Restarts since the service was last healthy
+    attempts: u32,
+    /// Restarts since the service was started
+    restarts: u32,
+    last_restart: Option<Instant>,
+    /// When the next restart is due, once one is scheduled
+    next_attempt: Option<Instant>,
+    /// The last restart attempt itself failed
+    restart_failed: bool,
+    /// The retry limit was reached; the service stays down
+    gave_up: bool,
+}
+
+impl ResidentService {
+    /// Current status reported by the plugin
+    pub fn status(&self) -> CubeMelonExecutionStatus {
+        (self.interface.get_status)(self.instance)
+    }
+
+    /// Current configuration reported by the plugin
+    pub fn configuration(&self) -> Option<String> {
+        let ptr = (self.interface.get_configuration)(self.instance);
+        if ptr.is_null() {
+            return None;
+        }
+        Some(unsafe { std::ffi::CStr::from_ptr(ptr as *const i8) }.to_string_lossy().into_owned())
+    }
+}
+
+/// Whether a status means the service is still active
+pub(crate) fn is_active(status: CubeMelonExecutionStatus) -> bool {
+    matches!(status, CubeMelonExecutionStatus::Running | CubeMelonExecutionStatus::Suspended)
+}
+
+impl RestartPolicy {
+    /// Whether a service in this status should be brought back
+    pub fn should_restart(self, status: CubeMelonExecutionStatus) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => status == CubeMelonExecutionStatus::Error,
+            RestartPolicy::Always => !is_active(status),
+        }
+    }
+}
+
+/// Delay before restart attempt number `attempts + 1`
+pub fn restart_delay(settings: &ResidentConfig, attempts: u32) -> Duration {
+    let factor = 1u64.checked_shl(attempts).unwrap_or(u64::MAX);
+    let delay = settings.backoff_ms.saturating_mul(factor).min(settings.max_backoff_ms);
+    Duration::from_millis(delay)
+}
+
+/// JSON text handed to `start` for a configured `config` value
+pub fn config_to_json(config: &toml::Value) -> Result<String, CubeMelonPluginErrorCode> {
+    match config {
+        toml::Value::String(json) => Ok(json.clone()),
+        other => serde_json::to_string(other).map_err(|_| CubeMelonPluginErrorCode::Encoding),
+    }
+}
+
+impl RuntimeData {
+    /// Configuration entry for a plugin, keyed by UUID or name
+    fn resident_config(&self, plugin_info: &PluginInfo) -> Option<&ResidentConfig> {
+        self.config
+            .resident
+            .get(&plugin_info.uuid.to_string())
+            .or_else(|| self.config.resident.get(&plugin_info.name))
+    }
+
+    /// Forget services whose instance was destroyed behind the supervisor's back
+    fn prune_residents(&mut self) {
+        let instances = &self.instances;
+        self.residents.retain(|uuid, service| {
+            let key = InstanceKey::new(*uuid, Some(RESIDENT_INSTANCE));
+            instances.get(&key).map(|instance| instance.handle()) == Some(service.instance)
+        });
+    }
+
+    fn resolve_resident(&self, plugin_id: &str) -> Result<PluginInfo, CubeMelonPluginErrorCode> {
+        self.resolve_plugin_id(plugin_id).map_err(|e| {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("{}", e));
+            CubeMelonPluginErrorCode::PluginNotFound
+        })
+    }
+
+    /// Load a plugin and start its resident service
+    ///
+    /// `config_json` overrides the `[resident]` configuration entry.
+    pub fn start_resident(
+        &mut self,
+        plugin_id: &str,
+        config_json: Option<&str>,
+    ) -> Result<PluginInfo, CubeMelonPluginErrorCode> {
+        self.prune_residents();
+        let plugin_info = self.resolve_resident(plugin_id)?;
+        let uuid = plugin_info.uuid;
+        if let Some(service) = self.residents.get(&uuid) {
+            if is_active(service.status()) {
+                runtime_log(CubeMelonLogLevel::Warn, &format!("Resident service already running: {}", plugin_info.name));
+                return Err(CubeMelonPluginErrorCode::InvalidState);
+            }
+        }
+
+        let config_json = match config_json {
+            Some(json) => json.to_string(),
+            None => match self.resident_config(&plugin_info).and_then(|c| c.config.as_ref()) {
+                Some(config) => config_to_json(config)?,
+                None => EMPTY_CONFIG.to_string(),
+            },
+        };
+        if let Err(e) = serde_json::from_str::<serde_json::Value>(&config_json) {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Invalid resident configuration for {}: {}", plugin_info.name, e));
+            return Err(CubeMelonPluginErrorCode::Parse);
+        }
+        let config_cstr = CString::new(config_json).map_err(|_| CubeMelonPluginErrorCode::InvalidParameter)?;
+
+        if let Err(e) = self.load_plugin(plugin_id) {
+            runtime_log(CubeMelonLogLevel::Error, &format!("Failed to load {}: {:#}", plugin_info.name, e));
+            return Err(CubeMelonPluginErrorCode::PluginLoadFailed);
+        }
+        let interface = self
+            .entry_points(uuid)?
+            .get_interface::<CubeMelonResidentInterfaceImpl>(CubeMelonPluginType::Resident)
+            .ok_or(CubeMelonPluginErrorCode::InterfaceNotSupported)?;
+        let instance = self.get_or_create_instance(uuid, Some(RESIDENT_INSTANCE))?;
+
+        let _caller = enter_plugin(uuid);
+        // A service that stopped or failed earlier has to be reset before it can start again
+        if matches!(
+            (interface.get_status)(instance),
+            CubeMelonExecutionStatus::Completed | CubeMelonExecutionStatus::Error | CubeMelonExecutionStatus::Cancelled
+        ) {
+            let rc = (interface.reset)(instance);
+            if rc != CubeMelonPluginErrorCode::Success {
+                runtime_log(CubeMelonLogLevel::Warn, &format!("Resident reset failed for {}: {:?}", plugin_info.name, rc));
+                return Err(rc);
+            }
+        }
+        let rc = (interface.start)(instance, config_cstr.as_ptr() as *const u8);
+        if rc != CubeMelonPluginErrorCode::Success {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Resident start failed for {}: {:?}", plugin_info.name, rc));
+            return Err(rc);
+        }
+
+        runtime_log(CubeMelonLogLevel::Info, &format!("Started resident service: {}", plugin_info.name));
+        let settings = self.resident_config(&plugin_info).cloned().unwrap_or_default();
+        self.residents.insert(uuid, ResidentService {
+            interface,
+            instance,
+            started_at: Local::now(),
+            settings,
+            attempts: 0,
+            restarts: 0,
+            last_restart: None,
+            next_attempt: None,
+            restart_failed: false,
+            gave_up: false,
+        });
+        Ok(plugin_info)
+    }
+
+    /// Suspend a running service
+    pub fn suspend_resident(&mut self, plugin_id: &str) -> Result<PluginInfo, CubeMelonPluginErrorCode> {
+        self.control_resident(plugin_id, "suspend", |iface| iface.suspend)
+    }
+
+    /// Resume a suspended service
+    pub fn resume_resident(&mut self, plugin_id: &str) -> Result<PluginInfo, CubeMelonPluginErrorCode> {
+        self.control_resident(plugin_id, "resume", |iface| iface.resume)
+    }
+
+    fn control_resident(
+        &mut self,
+        plugin_id: &str,
+        action: &str,
+        function: fn(&CubeMelonResidentInterfaceImpl) -> extern "C" fn(*mut CubeMelonPlugin) -> CubeMelonPluginErrorCode,
+    ) -> Result<PluginInfo, CubeMelonPluginErrorCode> {
+        self.prune_residents();
+        let plugin_info = self.resolve_resident(plugin_id)?;
+        let service = self.residents.get(&plugin_info.uuid).ok_or(CubeMelonPluginErrorCode::InvalidState)?;
+
+        let rc = {
+            let _caller = enter_plugin(plugin_info.uuid);
+            function(service.interface)(service.instance)
+        };
+        if rc != CubeMelonPluginErrorCode::Success {
+            runtime_log(CubeMelonLogLevel::Warn, &format!("Resident {} failed for {}: {:?}", action, plugin_info.name, rc));
+            return Err(rc);
+        }
+        runtime_log(CubeMelonLogLevel::Info, &format!("Resident service {}: {}", action, plugin_info.name));
+        Ok(plugin_info)
+    }
+
+    /// Stop a service and destroy its instance
+    pub fn stop_resident(&mut self, plugin_id: &str) -> Result<PluginInfo, CubeMelonPluginErrorCode> {
+        self.prune_residents();
+        let plugin_info = self.resolve_resident(plugin_id)?;
+        if !self.residents.contains_key(&plugin_info.uuid) {
+            return Err(CubeMelonPluginErrorCode::InvalidState);
+        }
+        self.stop_service(plugin_info.uuid)?;
+        Ok(plugin_info)
+    }
+
+    fn stop_service(&mut self, uuid: CubeMelonUUID) -> Result<(), CubeMelonPluginErrorCode> {
+        let Some(service) = self.residents.remove(&uuid) else {
+            return Ok(());
+        };
+        let mut outcome = Ok(());
+        if is_active(service.status()) {
+            let rc = {
+                let _caller = enter_plugin(uuid);
+                (service.interface.stop)(service.instance)
+            };
+            if rc != CubeMelonPluginErrorCode::Success {
+                runtime_log(CubeMelonLogLevel::Warn, &format!("Resident stop failed for {}: {:?}", uuid, rc));
+                outcome = Err(rc);
+            }
+        }
+        // The instance is torn down even if the service refused to stop
+        let _ = self.destroy_instance(uuid, Some(RESIDENT_INSTANCE));
+        runtime_log(CubeMelonLogLevel::Info, &format!("Stopped resident service: {}", uuid));
+        outcome
+    }
+
+    /// Apply restart policies to services that failed or stopped on their own
+    ///
+    /// Returns true if any service was restarted.
+    pub fn supervise_residents(&mut self) -> bool {
+        self.prune_residents();
+        let now = Instant::now();
+        let mut restarted = false;
+
+        for (uuid, service) in self.residents.iter_mut() {
+            let status = service.status();
+            if is_active(status) {
+                if service.attempts > 0 && service.last_restart.is_some_and(|t| now.duration_since(t) >= STABLE_AFTER) {
+                    service.attempts = 0;
+                }
+                continue;
+            }
+            if service.gave_up || !(service.restart_failed || service.settings.restart.should_restart(status)) {
+                continue;
+            }
+            if service.settings.restart == RestartPolicy::OnFailure && service.attempts >= service.settings.max_retries {
+                runtime_log(
+                    CubeMelonLogLevel::Error,
+                    &format!("Resident service {} failed {} times in a row; not restarting", uuid, service.attempts),
+                );
+                service.gave_up = true;
+                continue;
+            }
+
+            let due = *service.next_attempt.get_or_insert_with(|| {
+                let delay = restart_delay(&service.settings, service.attempts);
+                runtime_log(
+                    CubeMelonLogLevel::Warn,
+                    &format!("Resident service {} is {:?}; restarting in {} ms", uuid, status, delay.as_millis()),
+                );
+                now + delay
+            });
+            if now < due {
+                continue;
+            }
+
+            service.next_attempt = None;
+            service.attempts += 1;
+            service.last_restart = Some(now);
+            let rc = restart_service(*uuid, service, status);
+            service.restart_failed = rc != CubeMelonPluginErrorCode::Success;
+            if service.restart_failed {
+                runtime_log(
+                    CubeMelonLogLevel::Warn,
+                    &format!("Restart {} of resident service {} failed: {:?}", service.attempts, uuid, rc),
+                );
+            } else {
+                service.restarts += 1;
+                runtime_log(
+                    CubeMelonLogLevel::Info,
+                    &format!("Restarted resident service {} (attempt {}, was {:?})", uuid, service.attempts, status),
+                );
+                restarted = true;
+            }
+        }
+        restarted
+    }
+
+    /// Start every service configured with `autostart`
+    pub fn start_configured_residents(&mut self) {
+        let autostart: Vec<String> = self
+            .config
+            .resident
+            .iter()
+            .filter(|(_, config)| config.autostart)
+            .map(|(plugin_id, _)| plugin_id.clone())
+            .collect();
+        for plugin_id in autostart {
+            match self.start_resident(&plugin_id, None) {
+                Ok(plugin_info) => println!("Resident service '{}' started.", plugin_info.name),
+                Err(code) => {
+                    runtime_log(CubeMelonLogLevel::Error, &format!("Failed to start resident service {}: {:?}", plugin_id, code));
+                }
+            }
+        }
+    }
+
+    /// Stop every running service
+    pub fn stop_all_residents(&mut self) {
+        let uuids: Vec<CubeMelonUUID> = self.residents.keys().copied().collect();
+        for uuid in uuids {
+            let _ = self.stop_service(uuid);
+        }
+    }
+
+    /// Print running and configured services
+    pub fn print_resident_status(&mut self, plugin_id: Option<&str>) {
+        self.prune_residents();
+        let filter = match plugin_id {
+            Some(plugin_id) => match self.resolve_plugin_id(plugin_id) {
+                Ok(plugin_info) => Some(plugin_info.uuid),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        let mut shown = 0;
+        for plugin_info in &self.discovered_plugins {
+            if filter.is_some_and(|uuid| uuid != plugin_info.uuid) {
+                continue;
+            }
+            let configured = self.resident_config(plugin_info);
+            match self.residents.get(&plugin_info.uuid) {
+                Some(service) => {
+                    println!("{} ({})", plugin_info.name, plugin_info.uuid);
+                    println!("   Status: {:?}", service.status());
+                    println!("   Started: {}", service.started_at.format("%Y-%m-%d %H:%M:%S"));
+                    println!("   Configuration: {}", service.configuration().unwrap_or_default());
+                    println!("   Restart policy: {:?} ({} restarts)", service.settings.restart, service.restarts);
+                }
+                None if configured.is_some() || filter.is_some() => {
+                    println!("{} ({})", plugin_info.name, plugin_info.uuid);
+                    println!("   Status: Stopped");
+                    if let Some(config) = configured {
+                        println!("   Autostart: {}", config.autostart);
+                    }
+                }
+                None => continue,
+            }
+            shown += 1;
+        }
+        if shown == 0 {
+            println!("No resident services.");
+        }
+    }
+}
+
+/// `reset` then `start` with the configuration the plugin last reported
+fn restart_service(uuid: CubeMelonUUID, service: &ResidentService, status: CubeMelonExecutionStatus) -> CubeMelonPluginErrorCode {
+    let config = service.configuration().unwrap_or_else(|| EMPTY_CONFIG.to_string());
+    let config = match CString::new(config) {
+        Ok(config) => config,
+        Err(_) => return CubeMelonPluginErrorCode::InvalidParameter,
+    };
+
+    let _caller = enter_plugin(uuid);
+    if status != CubeMelonExecutionStatus::Idle {
+        let rc = (service.interface.reset)(service.instance);
+        if rc != CubeMelonPluginErrorCode::Success {
+            return rc;
+        }
+    }
+    (service.interface.start)(service.instance, config.as_ptr() as *const u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RuntimeConfig;
+
+    #[test]
+    fn test_config_table_becomes_json() {
+        let config: RuntimeConfig = toml::from_str(
+            r#"
+            [settings]
+            plugins_directory = "plugins"
+            language = "en-US"
+
+            [resident."Resident Plugin"]
+            config = { interval_ms = 500, label = "beat" }
+
+            [resident."b7e4c2a1-5d3f-4e8a-9c61-2f7d8e0a4b19"]
+            autostart = false
+            config = '{"interval_ms": 250}'
+            "#,
+        )
+        .unwrap();
+
+        let by_name = &config.resident["Resident Plugin"];
+        assert!(by_name.autostart);
+        let json: serde_json::Value = serde_json::from_str(&config_to_json(by_name.config.as_ref().unwrap()).unwrap()).unwrap();
+        assert_eq!(json["interval_ms"], 500);
+        assert_eq!(json["label"], "beat");
+
+        let by_uuid = &config.resident["b7e4c2a1-5d3f-4e8a-9c61-2f7d8e0a4b19"];
+        assert!(!by_uuid.autostart);
+        assert_eq!(config_to_json(by_uuid.config.as_ref().unwrap()).unwrap(), r#"{"interval_ms": 250}"#);
+    }
+
+    #[test]
+    fn test_restart_policies() {
+        use CubeMelonExecutionStatus as S;
+
+        assert!(!RestartPolicy::Never.should_restart(S::Error));
+        assert!(RestartPolicy::OnFailure.should_restart(S::Error));
+        assert!(!RestartPolicy::OnFailure.should_restart(S::Completed));
+        assert!(RestartPolicy::Always.should_restart(S::Completed));
+        assert!(RestartPolicy::Always.should_restart(S::Cancelled));
+        assert!(!RestartPolicy::Always.should_restart(S::Running));
+        assert!(!RestartPolicy::Always.should_restart(S::Suspended));
+
+        let config: ResidentConfig = toml::from_str("restart = \"on-failure\"\nmax_retries = 5").unwrap();
+        assert_eq!(config.restart, RestartPolicy::OnFailure);
+        assert_eq!(config.max_retries, 5);
+        assert_eq!(ResidentConfig::default().restart, RestartPolicy::Never);
+    }
+
+    #[test]
+    fn test_restart_delay_backs_off_exponentially() {
+        let settings = ResidentConfig { backoff_ms: 100, max_backoff_ms: 1000, ..Default::default() };
+        assert_eq!(restart_delay(&settings, 0), Duration::from_millis(100));
+        assert_eq!(restart_delay(&settings, 1), Duration::from_millis(200));
+        assert_eq!(restart_delay(&settings, 3), Duration::from_millis(800));
+        assert_eq!(restart_delay(&settings, 4), Duration::from_millis(1000));
+        assert_eq!(restart_delay(&settings, 200), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_config_without_resident_section() {
+        let config: RuntimeConfig = toml::from_str("[settings]\nlanguage = \"en-US\"\nplugins_directory = \"plugins\"\n").unwrap();
+        assert!(config.resident.is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+//! Plugin Search Paths
+//!
+//! Plugins are searched in `settings.plugins_directory` followed by the
+//! directories listed in `[search] paths`; relative paths are resolved against
+//! the executable's directory. `CUBEMELON_PLUGIN_PATH` (a platform path list)
+//! replaces all of them, and `CUBEMELON_PLUGIN_DEPTH` overrides
+//! `[search] max_depth`.
+//!
+//! Subdirectories are scanned up to `max_depth` levels deep. A subdirectory
+//! holding a library named after it (`foo/libfoo.so`, `foo/foo.dll`, ...) is a
+//! plugin bundle: only that library is a plugin, and the rest of the directory
+//! is left to the plugin as its resources.
+//!
+//! When several files declare the same UUID, the highest version wins (the
+//! first one found on a tie) and the others are reported as shadowed. Files
+//! built against an incompatible SDK are rejected before this choice.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use cubemelon_sdk::{CubeMelonUUID, CubeMelonVersion};
+
+use crate::loader::{is_plugin_library, library_extension};
+use crate::{PluginInfo, RuntimeData};
+
+/// Replaces the configured search paths
+pub const PLUGIN_PATH_ENV: &str = "CUBEMELON_PLUGIN_PATH";
+/// Replaces `[search] max_depth`
+pub const PLUGIN_DEPTH_ENV: &str = "CUBEMELON_PLUGIN_DEPTH";
+
+/// Plugin file hidden by another file with the same UUID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowedPlugin {
+    pub path: PathBuf,
+    pub uuid: CubeMelonUUID,
+    pub version: CubeMelonVersion,
+    /// File that is used instead
+    pub shadowed_by: PathBuf,
+}
+
+impl RuntimeData {
+    /// Directories searched for plugins, in priority order
+    pub fn plugin_search_paths(&self) -> Vec<PathBuf> {
+        if let Some(paths) = std::env::var_os(PLUGIN_PATH_ENV).filter(|paths| !paths.is_empty()) {
+            return std::env::split_paths(&paths).map(|path| resolve_search_path(path.as_os_str())).collect();
+        }
+        let mut paths = vec![self.get_plugins_directory()];
+        paths.extend(self.config.search.paths.iter().map(|path| resolve_search_path(path.as_ref())));
+        paths
+    }
+
+    /// How many levels of subdirectories are scanned
+    pub fn plugin_search_depth(&self) -> usize {
+        std::env::var(PLUGIN_DEPTH_ENV)
+            .ok()
+            .and_then(|depth| depth.trim().parse().ok())
+            .unwrap_or(self.config.search.max_depth)
+    }
+
+    /// Plugin library files in all search paths
+    pub fn find_plugin_files(&self) -> Vec<PathBuf> {
+        find_plugin_files(&self.plugin_search_paths(), self.plugin_search_depth())
+    }
+}
+
+/// Absolute search path (relative paths start at the executable's directory)
+fn resolve_search_path(path: &std::ffi::OsStr) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    let exe_path = std::env::current_exe().unwrap_or_default();
+    exe_path.parent().unwrap_or(Path::new(".")).join(path)
+}
+
+/// Plugin library files under `roots`, in search order
+///
+/// Files reachable through several roots are listed once.
+pub fn find_plugin_files(roots: &[PathBuf], max_depth: usize) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    for root in roots {
+        walk(root, max_depth, &mut visited, &mut files);
+    }
+    files
+}
+
+fn walk(dir: &Path, depth_left: usize, visited: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) {
+    // Guard against overlapping roots and symlink loops
+    if !visited.insert(dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf())) {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    entries.sort();
+
+    let mut subdirectories = Vec::new();
+    for path in entries {
+        if path.is_dir() {
+            subdirectories.push(path);
+        } else if path.is_file() && is_plugin_library(&path) {
+            files.push(path);
+        }
+    }
+    if depth_left == 0 {
+        return;
+    }
+    for subdirectory in subdirectories {
+        match bundle_library(&subdirectory) {
+            Some(library) => files.push(library),
+            None => walk(&subdirectory, depth_left - 1, visited, files),
+        }
+    }
+}
+
+/// The library a plugin bundle directory is named after, if it is one
+fn bundle_library(dir: &Path) -> Option<PathBuf> {
+    let name = dir.file_name()?;
+    let mut prefixed = OsString::from("lib");
+    prefixed.push(name);
+    [name.to_os_string(), prefixed]
+        .into_iter()
+        .map(|stem| {
+            let mut file = stem;
+            file.push(".");
+            file.push(library_extension());
+            dir.join(file)
+        })
+        .find(|path| path.is_file())
+}
+
+/// Keep one plugin per UUID: the highest version, or the first found on a tie
+pub fn select_plugins(candidates: Vec<PluginInfo>) -> (Vec<PluginInfo>, Vec<ShadowedPlugin>) {
+    let mut best: HashMap<CubeMelonUUID, usize> = HashMap::new();
+    for (index, candidate) in candidates.iter().enumerate() {
+        best.entry(candidate.uuid)
+            .and_modify(|current| {
+                if candidate.version > candidates[*current].version {
+                    *current = index;
+                }
+            })
+            .or_insert(index);
+    }
+
+    let mut shadowed = Vec::new();
+    let mut selected = Vec::new();
+    for (index, candidate) in candidates.iter().enumerate() {
+        let winner = best[&candidate.uuid];
+        if winner == index {
+            selected.push(candidate.clone());
+        } else {
+            shadowed.push(ShadowedPlugin {
+                path: candidate.path.clone(),
+                uuid: candidate.uuid,
+                version: candidate.version,
+                shadowed_by: candidates[winner].path.clone(),
+            });
+        }
+    }
+    (selected, shadowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cubemelon-search-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn library(name: &str) -> String {
+        format!("{}.{}", name, library_extension())
+    }
+
+    fn touch(path: PathBuf) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"").unwrap();
+        path
+    }
+
+    #[test]
+    fn test_recursive_search_with_bundles() {
+        let root = temp_dir("walk");
+        let top = touch(root.join(library("libtop")));
+        let nested = touch(root.join("group").join(library("libnested")));
+        touch(root.join("group").join("deeper").join("too_deep").join(library("libhidden")));
+        // Bundle: only the library named after the directory is a plugin
+        let bundled = touch(root.join("viewer").join(library("libviewer")));
+        touch(root.join("viewer").join(library("libhelper")));
+        touch(root.join("viewer").join("resources").join(library("libasset")));
+        touch(root.join("notes.txt"));
+
+        let files = find_plugin_files(&[root.clone(), root.join("group")], 2);
+        assert_eq!(files, vec![top.clone(), nested, bundled]);
+        assert_eq!(find_plugin_files(std::slice::from_ref(&root), 0), vec![top]);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    fn plugin(id: u8, version: CubeMelonVersion, path: &str) -> PluginInfo {
+        PluginInfo {
+            uuid: CubeMelonUUID::from_bytes([id; 16]),
+            version,
+            supported_types: 0,
+            name: format!("plugin-{}", id),
+            description: String::new(),
+            path: PathBuf::from(path),
+            capabilities: Default::default(),
+            dependencies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_highest_version_wins() {
+        let v1 = CubeMelonVersion::new(1, 0, 0);
+        let v2 = CubeMelonVersion::new(2, 0, 0);
+        let candidates = vec![plugin(1, v1, "a/one"), plugin(2, v1, "a/two"), plugin(1, v2, "b/one"), plugin(2, v1, "b/two")];
+
+        let (selected, shadowed) = select_plugins(candidates);
+        let paths: Vec<_> = selected.iter().map(|p| p.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["a/two", "b/one"]);
+        assert_eq!(
+            shadowed,
+            vec![
+                ShadowedPlugin { path: "a/one".into(), uuid: CubeMelonUUID::from_bytes([1; 16]), version: v1, shadowed_by: "b/one".into() },
+                ShadowedPlugin { path: "b/two".into(), uuid: CubeMelonUUID::from_bytes([2; 16]), version: v1, shadowed_by: "a/two".into() },
+            ]
+        );
+    }
+}
@@ -1,6 +1,13 @@
 //! Plugin State Interface Implementation
 //! 
 //! This module implements CubeMelonPluginStateInterface for RuntimeData.
+//!
+//! - `Host`: the runtime configuration (TOML)
+//! - `Local`: per-plugin values, persisted by `state_store` under the calling plugin's UUID
+//! - `Shared`: values common to all plugins, persisted by `state_store`
+//!
+//! Local and Shared values are UTF-8 strings; `load_state`/`save_state` exchange
+//! a whole namespace as a TOML table.
 
 use cubemelon_sdk::{
     CubeMelonPluginErrorCode, CubeMelonPluginStateScope, CubeMelonValue, CubeMelonLogLevel,
@@ -8,13 +15,52 @@ use cubemelon_sdk::{
     create_plugin_state_interface,
 };
 
-use crate::{RuntimeData, RuntimeConfig, host_services::{runtime_log, HostRuntimeProxy, with_runtime}};
+use crate::{RuntimeData, RuntimeConfig, host_services::{runtime_log, current_caller, HostRuntimeProxy, with_runtime}};
+use crate::state_store::{StateStore, StateNamespace};
 
 impl RuntimeData {
     /// Create the C ABI interface implementation for state management
     pub fn create_state_interface() -> CubeMelonPluginStateInterfaceImpl {
         create_plugin_state_interface::<Self>()
     }
+
+    /// Store and namespace addressed by a Local or Shared scope
+    ///
+    /// Local state belongs to the plugin the runtime is currently calling into.
+    fn state_namespace(
+        &self,
+        scope: CubeMelonPluginStateScope,
+    ) -> Result<(&StateStore, StateNamespace), CubeMelonPluginErrorCode> {
+        let store = self.state_store.as_ref().ok_or_else(|| {
+            runtime_log(CubeMelonLogLevel::Warn, "Plugin state storage unavailable (no config directory)");
+            CubeMelonPluginErrorCode::NotSupported
+        })?;
+        let namespace = match scope {
+            CubeMelonPluginStateScope::Local => match current_caller() {
+                Some(uuid) => StateNamespace::Local(uuid),
+                None => {
+                    runtime_log(CubeMelonLogLevel::Error, "Local state requested outside of a plugin call");
+                    return Err(CubeMelonPluginErrorCode::InvalidState);
+                }
+            },
+            CubeMelonPluginStateScope::Shared => StateNamespace::Shared,
+            CubeMelonPluginStateScope::Host => return Err(CubeMelonPluginErrorCode::InvalidParameter),
+        };
+        Ok((store, namespace))
+    }
+}
+
+/// Keys readable and writable in the Host scope
+const HOST_STATE_KEYS: [&str; 2] = ["plugins_directory", "language"];
+
+/// Array value of owned strings; its free_value releases the items too
+fn string_array_value<I: IntoIterator<Item = String>>(items: I) -> CubeMelonValue {
+    CubeMelonValue::array(items.into_iter().map(CubeMelonValue::string).collect())
+}
+
+/// Collapse a store result into an error code
+fn status_of(result: Result<(), CubeMelonPluginErrorCode>) -> CubeMelonPluginErrorCode {
+    result.err().unwrap_or(CubeMelonPluginErrorCode::Success)
 }
 
 impl CubeMelonPluginStateInterface for RuntimeData {
@@ -22,7 +68,7 @@ impl CubeMelonPluginStateInterface for RuntimeData {
     fn load_state(
         &self,
         scope: CubeMelonPluginStateScope,
-        _data: &mut CubeMelonValue,
+        data: &mut CubeMelonValue,
     ) -> CubeMelonPluginErrorCode {
         runtime_log(CubeMelonLogLevel::Debug, &format!("load_state called with scope: {:?}", scope));
         
@@ -30,9 +76,8 @@ impl CubeMelonPluginStateInterface for RuntimeData {
             CubeMelonPluginStateScope::Host => {
                 // Return host configuration as TOML string
                 match toml::to_string(&self.config) {
-                    Ok(_toml_content) => {
-                        // TODO: Set the TOML content to data
-                        // For now, just log success
+                    Ok(toml_content) => {
+                        *data = CubeMelonValue::string(toml_content);
                         runtime_log(CubeMelonLogLevel::Info, "Host configuration loaded successfully");
                         CubeMelonPluginErrorCode::Success
                     }
@@ -43,8 +88,13 @@ impl CubeMelonPluginStateInterface for RuntimeData {
                 }
             }
             CubeMelonPluginStateScope::Local | CubeMelonPluginStateScope::Shared => {
-                runtime_log(CubeMelonLogLevel::Warn, "Local and Shared scopes not supported by runtime state manager");
-                CubeMelonPluginErrorCode::NotSupported
+                match self.state_namespace(scope).and_then(|(store, ns)| store.load_document(ns)) {
+                    Ok(document) => {
+                        *data = CubeMelonValue::string(document);
+                        CubeMelonPluginErrorCode::Success
+                    }
+                    Err(code) => code,
+                }
             }
         }
     }
@@ -63,20 +113,20 @@ impl CubeMelonPluginStateInterface for RuntimeData {
             return CubeMelonPluginErrorCode::InvalidParameter;
         }
         
+        // Convert raw data to string
+        let data_slice = unsafe { std::slice::from_raw_parts(data, size) };
+        let document = match std::str::from_utf8(data_slice) {
+            Ok(s) => s,
+            Err(e) => {
+                runtime_log(CubeMelonLogLevel::Error, &format!("Invalid UTF-8 in state data: {}", e));
+                return CubeMelonPluginErrorCode::Encoding;
+            }
+        };
+        
         match scope {
             CubeMelonPluginStateScope::Host => {
-                // Convert raw data to string
-                let data_slice = unsafe { std::slice::from_raw_parts(data, size) };
-                let config_str = match std::str::from_utf8(data_slice) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        runtime_log(CubeMelonLogLevel::Error, &format!("Invalid UTF-8 in config data: {}", e));
-                        return CubeMelonPluginErrorCode::Encoding;
-                    }
-                };
-                
                 // Parse as TOML and update configuration
-                match toml::from_str::<RuntimeConfig>(config_str) {
+                match toml::from_str::<RuntimeConfig>(document) {
                     Ok(new_config) => {
                         self.config = new_config;
                         match Self::save_config(&self.config_path, &self.config) {
@@ -97,23 +147,15 @@ impl CubeMelonPluginStateInterface for RuntimeData {
                 }
             }
             CubeMelonPluginStateScope::Local | CubeMelonPluginStateScope::Shared => {
-                runtime_log(CubeMelonLogLevel::Warn, "Local and Shared scopes not supported by runtime state manager");
-                CubeMelonPluginErrorCode::NotSupported
+                status_of(self.state_namespace(scope).and_then(|(store, ns)| store.save_document(ns, document)))
             }
         }
     }
 
     /// Get format name of state data
-    fn get_format_name(&self, scope: CubeMelonPluginStateScope) -> *const u8 {
-        match scope {
-            CubeMelonPluginStateScope::Host => {
-                // Return "toml" as null-terminated string
-                b"toml\0".as_ptr()
-            }
-            CubeMelonPluginStateScope::Local | CubeMelonPluginStateScope::Shared => {
-                std::ptr::null()
-            }
-        }
+    fn get_format_name(&self, _scope: CubeMelonPluginStateScope) -> *const u8 {
+        // Host configuration and the Local/Shared stores are all TOML documents
+        b"toml\0".as_ptr()
     }
 
     /// Get state value for specific key
@@ -121,7 +163,7 @@ impl CubeMelonPluginStateInterface for RuntimeData {
         &self,
         scope: CubeMelonPluginStateScope,
         key: *const u8,
-        _value: &mut CubeMelonValue,
+        value: &mut CubeMelonValue,
     ) -> CubeMelonPluginErrorCode {
         runtime_log(CubeMelonLogLevel::Debug, &format!("get_state_value called with scope: {:?}", scope));
         
@@ -143,12 +185,12 @@ impl CubeMelonPluginStateInterface for RuntimeData {
                 
                 match key_str {
                     "plugins_directory" => {
-                        // TODO: Set the plugins_directory value to CubeMelonValue
+                        *value = CubeMelonValue::string(self.config.settings.plugins_directory.clone());
                         runtime_log(CubeMelonLogLevel::Info, "Returned plugins_directory value");
                         CubeMelonPluginErrorCode::Success
                     }
                     "language" => {
-                        // TODO: Set the language value to CubeMelonValue
+                        *value = CubeMelonValue::string(self.get_language().to_string());
                         runtime_log(CubeMelonLogLevel::Info, "Returned language value");
                         CubeMelonPluginErrorCode::Success
                     }
@@ -159,7 +201,14 @@ impl CubeMelonPluginStateInterface for RuntimeData {
                 }
             }
             CubeMelonPluginStateScope::Local | CubeMelonPluginStateScope::Shared => {
-                CubeMelonPluginErrorCode::NotSupported
+                match self.state_namespace(scope).and_then(|(store, ns)| store.get(ns, key_str)) {
+                    Ok(Some(stored)) => {
+                        *value = CubeMelonValue::string(stored);
+                        CubeMelonPluginErrorCode::Success
+                    }
+                    Ok(None) => CubeMelonPluginErrorCode::PluginNotFound,
+                    Err(code) => code,
+                }
             }
         }
     }
@@ -221,7 +270,7 @@ impl CubeMelonPluginStateInterface for RuntimeData {
                 }
             }
             CubeMelonPluginStateScope::Local | CubeMelonPluginStateScope::Shared => {
-                CubeMelonPluginErrorCode::NotSupported
+                status_of(self.state_namespace(scope).and_then(|(store, ns)| store.set(ns, key_str, value_str)))
             }
         }
     }
@@ -230,19 +279,24 @@ impl CubeMelonPluginStateInterface for RuntimeData {
     fn list_state_keys(
         &self,
         scope: CubeMelonPluginStateScope,
-        _keys: &mut CubeMelonValue,
+        keys: &mut CubeMelonValue,
     ) -> CubeMelonPluginErrorCode {
         runtime_log(CubeMelonLogLevel::Debug, &format!("list_state_keys called with scope: {:?}", scope));
         
         match scope {
             CubeMelonPluginStateScope::Host => {
-                // TODO: Set the list of available keys to CubeMelonValue
-                // Available keys: "plugins_directory", "language"
+                *keys = string_array_value(HOST_STATE_KEYS.iter().map(|k| k.to_string()));
                 runtime_log(CubeMelonLogLevel::Info, "Listed host state keys");
                 CubeMelonPluginErrorCode::Success
             }
             CubeMelonPluginStateScope::Local | CubeMelonPluginStateScope::Shared => {
-                CubeMelonPluginErrorCode::NotSupported
+                match self.state_namespace(scope).and_then(|(store, ns)| store.keys(ns)) {
+                    Ok(names) => {
+                        *keys = string_array_value(names);
+                        CubeMelonPluginErrorCode::Success
+                    }
+                    Err(code) => code,
+                }
             }
         }
     }
@@ -293,7 +347,8 @@ impl CubeMelonPluginStateInterface for RuntimeData {
                 }
             }
             CubeMelonPluginStateScope::Local | CubeMelonPluginStateScope::Shared => {
-                CubeMelonPluginErrorCode::NotSupported
+                // Clearing a key that is not set is not an error
+                status_of(self.state_namespace(scope).and_then(|(store, ns)| store.remove(ns, key_str).map(|_| ())))
             }
         }
     }
@@ -305,9 +360,11 @@ impl CubeMelonPluginStateInterface for HostRuntimeProxy {
         scope: cubemelon_sdk::CubeMelonPluginStateScope,
         _data: &mut cubemelon_sdk::CubeMelonValue,
     ) -> CubeMelonPluginErrorCode {
-        if let Some(rt) = with_runtime(|r| r as *const RuntimeData) {
-            let r = unsafe { &*rt };
+        let _caller = self.enter();
+        if let Some(code) = with_runtime(|r| {
             cubemelon_sdk::CubeMelonPluginStateInterface::load_state(r, scope, _data)
+        }) {
+            code
         } else {
             CubeMelonPluginErrorCode::NotInitialized
         }
@@ -319,6 +376,7 @@ impl CubeMelonPluginStateInterface for HostRuntimeProxy {
         data: *const u8,
         size: usize,
     ) -> CubeMelonPluginErrorCode {
+        let _caller = self.enter();
         if let Some(code) = crate::host_services::with_runtime_mut(|r| {
             cubemelon_sdk::CubeMelonPluginStateInterface::save_state(r, scope, data, size)
         }) {
@@ -332,9 +390,11 @@ impl CubeMelonPluginStateInterface for HostRuntimeProxy {
         &self,
         scope: cubemelon_sdk::CubeMelonPluginStateScope,
     ) -> *const u8 {
-        if let Some(rt) = with_runtime(|r| r as *const RuntimeData) {
-            let r = unsafe { &*rt };
+        let _caller = self.enter();
+        if let Some(value) = with_runtime(|r| {
             cubemelon_sdk::CubeMelonPluginStateInterface::get_format_name(r, scope)
+        }) {
+            value
         } else {
             std::ptr::null()
         }
@@ -346,9 +406,11 @@ impl CubeMelonPluginStateInterface for HostRuntimeProxy {
         key: *const u8,
         _value: &mut cubemelon_sdk::CubeMelonValue,
     ) -> CubeMelonPluginErrorCode {
-        if let Some(rt) = with_runtime(|r| r as *const RuntimeData) {
-            let r = unsafe { &*rt };
+        let _caller = self.enter();
+        if let Some(code) = with_runtime(|r| {
             cubemelon_sdk::CubeMelonPluginStateInterface::get_state_value(r, scope, key, _value)
+        }) {
+            code
         } else {
             CubeMelonPluginErrorCode::NotInitialized
         }
@@ -361,6 +423,7 @@ impl CubeMelonPluginStateInterface for HostRuntimeProxy {
         data: *const u8,
         size: usize,
     ) -> CubeMelonPluginErrorCode {
+        let _caller = self.enter();
         if let Some(code) = crate::host_services::with_runtime_mut(|r| {
             cubemelon_sdk::CubeMelonPluginStateInterface::set_state_value(r, scope, key, data, size)
         }) {
@@ -375,9 +438,11 @@ impl CubeMelonPluginStateInterface for HostRuntimeProxy {
         scope: cubemelon_sdk::CubeMelonPluginStateScope,
         _keys: &mut cubemelon_sdk::CubeMelonValue,
     ) -> CubeMelonPluginErrorCode {
-        if let Some(rt) = with_runtime(|r| r as *const RuntimeData) {
-            let r = unsafe { &*rt };
+        let _caller = self.enter();
+        if let Some(code) = with_runtime(|r| {
             cubemelon_sdk::CubeMelonPluginStateInterface::list_state_keys(r, scope, _keys)
+        }) {
+            code
         } else {
             CubeMelonPluginErrorCode::NotInitialized
         }
@@ -388,6 +453,7 @@ impl CubeMelonPluginStateInterface for HostRuntimeProxy {
         scope: cubemelon_sdk::CubeMelonPluginStateScope,
         key: *const u8,
     ) -> CubeMelonPluginErrorCode {
+        let _caller = self.enter();
         if let Some(code) = crate::host_services::with_runtime_mut(|r| {
             cubemelon_sdk::CubeMelonPluginStateInterface::clear_state_value(r, scope, key)
         }) {
@@ -397,3 +463,37 @@ impl CubeMelonPluginStateInterface for HostRuntimeProxy {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cubemelon_sdk::CubeMelonValueTag;
+
+    #[test]
+    fn test_string_array_value_owns_its_items() {
+        let mut keys = string_array_value(HOST_STATE_KEYS.iter().map(|k| k.to_string()));
+        assert_eq!(keys.tag, CubeMelonValueTag::Array);
+
+        let items: Vec<String> = unsafe { keys.as_array() }
+            .iter()
+            .map(|item| {
+                assert_eq!(item.tag, CubeMelonValueTag::String);
+                assert!(item.free_value.is_some());
+                unsafe { item.as_str() }.unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(items, ["plugins_directory", "language"]);
+
+        let free_fn = keys.free_value.expect("array must be freeable");
+        unsafe { free_fn(&mut keys) };
+    }
+
+    #[test]
+    fn test_empty_string_array_value() {
+        let mut keys = string_array_value(Vec::new());
+        assert!(unsafe { keys.as_array() }.is_empty());
+        if let Some(free_fn) = keys.free_value {
+            unsafe { free_fn(&mut keys) };
+        }
+    }
+}
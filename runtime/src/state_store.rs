@@ -0,0 +1,276 @@
+//! Persistent Plugin State
+//!
+//! Disk-backed storage behind the `Local` and `Shared` state scopes.
+//!
+//! Layout under the store root (next to the configuration file):
+//! ```text
+//! state/
+//!   shared.toml          Shared scope, common to all plugins
+//!   local/<uuid>.toml    Local scope, one namespace per plugin
+//! ```
+//! Each namespace is a flat TOML table of UTF-8 string values. Files are
+//! replaced atomically (write to a temporary file, sync, rename), so a crash
+//! leaves either the old or the new contents, never a torn file.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use cubemelon_sdk::{CubeMelonUUID, CubeMelonPluginErrorCode, CubeMelonLogLevel};
+
+use crate::host_services::runtime_log;
+
+/// Contents of one namespace
+pub type StateTable = BTreeMap<String, String>;
+
+/// Namespace addressed by a Local or Shared state request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateNamespace {
+    /// Private to one plugin
+    Local(CubeMelonUUID),
+    /// Common to all plugins
+    Shared,
+}
+
+/// Directory-backed key/value store
+#[derive(Debug)]
+pub struct StateStore {
+    root: PathBuf,
+    /// Serializes read-modify-write cycles across threads
+    lock: Mutex<()>,
+}
+
+impl StateStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root, lock: Mutex::new(()) }
+    }
+
+    /// File holding a namespace
+    pub fn namespace_path(&self, namespace: StateNamespace) -> PathBuf {
+        match namespace {
+            StateNamespace::Local(uuid) => self.root.join("local").join(format!("{}.toml", uuid)),
+            StateNamespace::Shared => self.root.join("shared.toml"),
+        }
+    }
+
+    /// All values of a namespace (empty if nothing was stored yet)
+    pub fn load(&self, namespace: StateNamespace) -> Result<StateTable, CubeMelonPluginErrorCode> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.read_table(namespace)
+    }
+
+    /// A namespace as TOML text
+    pub fn load_document(&self, namespace: StateNamespace) -> Result<String, CubeMelonPluginErrorCode> {
+        let table = self.load(namespace)?;
+        toml::to_string(&table).map_err(|e| {
+            runtime_log(CubeMelonLogLevel::Error, &format!("Failed to serialize state: {}", e));
+            CubeMelonPluginErrorCode::Parse
+        })
+    }
+
+    /// Replace a namespace with the table in a TOML document
+    pub fn save_document(&self, namespace: StateNamespace, document: &str) -> Result<(), CubeMelonPluginErrorCode> {
+        let table: StateTable = toml::from_str(document).map_err(|e| {
+            runtime_log(CubeMelonLogLevel::Error, &format!("State document is not a table of strings: {}", e));
+            CubeMelonPluginErrorCode::Parse
+        })?;
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.write_table(namespace, &table)
+    }
+
+    /// Value of a key, if present
+    pub fn get(&self, namespace: StateNamespace, key: &str) -> Result<Option<String>, CubeMelonPluginErrorCode> {
+        Ok(self.load(namespace)?.remove(key))
+    }
+
+    /// Store a value
+    pub fn set(&self, namespace: StateNamespace, key: &str, value: &str) -> Result<(), CubeMelonPluginErrorCode> {
+        if key.is_empty() {
+            return Err(CubeMelonPluginErrorCode::InvalidParameter);
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut table = self.read_table(namespace)?;
+        table.insert(key.to_string(), value.to_string());
+        self.write_table(namespace, &table)
+    }
+
+    /// Remove a key; returns whether it existed
+    pub fn remove(&self, namespace: StateNamespace, key: &str) -> Result<bool, CubeMelonPluginErrorCode> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut table = self.read_table(namespace)?;
+        if table.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.write_table(namespace, &table)?;
+        Ok(true)
+    }
+
+    /// Keys of a namespace in sorted order
+    pub fn keys(&self, namespace: StateNamespace) -> Result<Vec<String>, CubeMelonPluginErrorCode> {
+        Ok(self.load(namespace)?.into_keys().collect())
+    }
+
+    fn read_table(&self, namespace: StateNamespace) -> Result<StateTable, CubeMelonPluginErrorCode> {
+        let path = self.namespace_path(namespace);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(StateTable::new()),
+            Err(e) => {
+                runtime_log(CubeMelonLogLevel::Error, &format!("Failed to read state {:?}: {}", path, e));
+                return Err(CubeMelonPluginErrorCode::IO);
+            }
+        };
+        toml::from_str(&content).map_err(|e| {
+            runtime_log(CubeMelonLogLevel::Error, &format!("State file {:?} is corrupted: {}", path, e));
+            CubeMelonPluginErrorCode::DataCorrupted
+        })
+    }
+
+    fn write_table(&self, namespace: StateNamespace, table: &StateTable) -> Result<(), CubeMelonPluginErrorCode> {
+        let path = self.namespace_path(namespace);
+        let content = toml::to_string(table).map_err(|e| {
+            runtime_log(CubeMelonLogLevel::Error, &format!("Failed to serialize state: {}", e));
+            CubeMelonPluginErrorCode::Parse
+        })?;
+        write_atomically(&path, content.as_bytes()).map_err(|e| {
+            runtime_log(CubeMelonLogLevel::Error, &format!("Failed to write state {:?}: {}", path, e));
+            CubeMelonPluginErrorCode::IO
+        })
+    }
+}
+
+/// Replace a file so readers see either the old or the new contents
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp_path = dir.join(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+        return written;
+    }
+
+    // Persist the rename itself; not every platform can open directories
+    if let Ok(dir_handle) = fs::File::open(dir) {
+        let _ = dir_handle.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(name: &str) -> (StateStore, PathBuf) {
+        let root = std::env::temp_dir().join(format!("cubemelon-state-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        (StateStore::new(root.clone()), root)
+    }
+
+    fn uuid(last: u8) -> CubeMelonUUID {
+        let mut bytes = [0u8; 16];
+        bytes[15] = last;
+        CubeMelonUUID::from_bytes(bytes)
+    }
+
+    #[test]
+    fn test_values_round_trip_through_disk() {
+        let (store, root) = temp_store("round-trip");
+        let ns = StateNamespace::Local(uuid(1));
+
+        assert_eq!(store.get(ns, "theme"), Ok(None));
+        store.set(ns, "theme", "dark").unwrap();
+        store.set(ns, "count", "3").unwrap();
+
+        let reopened = StateStore::new(root.clone());
+        assert_eq!(reopened.get(ns, "theme"), Ok(Some("dark".to_string())));
+        assert_eq!(reopened.keys(ns), Ok(vec!["count".to_string(), "theme".to_string()]));
+
+        assert_eq!(reopened.remove(ns, "theme"), Ok(true));
+        assert_eq!(reopened.remove(ns, "theme"), Ok(false));
+        assert_eq!(reopened.keys(ns), Ok(vec!["count".to_string()]));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_namespaces_are_isolated() {
+        let (store, root) = temp_store("isolation");
+        store.set(StateNamespace::Local(uuid(1)), "key", "one").unwrap();
+        store.set(StateNamespace::Local(uuid(2)), "key", "two").unwrap();
+        store.set(StateNamespace::Shared, "key", "shared").unwrap();
+
+        assert_eq!(store.get(StateNamespace::Local(uuid(1)), "key"), Ok(Some("one".to_string())));
+        assert_eq!(store.get(StateNamespace::Local(uuid(2)), "key"), Ok(Some("two".to_string())));
+        assert_eq!(store.get(StateNamespace::Shared, "key"), Ok(Some("shared".to_string())));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_documents_replace_the_namespace() {
+        let (store, root) = temp_store("documents");
+        let ns = StateNamespace::Shared;
+        store.set(ns, "old", "value").unwrap();
+
+        store.save_document(ns, "greeting = \"hello\"\nname = \"melon\"\n").unwrap();
+        assert_eq!(store.keys(ns), Ok(vec!["greeting".to_string(), "name".to_string()]));
+
+        let document = store.load_document(ns).unwrap();
+        let parsed: StateTable = toml::from_str(&document).unwrap();
+        assert_eq!(parsed.get("name").map(String::as_str), Some("melon"));
+
+        assert_eq!(store.save_document(ns, "nested = { a = 1 }"), Err(CubeMelonPluginErrorCode::Parse));
+        assert_eq!(store.keys(ns).unwrap().len(), 2);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_corrupted_file_is_reported() {
+        let (store, root) = temp_store("corrupted");
+        let path = store.namespace_path(StateNamespace::Shared);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "this is = = not toml").unwrap();
+
+        assert_eq!(store.get(StateNamespace::Shared, "key"), Err(CubeMelonPluginErrorCode::DataCorrupted));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_atomic_write_leaves_no_temporary_files() {
+        let (store, root) = temp_store("atomic");
+        for i in 0..5 {
+            store.set(StateNamespace::Shared, "n", &i.to_string()).unwrap();
+        }
+
+        let leftovers: Vec<_> = fs::read_dir(&root)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+        assert_eq!(store.get(StateNamespace::Shared, "n"), Ok(Some("4".to_string())));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
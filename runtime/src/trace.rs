@@ -0,0 +1,312 @@
+//! Call Tracing
+//!
+//! Every task call runs in a span, and spans belong to a trace whose ID is
+//! carried in the request (`CubeMelonTaskRequest::trace_id`, `reserved[1]`):
+//! - A call whose request has no trace ID joins the span active on the calling
+//!   thread, or starts a new trace.
+//! - A plugin calling the host manager from inside its task makes a nested
+//!   call: the callee's span is a child of the caller's, and the runtime writes
+//!   the trace ID into the request handed to the callee.
+//! - A call carrying the ID of a trace with no active span on the thread (a
+//!   plugin host, or a plugin thread) becomes a child of that trace's most
+//!   recent open span.
+//!
+//! Log records made while a span is active carry its trace ID. The most
+//! recent traces are kept in memory and exported as JSON span trees by the
+//! `trace` command and `exec --trace`.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
+
+use chrono::{DateTime, Local, SecondsFormat};
+use serde_json::{json, Value};
+use cubemelon_sdk::{CubeMelonPluginErrorCode, CubeMelonTaskRequest, CubeMelonUUID};
+
+use crate::metrics::CallInterface;
+use crate::RuntimeData;
+
+/// Traces kept for export; older ones are dropped
+const MAX_TRACES: usize = 64;
+
+/// Spans recorded per trace; later ones still propagate the ID but are not kept
+const MAX_SPANS: usize = 1024;
+
+/// Span a thread is running in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanContext {
+    pub trace_id: u64,
+    /// 0 when the trace was joined without a span of its own (plugin hosts)
+    pub span_id: u64,
+}
+
+/// One task call
+#[derive(Debug, Clone)]
+struct Span {
+    id: u64,
+    parent: Option<u64>,
+    plugin: CubeMelonUUID,
+    interface: CallInterface,
+    start: DateTime<Local>,
+    started: Instant,
+    /// Set once the call has finished
+    finished: Option<(u64, CubeMelonPluginErrorCode)>,
+}
+
+struct Trace {
+    id: u64,
+    spans: Vec<Span>,
+}
+
+thread_local! {
+    static ACTIVE: Cell<Option<SpanContext>> = const { Cell::new(None) };
+}
+
+static TRACES: OnceLock<Mutex<VecDeque<Trace>>> = OnceLock::new();
+
+fn lock_traces() -> MutexGuard<'static, VecDeque<Trace>> {
+    TRACES.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// New non-zero trace or span ID that fits the request's pointer-sized slot
+pub(crate) fn new_id() -> u64 {
+    static SEED: OnceLock<u64> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let seed = *SEED.get_or_init(|| (crate::async_task::now_us() as u64) ^ ((std::process::id() as u64) << 32));
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+    // Spread consecutive IDs so they are easy to tell apart in logs
+    let id = seed.wrapping_add(n.wrapping_mul(0x9E37_79B9_7F4A_7C15)) & usize::MAX as u64;
+    if id == 0 { 1 } else { id }
+}
+
+/// Format a trace or span ID as in logs
+pub fn format_id(id: u64) -> String {
+    format!("{:016x}", id)
+}
+
+/// Parse an ID printed by `format_id`
+pub fn parse_id(text: &str) -> Option<u64> {
+    u64::from_str_radix(text, 16).ok().filter(|id| *id != 0)
+}
+
+/// Span active on this thread
+pub fn current() -> Option<SpanContext> {
+    ACTIVE.with(Cell::get)
+}
+
+/// Trace ID of the span active on this thread, 0 if none
+pub fn current_trace_id() -> u64 {
+    current().map_or(0, |span| span.trace_id)
+}
+
+/// Makes a span the active one on this thread until dropped
+pub(crate) struct ActiveSpan {
+    previous: Option<SpanContext>,
+}
+
+impl Drop for ActiveSpan {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| active.set(self.previous));
+    }
+}
+
+/// Run the rest of the scope in `span` (no-op for None)
+pub(crate) fn enter(span: Option<SpanContext>) -> ActiveSpan {
+    let previous = ACTIVE.with(|active| match span {
+        Some(span) => active.replace(Some(span)),
+        None => active.get(),
+    });
+    ActiveSpan { previous }
+}
+
+/// Open a span for a call on `plugin`
+///
+/// `requested_trace` is the request's trace ID; see the module docs for how
+/// the trace and the parent span are chosen.
+pub(crate) fn start_span(requested_trace: u64, plugin: CubeMelonUUID, interface: CallInterface) -> SpanContext {
+    let active = current();
+    let trace_id = match (requested_trace, active) {
+        (0, Some(active)) => active.trace_id,
+        (0, None) => new_id(),
+        (requested, _) => requested,
+    };
+    let span = SpanContext { trace_id, span_id: new_id() };
+
+    let mut traces = lock_traces();
+    let parent = match active {
+        Some(active) if active.trace_id == trace_id && active.span_id != 0 => Some(active.span_id),
+        _ => traces
+            .iter()
+            .find(|trace| trace.id == trace_id)
+            .and_then(|trace| trace.spans.iter().rev().find(|s| s.finished.is_none()))
+            .map(|s| s.id),
+    };
+    let trace = match traces.iter().position(|trace| trace.id == trace_id) {
+        Some(index) => &mut traces[index],
+        None => {
+            if traces.len() >= MAX_TRACES {
+                traces.pop_front();
+            }
+            traces.push_back(Trace { id: trace_id, spans: Vec::new() });
+            traces.back_mut().expect("trace just added")
+        }
+    };
+    if trace.spans.len() < MAX_SPANS {
+        trace.spans.push(Span {
+            id: span.span_id,
+            parent,
+            plugin,
+            interface,
+            start: Local::now(),
+            started: Instant::now(),
+            finished: None,
+        });
+    }
+    span
+}
+
+/// Close a span with the call's outcome
+pub(crate) fn finish_span(span: SpanContext, outcome: CubeMelonPluginErrorCode) {
+    let mut traces = lock_traces();
+    let recorded = traces
+        .iter_mut()
+        .find(|trace| trace.id == span.trace_id)
+        .and_then(|trace| trace.spans.iter_mut().find(|s| s.id == span.span_id));
+    if let Some(recorded) = recorded {
+        recorded.finished.get_or_insert((recorded.started.elapsed().as_micros() as u64, outcome));
+    }
+}
+
+/// Shallow copy of a request carrying `trace_id`
+///
+/// The copy borrows the caller's buffers; it never frees them.
+pub(crate) fn traced_request(request: &CubeMelonTaskRequest, trace_id: u64) -> CubeMelonTaskRequest {
+    let mut traced = request.borrowed_copy();
+    traced.set_trace_id(trace_id);
+    traced
+}
+
+/// IDs of the kept traces, oldest first
+pub fn recent_traces() -> Vec<u64> {
+    lock_traces().iter().map(|trace| trace.id).collect()
+}
+
+/// Span tree of a trace as JSON; `plugin_name` labels the spans
+pub fn export(trace_id: u64, plugin_name: impl Fn(CubeMelonUUID) -> Option<String>) -> Option<Value> {
+    let traces = lock_traces();
+    let trace = traces.iter().find(|trace| trace.id == trace_id)?;
+
+    fn subtree(spans: &[Span], span: &Span, plugin_name: &dyn Fn(CubeMelonUUID) -> Option<String>) -> Value {
+        let children: Vec<Value> = spans
+            .iter()
+            .filter(|child| child.parent == Some(span.id))
+            .map(|child| subtree(spans, child, plugin_name))
+            .collect();
+        json!({
+            "span_id": format_id(span.id),
+            "plugin": span.plugin.to_string(),
+            "name": plugin_name(span.plugin),
+            "interface": span.interface.name(),
+            "start": span.start.to_rfc3339_opts(SecondsFormat::Micros, false),
+            "duration_us": span.finished.map(|(us, _)| us),
+            "outcome": span.finished.map(|(_, code)| format!("{:?}", code)),
+            "children": children,
+        })
+    }
+
+    let known: Vec<u64> = trace.spans.iter().map(|s| s.id).collect();
+    let roots: Vec<Value> = trace
+        .spans
+        .iter()
+        .filter(|span| span.parent.is_none_or(|parent| !known.contains(&parent)))
+        .map(|span| subtree(&trace.spans, span, &plugin_name))
+        .collect();
+    Some(json!({ "trace_id": format_id(trace.id), "spans": roots }))
+}
+
+impl RuntimeData {
+    /// Span tree of a trace, with spans labelled by plugin name
+    pub fn trace_tree(&self, trace_id: u64) -> Option<Value> {
+        export(trace_id, |uuid| self.discovered_plugins.iter().find(|p| p.uuid == uuid).map(|p| p.name.clone()))
+    }
+
+    /// Print the kept traces, or the span tree of one
+    pub fn print_traces(&self, trace_id: Option<&str>) -> anyhow::Result<()> {
+        if let Some(text) = trace_id {
+            let tree = parse_id(text)
+                .and_then(|id| self.trace_tree(id))
+                .ok_or_else(|| anyhow::anyhow!("Trace not found: {}", text))?;
+            println!("{}", serde_json::to_string_pretty(&tree)?);
+            return Ok(());
+        }
+
+        let traces = recent_traces();
+        if traces.is_empty() {
+            println!("No traces recorded yet.");
+            return Ok(());
+        }
+        println!("Recent traces:");
+        for id in traces {
+            let Some(tree) = self.trace_tree(id) else { continue };
+            let roots = tree["spans"].as_array().map(Vec::as_slice).unwrap_or_default();
+            let names: Vec<&str> = roots.iter().filter_map(|span| span["name"].as_str()).collect();
+            println!("  {}  {}", format_id(id), names.join(", "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_calls_join_the_callers_trace() {
+        let outer_plugin = CubeMelonUUID::from_bytes([0x71; 16]);
+        let inner_plugin = CubeMelonUUID::from_bytes([0x72; 16]);
+
+        let outer = start_span(0, outer_plugin, CallInterface::SingleTask);
+        let inner = {
+            let _active = enter(Some(outer));
+            assert_eq!(current_trace_id(), outer.trace_id);
+            let inner = start_span(0, inner_plugin, CallInterface::AsyncTask);
+            finish_span(inner, CubeMelonPluginErrorCode::Timeout);
+            inner
+        };
+        assert_eq!(current(), None);
+        assert_eq!(inner.trace_id, outer.trace_id);
+
+        // A request carrying the ID from another thread joins the open outer span
+        let joined = std::thread::spawn(move || start_span(outer.trace_id, inner_plugin, CallInterface::SingleTask))
+            .join()
+            .unwrap();
+        finish_span(joined, CubeMelonPluginErrorCode::Success);
+        finish_span(outer, CubeMelonPluginErrorCode::Success);
+
+        let tree = export(outer.trace_id, |_| Some("P".to_string())).unwrap();
+        assert_eq!(tree["trace_id"], format_id(outer.trace_id));
+        let roots = tree["spans"].as_array().unwrap();
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0]["span_id"], format_id(outer.span_id));
+        let children = roots[0]["children"].as_array().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0]["interface"], "async_task");
+        assert_eq!(children[0]["outcome"], "Timeout");
+        assert_eq!(children[1]["span_id"], format_id(joined.span_id));
+    }
+
+    #[test]
+    fn test_traced_request_carries_the_id() {
+        let request = CubeMelonTaskRequest::empty();
+        let id = new_id();
+        assert_ne!(id, 0);
+        assert_eq!(parse_id(&format_id(id)), Some(id));
+
+        let traced = traced_request(&request, id);
+        assert_eq!(traced.trace_id(), id);
+        assert!(traced.input_json.free_string.is_none());
+        assert_eq!(request.trace_id(), 0);
+    }
+}
@@ -0,0 +1,336 @@
+//! Timeout Enforcement for Synchronous Tasks
+//!
+//! `execute_task` honours `CubeMelonTaskRequest::timeout_us` (0 means no
+//! limit). A request with a timeout runs on a worker thread against a copy of
+//! the request while the caller waits for the deadline:
+//!
+//! - The copy carries a cancel flag that goes up at the deadline, so
+//!   cooperative plugins can poll `CubeMelonTaskRequest::is_cancelled` and
+//!   return early.
+//! - On expiry the caller gets `Timeout` right away. The instance is tainted:
+//!   it leaves the instance table, so the next call gets a fresh one, and it is
+//!   destroyed as soon as the plugin returns.
+//! - Input that cannot be copied (pointer or custom values) keeps the call on
+//!   the calling thread. The flag still goes up at the deadline, and a late
+//!   result is replaced by `Timeout`.
+//! - Manager and state calls the plugin makes from the worker thread are run
+//!   on the calling thread while it waits (see `isolation`).
+//!
+//! Isolated plugins are handled by their plugin host connection, which kills
+//! and restarts the process instead.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use cubemelon_sdk::{
+    CubeMelonExecutionStatus, CubeMelonLogLevel, CubeMelonPlugin, CubeMelonPluginErrorCode,
+    CubeMelonSingleTaskInterfaceImpl, CubeMelonTaskRequest, CubeMelonTaskResult,
+    CubeMelonUUID,
+};
+
+use crate::async_task::{free_task_result, now_us, owned_copy};
+use crate::host_services::{enter_plugin, runtime_log};
+use crate::instances::{InstanceKey, PluginInstance};
+use crate::ipc::OwnedRequest;
+use crate::isolation;
+use crate::trace;
+use crate::RuntimeData;
+
+/// Instance given up on, destroyed by the worker once the plugin returns
+struct TaintedInstance(InstanceKey, PluginInstance);
+
+// Only the worker thread touches it again, after the plugin let go of it
+unsafe impl Send for TaintedInstance {}
+
+/// Progress of a call running on a worker thread
+enum Watch {
+    Running,
+    /// The plugin returned before the deadline
+    Finished(CubeMelonPluginErrorCode, CubeMelonTaskResult),
+    /// The caller gave up
+    Abandoned(Option<TaintedInstance>),
+}
+
+struct WatchedCall {
+    watch: Mutex<Watch>,
+    changed: Condvar,
+    cancelled: AtomicBool,
+}
+
+/// Instance pointer handed to the worker thread
+struct InstanceHandle(*mut CubeMelonPlugin);
+
+unsafe impl Send for InstanceHandle {}
+
+/// Mark a result as timed out
+fn time_out(result: &mut CubeMelonTaskResult) -> CubeMelonPluginErrorCode {
+    result.status = CubeMelonExecutionStatus::Error;
+    result.error_code = CubeMelonPluginErrorCode::Timeout;
+    CubeMelonPluginErrorCode::Timeout
+}
+
+/// Raise a cancel flag at the deadline unless the call ends first
+///
+/// The call has ended once the returned sender is dropped.
+fn raise_at_deadline(flag: Arc<AtomicBool>, timeout: Duration) -> mpsc::Sender<()> {
+    let (done, ended) = mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        if let Err(mpsc::RecvTimeoutError::Timeout) = ended.recv_timeout(timeout) {
+            flag.store(true, Ordering::Release);
+        }
+    });
+    done
+}
+
+/// Run a SingleTask call on a worker thread and wait for it until the timeout
+///
+/// Returns None on expiry. `abandon` then runs under the watch lock and picks
+/// the instance the worker destroys once the plugin returns.
+fn run_watched(
+    uuid: CubeMelonUUID,
+    single_task: &'static CubeMelonSingleTaskInterfaceImpl,
+    instance: *mut CubeMelonPlugin,
+    mut copy: OwnedRequest,
+    timeout: Duration,
+    abandon: impl FnOnce() -> Option<TaintedInstance>,
+) -> std::io::Result<Option<(CubeMelonPluginErrorCode, CubeMelonTaskResult)>> {
+    let call = Arc::new(WatchedCall {
+        watch: Mutex::new(Watch::Running),
+        changed: Condvar::new(),
+        cancelled: AtomicBool::new(false),
+    });
+    let worker_call = Arc::clone(&call);
+    let handle = InstanceHandle(instance);
+    let span = trace::current();
+    std::thread::Builder::new()
+        .name(format!("cubemelon-timed-{}", uuid))
+        .spawn(move || {
+            let handle = handle;
+            copy.0.set_cancel_flag(&worker_call.cancelled);
+            let mut result = CubeMelonTaskResult::empty();
+            let rc = {
+                let _active = trace::enter(span);
+                let _caller = enter_plugin(uuid);
+                (single_task.execute)(handle.0, &copy.0, &mut result)
+            };
+
+            let mut watch = worker_call.watch.lock().unwrap_or_else(|e| e.into_inner());
+            match std::mem::replace(&mut *watch, Watch::Running) {
+                Watch::Running => {
+                    *watch = Watch::Finished(rc, result);
+                    worker_call.changed.notify_all();
+                }
+                Watch::Abandoned(tainted) => {
+                    drop(watch);
+                    free_task_result(&mut result);
+                    runtime_log(
+                        CubeMelonLogLevel::Info,
+                        &format!("Timed out task of {} returned late: {:?}", uuid, rc),
+                    );
+                    if let Some(TaintedInstance(key, instance)) = tainted {
+                        instance.teardown(&key);
+                    }
+                }
+                Watch::Finished(..) => unreachable!("a call finishes once"),
+            }
+        })?;
+
+    // The plugin's own manager and state calls are run here meanwhile
+    let deadline = Instant::now() + timeout;
+    let mut watch = loop {
+        isolation::serve_forwarded_calls();
+        let watch = call.watch.lock().unwrap_or_else(|e| e.into_inner());
+        let left = deadline.saturating_duration_since(Instant::now());
+        if !matches!(*watch, Watch::Running) || left.is_zero() {
+            break watch;
+        }
+        let slice = left.min(isolation::CALL_POLL_INTERVAL);
+        drop(call.changed.wait_timeout(watch, slice).unwrap_or_else(|e| e.into_inner()));
+    };
+
+    if let Watch::Finished(rc, finished) = std::mem::replace(&mut *watch, Watch::Running) {
+        return Ok(Some((rc, finished)));
+    }
+    call.cancelled.store(true, Ordering::Release);
+    *watch = Watch::Abandoned(abandon());
+    Ok(None)
+}
+
+impl RuntimeData {
+    /// Execute a SingleTask call within the request's timeout
+    pub(crate) fn execute_with_timeout(
+        &mut self,
+        key: InstanceKey,
+        single_task: &'static CubeMelonSingleTaskInterfaceImpl,
+        instance: *mut CubeMelonPlugin,
+        request: &CubeMelonTaskRequest,
+        result: &mut CubeMelonTaskResult,
+    ) -> CubeMelonPluginErrorCode {
+        let timeout = Duration::from_micros(request.timeout_us.max(0) as u64);
+        let Some(mut copy) = owned_copy(request) else {
+            return self.execute_with_deadline(key, single_task, instance, request, result, timeout);
+        };
+        if copy.0.request_time_us <= 0 {
+            copy.0.request_time_us = now_us();
+        }
+
+        let instances = &mut self.instances;
+        let abandon = || instances.remove(&key).map(|instance| TaintedInstance(key.clone(), instance));
+        match run_watched(key.uuid, single_task, instance, copy, timeout, abandon) {
+            Ok(Some((rc, finished))) => {
+                *result = finished;
+                rc
+            }
+            Ok(None) => {
+                runtime_log(
+                    CubeMelonLogLevel::Warn,
+                    &format!("Task on {} timed out after {} us; recycling the instance", key, request.timeout_us),
+                );
+                time_out(result)
+            }
+            Err(e) => {
+                runtime_log(CubeMelonLogLevel::Error, &format!("Failed to spawn task worker: {}", e));
+                CubeMelonPluginErrorCode::ResourceExhausted
+            }
+        }
+    }
+
+    /// Execute on the calling thread, raising the cancel flag at the deadline
+    fn execute_with_deadline(
+        &mut self,
+        key: InstanceKey,
+        single_task: &'static CubeMelonSingleTaskInterfaceImpl,
+        instance: *mut CubeMelonPlugin,
+        request: &CubeMelonTaskRequest,
+        result: &mut CubeMelonTaskResult,
+        timeout: Duration,
+    ) -> CubeMelonPluginErrorCode {
+        // Borrows the caller's buffers, which outlive this call
+        let mut flagged = request.borrowed_copy();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        flagged.set_cancel_flag(&*cancelled);
+
+        let watchdog = raise_at_deadline(Arc::clone(&cancelled), timeout);
+        let rc = {
+            let _caller = enter_plugin(key.uuid);
+            (single_task.execute)(instance, &flagged, result)
+        };
+        drop(watchdog);
+
+        if !cancelled.load(Ordering::Acquire) {
+            return rc;
+        }
+        free_task_result(result);
+        if let Some(instance) = self.instances.remove(&key) {
+            instance.teardown(&key);
+        }
+        runtime_log(
+            CubeMelonLogLevel::Warn,
+            &format!("Task on {} overran its {} us timeout; recycled the instance", key, request.timeout_us),
+        );
+        time_out(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Sender};
+    use cubemelon_sdk::{
+        create_plugin_instance, create_single_task_interface, destroy_plugin_instance, CubeMelonLanguage,
+        CubeMelonSingleTaskInterface, CubeMelonString, CubeMelonTaskType,
+    };
+
+    fn make_request(input_json: &str, timeout_us: i64) -> CubeMelonTaskRequest {
+        CubeMelonTaskRequest::new(
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            CubeMelonString::from_string(input_json.to_string()),
+            CubeMelonTaskType::Generic,
+            CubeMelonLanguage::EN_US,
+            now_us(),
+            timeout_us,
+        )
+    }
+
+    /// Echoes its input back
+    struct EchoPlugin;
+
+    impl CubeMelonSingleTaskInterface for EchoPlugin {
+        fn execute(
+            &mut self,
+            request: &CubeMelonTaskRequest,
+            result: &mut CubeMelonTaskResult,
+        ) -> CubeMelonPluginErrorCode {
+            let input = request.input_json.as_str().unwrap_or("").to_string();
+            *result = CubeMelonTaskResult::success(
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                CubeMelonString::from_string(input),
+                0,
+            );
+            CubeMelonPluginErrorCode::Success
+        }
+    }
+
+    /// Works until the request is cancelled, then reports it
+    struct CooperativePlugin(Sender<()>);
+
+    impl CubeMelonSingleTaskInterface for CooperativePlugin {
+        fn execute(
+            &mut self,
+            request: &CubeMelonTaskRequest,
+            _result: &mut CubeMelonTaskResult,
+        ) -> CubeMelonPluginErrorCode {
+            while !request.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            let _ = self.0.send(());
+            CubeMelonPluginErrorCode::Cancelled
+        }
+    }
+
+    #[test]
+    fn test_call_within_timeout_returns_result() {
+        let uuid = CubeMelonUUID::from_bytes([0xD1; 16]);
+        let single_task: &'static _ = Box::leak(Box::new(create_single_task_interface::<EchoPlugin>()));
+        let instance = create_plugin_instance(EchoPlugin);
+        let request = make_request("{\"n\":1}", 5_000_000);
+
+        let copy = owned_copy(&request).unwrap();
+        let outcome = run_watched(uuid, single_task, instance, copy, Duration::from_secs(5), || None).unwrap();
+        let (rc, mut result) = outcome.expect("the call finishes in time");
+        assert_eq!(rc, CubeMelonPluginErrorCode::Success);
+        assert_eq!(result.output_json.as_str().unwrap(), "{\"n\":1}");
+
+        free_task_result(&mut result);
+        destroy_plugin_instance(instance);
+    }
+
+    #[test]
+    fn test_timeout_raises_cancel_flag() {
+        let uuid = CubeMelonUUID::from_bytes([0xD2; 16]);
+        let single_task: &'static _ = Box::leak(Box::new(create_single_task_interface::<CooperativePlugin>()));
+        let (tx, rx) = channel();
+        let instance = create_plugin_instance(CooperativePlugin(tx));
+        let request = make_request("{}", 50_000);
+
+        let copy = owned_copy(&request).unwrap();
+        let mut abandoned = false;
+        let outcome = run_watched(uuid, single_task, instance, copy, Duration::from_millis(50), || {
+            abandoned = true;
+            None
+        });
+        assert!(outcome.unwrap().is_none());
+        assert!(abandoned);
+
+        let mut result = CubeMelonTaskResult::empty();
+        assert_eq!(time_out(&mut result), CubeMelonPluginErrorCode::Timeout);
+        assert_eq!(result.status, CubeMelonExecutionStatus::Error);
+
+        // The plugin sees the flag; its instance is left to the worker thread
+        rx.recv_timeout(Duration::from_secs(5)).expect("the plugin notices the cancellation");
+    }
+}
@@ -41,6 +41,7 @@ pub enum CubeMelonPluginErrorCode {
     NotImplemented = -32,            // Not implemented
     PluginLoadFailed = -33,          // Plugin load failed
     PluginUnloadFailed = -34,        // Plugin unload failed
+    IntegrityCheckFailed = -35,      // Plugin hash or signature check failed
     
     // Communication/I/O related (-40 ~ -49)
     ConnectionFailed = -40,          // Connection failed
@@ -121,6 +122,7 @@ impl CubeMelonPluginErrorCode {
             CubeMelonPluginErrorCode::NotImplemented => "Not implemented",
             CubeMelonPluginErrorCode::PluginLoadFailed => "Plugin load failed",
             CubeMelonPluginErrorCode::PluginUnloadFailed => "Plugin unload failed",
+            CubeMelonPluginErrorCode::IntegrityCheckFailed => "Plugin integrity check failed",
             
             // Communication/I/O related
             CubeMelonPluginErrorCode::ConnectionFailed => "Connection failed",
@@ -143,7 +143,7 @@ where
         // The <T, _, _> syntax specifies the plugin type T, with other generics inferred
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
             // Here plugin_instance is guaranteed to be of type &mut T
-            plugin_instance.execute(request_ref, callback_opt)
+            crate::panic::catch_panic(|| plugin_instance.execute(request_ref, callback_opt))
         }) {
             Some(error_code) => error_code,
             None => {
@@ -167,7 +167,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.cancel(request_ref)
+            crate::panic::catch_panic(|| plugin_instance.cancel(request_ref))
         }) {
             Some(error_code) => error_code,
             None => {
@@ -233,7 +233,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.get_all_plugins_basic_info(language, out_infos_ref)
+            crate::panic::catch_panic(|| plugin_instance.get_all_plugins_basic_info(language, out_infos_ref))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -256,7 +256,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.get_plugin_detailed_info(target_uuid, language, out_detailed_json_ref)
+            crate::panic::catch_panic(|| plugin_instance.get_plugin_detailed_info(target_uuid, language, out_detailed_json_ref))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -278,7 +278,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.find_plugins_for_task(task_json, out_uuids_ref)
+            crate::panic::catch_panic(|| plugin_instance.find_plugins_for_task(task_json, out_uuids_ref))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -296,7 +296,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.is_plugin_alive(target_uuid)
+            crate::panic::catch_panic(|| plugin_instance.is_plugin_alive(target_uuid))
         }) {
             Some(is_alive) => is_alive,
             None => false, // Plugin not found = not alive
@@ -320,7 +320,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.execute_task(target_uuid, request_ref, result_ref)
+            crate::panic::catch_panic(|| plugin_instance.execute_task(target_uuid, request_ref, result_ref))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -350,7 +350,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.execute_async_task(target_uuid, request_ref, callback_opt)
+            crate::panic::catch_panic(|| plugin_instance.execute_async_task(target_uuid, request_ref, callback_opt))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -371,7 +371,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.cancel_async_task(request_ref)
+            crate::panic::catch_panic(|| plugin_instance.cancel_async_task(request_ref))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
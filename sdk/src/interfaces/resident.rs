@@ -199,7 +199,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.get_status()
+            crate::panic::catch_panic(|| plugin_instance.get_status())
         }) {
             Some(status) => status,
             None => CubeMelonExecutionStatus::Error, // Plugin not found
@@ -216,7 +216,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.get_configuration()
+            crate::panic::catch_panic(|| plugin_instance.get_configuration())
         }) {
             Some(config_ptr) => config_ptr,
             None => std::ptr::null(), // Plugin not found
@@ -234,7 +234,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.update_configuration(config_json)
+            crate::panic::catch_panic(|| plugin_instance.update_configuration(config_json))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -252,7 +252,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.start(config_json)
+            crate::panic::catch_panic(|| plugin_instance.start(config_json))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -269,7 +269,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.suspend()
+            crate::panic::catch_panic(|| plugin_instance.suspend())
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -286,7 +286,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.resume()
+            crate::panic::catch_panic(|| plugin_instance.resume())
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -303,7 +303,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.stop()
+            crate::panic::catch_panic(|| plugin_instance.stop())
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -320,7 +320,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.reset()
+            crate::panic::catch_panic(|| plugin_instance.reset())
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
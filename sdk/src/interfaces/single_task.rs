@@ -104,7 +104,7 @@ where
         // The <T, _, _> syntax specifies the plugin type T, with other generics inferred
         let error_code = match crate::instance::with_plugin_mut::<T, _, _>(plugin, |p| {
             // Here plugin_instance is guaranteed to be of type &mut T
-            p.execute(request_ref, result_ref)
+            crate::panic::catch_panic(|| p.execute(request_ref, result_ref))
         }) {
             Some(code) => code,
             None => {
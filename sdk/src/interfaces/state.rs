@@ -235,7 +235,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.load_state(scope, data_ref)
+            crate::panic::catch_panic(|| plugin_instance.load_state(scope, data_ref))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -255,7 +255,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.save_state(scope, data, size)
+            crate::panic::catch_panic(|| plugin_instance.save_state(scope, data, size))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -273,7 +273,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.get_format_name(scope)
+            crate::panic::catch_panic(|| plugin_instance.get_format_name(scope))
         }) {
             Some(format_ptr) => format_ptr,
             None => std::ptr::null(),
@@ -296,7 +296,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.get_state_value(scope, key, value_ref)
+            crate::panic::catch_panic(|| plugin_instance.get_state_value(scope, key, value_ref))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -317,7 +317,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.set_state_value(scope, key, data, size)
+            crate::panic::catch_panic(|| plugin_instance.set_state_value(scope, key, data, size))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -339,7 +339,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.list_state_keys(scope, keys_ref)
+            crate::panic::catch_panic(|| plugin_instance.list_state_keys(scope, keys_ref))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
@@ -358,7 +358,7 @@ where
 
         // Access plugin instance safely through type-safe wrapper
         match crate::instance::with_plugin_mut::<T, _, _>(plugin, |plugin_instance| {
-            plugin_instance.clear_state_value(scope, key)
+            crate::panic::catch_panic(|| plugin_instance.clear_state_value(scope, key))
         }) {
             Some(error_code) => error_code,
             None => CubeMelonPluginErrorCode::PluginNotFound,
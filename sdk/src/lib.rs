@@ -66,6 +66,8 @@ pub mod memory;
 pub mod macros;
 pub mod instance;
 pub mod interfaces;
+pub mod manifest;
+pub mod panic;
 //pub mod interface_ex;
 //pub mod compat;
 
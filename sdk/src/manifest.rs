@@ -0,0 +1,157 @@
+//! Embedded plugin manifest
+//!
+//! `#[plugin_impl]` embeds a manifest in every plugin library so that hosts can
+//! discover a plugin without loading the library or running any of its code.
+//! The manifest is a fixed header (magic, SDK version, UUID, version, supported
+//! types) followed by UTF-8 TOML carrying the localized names, descriptions and
+//! dependencies:
+//!
+//! ```toml
+//! [name]
+//! default = "Hello World Plugin"
+//! "ja-JP" = "ハローワールドプラグイン"
+//!
+//! [description]
+//! default = "The simplest possible plugin example."
+//!
+//! [[dependencies]]
+//! uuid = "d6090f56-26a4-420c-9e25-a37dd8ebae2e"
+//! min_version = "1.0.0"
+//! ```
+//!
+//! Hosts locate the header by its magic bytes, so no object file parser is
+//! needed for ELF, PE or Mach-O.
+
+use crate::types::{CubeMelonUUID, CubeMelonVersion};
+
+/// Marks the start of a manifest (the last byte is the layout version)
+pub const MANIFEST_MAGIC: [u8; 16] = *b"\x7fCUBEMELON-META\x01";
+
+/// Size of the fixed header preceding the TOML metadata
+pub const MANIFEST_HEADER_SIZE: usize = 52;
+
+/// Manifest embedded in a plugin library
+///
+/// The header is laid out without padding: magic (16 bytes), SDK version (4),
+/// plugin version (4), UUID (16), supported types (8), metadata length (4).
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CubeMelonPluginManifest<const N: usize> {
+    pub magic: [u8; 16],
+    pub sdk_version: CubeMelonVersion,
+    pub version: CubeMelonVersion,
+    pub uuid: CubeMelonUUID,
+    pub supported_types: u64,
+    pub metadata_len: u32,
+    /// UTF-8 TOML (not NULL-terminated)
+    pub metadata: [u8; N],
+}
+
+impl<const N: usize> CubeMelonPluginManifest<N> {
+    /// Create a manifest for a plugin built against this SDK
+    pub const fn new(
+        uuid: CubeMelonUUID,
+        version: CubeMelonVersion,
+        supported_types: u64,
+        metadata: [u8; N],
+    ) -> Self {
+        Self {
+            magic: MANIFEST_MAGIC,
+            sdk_version: crate::SDK_VERSION,
+            version,
+            uuid,
+            supported_types,
+            metadata_len: N as u32,
+            metadata,
+        }
+    }
+}
+
+/// Manifest read back from the bytes of a plugin file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedManifest<'a> {
+    pub sdk_version: CubeMelonVersion,
+    pub version: CubeMelonVersion,
+    pub uuid: CubeMelonUUID,
+    pub supported_types: u64,
+    pub metadata: &'a str,
+}
+
+/// Find the manifest in the contents of a plugin file
+///
+/// Returns `None` if the file carries no (well-formed) manifest. The file must
+/// have been built for the host's architecture, as the header uses native
+/// byte order.
+pub fn find_embedded_manifest(file: &[u8]) -> Option<EmbeddedManifest<'_>> {
+    let mut offset = 0;
+    while let Some(found) = file[offset..].windows(MANIFEST_MAGIC.len()).position(|w| w == MANIFEST_MAGIC) {
+        let start = offset + found;
+        if let Some(manifest) = parse_manifest_at(file, start) {
+            return Some(manifest);
+        }
+        offset = start + 1;
+    }
+    None
+}
+
+/// Decode a manifest header starting at `start`
+fn parse_manifest_at(file: &[u8], start: usize) -> Option<EmbeddedManifest<'_>> {
+    let header = file.get(start..start + MANIFEST_HEADER_SIZE)?;
+    let version_at = |at: usize| {
+        CubeMelonVersion::new(u16::from_ne_bytes([header[at], header[at + 1]]), header[at + 2], header[at + 3])
+    };
+    let mut uuid = [0u8; 16];
+    uuid.copy_from_slice(&header[24..40]);
+    let mut supported_types = [0u8; 8];
+    supported_types.copy_from_slice(&header[40..48]);
+    let metadata_len = u32::from_ne_bytes([header[48], header[49], header[50], header[51]]) as usize;
+
+    let metadata_start = start + MANIFEST_HEADER_SIZE;
+    let metadata = file.get(metadata_start..metadata_start.checked_add(metadata_len)?)?;
+    Some(EmbeddedManifest {
+        sdk_version: version_at(16),
+        version: version_at(20),
+        uuid: CubeMelonUUID::from_bytes(uuid),
+        supported_types: u64::from_ne_bytes(supported_types),
+        metadata: std::str::from_utf8(metadata).ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &[u8; 27] = b"[name]\ndefault = \"Example\"\n";
+
+    fn as_bytes<T>(value: &T) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) }
+    }
+
+    #[test]
+    fn test_header_layout() {
+        assert_eq!(std::mem::offset_of!(CubeMelonPluginManifest<0>, metadata), MANIFEST_HEADER_SIZE);
+        assert_eq!(std::mem::offset_of!(CubeMelonPluginManifest<0>, supported_types), 40);
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let uuid = CubeMelonUUID::from_bytes([7; 16]);
+        let manifest = CubeMelonPluginManifest::new(uuid, CubeMelonVersion::new(1, 2, 3), 0x42, *METADATA);
+
+        // Surround the manifest with unrelated bytes, including a stray magic
+        let mut file = b"junk".to_vec();
+        file.extend_from_slice(&MANIFEST_MAGIC);
+        file.extend_from_slice(&[0xff; 40]);
+        file.extend_from_slice(as_bytes(&manifest));
+        file.extend_from_slice(b"trailer");
+
+        let found = find_embedded_manifest(&file).expect("manifest");
+        assert_eq!(found.sdk_version, crate::SDK_VERSION);
+        assert_eq!(found.version, CubeMelonVersion::new(1, 2, 3));
+        assert_eq!(found.uuid, uuid);
+        assert_eq!(found.supported_types, 0x42);
+        assert_eq!(found.metadata.as_bytes(), METADATA);
+
+        assert_eq!(find_embedded_manifest(b"no manifest here"), None);
+    }
+}
@@ -0,0 +1,189 @@
+//! Panic containment for C ABI entry points
+//!
+//! A Rust panic must never unwind into the host. Every entry point generated
+//! by the SDK macros and the `create_*_interface` helpers runs the plugin's
+//! code through [`catch_panic`], which turns a panic into a fallback return
+//! value: `ThreadPanic` for error codes, NULL for pointers, `Error` for
+//! execution states.
+//!
+//! The panic message and location are reported through the host's log
+//! service (registered when the plugin is initialized) and kept for
+//! [`last_panic`]. Panics outside an entry point, e.g. on a thread the plugin
+//! spawned itself, are left to the previous panic hook.
+
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, Once};
+
+use crate::error::CubeMelonPluginErrorCode;
+use crate::structs::CubeMelonHostServices;
+use crate::types::{CubeMelonExecutionStatus, CubeMelonLogLevel, CubeMelonUUID, CubeMelonVersion};
+
+/// Host log function
+type LogFn = unsafe extern "C" fn(CubeMelonLogLevel, *const u8, *const u8);
+
+/// Log service and plugin name used to report contained panics
+static PANIC_LOG: Mutex<Option<(LogFn, &'static str)>> = Mutex::new(None);
+
+/// Description of the most recent contained panic
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+static INSTALL_HOOK: Once = Once::new();
+
+thread_local! {
+    /// Number of `catch_panic` calls active on this thread
+    static GUARD_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Message and location recorded by the hook for the innermost guard
+    static CAUGHT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Value an entry point returns when the plugin panicked
+pub trait PanicFallback {
+    fn panic_fallback() -> Self;
+}
+
+impl PanicFallback for CubeMelonPluginErrorCode {
+    fn panic_fallback() -> Self {
+        CubeMelonPluginErrorCode::ThreadPanic
+    }
+}
+
+impl PanicFallback for CubeMelonExecutionStatus {
+    fn panic_fallback() -> Self {
+        CubeMelonExecutionStatus::Error
+    }
+}
+
+impl PanicFallback for CubeMelonUUID {
+    fn panic_fallback() -> Self {
+        CubeMelonUUID::from_bytes([0; 16])
+    }
+}
+
+impl PanicFallback for CubeMelonVersion {
+    fn panic_fallback() -> Self {
+        CubeMelonVersion::new(0, 0, 0)
+    }
+}
+
+impl<T> PanicFallback for *const T {
+    fn panic_fallback() -> Self {
+        std::ptr::null()
+    }
+}
+
+impl<T> PanicFallback for *mut T {
+    fn panic_fallback() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+macro_rules! default_panic_fallback {
+    ($($ty:ty),*) => {
+        $(impl PanicFallback for $ty {
+            fn panic_fallback() -> Self {
+                Default::default()
+            }
+        })*
+    };
+}
+
+// false / 0 / nothing
+default_panic_fallback!((), bool, u32, u64, usize);
+
+/// Run plugin code, turning a panic into the return type's fallback value
+pub fn catch_panic<R: PanicFallback>(f: impl FnOnce() -> R) -> R {
+    INSTALL_HOOK.call_once(install_hook);
+
+    GUARD_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let outcome = panic::catch_unwind(AssertUnwindSafe(f));
+    GUARD_DEPTH.with(|depth| depth.set(depth.get() - 1));
+
+    outcome.unwrap_or_else(|payload| {
+        let description = CAUGHT
+            .with(|caught| caught.borrow_mut().take())
+            .unwrap_or_else(|| payload_message(payload.as_ref()).to_string());
+        report_panic(description);
+        R::panic_fallback()
+    })
+}
+
+/// Report contained panics through the host's log service
+///
+/// Called by the generated `initialize` wrapper.
+pub fn set_panic_log(host_services: Option<&CubeMelonHostServices>, plugin_name: &'static str) {
+    if let Some(log) = host_services.and_then(|services| services.log) {
+        *PANIC_LOG.lock().unwrap_or_else(|e| e.into_inner()) = Some((log, plugin_name));
+    }
+}
+
+/// Message and location of the most recent contained panic
+pub fn last_panic() -> Option<String> {
+    LAST_PANIC.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+fn install_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if GUARD_DEPTH.with(Cell::get) == 0 {
+            previous(info);
+            return;
+        }
+        let message = payload_message(info.payload());
+        let description = match info.location() {
+            Some(location) => format!("panicked at {}: {}", location, message),
+            None => format!("panicked: {}", message),
+        };
+        CAUGHT.with(|caught| *caught.borrow_mut() = Some(description));
+    }));
+}
+
+fn payload_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+fn report_panic(description: String) {
+    let log = *PANIC_LOG.lock().unwrap_or_else(|e| e.into_inner());
+    match log {
+        Some((log, plugin_name)) => {
+            let services = CubeMelonHostServices::new(Some(log), None, None);
+            services.log_message(CubeMelonLogLevel::Error, plugin_name, &description);
+        }
+        None => eprintln!("{}", description),
+    }
+    *LAST_PANIC.lock().unwrap_or_else(|e| e.into_inner()) = Some(description);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_becomes_fallback() {
+        assert_eq!(catch_panic(|| CubeMelonPluginErrorCode::Success), CubeMelonPluginErrorCode::Success);
+
+        let code = catch_panic(|| -> CubeMelonPluginErrorCode { panic!("boom {}", 42) });
+        assert_eq!(code, CubeMelonPluginErrorCode::ThreadPanic);
+        let description = last_panic().unwrap();
+        assert!(description.contains("boom 42"), "{}", description);
+        assert!(description.contains("panic.rs"), "{}", description);
+
+        assert!(catch_panic(|| -> *const u8 { panic!("null") }).is_null());
+        assert_eq!(catch_panic(|| -> CubeMelonExecutionStatus { panic!() }), CubeMelonExecutionStatus::Error);
+        assert!(!catch_panic(|| -> bool { panic!() }));
+
+        // An inner guard contains its own panic
+        let outer = catch_panic(|| {
+            let inner = catch_panic(|| -> u32 { panic!("inner") });
+            assert_eq!(inner, 0);
+            CubeMelonPluginErrorCode::Success
+        });
+        assert_eq!(outer, CubeMelonPluginErrorCode::Success);
+    }
+}
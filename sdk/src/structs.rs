@@ -9,6 +9,8 @@ use crate::types::{
 use crate::error::CubeMelonPluginErrorCode;
 use crate::memory::{CubeMelonString, CubeMelonValue,};
 use crate::instance::CubeMelonPlugin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Plugin basic information structure
 #[repr(C)]
@@ -45,6 +47,31 @@ impl CubeMelonPluginBasicInfo {
     }
 }
 
+/// Plugin dependency declaration
+///
+/// Exported by `get_plugin_dependencies`. The host loads every dependency
+/// before the plugin that declares it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CubeMelonPluginDependency {
+    /// UUID of the required plugin
+    pub uuid: CubeMelonUUID,
+    /// Lowest acceptable version of the required plugin
+    pub min_version: CubeMelonVersion,
+}
+
+impl CubeMelonPluginDependency {
+    /// Create a new dependency declaration
+    pub const fn new(uuid: CubeMelonUUID, min_version: CubeMelonVersion) -> Self {
+        Self { uuid, min_version }
+    }
+
+    /// Whether a plugin version satisfies this dependency
+    pub fn is_satisfied_by(&self, version: CubeMelonVersion) -> bool {
+        version >= self.min_version
+    }
+}
+
 /// Task request structure containing all information needed to execute a task
 #[repr(C)]
 #[derive(Debug)]
@@ -61,14 +88,25 @@ pub struct CubeMelonTaskRequest {
     pub language: CubeMelonLanguage,
     /// Task execution start time (microseconds)
     pub request_time_us: i64,
-    /// Timeout for asynchronous execution (microseconds)
+    /// Timeout (microseconds, 0 = none)
     pub timeout_us: i64,
     /// Application-specific data
     pub user_data: *mut std::ffi::c_void,
-    /// Reserved for future expansion
+    /// Owned by the host; callers must leave both slots zeroed
+    ///
+    /// `reserved[0]` is the host's cancel flag (see `is_cancelled`), which is
+    /// dereferenced when non-null; `reserved[1]` the trace ID (see `trace_id`).
+    /// `new` and `empty` zero them, and hosts fill them in with
+    /// `set_cancel_flag` and `set_trace_id` on their own copy of the request.
     pub reserved: [*mut std::ffi::c_void; 2],
 }
 
+/// Slot of `CubeMelonTaskRequest::reserved` holding the cancel flag
+const CANCEL_FLAG_SLOT: usize = 0;
+
+/// Slot of `CubeMelonTaskRequest::reserved` holding the trace ID
+const TRACE_ID_SLOT: usize = 1;
+
 impl CubeMelonTaskRequest {
     /// Create a new task request
     pub fn new(
@@ -107,6 +145,73 @@ impl CubeMelonTaskRequest {
             reserved: [std::ptr::null_mut(); 2],
         }
     }
+
+    /// Shallow copy sharing this request's input buffers
+    ///
+    /// The copy never frees the input JSON, so this request must outlive it.
+    /// Hosts use it to hand a plugin the request with reserved slots of their own.
+    pub fn borrowed_copy(&self) -> Self {
+        Self {
+            caller: self.caller,
+            input_data: self.input_data,
+            input_json: CubeMelonString {
+                str: self.input_json.str,
+                free_string: None,
+            },
+            task_type: self.task_type,
+            language: self.language.clone(),
+            request_time_us: self.request_time_us,
+            timeout_us: self.timeout_us,
+            user_data: self.user_data,
+            reserved: self.reserved,
+        }
+    }
+
+    /// Time (microseconds since the Unix epoch) by which the task should finish
+    pub fn deadline_us(&self) -> Option<i64> {
+        (self.request_time_us > 0 && self.timeout_us > 0).then(|| self.request_time_us.saturating_add(self.timeout_us))
+    }
+
+    /// Whether the host wants the task to stop
+    ///
+    /// Long-running plugins should poll this and return early; the flag goes
+    /// up when the host gives up on the task, e.g. at its timeout. A non-null
+    /// `reserved[0]` must point to the host's flag.
+    pub fn is_cancelled(&self) -> bool {
+        let flag = self.reserved[CANCEL_FLAG_SLOT] as *const AtomicBool;
+        if !flag.is_null() && unsafe { (*flag).load(Ordering::Acquire) } {
+            return true;
+        }
+        self.deadline_us().is_some_and(|deadline| now_us() >= deadline)
+    }
+
+    /// Attach the host's cancel flag
+    ///
+    /// The flag must outlive every use of the request by the plugin.
+    pub fn set_cancel_flag(&mut self, flag: *const AtomicBool) {
+        self.reserved[CANCEL_FLAG_SLOT] = flag as *mut std::ffi::c_void;
+    }
+
+    /// Correlation ID of the call tree this task belongs to (0 = none)
+    ///
+    /// The host sets it on every request it hands to a plugin and carries it
+    /// over to nested calls made through the manager. A plugin making nested
+    /// calls from threads of its own should copy it with `set_trace_id`.
+    pub fn trace_id(&self) -> u64 {
+        self.reserved[TRACE_ID_SLOT] as usize as u64
+    }
+
+    /// Set the trace ID (pointer-sized; higher bits are dropped on 32-bit targets)
+    pub fn set_trace_id(&mut self, trace_id: u64) {
+        self.reserved[TRACE_ID_SLOT] = trace_id as usize as *mut std::ffi::c_void;
+    }
+}
+
+fn now_us() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
 }
 
 unsafe impl Send for CubeMelonTaskRequest {}
@@ -412,6 +517,46 @@ mod tests {
         assert_eq!(info.supported_types, types);
     }
 
+    #[test]
+    fn test_request_cancellation() {
+        let mut request = CubeMelonTaskRequest::empty();
+        assert_eq!(request.deadline_us(), None);
+        assert!(!request.is_cancelled());
+
+        let flag = AtomicBool::new(false);
+        request.set_cancel_flag(&flag);
+        assert!(!request.is_cancelled());
+        flag.store(true, Ordering::Release);
+        assert!(request.is_cancelled());
+
+        request.set_cancel_flag(std::ptr::null());
+        request.request_time_us = now_us() - 2_000_000;
+        request.timeout_us = 1_000_000;
+        assert_eq!(request.deadline_us(), Some(request.request_time_us + 1_000_000));
+        assert!(request.is_cancelled());
+        request.timeout_us = 60_000_000;
+        assert!(!request.is_cancelled());
+    }
+
+    #[test]
+    fn test_request_trace_id() {
+        let mut request = CubeMelonTaskRequest::empty();
+        assert_eq!(request.trace_id(), 0);
+        request.set_trace_id(0x5eed);
+        assert_eq!(request.trace_id(), 0x5eed);
+        // The trace ID slot is not taken for a cancel flag
+        assert!(!request.is_cancelled());
+    }
+
+    #[test]
+    fn test_dependency_version_check() {
+        let dependency = CubeMelonPluginDependency::new(CubeMelonUUID::from_bytes([2; 16]), CubeMelonVersion::new(1, 2, 0));
+
+        assert!(dependency.is_satisfied_by(CubeMelonVersion::new(1, 2, 0)));
+        assert!(dependency.is_satisfied_by(CubeMelonVersion::new(2, 0, 0)));
+        assert!(!dependency.is_satisfied_by(CubeMelonVersion::new(1, 1, 9)));
+    }
+
     #[test]
     fn test_task_request_creation() {
         let caller = std::ptr::null();
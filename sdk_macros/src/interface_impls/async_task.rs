@@ -87,7 +87,7 @@ fn parse_async_task_methods(input: &ItemImpl) -> Result<AsyncTaskMethods, syn::E
     if methods.execute_method.is_none() {
         return Err(syn::Error::new_spanned(
             input,
-            "AsyncTaskInterface implementation must include an 'execute' method with signature: execute(&mut self, request: &CubeMelonTaskRequest, callback: CubeMelonTaskCallback) -> CubeMelonPluginErrorCode",
+            "AsyncTaskInterface implementation must include an 'execute' method with signature: execute(&mut self, request: &CubeMelonTaskRequest, callback: Option<CubeMelonTaskCallback>) -> CubeMelonPluginErrorCode",
         ));
     }
 
@@ -114,11 +114,11 @@ fn parse_async_task_methods(input: &ItemImpl) -> Result<AsyncTaskMethods, syn::E
 fn validate_execute_method_signature(method: &syn::ImplItemFn) -> Result<(), syn::Error> {
     let sig = &method.sig;
     
-    // Should have 3 parameters: &mut self, &CubeMelonTaskRequest, CubeMelonTaskCallback
+    // Should have 3 parameters: &mut self, &CubeMelonTaskRequest, Option<CubeMelonTaskCallback>
     if sig.inputs.len() != 3 {
         return Err(syn::Error::new_spanned(
             sig,
-            "execute method must have exactly 3 parameters: (&mut self, &CubeMelonTaskRequest, CubeMelonTaskCallback)",
+            "execute method must have exactly 3 parameters: (&mut self, &CubeMelonTaskRequest, Option<CubeMelonTaskCallback>)",
         ));
     }
 
@@ -191,7 +191,7 @@ fn generate_async_task_interface_impl(
             fn execute(
                 &mut self,
                 request: &::cubemelon_sdk::structs::CubeMelonTaskRequest,
-                callback: ::cubemelon_sdk::structs::CubeMelonTaskCallback,
+                callback: Option<::cubemelon_sdk::structs::CubeMelonTaskCallback>,
             ) -> ::cubemelon_sdk::error::CubeMelonPluginErrorCode {
                 self.#execute_method(request, callback)
             }
@@ -245,7 +245,7 @@ mod tests {
                 pub fn execute(
                     &mut self,
                     request: &CubeMelonTaskRequest,
-                    callback: CubeMelonTaskCallback,
+                    callback: Option<CubeMelonTaskCallback>,
                 ) -> CubeMelonPluginErrorCode {
                     CubeMelonPluginErrorCode::Success
                 }
@@ -292,7 +292,7 @@ mod tests {
                 pub fn execute(
                     &mut self,
                     request: &CubeMelonTaskRequest,
-                    callback: CubeMelonTaskCallback,
+                    callback: Option<CubeMelonTaskCallback>,
                 ) -> CubeMelonPluginErrorCode {
                     CubeMelonPluginErrorCode::Success
                 }
@@ -310,7 +310,7 @@ mod tests {
             pub fn execute(
                 &mut self,
                 request: &CubeMelonTaskRequest,
-                callback: CubeMelonTaskCallback,
+                callback: Option<CubeMelonTaskCallback>,
             ) -> CubeMelonPluginErrorCode {
                 CubeMelonPluginErrorCode::Success
             }
@@ -370,7 +370,7 @@ mod tests {
                 pub fn execute(
                     &mut self,
                     request: &CubeMelonTaskRequest,
-                    callback: CubeMelonTaskCallback,
+                    callback: Option<CubeMelonTaskCallback>,
                 ) -> CubeMelonPluginErrorCode {
                     CubeMelonPluginErrorCode::Success
                 }
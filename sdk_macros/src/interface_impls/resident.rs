@@ -152,6 +152,19 @@ fn generate_resident_interface_impl(
     let stop_method = &methods.stop_method.as_ref().unwrap().sig.ident;
     let reset_method = &methods.reset_method.as_ref().unwrap().sig.ident;
 
+    // The C ABI passes configuration as a NULL-terminated UTF-8 string;
+    // user methods receive it as &str (empty when NULL)
+    let config_str = quote! {
+        let config_json: &str = if config_json.is_null() {
+            ""
+        } else {
+            match unsafe { ::std::ffi::CStr::from_ptr(config_json as *const ::std::os::raw::c_char) }.to_str() {
+                Ok(s) => s,
+                Err(_) => return ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Encoding,
+            }
+        };
+    };
+
     Ok(quote! {
         impl ::cubemelon_sdk::interfaces::resident::CubeMelonResidentInterface for #struct_name {
             fn get_status(&self) -> ::cubemelon_sdk::types::CubeMelonExecutionStatus {
@@ -164,15 +177,17 @@ fn generate_resident_interface_impl(
 
             fn update_configuration(
                 &mut self,
-                config_json: &str,
+                config_json: *const u8,
             ) -> ::cubemelon_sdk::error::CubeMelonPluginErrorCode {
+                #config_str
                 self.#update_configuration_method(config_json)
             }
 
             fn start(
                 &mut self,
-                config_json: &str,
+                config_json: *const u8,
             ) -> ::cubemelon_sdk::error::CubeMelonPluginErrorCode {
+                #config_str
                 self.#start_method(config_json)
             }
 
@@ -7,6 +7,7 @@ use proc_macro::TokenStream;
 use syn::{parse_macro_input, ItemImpl};
 
 mod plugin;
+mod manifest;
 mod interface_impls;
 
 /// Mark a struct as a CubeMelon plugin
@@ -105,8 +106,15 @@ pub fn plugin(args: TokenStream, input: TokenStream) -> TokenStream {
 /// - All C ABI export functions (get_plugin_uuid, create_plugin, etc.)
 /// - PluginBase trait implementation
 /// - Static CubeMelonInterface structure
-/// - C ABI wrapper functions
+/// - C ABI wrapper functions, which turn a panic in plugin code into
+///   `ThreadPanic` (or a NULL/default return) and report it through the host's
+///   log service
 /// - Plugin instance management code
+/// - An embedded manifest (`CUBEMELON_PLUGIN_MANIFEST`) that lets hosts read the
+///   plugin's metadata without loading it, when the UUID, version and supported
+///   types are constant expressions and names, descriptions, dependencies and
+///   capabilities are literals (`multilang_map!`,
+///   `vec![CubeMelonPluginDependency::new(..)]`, a string)
 /// 
 /// # Required methods
 /// These methods must be implemented in the impl block:
@@ -121,6 +129,9 @@ pub fn plugin(args: TokenStream, input: TokenStream) -> TokenStream {
 /// - `get_description(&self, CubeMelonLanguage) -> *const u8` - Defaults to "No description"
 /// - `initialize(&mut self, ...) -> Result<(), CubeMelonPluginErrorCode>` - Defaults to `Ok(())`
 /// - `uninitialize(&mut self) -> Result<(), CubeMelonPluginErrorCode>` - Defaults to `Ok(())`
+/// - `get_dependencies() -> Vec<CubeMelonPluginDependency>` - Defaults to no dependencies
+/// - `get_capabilities() -> &'static str` - TOML table body (`task_types`, `input_formats`,
+///   `output_formats`, `languages`) hosts use to match plugins to tasks. Defaults to none declared
 /// 
 /// # Constructor requirement
 /// The plugin struct should have a `new() -> Self` method (or be Default),
@@ -0,0 +1,307 @@
+//! Plugin manifest generation
+//!
+//! `#[plugin_impl]` embeds a `CubeMelonPluginManifest` in the plugin library so
+//! hosts can read the plugin's metadata without loading it. This only works
+//! when the metadata is spelled out in the source: the UUID, version and
+//! supported types must be constant expressions, and names, descriptions,
+//! dependencies and capabilities must be literals. Otherwise no manifest is
+//! generated and hosts fall back to loading the library.
+
+use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse::Parse, parse::ParseStream, punctuated::Punctuated, BinOp, Expr, ImplItemFn, Lit, LitStr, Stmt,
+    Token,
+};
+
+/// Plugin methods the manifest is derived from
+pub struct ManifestSource<'a> {
+    pub uuid: &'a ImplItemFn,
+    pub version: &'a ImplItemFn,
+    pub supported_types: &'a ImplItemFn,
+    pub name: Option<&'a ImplItemFn>,
+    pub description: Option<&'a ImplItemFn>,
+    pub dependencies: Option<&'a ImplItemFn>,
+    pub capabilities: Option<&'a ImplItemFn>,
+}
+
+/// Generate the exported manifest static, or nothing if the metadata is not static
+pub fn generate_manifest(source: &ManifestSource) -> TokenStream2 {
+    let Some(metadata) = manifest_metadata(source) else {
+        return quote! {};
+    };
+    let (Some(uuid), Some(version), Some(supported_types)) = (
+        body_expr(source.uuid).filter(|e| is_const_expr(e)),
+        body_expr(source.version).filter(|e| is_const_expr(e)),
+        body_expr(source.supported_types).filter(|e| is_const_expr(e)),
+    ) else {
+        return quote! {};
+    };
+
+    let len = Literal::usize_unsuffixed(metadata.len());
+    let bytes = syn::LitByteStr::new(metadata.as_bytes(), Span::call_site());
+    quote! {
+        /// Plugin metadata readable without loading the library
+        #[no_mangle]
+        #[cfg_attr(target_vendor = "apple", unsafe(link_section = "__DATA,__cubemelon"))]
+        #[cfg_attr(windows, unsafe(link_section = ".cubemel"))]
+        #[cfg_attr(not(any(windows, target_vendor = "apple")), unsafe(link_section = ".cubemelon"))]
+        pub static CUBEMELON_PLUGIN_MANIFEST: ::cubemelon_sdk::manifest::CubeMelonPluginManifest<#len> =
+            ::cubemelon_sdk::manifest::CubeMelonPluginManifest::new(#uuid, #version, #supported_types, *#bytes);
+    }
+}
+
+/// TOML part of the manifest: localized strings, dependencies and capabilities
+fn manifest_metadata(source: &ManifestSource) -> Option<String> {
+    let mut toml = String::new();
+    for (table, method) in [("name", source.name), ("description", source.description)] {
+        // Plugins without the method use the generated default text
+        let Some(method) = method else { continue };
+        let map = multilang_map(body_expr(method)?)?;
+        toml.push_str(&format!("[{}]\ndefault = {}\n", table, toml_string(&map.default.value())));
+        for (language, text) in &map.entries {
+            toml.push_str(&format!("{} = {}\n", toml_string(&language.value()), toml_string(&text.value())));
+        }
+        toml.push('\n');
+    }
+    if let Some(method) = source.dependencies {
+        for (uuid, min_version) in dependency_list(body_expr(method)?)? {
+            toml.push_str(&format!(
+                "[[dependencies]]\nuuid = {}\nmin_version = {}\n\n",
+                toml_string(&uuid),
+                toml_string(&min_version)
+            ));
+        }
+    }
+    if let Some(method) = source.capabilities {
+        // The body of the [capabilities] table, spelled out as a string literal
+        let Expr::Lit(syn::ExprLit { lit: Lit::Str(table), .. }) = body_expr(method)? else {
+            return None;
+        };
+        toml.push_str("[capabilities]\n");
+        for line in table.value().lines().map(str::trim).filter(|line| !line.is_empty()) {
+            toml.push_str(line);
+            toml.push('\n');
+        }
+        toml.push('\n');
+    }
+    Some(toml)
+}
+
+/// The expression a method body consists of, if it is a single expression
+fn body_expr(method: &ImplItemFn) -> Option<&Expr> {
+    match method.block.stmts.as_slice() {
+        [Stmt::Expr(expr, None)] => Some(expr),
+        _ => None,
+    }
+}
+
+/// Name of the macro an expression invokes
+fn macro_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Macro(mac) => mac.mac.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// Whether an expression can be evaluated in a static initializer
+///
+/// Deliberately conservative: literals, constants, casts, `|` and the SDK's
+/// `uuid!`, `version!` and `plugin_types!` macros.
+fn is_const_expr(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(_) => true,
+        Expr::Path(path) => path.qself.is_none() && path.path.segments.first().is_some_and(|s| s.ident != "Self"),
+        Expr::Cast(cast) => is_const_expr(&cast.expr),
+        Expr::Binary(binary) => {
+            matches!(binary.op, BinOp::BitOr(_)) && is_const_expr(&binary.left) && is_const_expr(&binary.right)
+        }
+        Expr::Paren(paren) => is_const_expr(&paren.expr),
+        Expr::Group(group) => is_const_expr(&group.expr),
+        Expr::Struct(init) => init.rest.is_none() && init.fields.iter().all(|f| is_const_expr(&f.expr)),
+        Expr::Macro(mac) => match macro_name(expr).as_deref() {
+            Some("uuid") | Some("plugin_types") => true,
+            Some("version") => mac
+                .mac
+                .parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+                .is_ok_and(|args| args.iter().all(is_const_expr)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Arguments of `multilang_map!(language, "default", { "code" => "text", ... })`
+struct MultilangMap {
+    default: LitStr,
+    entries: Vec<(LitStr, LitStr)>,
+}
+
+impl Parse for MultilangMap {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Expr>()?;
+        input.parse::<Token![,]>()?;
+        let default = input.parse::<LitStr>()?;
+        input.parse::<Token![,]>()?;
+        let content;
+        syn::braced!(content in input);
+        let entries = Punctuated::<(LitStr, LitStr), Token![,]>::parse_terminated_with(&content, |entry| {
+            let code = entry.parse::<LitStr>()?;
+            entry.parse::<Token![=>]>()?;
+            Ok((code, entry.parse::<LitStr>()?))
+        })?;
+        Ok(Self { default, entries: entries.into_iter().collect() })
+    }
+}
+
+fn multilang_map(expr: &Expr) -> Option<MultilangMap> {
+    match expr {
+        Expr::Macro(mac) if macro_name(expr).as_deref() == Some("multilang_map") => mac.mac.parse_body().ok(),
+        _ => None,
+    }
+}
+
+/// `(uuid, min_version)` pairs of `vec![CubeMelonPluginDependency::new(uuid!(..), version!(..)), ...]`
+fn dependency_list(expr: &Expr) -> Option<Vec<(String, String)>> {
+    let mac = match expr {
+        Expr::Macro(mac) if macro_name(expr).as_deref() == Some("vec") => mac,
+        _ => return None,
+    };
+    let items = mac.mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated).ok()?;
+    items
+        .iter()
+        .map(|item| {
+            let Expr::Call(call) = item else { return None };
+            if call.args.len() != 2 {
+                return None;
+            }
+            Some((literal_uuid(&call.args[0])?, literal_version(&call.args[1])?))
+        })
+        .collect()
+}
+
+fn literal_uuid(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Macro(mac) if macro_name(expr).as_deref() == Some("uuid") => {
+            mac.mac.parse_body::<LitStr>().ok().map(|lit| lit.value())
+        }
+        _ => None,
+    }
+}
+
+fn literal_version(expr: &Expr) -> Option<String> {
+    let Expr::Macro(mac) = expr else { return None };
+    if macro_name(expr).as_deref() != Some("version") {
+        return None;
+    }
+    let parts = mac.mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated).ok()?;
+    let numbers = parts
+        .iter()
+        .map(|part| match part {
+            Expr::Lit(lit) => match &lit.lit {
+                Lit::Int(int) => int.base10_parse::<u16>().ok(),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Option<Vec<u16>>>()?;
+    match numbers.as_slice() {
+        [major, minor, patch] => Some(format!("{}.{}.{}", major, minor, patch)),
+        _ => None,
+    }
+}
+
+/// Quote a string as a TOML basic string
+fn toml_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::{parse_quote, ItemImpl};
+
+    fn method<'a>(input: &'a ItemImpl, name: &str) -> Option<&'a ImplItemFn> {
+        input.items.iter().find_map(|item| match item {
+            syn::ImplItem::Fn(method) if method.sig.ident == name => Some(method),
+            _ => None,
+        })
+    }
+
+    fn source(input: &ItemImpl) -> ManifestSource<'_> {
+        ManifestSource {
+            uuid: method(input, "get_uuid").unwrap(),
+            version: method(input, "get_version").unwrap(),
+            supported_types: method(input, "get_supported_types").unwrap(),
+            name: method(input, "get_name"),
+            description: method(input, "get_description"),
+            dependencies: method(input, "get_dependencies"),
+            capabilities: method(input, "get_capabilities"),
+        }
+    }
+
+    #[test]
+    fn test_manifest_from_literal_metadata() {
+        let input: ItemImpl = parse_quote! {
+            impl TestPlugin {
+                pub fn get_uuid() -> CubeMelonUUID { uuid!("12345678-1234-5678-9abc-123456789abc") }
+                pub fn get_version() -> CubeMelonVersion { version!(1, 2, 3) }
+                pub fn get_supported_types() -> u64 {
+                    CubeMelonPluginType::SingleTask as u64 | CubeMelonPluginType::Resident as u64
+                }
+                pub fn get_name(&self, language: CubeMelonLanguage) -> *const u8 {
+                    multilang_map!(language, "Test \"Plugin\"", { "ja-JP" => "テスト" })
+                }
+                pub fn get_dependencies() -> Vec<CubeMelonPluginDependency> {
+                    vec![CubeMelonPluginDependency::new(uuid!("d6090f56-26a4-420c-9e25-a37dd8ebae2e"), version!(1, 0, 0))]
+                }
+                pub fn get_capabilities() -> &'static str {
+                    "task_types = [\"image\"]"
+                }
+            }
+        };
+        let source = source(&input);
+        assert_eq!(
+            manifest_metadata(&source).unwrap(),
+            "[name]\ndefault = \"Test \\\"Plugin\\\"\"\n\"ja-JP\" = \"テスト\"\n\n\
+             [[dependencies]]\nuuid = \"d6090f56-26a4-420c-9e25-a37dd8ebae2e\"\nmin_version = \"1.0.0\"\n\n\
+             [capabilities]\ntask_types = [\"image\"]\n\n"
+        );
+        assert!(generate_manifest(&source).to_string().contains("CUBEMELON_PLUGIN_MANIFEST"));
+    }
+
+    #[test]
+    fn test_no_manifest_for_computed_metadata() {
+        let input: ItemImpl = parse_quote! {
+            impl TestPlugin {
+                pub fn get_uuid() -> CubeMelonUUID { uuid!("12345678-1234-5678-9abc-123456789abc") }
+                pub fn get_version() -> CubeMelonVersion { version!(1, 0, 0) }
+                pub fn get_supported_types() -> u64 { compute_types() }
+            }
+        };
+        assert!(generate_manifest(&source(&input)).is_empty());
+
+        let input: ItemImpl = parse_quote! {
+            impl TestPlugin {
+                pub fn get_uuid() -> CubeMelonUUID { uuid!("12345678-1234-5678-9abc-123456789abc") }
+                pub fn get_version() -> CubeMelonVersion { version!(1, 0, 0) }
+                pub fn get_supported_types() -> u64 { 0 }
+                pub fn get_name(&self, language: CubeMelonLanguage) -> *const u8 { self.name.as_ptr() }
+            }
+        };
+        assert!(generate_manifest(&source(&input)).is_empty());
+    }
+}
@@ -11,6 +11,8 @@ use syn::{
     parse::Parse, parse::ParseStream, Token, punctuated::Punctuated, Expr, ExprLit
 };
 
+use crate::manifest::{generate_manifest, ManifestSource};
+
 /// Custom NestedMeta replacement for syn 2.0
 #[derive(Debug, Clone)]
 #[allow(dead_code)] // Lit variant is for future extensibility
@@ -187,6 +189,15 @@ fn process_plugin_impl_attribute(
     let c_abi_exports = generate_c_abi_exports(struct_name, &plugin_methods);
     let c_abi_interface = generate_c_abi_interface(struct_name, &plugin_methods);
     let c_abi_wrappers = generate_c_abi_wrappers(struct_name, &plugin_methods);
+    let manifest = generate_manifest(&ManifestSource {
+        uuid: plugin_methods.get_uuid_method.as_ref().unwrap(),
+        version: plugin_methods.get_version_method.as_ref().unwrap(),
+        supported_types: plugin_methods.get_supported_types_method.as_ref().unwrap(),
+        name: plugin_methods.get_name_method.as_ref(),
+        description: plugin_methods.get_description_method.as_ref(),
+        dependencies: plugin_methods.get_dependencies_method.as_ref(),
+        capabilities: plugin_methods.get_capabilities_method.as_ref(),
+    });
 
     Ok(quote! {
         // Include the original impl block
@@ -203,6 +214,9 @@ fn process_plugin_impl_attribute(
         
         // Generate C ABI wrapper functions
         #c_abi_wrappers
+
+        // Embed metadata for discovery without loading
+        #manifest
     })
 }
 
@@ -232,6 +246,8 @@ struct PluginMethods {
     get_description_method: Option<syn::ImplItemFn>,
     initialize_method: Option<syn::ImplItemFn>,
     uninitialize_method: Option<syn::ImplItemFn>,
+    get_dependencies_method: Option<syn::ImplItemFn>,
+    get_capabilities_method: Option<syn::ImplItemFn>,
     
     // Constructor method (new)
     new_method: Option<syn::ImplItemFn>,
@@ -289,6 +305,8 @@ fn parse_plugin_methods(input: &ItemImpl) -> Result<PluginMethods, syn::Error> {
         get_description_method: None,
         initialize_method: None,
         uninitialize_method: None,
+        get_dependencies_method: None,
+        get_capabilities_method: None,
         new_method: None,
         other_methods: Vec::new(),
     };
@@ -307,6 +325,8 @@ fn parse_plugin_methods(input: &ItemImpl) -> Result<PluginMethods, syn::Error> {
                 "get_description" => methods.get_description_method = Some(method.clone()),
                 "initialize" => methods.initialize_method = Some(method.clone()),
                 "uninitialize" => methods.uninitialize_method = Some(method.clone()),
+                "get_dependencies" => methods.get_dependencies_method = Some(method.clone()),
+                "get_capabilities" => methods.get_capabilities_method = Some(method.clone()),
                 "new" => methods.new_method = Some(method.clone()),
                 _ => methods.other_methods.push(item.clone()),
             }
@@ -465,11 +485,25 @@ fn generate_c_abi_exports(
         quote! { #struct_name::default() }
     };
 
+    let capabilities_call = if let Some(method) = &methods.get_capabilities_method {
+        let method_name = &method.sig.ident;
+        quote! { ::std::ffi::CString::new(#struct_name::#method_name()).ok() }
+    } else {
+        quote! { None } // Default: no capabilities declared
+    };
+
+    let dependencies_call = if let Some(method) = &methods.get_dependencies_method {
+        let method_name = &method.sig.ident;
+        quote! { #struct_name::#method_name() }
+    } else {
+        quote! { Vec::new() } // Default: no dependencies
+    };
+
     quote! {
         /// C ABI: Get plugin UUID
         #[no_mangle]
         pub extern "C" fn get_plugin_uuid() -> ::cubemelon_sdk::types::CubeMelonUUID {
-            #struct_name::#uuid_method()
+            ::cubemelon_sdk::panic::catch_panic(|| #struct_name::#uuid_method())
         }
 
         /// C ABI: Get plugin SDK version
@@ -481,26 +515,28 @@ fn generate_c_abi_exports(
         /// C ABI: Get plugin version
         #[no_mangle]
         pub extern "C" fn get_plugin_version() -> ::cubemelon_sdk::types::CubeMelonVersion {
-            #struct_name::#version_method()
+            ::cubemelon_sdk::panic::catch_panic(|| #struct_name::#version_method())
         }
 
         /// C ABI: Get supported plugin types
         #[no_mangle]
         pub extern "C" fn get_plugin_supported_types() -> u64 {
-            #struct_name::#supported_types_method()
+            ::cubemelon_sdk::panic::catch_panic(|| #struct_name::#supported_types_method())
         }
 
         /// C ABI: Create plugin instance
         #[no_mangle]
         pub extern "C" fn create_plugin() -> *mut ::cubemelon_sdk::instance::CubeMelonPlugin {
-            let plugin = #constructor_call;
-            ::cubemelon_sdk::instance::create_plugin_instance(plugin)
+            ::cubemelon_sdk::panic::catch_panic(|| {
+                let plugin = #constructor_call;
+                ::cubemelon_sdk::instance::create_plugin_instance(plugin)
+            })
         }
 
         /// C ABI: Destroy plugin instance
         #[no_mangle]
         pub extern "C" fn destroy_plugin(plugin: *mut ::cubemelon_sdk::instance::CubeMelonPlugin) {
-            ::cubemelon_sdk::instance::destroy_plugin_instance(plugin);
+            ::cubemelon_sdk::panic::catch_panic(|| ::cubemelon_sdk::instance::destroy_plugin_instance(plugin));
         }
 
         /// C ABI: Check if plugin can be unloaded
@@ -508,6 +544,43 @@ fn generate_c_abi_exports(
         pub extern "C" fn can_unload_now() -> bool {
             ::cubemelon_sdk::instance::get_plugin_ref_count() == 0
         }
+
+        /// C ABI: Get the plugin's declared capabilities as a TOML table body
+        /// Returns a static string (NULL if none declared); the caller must not free it
+        #[no_mangle]
+        pub extern "C" fn get_plugin_capabilities() -> *const u8 {
+            static CAPABILITIES: ::std::sync::OnceLock<Option<::std::ffi::CString>> = ::std::sync::OnceLock::new();
+            ::cubemelon_sdk::panic::catch_panic(|| {
+                CAPABILITIES
+                    .get_or_init(|| #capabilities_call)
+                    .as_ref()
+                    .map_or(std::ptr::null(), |toml| toml.as_ptr() as *const u8)
+            })
+        }
+
+        /// C ABI: Get plugins that must be loaded before this one
+        /// Returns a static array (NULL if empty); the caller must not free it
+        #[no_mangle]
+        pub extern "C" fn get_plugin_dependencies(
+            count: *mut usize,
+        ) -> *const ::cubemelon_sdk::structs::CubeMelonPluginDependency {
+            static DEPENDENCIES: ::std::sync::OnceLock<Vec<::cubemelon_sdk::structs::CubeMelonPluginDependency>> =
+                ::std::sync::OnceLock::new();
+            if !count.is_null() {
+                unsafe { *count = 0 };
+            }
+            ::cubemelon_sdk::panic::catch_panic(|| {
+                let dependencies = DEPENDENCIES.get_or_init(|| #dependencies_call);
+                if !count.is_null() {
+                    unsafe { *count = dependencies.len() };
+                }
+                if dependencies.is_empty() {
+                    std::ptr::null()
+                } else {
+                    dependencies.as_ptr()
+                }
+            })
+        }
     }
 }
 
@@ -555,7 +628,7 @@ fn generate_c_abi_interface(
 
         /// C ABI wrapper: Get supported types
         extern "C" fn __cubemelon_c_get_supported_types() -> u64 {
-            #struct_name::#supported_types_method()
+            ::cubemelon_sdk::panic::catch_panic(|| #struct_name::#supported_types_method())
         }
 
         /// C ABI wrapper: Is thread safe (default implementation)
@@ -581,7 +654,7 @@ fn generate_c_abi_wrappers(
         quote! {
             /// C ABI wrapper: Is thread safe (user implementation)
             extern "C" fn __cubemelon_c_is_thread_safe() -> bool {
-                #struct_name::#method_name()
+                ::cubemelon_sdk::panic::catch_panic(|| #struct_name::#method_name())
             }
         }
     } else {
@@ -593,7 +666,7 @@ fn generate_c_abi_wrappers(
         quote! {
             /// C ABI wrapper: Get thread requirements (user implementation)
             extern "C" fn __cubemelon_c_get_thread_requirements() -> u32 {
-                #struct_name::#method_name()
+                ::cubemelon_sdk::panic::catch_panic(|| #struct_name::#method_name())
             }
         }
     } else {
@@ -610,7 +683,7 @@ fn generate_c_abi_wrappers(
                 language: ::cubemelon_sdk::types::CubeMelonLanguage,
             ) -> *const u8 {
                 ::cubemelon_sdk::instance::with_plugin::<#struct_name, _, _>(plugin, |p| {
-                    p.#method_name(language)
+                    ::cubemelon_sdk::panic::catch_panic(|| p.#method_name(language))
                 }).unwrap_or(std::ptr::null())
             }
         }
@@ -636,7 +709,7 @@ fn generate_c_abi_wrappers(
                 language: ::cubemelon_sdk::types::CubeMelonLanguage,
             ) -> *const u8 {
                 ::cubemelon_sdk::instance::with_plugin::<#struct_name, _, _>(plugin, |p| {
-                    p.#method_name(language)
+                    ::cubemelon_sdk::panic::catch_panic(|| p.#method_name(language))
                 }).unwrap_or(std::ptr::null())
             }
         }
@@ -671,12 +744,13 @@ fn generate_c_abi_wrappers(
                 } else {
                     unsafe { Some(&*host_services) }
                 };
+                ::cubemelon_sdk::panic::set_panic_log(host_services_opt, env!("CARGO_PKG_NAME"));
 
                 ::cubemelon_sdk::instance::with_plugin_mut::<#struct_name, _, _>(plugin, |p| {
-                    match p.#method_name(host_services_opt) {
+                    ::cubemelon_sdk::panic::catch_panic(|| match p.#method_name(host_services_opt) {
                         Ok(()) => ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Success,
                         Err(err) => err,
-                    }
+                    })
                 }).unwrap_or(::cubemelon_sdk::error::CubeMelonPluginErrorCode::PluginNotFound)
             }
         }
@@ -685,8 +759,9 @@ fn generate_c_abi_wrappers(
             /// C ABI wrapper: Initialize plugin (default implementation)
             extern "C" fn __cubemelon_c_initialize(
                 _plugin: *mut ::cubemelon_sdk::instance::CubeMelonPlugin,
-                _host_services: *const ::cubemelon_sdk::structs::CubeMelonHostServices,
+                host_services: *const ::cubemelon_sdk::structs::CubeMelonHostServices,
             ) -> ::cubemelon_sdk::error::CubeMelonPluginErrorCode {
+                ::cubemelon_sdk::panic::set_panic_log(unsafe { host_services.as_ref() }, env!("CARGO_PKG_NAME"));
                 ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Success
             }
         }
@@ -705,10 +780,10 @@ fn generate_c_abi_wrappers(
                 }
 
                 ::cubemelon_sdk::instance::with_plugin_mut::<#struct_name, _, _>(plugin, |p| {
-                    match p.#method_name() {
+                    ::cubemelon_sdk::panic::catch_panic(|| match p.#method_name() {
                         Ok(()) => ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Success,
                         Err(err) => err,
-                    }
+                    })
                 }).unwrap_or(::cubemelon_sdk::error::CubeMelonPluginErrorCode::PluginNotFound)
             }
         }
@@ -804,6 +879,17 @@ mod tests {
         let methods = parse_plugin_methods(&input);
         assert!(methods.is_err());
     }
+
+    #[test]
+    fn test_interface_tables_are_static() {
+        let struct_name: syn::Ident = parse_quote!(TestPlugin);
+        let interfaces = ["single_task".to_string(), "async_task".to_string()];
+        let handlers = generate_interface_handlers(&struct_name, &interfaces).to_string();
+
+        // Each query returns the same table instead of allocating a new one
+        assert!(!handlers.contains("into_raw"));
+        assert_eq!(handlers.matches("OnceLock :: new").count(), 2);
+    }
 }
 
 /// Generate get_plugin_interface function for specified interfaces
@@ -890,10 +976,10 @@ fn generate_interface_handlers(struct_name: &syn::Ident, interfaces: &[String])
                 interface_checks.push(quote! {
                     // Handle SingleTask interface
                     if (plugin_types & (CubeMelonPluginType::SingleTask as u64)) != 0 {
-                        let single_task_interface = ::cubemelon_sdk::interfaces::single_task::create_single_task_interface::<#struct_name>();
-                        let boxed_interface = Box::new(single_task_interface);
+                        static TABLE: std::sync::OnceLock<::cubemelon_sdk::interfaces::single_task::CubeMelonSingleTaskInterfaceImpl> = std::sync::OnceLock::new();
+                        let table = TABLE.get_or_init(::cubemelon_sdk::interfaces::single_task::create_single_task_interface::<#struct_name>);
                         unsafe {
-                            *interface = Box::into_raw(boxed_interface) as *const std::ffi::c_void;
+                            *interface = table as *const _ as *const std::ffi::c_void;
                         }
                         return ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Success;
                     }
@@ -903,10 +989,10 @@ fn generate_interface_handlers(struct_name: &syn::Ident, interfaces: &[String])
                 interface_checks.push(quote! {
                     // Handle AsyncTask interface
                     if (plugin_types & (CubeMelonPluginType::AsyncTask as u64)) != 0 {
-                        let async_task_interface = ::cubemelon_sdk::interfaces::async_task::create_async_task_interface::<#struct_name>();
-                        let boxed_interface = Box::new(async_task_interface);
+                        static TABLE: std::sync::OnceLock<::cubemelon_sdk::interfaces::async_task::CubeMelonAsyncTaskInterfaceImpl> = std::sync::OnceLock::new();
+                        let table = TABLE.get_or_init(::cubemelon_sdk::interfaces::async_task::create_async_task_interface::<#struct_name>);
                         unsafe {
-                            *interface = Box::into_raw(boxed_interface) as *const std::ffi::c_void;
+                            *interface = table as *const _ as *const std::ffi::c_void;
                         }
                         return ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Success;
                     }
@@ -916,10 +1002,10 @@ fn generate_interface_handlers(struct_name: &syn::Ident, interfaces: &[String])
                 interface_checks.push(quote! {
                     // Handle Resident interface
                     if (plugin_types & (CubeMelonPluginType::Resident as u64)) != 0 {
-                        let resident_interface = ::cubemelon_sdk::interfaces::resident::create_resident_interface::<#struct_name>();
-                        let boxed_interface = Box::new(resident_interface);
+                        static TABLE: std::sync::OnceLock<::cubemelon_sdk::interfaces::resident::CubeMelonResidentInterfaceImpl> = std::sync::OnceLock::new();
+                        let table = TABLE.get_or_init(::cubemelon_sdk::interfaces::resident::create_resident_interface::<#struct_name>);
                         unsafe {
-                            *interface = Box::into_raw(boxed_interface) as *const std::ffi::c_void;
+                            *interface = table as *const _ as *const std::ffi::c_void;
                         }
                         return ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Success;
                     }
@@ -929,10 +1015,10 @@ fn generate_interface_handlers(struct_name: &syn::Ident, interfaces: &[String])
                 interface_checks.push(quote! {
                     // Handle State interface
                     if (plugin_types & (CubeMelonPluginType::State as u64)) != 0 {
-                        let state_interface = ::cubemelon_sdk::interfaces::state::create_state_interface::<#struct_name>();
-                        let boxed_interface = Box::new(state_interface);
+                        static TABLE: std::sync::OnceLock<::cubemelon_sdk::interfaces::state::CubeMelonPluginStateInterfaceImpl> = std::sync::OnceLock::new();
+                        let table = TABLE.get_or_init(::cubemelon_sdk::interfaces::state::create_state_interface::<#struct_name>);
                         unsafe {
-                            *interface = Box::into_raw(boxed_interface) as *const std::ffi::c_void;
+                            *interface = table as *const _ as *const std::ffi::c_void;
                         }
                         return ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Success;
                     }
@@ -942,10 +1028,10 @@ fn generate_interface_handlers(struct_name: &syn::Ident, interfaces: &[String])
                 interface_checks.push(quote! {
                     // Handle Manager interface
                     if (plugin_types & (CubeMelonPluginType::Manager as u64)) != 0 {
-                        let manager_interface = ::cubemelon_sdk::interfaces::manager::create_manager_interface::<#struct_name>();
-                        let boxed_interface = Box::new(manager_interface);
+                        static TABLE: std::sync::OnceLock<::cubemelon_sdk::interfaces::manager::CubeMelonPluginManagerInterfaceImpl> = std::sync::OnceLock::new();
+                        let table = TABLE.get_or_init(::cubemelon_sdk::interfaces::manager::create_manager_interface::<#struct_name>);
                         unsafe {
-                            *interface = Box::into_raw(boxed_interface) as *const std::ffi::c_void;
+                            *interface = table as *const _ as *const std::ffi::c_void;
                         }
                         return ::cubemelon_sdk::error::CubeMelonPluginErrorCode::Success;
                     }